@@ -48,6 +48,10 @@ if bashio::config.has_value govee_api_key ; then
   export GOVEE_API_KEY="$(bashio::config govee_api_key)"
 fi
 
+if bashio::config.has_value govee_api_key_usage ; then
+  export GOVEE_API_KEY_USAGE="$(bashio::config govee_api_key_usage)"
+fi
+
 if bashio::config.has_value no_multicast ; then
   export GOVEE_LAN_NO_MULTICAST="$(bashio::config no_multicast)"
 fi
@@ -68,6 +72,22 @@ if bashio::config.has_value temperature_scale ; then
   export GOVEE_TEMPERATURE_SCALE="$(bashio::config temperature_scale)"
 fi
 
+if bashio::config.has_value color_temp_mireds ; then
+  export GOVEE_HASS_COLOR_TEMP_MIREDS="$(bashio::config color_temp_mireds)"
+fi
+
+if bashio::config.has_value log_api ; then
+  export GOVEE_LOG_API="$(bashio::config log_api)"
+fi
+
+if bashio::config.has_value quiet_hours ; then
+  export GOVEE_QUIET_HOURS="$(bashio::config quiet_hours)"
+fi
+
+if bashio::config.has_value state_grace_period ; then
+  export GOVEE_STATE_GRACE_PERIOD="$(bashio::config state_grace_period)"
+fi
+
 env | grep GOVEE_ | sed -r 's/_(EMAIL|KEY|PASSWORD)=.*/_\1=REDACTED/'
 set -x
 
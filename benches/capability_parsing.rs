@@ -0,0 +1,61 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use govee::{from_json, DeviceCapability, HttpDeviceInfo, ParsedWorkMode};
+use serde::Deserialize;
+
+const LIST_DEVICES: &str = include_str!("../test-data/list_devices.json");
+const LIST_DEVICES_2: &str = include_str!("../test-data/list_devices_2.json");
+const WORK_MODES: &[(&str, &str)] = &[
+    (
+        "issue-81",
+        include_str!("../test-data/work-mode-issue-81.json"),
+    ),
+    (
+        "issue-93",
+        include_str!("../test-data/work-mode-issue-93.json"),
+    ),
+    (
+        "issue-100",
+        include_str!("../test-data/work-mode-issue-100.json"),
+    ),
+];
+
+#[derive(Deserialize)]
+struct DeviceList {
+    data: Vec<HttpDeviceInfo>,
+}
+
+fn device_list(c: &mut Criterion) {
+    let mut group = c.benchmark_group("device_list");
+    for (label, text) in [
+        ("list_devices", LIST_DEVICES),
+        ("list_devices_2", LIST_DEVICES_2),
+    ] {
+        group.bench_function(label, |b| {
+            b.iter(|| {
+                from_json::<DeviceList, _>(black_box(text))
+                    .unwrap()
+                    .data
+                    .len()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn work_mode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("work_mode");
+    for (label, text) in WORK_MODES {
+        group.bench_function(format!("from_json/{label}"), |b| {
+            b.iter(|| from_json::<DeviceCapability, _>(black_box(text)).unwrap())
+        });
+
+        let cap: DeviceCapability = from_json(text).unwrap();
+        group.bench_function(format!("with_capability/{label}"), |b| {
+            b.iter(|| ParsedWorkMode::with_capability(black_box(&cap)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, device_list, work_mode);
+criterion_main!(benches);
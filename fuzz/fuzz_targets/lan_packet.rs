@@ -0,0 +1,9 @@
+//! Feed arbitrary datagrams to the LAN API response parser.
+//! Run with `cargo +nightly fuzz run lan_packet` from the top of the repo.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = govee::lan_api::parse_response(data);
+});
@@ -0,0 +1,246 @@
+//! Verbose logging of the requests made to, and the responses received
+//! from, the platform and undocumented APIs, for debugging changes
+//! that Govee make to their protocols.
+//!
+//! Credentials and tokens are redacted from the logged bodies unless
+//! GOVEE_LOG_SENSITIVE_DATA is set, long bodies are truncated, and
+//! only a limited number of bodies are logged each minute, so that
+//! this can be left on while waiting for a problem to reproduce.
+use crate::undoc_api::should_log_sensitive_data;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde_json::Value as JsonValue;
+use std::time::{Duration, Instant};
+
+/// The longest body that is logged in full
+const MAX_BODY_LEN: usize = 2048;
+
+/// How many bodies may be logged in each RATE_WINDOW
+const MAX_PER_WINDOW: u32 = 30;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Fields whose values are never logged, compared ignoring case
+const SENSITIVE_FIELDS: &[&str] = &[
+    "password",
+    "email",
+    "token",
+    "accessToken",
+    "refreshToken",
+    "apiKey",
+    "Govee-API-Key",
+    "Authorization",
+    "p12",
+    "p12Pass",
+    "secretCode",
+    "topic",
+];
+
+const REDACTED: &str = "REDACTED";
+
+static ENABLED: OnceCell<()> = OnceCell::new();
+static LIMITER: Mutex<Option<RateWindow>> = Mutex::new(None);
+
+/// Begin logging API bodies for the remainder of the process
+pub fn enable() {
+    if ENABLED.set(()).is_ok() {
+        log::info!("Logging API request and response bodies, up to {MAX_PER_WINDOW} per minute");
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.get().is_some()
+}
+
+struct RateWindow {
+    start: Instant,
+    count: u32,
+    suppressed: u32,
+}
+
+impl RateWindow {
+    fn new(now: Instant) -> Self {
+        Self {
+            start: now,
+            count: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Returns whether another body may be logged at `now`, along
+    /// with the number that were suppressed in the prior window
+    /// when a new window has begun
+    fn admit(&mut self, now: Instant) -> (bool, u32) {
+        let mut suppressed = 0;
+        if now.duration_since(self.start) >= RATE_WINDOW {
+            suppressed = self.suppressed;
+            *self = Self::new(now);
+        }
+        if self.count < MAX_PER_WINDOW {
+            self.count += 1;
+            (true, suppressed)
+        } else {
+            self.suppressed += 1;
+            (false, suppressed)
+        }
+    }
+}
+
+fn admit() -> bool {
+    let now = Instant::now();
+    let (admitted, suppressed) = LIMITER
+        .lock()
+        .get_or_insert_with(|| RateWindow::new(now))
+        .admit(now);
+    if suppressed > 0 {
+        log::info!("API logging: {suppressed} bodies were not logged due to the rate limit");
+    }
+    admitted
+}
+
+fn redact(value: &mut JsonValue) {
+    match value {
+        JsonValue::Array(list) => {
+            for v in list {
+                redact(v);
+            }
+        }
+        JsonValue::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if SENSITIVE_FIELDS
+                    .iter()
+                    .any(|field| field.eq_ignore_ascii_case(key))
+                {
+                    *v = JsonValue::String(REDACTED.to_string());
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The loggable form of a setting that holds a secret, showing
+/// only whether it was set
+pub fn mask_secret(value: Option<String>) -> JsonValue {
+    value
+        .map(|_| JsonValue::String(REDACTED.to_string()))
+        .unwrap_or(JsonValue::Null)
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() <= MAX_BODY_LEN {
+        return text;
+    }
+    let mut end = MAX_BODY_LEN;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let remaining = text.len() - end;
+    text.truncate(end);
+    text.push_str(&format!("... ({remaining} more bytes)"));
+    text
+}
+
+/// Produce the loggable form of a body
+fn format_body(body: &[u8]) -> String {
+    if should_log_sensitive_data() {
+        return truncate(String::from_utf8_lossy(body).to_string());
+    }
+    match serde_json::from_slice::<JsonValue>(body) {
+        Ok(mut value) => {
+            redact(&mut value);
+            truncate(value.to_string())
+        }
+        // We can't tell what might be sensitive in it
+        Err(_) => format!("<{} bytes that are not JSON>", body.len()),
+    }
+}
+
+fn log_request<B: serde::Serialize + ?Sized>(method: &reqwest::Method, url: &str, body: &B) {
+    if !admit() {
+        return;
+    }
+    let body = match serde_json::to_vec(body) {
+        Ok(body) => format_body(&body),
+        Err(err) => format!("<unserializable: {err:#}>"),
+    };
+    log::info!("API request: {method} {url}: {body}");
+}
+
+pub trait LoggedJson {
+    /// Equivalent to `json`, but also logs the body
+    fn logged_json<B: serde::Serialize + ?Sized>(self, body: &B) -> Self;
+}
+
+impl LoggedJson for reqwest::RequestBuilder {
+    fn logged_json<B: serde::Serialize + ?Sized>(self, body: &B) -> Self {
+        if is_enabled() {
+            if let Some(request) = self.try_clone().and_then(|b| b.build().ok()) {
+                log_request(request.method(), request.url().as_str(), body);
+            }
+        }
+        self.json(body)
+    }
+}
+
+/// Log the body of a response received from `url`
+pub fn log_response(url: &reqwest::Url, status: reqwest::StatusCode, body: &[u8]) {
+    if !is_enabled() || !admit() {
+        return;
+    }
+    log::info!(
+        "API response: {url} status {}: {}",
+        status.as_u16(),
+        format_body(body)
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn redaction() {
+        let mut value = json!({
+            "email": "someone@example.com",
+            "password": "hunter2",
+            "client": {"token": "abc", "accountId": 12},
+            "devices": [{"deviceSettings": {"topic": "t", "secretCode": "s", "sku": "H6072"}}],
+        });
+        redact(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "email": REDACTED,
+                "password": REDACTED,
+                "client": {"token": REDACTED, "accountId": 12},
+                "devices": [{"deviceSettings": {"topic": REDACTED, "secretCode": REDACTED, "sku": "H6072"}}],
+            })
+        );
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate("short".to_string()), "short");
+        let long = "é".repeat(MAX_BODY_LEN);
+        let truncated = truncate(long.clone());
+        assert!(truncated.starts_with(&long[..MAX_BODY_LEN]));
+        assert!(truncated.ends_with(&format!("... ({} more bytes)", MAX_BODY_LEN)));
+        assert_eq!(format_body(b"not json"), "<8 bytes that are not JSON>");
+    }
+
+    #[test]
+    fn rate_limit() {
+        let start = Instant::now();
+        let mut window = RateWindow::new(start);
+        for _ in 0..MAX_PER_WINDOW {
+            assert_eq!(window.admit(start), (true, 0));
+        }
+        assert_eq!(window.admit(start), (false, 0));
+        assert_eq!(window.admit(start + Duration::from_secs(1)), (false, 0));
+        assert_eq!(window.admit(start + RATE_WINDOW), (true, 2));
+        assert_eq!(window.admit(start + RATE_WINDOW), (true, 0));
+    }
+}
@@ -232,6 +232,15 @@ impl PacketManager {
             0x04,
             code,
         ));
+        all_codecs.push(packet!(
+            &["Generic:Light"],
+            NotifySceneCode,
+            NotifySceneCode,
+            0xaa,
+            0x05,
+            0x04,
+            code,
+        ));
         all_codecs.push(packet!(
             &["Generic:Light"],
             SetDevicePower,
@@ -367,6 +376,16 @@ pub struct SetSceneCode {
     pub code: u16,
 }
 
+/// Reported by a light when it is put into a scene,
+/// including when that is done from the Govee app.
+/// The layout mirrors that of SetSceneCode, with the 0xaa status
+/// prefix, but it hasn't yet been confirmed against a captured
+/// packet, so codes are only used when they match a known scene.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct NotifySceneCode {
+    pub code: u16,
+}
+
 #[derive(Clone, Default, Debug, PartialEq, Eq)]
 pub struct SetDevicePower {
     pub on: bool,
@@ -376,6 +395,7 @@ pub struct SetDevicePower {
 pub enum GoveeBlePacket {
     Generic(HexBytes),
     SetSceneCode(SetSceneCode),
+    NotifySceneCode(NotifySceneCode),
     SetDevicePower(SetDevicePower),
     SetHumidifierNightlight(SetHumidifierNightlightParams),
     NotifyHumidifierMode(NotifyHumidifierMode),
@@ -492,6 +512,19 @@ mod test {
         );
     }
 
+    #[test]
+    fn scene_code_notification() {
+        // Constructed to show the expected layout, rather than captured:
+        // the code is little endian, as it is for SetSceneCode
+        assert_eq!(
+            MGR.decode_for_sku(
+                "Generic:Light",
+                &[0xaa, 0x05, 0x04, 0xcf, 0x27, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x43]
+            ),
+            GoveeBlePacket::NotifySceneCode(NotifySceneCode { code: 10191 })
+        );
+    }
+
     fn round_trip<T: 'static + std::fmt::Debug>(sku: &str, value: &T, expect: GoveeBlePacket) {
         let bytes = Base64HexBytes::encode_for_sku(sku, value).unwrap();
         let decoded = bytes.decode_for_sku(sku);
@@ -505,6 +538,11 @@ mod test {
             &SetSceneCode { code: 123 },
             GoveeBlePacket::SetSceneCode(SetSceneCode { code: 123 }),
         );
+        round_trip(
+            "Generic:Light",
+            &NotifySceneCode { code: 10191 },
+            GoveeBlePacket::NotifySceneCode(NotifySceneCode { code: 10191 }),
+        );
         round_trip(
             "Generic:Light",
             &SetDevicePower { on: true },
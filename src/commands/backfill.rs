@@ -0,0 +1,247 @@
+use crate::platform_api::DeviceType;
+use crate::service::hass::topic_safe_id_str;
+use crate::service::quirks::resolve_quirk;
+use crate::undoc_api::{DeviceEntry, GoveeUndocumentedApi, ThermometerReading};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use mosquitto_rs::{Client, QoS};
+use serde_json::json;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The number of readings to request per page
+const PAGE_SIZE: u64 = 1440;
+/// Guard against the cursor never reaching the end of the history
+const MAX_PAGES: usize = 200;
+
+/// Fetch the temperature and humidity history that the Govee cloud
+/// retains for thermometers, and either replay it into MQTT or write
+/// it out as CSV, so that a new installation needn't start out with
+/// empty graphs.
+///
+/// Home Assistant doesn't accept timestamped readings for MQTT sensors,
+/// so the replayed readings are published to `gv2mqtt/history/<id>`
+/// rather than to the sensor state topics; use something like Node-RED
+/// or Telegraf to load them into your time series database.
+#[derive(clap::Parser, Debug)]
+pub struct BackfillCommand {
+    /// Only fetch history for this device, which may be specified
+    /// by its id or by its name in the Govee App.
+    /// Can be specified multiple times.
+    /// The default is to fetch history for every thermometer in
+    /// the account.
+    #[arg(long = "device")]
+    devices: Vec<String>,
+
+    /// How many days of history to fetch
+    #[arg(long, default_value_t = 7)]
+    days: u32,
+
+    /// Write CSV to this file instead of publishing to MQTT.
+    /// Use `-` to write to stdout.
+    #[arg(long)]
+    csv: Option<PathBuf>,
+}
+
+pub struct DeviceHistory {
+    pub entry: DeviceEntry,
+    pub readings: Vec<ThermometerReading>,
+}
+
+fn has_temperature_history(entry: &DeviceEntry) -> bool {
+    resolve_quirk(&entry.sku)
+        .map(|q| q.device_type == DeviceType::Thermometer)
+        .unwrap_or(false)
+        || entry.device_ext.last_device_data.tem.is_some()
+}
+
+fn matches_label(entry: &DeviceEntry, label: &str) -> bool {
+    entry.device.eq_ignore_ascii_case(label) || entry.device_name.eq_ignore_ascii_case(label)
+}
+
+/// Select the devices named by `labels`, or all thermometers if
+/// no labels were given
+pub fn select_devices(
+    entries: Vec<DeviceEntry>,
+    labels: &[String],
+) -> anyhow::Result<Vec<DeviceEntry>> {
+    if labels.is_empty() {
+        return Ok(entries
+            .into_iter()
+            .filter(has_temperature_history)
+            .collect());
+    }
+
+    for label in labels {
+        anyhow::ensure!(
+            entries.iter().any(|entry| matches_label(entry, label)),
+            "device '{label}' not found in the Govee account"
+        );
+    }
+    Ok(entries
+        .into_iter()
+        .filter(|entry| labels.iter().any(|label| matches_label(entry, label)))
+        .collect())
+}
+
+/// Page backwards through the history for a device until we
+/// reach `since`, returning the readings in chronological order
+pub async fn fetch_history(
+    client: &GoveeUndocumentedApi,
+    token: &str,
+    entry: &DeviceEntry,
+    since: DateTime<Utc>,
+) -> anyhow::Result<Vec<ThermometerReading>> {
+    let since_ms = since.timestamp_millis();
+    let mut readings = vec![];
+    let mut index = 0;
+
+    for _ in 0..MAX_PAGES {
+        let page = client
+            .get_thermometer_history(token, &entry.sku, &entry.device, index, PAGE_SIZE)
+            .await
+            .with_context(|| format!("fetching history for {}", entry.device_name))?;
+
+        let reached_start =
+            page.datas.is_empty() || page.datas.iter().any(|reading| reading.time < since_ms);
+        readings.extend(page.datas.into_iter().filter(|r| r.time >= since_ms));
+
+        match page.index {
+            Some(next) if !reached_start && next != index => {
+                index = next;
+            }
+            _ => break,
+        }
+    }
+
+    readings.sort_by_key(|r| r.time);
+    readings.dedup_by_key(|r| r.time);
+    Ok(readings)
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub fn write_csv<W: Write>(mut out: W, histories: &[DeviceHistory]) -> anyhow::Result<()> {
+    writeln!(
+        out,
+        "device,sku,name,timestamp,temperature_c,humidity_percent"
+    )?;
+    for history in histories {
+        for reading in &history.readings {
+            let Some(timestamp) = reading.timestamp() else {
+                continue;
+            };
+            writeln!(
+                out,
+                "{},{},{},{},{:.2},{:.2}",
+                csv_field(&history.entry.device),
+                csv_field(&history.entry.sku),
+                csv_field(&history.entry.device_name),
+                timestamp.to_rfc3339(),
+                reading.celsius(),
+                reading.relative_humidity()
+            )?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+impl BackfillCommand {
+    async fn publish(&self, args: &crate::Args, histories: &[DeviceHistory]) -> anyhow::Result<()> {
+        let hass_args = &args.hass_args;
+        let mqtt_host = hass_args.mqtt_host()?;
+        let mqtt_port = hass_args.mqtt_port()?;
+
+        let client = Client::with_id(
+            &format!("govee2mqtt-backfill/{}", uuid::Uuid::new_v4().simple()),
+            true,
+        )?;
+        client.set_username_and_password(
+            hass_args.mqtt_username()?.as_deref(),
+            hass_args.mqtt_password()?.as_deref(),
+        )?;
+        client
+            .connect(
+                &mqtt_host,
+                mqtt_port.into(),
+                Duration::from_secs(60),
+                hass_args.mqtt_bind_address(),
+            )
+            .await
+            .with_context(|| format!("connecting to mqtt broker {mqtt_host}:{mqtt_port}"))?;
+
+        for history in histories {
+            let topic = format!(
+                "gv2mqtt/history/{}",
+                topic_safe_id_str(&history.entry.device)
+            );
+            for reading in &history.readings {
+                let Some(timestamp) = reading.timestamp() else {
+                    continue;
+                };
+                let payload = json!({
+                    "time": timestamp.to_rfc3339(),
+                    "temperature": reading.celsius(),
+                    "humidity": reading.relative_humidity(),
+                })
+                .to_string();
+                client
+                    .publish(&topic, &payload, QoS::AtLeastOnce, false)
+                    .await?;
+            }
+            eprintln!(
+                "Published {} readings for {} to {topic}",
+                history.readings.len(),
+                history.entry.device_name
+            );
+        }
+
+        Ok(())
+    }
+
+    pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
+        let client = args.undoc_args.api_client()?;
+        let acct = client.login_account_cached().await?;
+        let info = client.get_device_list(&acct.token).await?;
+
+        let entries = select_devices(info.devices, &self.devices)?;
+        anyhow::ensure!(
+            !entries.is_empty(),
+            "no thermometers found in the Govee account"
+        );
+
+        let since = Utc::now() - chrono::Duration::days(self.days.into());
+        let mut histories = vec![];
+        for entry in entries {
+            let readings = fetch_history(&client, &acct.token, &entry, since).await?;
+            eprintln!(
+                "Fetched {} readings for {} ({} {})",
+                readings.len(),
+                entry.device_name,
+                entry.device,
+                entry.sku
+            );
+            histories.push(DeviceHistory { entry, readings });
+        }
+
+        match &self.csv {
+            Some(path) if path.as_os_str() == "-" => {
+                write_csv(std::io::stdout().lock(), &histories)
+            }
+            Some(path) => {
+                let file = std::fs::File::create(path)
+                    .with_context(|| format!("creating {}", path.display()))?;
+                write_csv(std::io::BufWriter::new(file), &histories)
+            }
+            None => self.publish(args, &histories).await,
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use crate::lan_api::{Client as LanClient, LanDevice};
+use crate::platform_api::{DeviceType, HttpDeviceInfo};
+use crate::service::device::normalize_device_id;
+use crate::service::iot::capture_device_messages;
+use anyhow::Context;
+use serde_json::{json, Value as JsonValue};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Stands in for the real device id in captured fixtures
+const REDACTED_ID: &str = "AA:BB:CC:DD:EE:FF:00:01";
+const REDACTED_IP: &str = "192.0.2.1";
+
+/// Capture the capability document, current platform API state and a
+/// few LAN API status samples for a device, and save them into the
+/// `test-data/` layout so that they can be contributed as regression
+/// fixtures. If the Govee account is configured, the favorite scenes
+/// of lights and a page of the history of thermometers that is held
+/// by the Govee cloud are captured too, as are the messages that the
+/// Govee IoT service pushes about the device, with `--iot-seconds`.
+///
+/// The device id, its name, and its IP address are replaced with
+/// placeholders, but please review the files before sharing them.
+///
+/// Some of what is parsed has yet to be captured from a real device:
+/// the thermometer history and the favorite scenes from the Govee
+/// cloud, and the IoT messages of presence, leak and contact sensors
+/// and of scene switches. Their tests use samples constructed from the
+/// fields that we parse, and the parsing tolerates fields that it
+/// doesn't know of, until captures made by this command replace them.
+#[derive(clap::Parser, Debug)]
+pub struct CaptureFixtureCommand {
+    /// The device to capture, specified by its id or by its
+    /// name in the Govee App
+    device: String,
+
+    /// A short label to include in the file names, such as
+    /// the number of the issue that the fixture relates to
+    #[arg(long, default_value = "capture")]
+    label: String,
+
+    /// The directory in which to save the fixtures
+    #[arg(long, default_value = "test-data")]
+    output_dir: PathBuf,
+
+    /// The IP address of the device, for capturing LAN API status.
+    /// If omitted, LAN discovery is used to locate the device.
+    #[arg(long)]
+    ip: Option<IpAddr>,
+
+    /// How many LAN API status samples to capture
+    #[arg(long, default_value_t = 3)]
+    lan_samples: usize,
+
+    /// How many seconds to spend recording the messages that the
+    /// Govee IoT service pushes about the device. Sensors such as
+    /// presence, leak and contact sensors, and scene switches only
+    /// push when they are triggered, so trigger the device while
+    /// this is running. Requires the Govee account to be configured.
+    #[arg(long, default_value_t = 0)]
+    iot_seconds: u64,
+}
+
+/// Replace every occurrence of the sensitive strings in `value`
+fn redact(value: &mut JsonValue, replacements: &[(String, &str)]) {
+    match value {
+        JsonValue::String(s) => {
+            for (from, to) in replacements {
+                if !from.is_empty() && s.contains(from.as_str()) {
+                    *s = s.replace(from.as_str(), to);
+                }
+            }
+        }
+        JsonValue::Array(list) => {
+            for v in list {
+                redact(v, replacements);
+            }
+        }
+        JsonValue::Object(map) => {
+            for v in map.values_mut() {
+                redact(v, replacements);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn write_fixture(path: &Path, value: &JsonValue) -> anyhow::Result<()> {
+    let text = serde_json::to_string_pretty(value)?;
+    std::fs::write(path, text + "\n").with_context(|| format!("writing {}", path.display()))?;
+    eprintln!("Wrote {}", path.display());
+    Ok(())
+}
+
+impl CaptureFixtureCommand {
+    async fn find_lan_device(&self, args: &crate::Args, id: &str) -> anyhow::Result<LanDevice> {
+        if let Some(ip) = self.ip {
+            let (client, _scan) = LanClient::new(Default::default()).await?;
+            return client.scan_ip(ip).await;
+        }
+
+        let options = args.lan_disco_args.to_disco_options()?;
+        let (_client, mut scan) = LanClient::new(options).await?;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(device) = scan.recv().await {
+                if normalize_device_id(&device.device) == id {
+                    return Ok(device);
+                }
+            }
+            anyhow::bail!("LAN discovery stopped")
+        })
+        .await
+        .context("device didn't respond to LAN discovery within 10 seconds")?
+    }
+
+    async fn capture_lan_status(
+        &self,
+        args: &crate::Args,
+        id: &str,
+    ) -> anyhow::Result<Vec<JsonValue>> {
+        let device = self.find_lan_device(args, id).await?;
+        let (client, _scan) = LanClient::new(Default::default()).await?;
+
+        let mut samples = vec![];
+        for i in 0..self.lan_samples {
+            if i > 0 {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+            let status = client.query_status(&device).await?;
+            samples.push(serde_json::to_value(status)?);
+        }
+        Ok(samples)
+    }
+
+    async fn capture_thermometer_history(
+        &self,
+        args: &crate::Args,
+        info: &HttpDeviceInfo,
+    ) -> anyhow::Result<JsonValue> {
+        let client = args.undoc_args.api_client()?;
+        let acct = client.login_account_cached().await?;
+        client
+            .get_thermometer_history_json(&acct.token, &info.sku, &info.device, 0, 10)
+            .await
+    }
+
+    async fn capture_favorite_scenes(
+        &self,
+        args: &crate::Args,
+        info: &HttpDeviceInfo,
+    ) -> anyhow::Result<JsonValue> {
+        let client = args.undoc_args.api_client()?;
+        let acct = client.login_account_cached().await?;
+        client
+            .get_favorite_scenes_json(&acct.token, &info.sku, &info.device)
+            .await
+    }
+
+    pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
+        let client = args.api_args.api_client()?;
+        let wanted = normalize_device_id(&self.device);
+        let info = client
+            .get_devices()
+            .await?
+            .into_iter()
+            .find(|info| {
+                normalize_device_id(&info.device) == wanted
+                    || info.device_name.eq_ignore_ascii_case(&self.device)
+            })
+            .ok_or_else(|| anyhow::anyhow!("device '{}' not found", self.device))?;
+        let state = client.get_device_state(&info).await?;
+
+        let id = normalize_device_id(&info.device);
+        let lan_samples = match self.capture_lan_status(args, &id).await {
+            Ok(samples) => samples,
+            Err(err) => {
+                eprintln!("Not capturing LAN API status: {err:#}");
+                vec![]
+            }
+        };
+
+        let mut replacements = vec![
+            (info.device.clone(), REDACTED_ID),
+            (id.clone(), REDACTED_ID),
+            (info.device_name.clone(), info.sku.as_str()),
+        ];
+        if let Some(ip) = self.ip {
+            replacements.push((ip.to_string(), REDACTED_IP));
+        }
+
+        std::fs::create_dir_all(&self.output_dir)
+            .with_context(|| format!("creating {}", self.output_dir.display()))?;
+        let prefix = format!("{}-{}", info.sku, self.label);
+
+        let mut devices = json!({
+            "code": 200,
+            "message": "success",
+            "data": [info],
+        });
+        redact(&mut devices, &replacements);
+        write_fixture(
+            &self.output_dir.join(format!("{prefix}-list-devices.json")),
+            &devices,
+        )?;
+
+        let mut state = json!({
+            "requestId": "uuid",
+            "msg": "success",
+            "code": 200,
+            "payload": state,
+        });
+        redact(&mut state, &replacements);
+        write_fixture(
+            &self.output_dir.join(format!("{prefix}-device-state.json")),
+            &state,
+        )?;
+
+        if info.device_type == DeviceType::Thermometer {
+            match self.capture_thermometer_history(args, &info).await {
+                Ok(mut history) => {
+                    redact(&mut history, &replacements);
+                    write_fixture(
+                        &self
+                            .output_dir
+                            .join(format!("{prefix}-thermometer-history.json")),
+                        &history,
+                    )?;
+                }
+                Err(err) => eprintln!("Not capturing thermometer history: {err:#}"),
+            }
+        }
+
+        if info.device_type == DeviceType::Light {
+            match self.capture_favorite_scenes(args, &info).await {
+                Ok(mut favorites) => {
+                    redact(&mut favorites, &replacements);
+                    write_fixture(
+                        &self
+                            .output_dir
+                            .join(format!("{prefix}-favorite-scenes.json")),
+                        &favorites,
+                    )?;
+                }
+                Err(err) => eprintln!("Not capturing favorite scenes: {err:#}"),
+            }
+        }
+
+        if self.iot_seconds > 0 {
+            eprintln!(
+                "Recording IoT messages for {} seconds; trigger the device now",
+                self.iot_seconds
+            );
+            match capture_device_messages(args, &info.device, Duration::from_secs(self.iot_seconds))
+                .await
+            {
+                Ok(messages) => {
+                    let mut iot = JsonValue::Array(messages);
+                    redact(&mut iot, &replacements);
+                    write_fixture(&self.output_dir.join(format!("{prefix}-iot.json")), &iot)?;
+                }
+                Err(err) => eprintln!("Not capturing IoT messages: {err:#}"),
+            }
+        }
+
+        if !lan_samples.is_empty() {
+            let mut lan = JsonValue::Array(lan_samples);
+            redact(&mut lan, &replacements);
+            write_fixture(
+                &self.output_dir.join(format!("{prefix}-lan-status.json")),
+                &lan,
+            )?;
+        }
+
+        eprintln!("Please review the files for anything private before sharing them");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redaction() {
+        let mut value = json!({
+            "device": "AB:CD:00:11:22:33:44:55",
+            "deviceName": "Bedroom Purifier",
+            "nested": [{"note": "Bedroom Purifier is AB:CD:00:11:22:33:44:55"}],
+            "brightness": 100,
+        });
+        redact(
+            &mut value,
+            &[
+                ("AB:CD:00:11:22:33:44:55".to_string(), REDACTED_ID),
+                ("Bedroom Purifier".to_string(), "H7122"),
+            ],
+        );
+        assert_eq!(
+            value,
+            json!({
+                "device": REDACTED_ID,
+                "deviceName": "H7122",
+                "nested": [{"note": format!("H7122 is {REDACTED_ID}")}],
+                "brightness": 100,
+            })
+        );
+    }
+}
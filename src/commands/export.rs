@@ -0,0 +1,92 @@
+use crate::commands::backfill::{fetch_history, select_devices, write_csv, DeviceHistory};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::path::PathBuf;
+
+/// Export the temperature and humidity history of one or more
+/// thermometers as CSV, for offline analysis.
+/// The history is fetched from the Govee cloud.
+#[derive(clap::Parser, Debug)]
+pub struct ExportCommand {
+    /// The device to export, specified by its id or by its name
+    /// in the Govee App. Can be specified multiple times.
+    #[arg(long = "device", required = true)]
+    devices: Vec<String>,
+
+    /// The start of the time range to export; either a date
+    /// such as `2024-01-31`, or an RFC 3339 timestamp.
+    /// The default is 7 days ago.
+    #[arg(long, value_parser = parse_time)]
+    from: Option<DateTime<Utc>>,
+
+    /// The end of the time range to export; either a date
+    /// such as `2024-01-31`, or an RFC 3339 timestamp.
+    /// The default is now.
+    #[arg(long, value_parser = parse_time)]
+    to: Option<DateTime<Utc>>,
+
+    /// Write the CSV to this file rather than to stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+fn parse_time(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight to be valid")
+            .and_utc());
+    }
+    Ok(DateTime::parse_from_rfc3339(s)
+        .with_context(|| format!("'{s}' is neither a YYYY-MM-DD date nor an RFC 3339 timestamp"))?
+        .with_timezone(&Utc))
+}
+
+impl ExportCommand {
+    pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = self.from.unwrap_or_else(|| to - chrono::Duration::days(7));
+        anyhow::ensure!(from < to, "--from must be earlier than --to");
+
+        let client = args.undoc_args.api_client()?;
+        let acct = client.login_account_cached().await?;
+        let info = client.get_device_list(&acct.token).await?;
+
+        let to_ms = to.timestamp_millis();
+        let mut histories = vec![];
+        for entry in select_devices(info.devices, &self.devices)? {
+            let mut readings = fetch_history(&client, &acct.token, &entry, from).await?;
+            readings.retain(|r| r.time <= to_ms);
+            histories.push(DeviceHistory { entry, readings });
+        }
+
+        match &self.output {
+            Some(path) => {
+                let file = std::fs::File::create(path)
+                    .with_context(|| format!("creating {}", path.display()))?;
+                write_csv(std::io::BufWriter::new(file), &histories)
+            }
+            None => write_csv(std::io::stdout().lock(), &histories),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn time_parsing() {
+        assert_eq!(
+            parse_time("2024-01-31").unwrap().to_rfc3339(),
+            "2024-01-31T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_time("2024-01-31T10:00:00-08:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2024-01-31T18:00:00+00:00"
+        );
+        assert!(parse_time("yesterday").is_err());
+    }
+}
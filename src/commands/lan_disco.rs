@@ -1,4 +1,5 @@
 use crate::lan_api::Client;
+use crate::service::device::Freshness;
 use tokio::time::{Duration, Instant};
 
 #[derive(clap::Parser, Debug)]
@@ -23,9 +24,10 @@ impl LanDiscoCommand {
 
                 device.set_lan_device(lan_device.clone());
 
+                let freshness = Freshness::now();
                 let status = match client.query_status(&lan_device).await {
                     Ok(status) => {
-                        device.set_lan_device_status(status.clone());
+                        device.set_lan_device_status(status.clone(), freshness);
                         if status.on {
                             format!(
                                 "{pct}% #{r:02x}{g:02x}{b:02x} {k}k",
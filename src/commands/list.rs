@@ -1,4 +1,5 @@
 use crate::lan_api::Client as LanClient;
+use crate::service::device::Freshness;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
@@ -13,6 +14,7 @@ pub struct ListCommand {
 impl ListCommand {
     pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
         let state = Arc::new(crate::service::state::State::new());
+        let id_format = args.config_args.load()?.device_id_format;
 
         let options = args.lan_disco_args.to_disco_options()?;
         if options.is_empty() {
@@ -39,11 +41,12 @@ impl ListCommand {
                         .await
                         .set_lan_device(lan_device.clone());
 
+                    let freshness = Freshness::now();
                     if let Ok(status) = client.query_status(&lan_device).await {
                         state
                             .device_mut(&lan_device.sku, &lan_device.device)
                             .await
-                            .set_lan_device_status(status);
+                            .set_lan_device_status(status, freshness);
                     }
                 }
             }))
@@ -80,7 +83,7 @@ impl ListCommand {
             println!(
                 "{sku:<7} {id} {ip:<15} {name} {room}",
                 sku = d.sku,
-                id = d.id,
+                id = id_format.format(&d.id),
                 ip = d
                     .ip_addr()
                     .map(|ip| ip.to_string())
@@ -0,0 +1,302 @@
+use mosquitto_rs::{Client, Event, QoS};
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Generate synthetic command traffic against a running govee2mqtt
+/// instance via the MQTT broker, and report on how the bridge keeps up.
+///
+/// By default, commands are sent to synthetic devices that the bridge
+/// doesn't know of, which exercises the MQTT router, queueing and
+/// publishing without touching any real device.
+/// With --real-devices, commands are sent to the lights that the bridge
+/// has published manifests for, or to those named via --device, which
+/// exercises rate limiting and device control as well. The lights will
+/// change as the commands are applied, so this asks for confirmation!
+#[derive(clap::Parser, Debug)]
+pub struct LoadTestCommand {
+    /// The number of synthetic devices to send commands to, or with
+    /// --real-devices, the maximum number of discovered lights
+    #[arg(long, default_value_t = 50)]
+    devices: usize,
+
+    /// Send commands to real lights, rather than synthetic devices
+    #[arg(long)]
+    real_devices: bool,
+
+    /// Send commands to this device id instead of the discovered
+    /// lights. Can be specified multiple times.
+    #[arg(long = "device", requires = "real_devices")]
+    device_ids: Vec<String>,
+
+    /// The number of commands to send per second, across all devices
+    #[arg(long, default_value_t = 10.0)]
+    rate: f64,
+
+    /// How long to run the test, in seconds
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+
+    /// How long to keep listening for responses from the bridge
+    /// after the last command has been sent, in seconds
+    #[arg(long, default_value_t = 5)]
+    settle: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    send_errors: AtomicU64,
+    received: AtomicU64,
+}
+
+/// How long to wait for the bridge's retained device manifests
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Returns the id used in the command topic of the device described by
+/// the manifest on `topic`, if that device has a light entity
+fn light_id_from_manifest(topic: &str, payload: &[u8]) -> Option<String> {
+    let id = topic.strip_prefix("gv2mqtt/")?.strip_suffix("/manifest")?;
+    let manifest: serde_json::Value = serde_json::from_slice(payload).ok()?;
+    manifest
+        .get("entities")?
+        .as_array()?
+        .iter()
+        .any(|entity| entity.get("platform").and_then(|p| p.as_str()) == Some("light"))
+        .then(|| id.to_string())
+}
+
+/// Returns the ids of `count` devices that the bridge doesn't know of
+fn synthetic_ids(count: usize) -> Vec<String> {
+    (1..=count).map(|n| format!("loadtest{n:04}")).collect()
+}
+
+/// Ask the user to confirm that real lights are to be driven
+fn confirm_real_devices(count: usize) -> anyhow::Result<()> {
+    eprint!(
+        "This will repeatedly turn {count} real lights on and off and change \
+         their brightness and color. Type 'yes' to continue: "
+    );
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    anyhow::ensure!(
+        answer.trim() == "yes",
+        "not confirmed; no commands were sent"
+    );
+    Ok(())
+}
+
+impl LoadTestCommand {
+    /// Synthetic devices, unless --real-devices was given, in which case
+    /// the devices named via --device, or else the lights that the
+    /// bridge has published manifests for
+    async fn target_ids(
+        &self,
+        client: &Client,
+        subscriber: &async_channel::Receiver<Event>,
+    ) -> anyhow::Result<Vec<String>> {
+        if !self.real_devices {
+            return Ok(synthetic_ids(self.devices));
+        }
+        if !self.device_ids.is_empty() {
+            return Ok(self.device_ids.clone());
+        }
+
+        client
+            .subscribe("gv2mqtt/+/manifest", QoS::AtMostOnce)
+            .await?;
+        let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+        let mut ids = vec![];
+        while let Ok(Ok(event)) = tokio::time::timeout_at(deadline, subscriber.recv()).await {
+            if let Event::Message(msg) = event {
+                if let Some(id) = light_id_from_manifest(&msg.topic, &msg.payload) {
+                    if !ids.contains(&id) {
+                        ids.push(id);
+                    }
+                }
+            }
+        }
+        client.unsubscribe("gv2mqtt/+/manifest").await?;
+
+        ids.sort();
+        ids.truncate(self.devices);
+        Ok(ids)
+    }
+
+    /// Produce a varied, but deterministic, light command for
+    /// the nth message in the sequence
+    fn command_for(n: u64) -> serde_json::Value {
+        match n % 4 {
+            0 => json!({"state": "ON"}),
+            1 => json!({"state": "ON", "brightness": (n * 37 % 100) + 1}),
+            2 => json!({
+                "state": "ON",
+                "color": {
+                    "r": n * 71 % 256,
+                    "g": n * 113 % 256,
+                    "b": n * 197 % 256,
+                }
+            }),
+            _ => json!({"state": "OFF"}),
+        }
+    }
+
+    pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
+        anyhow::ensure!(self.rate > 0.0, "--rate must be greater than zero");
+
+        let hass_args = &args.hass_args;
+        let mqtt_host = hass_args.mqtt_host()?;
+        let mqtt_port = hass_args.mqtt_port()?;
+
+        let client = Client::with_id(
+            &format!("govee2mqtt-loadtest/{}", uuid::Uuid::new_v4().simple()),
+            true,
+        )?;
+        client.set_username_and_password(
+            hass_args.mqtt_username()?.as_deref(),
+            hass_args.mqtt_password()?.as_deref(),
+        )?;
+        client
+            .connect(
+                &mqtt_host,
+                mqtt_port.into(),
+                Duration::from_secs(60),
+                hass_args.mqtt_bind_address(),
+            )
+            .await?;
+        let subscriber = client.subscriber().expect("to own the subscriber");
+
+        let ids = self.target_ids(&client, &subscriber).await?;
+        anyhow::ensure!(
+            !ids.is_empty(),
+            "no devices to send commands to; is the bridge running and \
+             connected to this broker?"
+        );
+        if self.real_devices {
+            confirm_real_devices(ids.len())?;
+        }
+
+        // Watch everything that the bridge publishes so that we can
+        // see how it responds to the load
+        client.subscribe("gv2mqtt/#", QoS::AtMostOnce).await?;
+
+        let counters = Arc::new(Counters::default());
+        let topic_counts = Arc::new(parking_lot::Mutex::new(HashMap::<String, u64>::new()));
+
+        let listener = {
+            let counters = counters.clone();
+            let topic_counts = topic_counts.clone();
+            tokio::spawn(async move {
+                while let Ok(event) = subscriber.recv().await {
+                    if let Event::Message(msg) = event {
+                        // Ignore our own commands as they are echoed back,
+                        // and what was retained before we started
+                        if msg.topic.contains("/command") || msg.retain {
+                            continue;
+                        }
+                        counters.received.fetch_add(1, Ordering::Relaxed);
+                        let kind = msg.topic.split('/').nth(1).unwrap_or("unknown").to_string();
+                        *topic_counts.lock().entry(kind).or_default() += 1;
+                    }
+                }
+            })
+        };
+
+        eprintln!(
+            "Sending {rate} commands/s to {n} devices for {duration}s",
+            rate = self.rate,
+            n = ids.len(),
+            duration = self.duration
+        );
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(self.duration);
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / self.rate));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+
+        let mut n = 0u64;
+        while Instant::now() < deadline {
+            interval.tick().await;
+            let id = &ids[n as usize % ids.len()];
+            let topic = format!("gv2mqtt/light/{id}/command");
+            let payload = Self::command_for(n).to_string();
+            match client
+                .publish(&topic, &payload, QoS::AtMostOnce, false)
+                .await
+            {
+                Ok(_) => {
+                    counters.sent.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) => {
+                    log::warn!("publish {topic}: {err:#}");
+                    counters.send_errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            n += 1;
+        }
+        let send_elapsed = started.elapsed();
+
+        tokio::time::sleep(Duration::from_secs(self.settle)).await;
+        listener.abort();
+
+        let sent = counters.sent.load(Ordering::Relaxed);
+        println!("Elapsed:          {send_elapsed:?}");
+        println!(
+            "Commands sent:    {sent} ({:.1}/s)",
+            sent as f64 / send_elapsed.as_secs_f64()
+        );
+        println!(
+            "Send errors:      {}",
+            counters.send_errors.load(Ordering::Relaxed)
+        );
+        println!(
+            "Bridge messages:  {}",
+            counters.received.load(Ordering::Relaxed)
+        );
+
+        let mut topic_counts: Vec<_> = topic_counts.lock().drain().collect();
+        topic_counts.sort();
+        for (kind, count) in topic_counts {
+            println!("  {kind:<14}  {count}");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn light_manifests() {
+        let light = br#"{"device": "AA:BB:CC:DD:EE:FF:42:2A", "sku": "H6072", "name": "Lamp",
+            "entities": [{"unique_id": "gv2mqtt-AABBCCDDEEFF422A", "platform": "light"}]}"#;
+        let sensor = br#"{"device": "AA:BB:CC:DD:EE:FF:42:2C", "sku": "H5112", "name": "Thermometer",
+            "entities": [{"unique_id": "sensor-AABBCCDDEEFF422C-temperature", "platform": "sensor"}]}"#;
+
+        assert_eq!(
+            light_id_from_manifest("gv2mqtt/AABBCCDDEEFF422A/manifest", light).as_deref(),
+            Some("AABBCCDDEEFF422A")
+        );
+        assert_eq!(
+            light_id_from_manifest("gv2mqtt/AABBCCDDEEFF422C/manifest", sensor),
+            None
+        );
+        assert_eq!(
+            light_id_from_manifest("gv2mqtt/AABBCCDDEEFF422A/manifest", b""),
+            None
+        );
+        assert_eq!(light_id_from_manifest("gv2mqtt/leader", light), None);
+    }
+
+    #[test]
+    fn synthetic_targets() {
+        assert_eq!(synthetic_ids(2), vec!["loadtest0001", "loadtest0002"]);
+        assert!(synthetic_ids(0).is_empty());
+    }
+}
@@ -1,7 +1,12 @@
+pub mod backfill;
+pub mod capture_fixture;
+pub mod export;
 pub mod http_control;
 pub mod lan_control;
 pub mod lan_disco;
 pub mod list;
 pub mod list_http;
+pub mod loadtest;
 pub mod serve;
+pub mod support_matrix;
 pub mod undoc;
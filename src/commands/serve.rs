@@ -1,32 +1,108 @@
+use crate::api_log::mask_secret;
+use crate::hass_mqtt::instance::EntityInstance;
+use crate::hass_mqtt::sensor::{BridgeStatisticsDiagnostic, PlatformApiUsageDiagnostic};
+use crate::hass_mqtt::work_mode::load_work_mode_overrides;
 use crate::lan_api::Client as LanClient;
-use crate::service::device::Device;
+use crate::opt_env_var;
+use crate::platform_api::{GoveeApiClient, PlatformApiUsage};
+use crate::service::config::watch_config_file;
+use crate::service::device::{CapabilityChanges, Device, Freshness};
 use crate::service::hass::spawn_hass_integration;
 use crate::service::http::run_http_server;
+use crate::service::identity::set_instance_name;
 use crate::service::iot::start_iot_client;
+use crate::service::polling::{
+    jittered, poll_intervals, set_poll_intervals, spread_evenly, PollIntervals,
+    DEFAULT_METADATA_REFRESH_INTERVAL, DEFAULT_SCENE_REFRESH_INTERVAL, DEFAULT_STATE_POLL_INTERVAL,
+};
+use crate::service::quiet_hours::{is_quiet_now, set_quiet_hours, QuietHours};
+use crate::service::removed::{removed_devices_file_name, RemovedDevices};
+use crate::service::startup::{startup_file_name, StartupTracker, Subsystem};
 use crate::service::state::StateHandle;
+use crate::service::state_grace::{release_held_state, set_state_grace_period};
+use crate::undoc_api::GoveeUndocumentedApi;
 use crate::version_info::govee_version;
 use anyhow::Context;
-use chrono::Utc;
-use once_cell::sync::Lazy;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value as JsonValue};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
 
-pub const POLL_INTERVAL: Lazy<chrono::Duration> = Lazy::new(|| chrono::Duration::seconds(900));
+/// How often we check for devices whose state has become stale
+const STATE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// The period across which the scene catalog requests are spread
+const SCENE_REFRESH_WINDOW: Duration = Duration::from_secs(600);
 
 #[derive(clap::Parser, Debug)]
 pub struct ServeCommand {
     /// The port on which the HTTP API will listen
     #[arg(long, default_value_t = 8056)]
     http_port: u16,
+
+    /// How stale, in seconds, the state of a device may become before
+    /// it is polled. Devices that report their own state changes via
+    /// the LAN or IoT APIs are rarely polled.
+    /// You may also set this via the GOVEE_STATE_POLL_INTERVAL
+    /// environment variable. The default is 900.
+    #[arg(long)]
+    state_poll_interval: Option<u64>,
+
+    /// How often, in seconds, to refresh the scene catalogs.
+    /// You may also set this via the GOVEE_SCENE_REFRESH_INTERVAL
+    /// environment variable. The default is 86400 (daily).
+    #[arg(long)]
+    scene_refresh_interval: Option<u64>,
+
+    /// How often, in seconds, to refresh the device list and the
+    /// room assignments from the Govee account.
+    /// You may also set this via the GOVEE_METADATA_REFRESH_INTERVAL
+    /// environment variable. The default is 3600 (hourly).
+    #[arg(long)]
+    metadata_refresh_interval: Option<u64>,
+
+    /// A daily window, such as 23:00-06:30, in local time, during
+    /// which diagnostic entities are not updated.
+    /// You may also set this via the GOVEE_QUIET_HOURS
+    /// environment variable.
+    #[arg(long)]
+    quiet_hours: Option<String>,
+
+    /// A JSON file that maps SKU -> work mode -> overrides, such as
+    /// the label of the mode, which are merged over those built
+    /// in to govee2mqtt.
+    /// You may also set this via the GOVEE_WORK_MODE_OVERRIDES
+    /// environment variable.
+    #[arg(long)]
+    work_mode_overrides: Option<PathBuf>,
+
+    /// How long, in seconds, to hold back the state of each device
+    /// after startup until we have a fresh reading from it, so that
+    /// hass doesn't show cached values as current.
+    /// You may also set this via the GOVEE_STATE_GRACE_PERIOD
+    /// environment variable. The default is 0, which publishes
+    /// whatever is known right away.
+    #[arg(long)]
+    state_grace_period: Option<u64>,
 }
 
-async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Result<()> {
-    let now = Utc::now();
+/// Returns true if the state of the device is stale and
+/// polling it could help
+fn is_due_for_poll(device: &Device, now: DateTime<Utc>) -> bool {
+    if device.removed {
+        return false;
+    }
 
     if device.is_ble_only_device() == Some(true) {
         // We can't poll this device, we have no ble support
-        return Ok(());
+        return false;
+    }
+
+    if device.recently_commanded(now) {
+        // The state may not yet reflect the command; the poll
+        // that follows the command will pick up the result
+        return false;
     }
 
     let poll_interval = device.preferred_poll_interval();
@@ -37,7 +113,7 @@ async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Res
     };
 
     if !can_update {
-        return Ok(());
+        return false;
     }
 
     let device_state = device.device_state();
@@ -47,24 +123,28 @@ async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Res
     };
 
     if !needs_update {
-        return Ok(());
+        return false;
     }
 
-    let needs_platform = device.needs_platform_poll();
-
     // Don't interrogate via HTTP if we can use the LAN.
     // If we have LAN and the device is stale, it is likely
     // offline and there is little sense in burning up request
     // quota to the platform API for it
-    if device.lan_device.is_some() && !needs_platform {
+    if device.lan_device.is_some() && !device.needs_platform_poll() {
         log::trace!("LAN-available device {device} needs a status update; it's likely offline.");
+        return false;
+    }
+
+    true
+}
+
+async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Result<()> {
+    if !is_due_for_poll(device, Utc::now()) {
         return Ok(());
     }
 
-    if !needs_platform {
-        if state.poll_iot_api(&device).await? {
-            return Ok(());
-        }
+    if !device.needs_platform_poll() && state.poll_iot_api(device).await? {
+        return Ok(());
     }
 
     state.poll_platform_api(&device).await?;
@@ -72,49 +152,383 @@ async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Res
     Ok(())
 }
 
-async fn periodic_state_poll(state: StateHandle) -> anyhow::Result<()> {
+async fn periodic_state_poll(state: StateHandle) {
     sleep(Duration::from_secs(20)).await;
+    let mut was_quiet = false;
     loop {
-        for d in state.devices().await {
+        let tick = jittered(STATE_CHECK_INTERVAL.min(poll_intervals().state));
+        let started = Instant::now();
+
+        // Rather than polling everything that is due in a burst,
+        // spread the polls evenly across the tick. Each device also
+        // has its own slightly different interval, so devices that
+        // were due together at startup drift apart over time.
+        let now = Utc::now();
+        let due: Vec<String> = state
+            .devices()
+            .await
+            .into_iter()
+            .filter(|d| is_due_for_poll(d, now))
+            .map(|d| d.id)
+            .collect();
+        let pace = spread_evenly(tick, due.len());
+
+        for (i, id) in due.iter().enumerate() {
+            if i > 0 {
+                sleep(pace).await;
+            }
+            // Re-read the device, as its state may have been
+            // updated by some other means while we were waiting
+            let Some(d) = state.device_by_id(id).await else {
+                continue;
+            };
             if let Err(err) = poll_single_device(&state, &d).await {
                 log::error!("while polling {d}: {err:#}");
             }
         }
 
-        sleep(Duration::from_secs(60)).await;
+        // The quota usage and bridge statistics are diagnostics, and
+        // so are held back during the quiet hours
+        let quiet = is_quiet_now();
+        if let Some(hass) = state.get_hass_client().await {
+            if let Err(err) = hass.advise_hass_of_availability(&state).await {
+                log::error!("while reporting device availability: {err:#}");
+            }
+
+            // Keep the quota usage sensor current; it is only
+            // registered when we have a platform API client
+            if !quiet && state.get_platform_client().await.is_some() {
+                if let Err(err) = PlatformApiUsageDiagnostic::new(&state)
+                    .notify_state(&hass)
+                    .await
+                {
+                    log::error!("while reporting platform API usage: {err:#}");
+                }
+            }
+            if !quiet {
+                if let Err(err) = BridgeStatisticsDiagnostic::new(&state)
+                    .notify_state(&hass)
+                    .await
+                {
+                    log::error!("while reporting bridge statistics: {err:#}");
+                }
+            }
+        }
+
+        // Catch up on the diagnostics, including the firmware update
+        // checks, that were held back during the quiet hours
+        if was_quiet && !quiet {
+            log::info!("Quiet hours have ended; reporting the deferred diagnostics");
+            for device in state.devices().await {
+                if let Err(err) = state.notify_of_state_change(&device.id).await {
+                    log::error!("while reporting the state of {device}: {err:#}");
+                }
+            }
+        }
+        was_quiet = quiet;
+
+        sleep(tick.saturating_sub(started.elapsed())).await;
+    }
+}
+
+/// Load the device list from the platform API. Returns true if the
+/// capabilities of any previously known device have changed
+async fn load_platform_devices(
+    state: &StateHandle,
+    client: &GoveeApiClient,
+) -> anyhow::Result<bool> {
+    let mut capabilities_changed = false;
+    for info in client.get_devices().await? {
+        let mut device = state.device_mut(&info.sku, &info.device).await;
+        // Replacing the info forgets which capabilities were found
+        // to be unusable, so only do that when it has changed
+        let unchanged = device.http_device_info.as_ref().is_some_and(|current| {
+            serde_json::to_value(current).ok() == serde_json::to_value(&info).ok()
+        });
+        if !unchanged {
+            if let Some(current) = &device.http_device_info {
+                let changes = CapabilityChanges::between(current, &info);
+                if !changes.is_empty() {
+                    log::warn!(
+                        "Capabilities of {device} have changed: {}",
+                        serde_json::to_string(&changes)?
+                    );
+                    capabilities_changed = true;
+                }
+            }
+            device.set_http_device_info(info);
+        }
+    }
+    state.apply_device_config().await;
+    Ok(capabilities_changed)
+}
+
+async fn load_undoc_devices(
+    state: &StateHandle,
+    client: &GoveeUndocumentedApi,
+    token: &str,
+) -> anyhow::Result<()> {
+    let info = client.get_device_list(token).await?;
+    let mut group_by_id = HashMap::new();
+    for group in info.groups {
+        group_by_id.insert(group.group_id, group.group_name);
+    }
+    for entry in info.devices {
+        let mut device = state.device_mut(&entry.sku, &entry.device).await;
+        let room_name = group_by_id.get(&entry.group_id).map(|name| name.as_str());
+        device.set_undoc_device_info(entry, room_name);
+    }
+    state.link_gateway_devices().await;
+    state.apply_device_config().await;
+    Ok(())
+}
+
+/// Refresh the device list and room assignments, and register
+/// entities for any devices or capabilities that have appeared
+async fn refresh_metadata(state: &StateHandle) -> anyhow::Result<()> {
+    let mut capabilities_changed = false;
+    if let Some(client) = state.get_platform_client().await {
+        capabilities_changed = load_platform_devices(state, &client)
+            .await
+            .context("refreshing platform API device list")?;
+    }
+    if let Some(client) = state.get_undoc_client().await {
+        let acct = client.login_account_cached().await?;
+        load_undoc_devices(state, &client, &acct.token)
+            .await
+            .context("refreshing undocumented API device list")?;
+    }
+    if let Some(hass) = state.get_hass_client().await {
+        if capabilities_changed {
+            // Entities for changed capabilities need their configs
+            // republishing; this also removes those that no longer apply
+            hass.register_with_hass(state).await?;
+        } else {
+            hass.register_new_entities(state).await?;
+        }
+    }
+    // The device list carries the latest thermometer readings
+    // and Wi-Fi signal strengths
+    for device in state.devices().await {
+        if device.thermometer_state().is_some() || device.wifi_rssi().is_some() {
+            state.notify_of_state_change(&device.id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Fetch the scene catalogs afresh, spreading the requests across
+/// SCENE_REFRESH_WINDOW, and republish the entities of the devices
+/// whose scene lists have changed so that those offered in hass
+/// are current
+async fn refresh_scenes(state: &StateHandle) -> anyhow::Result<()> {
+    let devices = state.devices().await;
+    let pace = spread_evenly(SCENE_REFRESH_WINDOW, devices.len());
+
+    for (i, device) in devices.iter().enumerate() {
+        if i > 0 {
+            sleep(pace).await;
+        }
+        let before = state.device_offered_scenes(device).await.ok();
+
+        if let Some(info) = &device.http_device_info {
+            GoveeApiClient::invalidate_scene_caches(info)?;
+        }
+        GoveeUndocumentedApi::invalidate_scenes_for_device(&device.sku)?;
+
+        if let Err(err) = state.device_list_scenes(device).await {
+            log::warn!("while refreshing scenes for {device}: {err:#}");
+        }
+        if device.lan_device.is_some() {
+            if let Err(err) = GoveeUndocumentedApi::get_scenes_for_device(&device.sku).await {
+                log::warn!("while refreshing LAN scenes for {device}: {err:#}");
+            }
+        }
+
+        if state.device_offered_scenes(device).await.ok() == before {
+            continue;
+        }
+        log::info!("The scenes of {device} have changed");
+        if let Some(hass) = state.get_hass_client().await {
+            if let Err(err) = hass.republish_device_entities(state, device).await {
+                log::error!("while republishing the entities of {device}: {err:#}");
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn periodic_metadata_refresh(state: StateHandle, interval: Duration) {
+    loop {
+        sleep(jittered(interval)).await;
+        log::debug!("Refreshing device metadata");
+        if let Err(err) = refresh_metadata(&state).await {
+            log::error!("periodic_metadata_refresh: {err:#}");
+        }
+    }
+}
+
+async fn periodic_scene_refresh(state: StateHandle, interval: Duration) {
+    loop {
+        sleep(jittered(interval)).await;
+        log::debug!("Refreshing scene catalogs");
+        if let Err(err) = refresh_scenes(&state).await {
+            log::error!("periodic_scene_refresh: {err:#}");
+        }
+    }
+}
+
+fn interval_arg(value: Option<u64>, env: &str, default: Duration) -> anyhow::Result<Duration> {
+    match value {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => Ok(opt_env_var(env)?
+            .map(Duration::from_secs)
+            .unwrap_or(default)),
     }
 }
 
 impl ServeCommand {
+    fn poll_intervals(&self) -> anyhow::Result<PollIntervals> {
+        Ok(PollIntervals {
+            state: interval_arg(
+                self.state_poll_interval,
+                "GOVEE_STATE_POLL_INTERVAL",
+                DEFAULT_STATE_POLL_INTERVAL,
+            )?,
+            scenes: interval_arg(
+                self.scene_refresh_interval,
+                "GOVEE_SCENE_REFRESH_INTERVAL",
+                DEFAULT_SCENE_REFRESH_INTERVAL,
+            )?,
+            metadata: interval_arg(
+                self.metadata_refresh_interval,
+                "GOVEE_METADATA_REFRESH_INTERVAL",
+                DEFAULT_METADATA_REFRESH_INTERVAL,
+            )?,
+        })
+    }
+
+    /// The settings resolved from the command line, the environment
+    /// and their defaults, with secrets masked
+    fn effective_settings(&self, args: &crate::Args) -> anyhow::Result<JsonValue> {
+        let disco = args.lan_disco_args.to_disco_options()?;
+        let intervals = self.poll_intervals()?;
+        Ok(json!({
+            "api_key": mask_secret(args.api_args.opt_api_key()?),
+            "api_key_usage": args.api_args.api_key_usage()?,
+            "govee_email": mask_secret(args.undoc_args.opt_email()?),
+            "govee_password": mask_secret(args.undoc_args.opt_password()?),
+            "config_file": args.config_args.config_file()?,
+            "log_api": args.log_api()?,
+            "strict_parsing": args.strict_parsing()?,
+            "lan": {
+                "enable_multicast": disco.enable_multicast,
+                "additional_addresses": disco.additional_addresses,
+                "broadcast_all_interfaces": disco.broadcast_all_interfaces,
+                "global_broadcast": disco.global_broadcast,
+                "disco_timeout": args.lan_disco_args.disco_timeout()?,
+            },
+            "hass": args.hass_args.effective_settings()?,
+            "http_port": self.http_port,
+            "state_poll_interval": intervals.state.as_secs(),
+            "scene_refresh_interval": intervals.scenes.as_secs(),
+            "metadata_refresh_interval": intervals.metadata.as_secs(),
+            "quiet_hours": self.quiet_hours()?,
+            "work_mode_overrides": self.work_mode_overrides()?,
+            "state_grace_period": self.state_grace_period()?.as_secs(),
+        }))
+    }
+
+    fn quiet_hours(&self) -> anyhow::Result<Option<String>> {
+        match self.quiet_hours.clone() {
+            Some(hours) => Ok(Some(hours)),
+            None => opt_env_var("GOVEE_QUIET_HOURS"),
+        }
+    }
+
+    fn work_mode_overrides(&self) -> anyhow::Result<Option<PathBuf>> {
+        match &self.work_mode_overrides {
+            Some(path) => Ok(Some(path.clone())),
+            None => opt_env_var("GOVEE_WORK_MODE_OVERRIDES"),
+        }
+    }
+
+    fn state_grace_period(&self) -> anyhow::Result<Duration> {
+        interval_arg(
+            self.state_grace_period,
+            "GOVEE_STATE_GRACE_PERIOD",
+            Duration::ZERO,
+        )
+    }
+
     pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
         log::info!("Starting service. version {}", govee_version());
+        if let Some(name) = args.hass_args.instance_name()? {
+            log::info!("Instance name is {name}");
+            set_instance_name(name)?;
+        }
+        let intervals = self.poll_intervals()?;
+        set_poll_intervals(intervals)?;
+        if let Some(hours) = self.quiet_hours()? {
+            set_quiet_hours(QuietHours::parse(&hours)?)?;
+        }
+        if let Some(path) = self.work_mode_overrides()? {
+            load_work_mode_overrides(&path)?;
+        }
+        let grace_period = self.state_grace_period()?;
+        if !grace_period.is_zero() {
+            set_state_grace_period(grace_period)?;
+        }
         let state = Arc::new(crate::service::state::State::new());
+        state.set_config(args.config_args.load()?).await;
+        state
+            .set_effective_settings(self.effective_settings(args)?)
+            .await;
+
+        let startup = Arc::new(StartupTracker::load(startup_file_name()));
+        state.set_startup_tracker(startup.clone()).await;
+        state
+            .set_removed_devices(Arc::new(RemovedDevices::load(removed_devices_file_name())))
+            .await;
+        if startup.is_safe_mode() {
+            log::error!(
+                "Starting in SAFE MODE after {}. Devices will not be available. \
+                 Details are available from /api/startup; \
+                 the next restart will try everything again.",
+                startup.status().reason()
+            );
+        }
 
         // First, use the HTTP APIs to determine the list of devices and
         // their names.
 
-        if let Ok(client) = args.api_args.api_client() {
+        let client = args
+            .api_args
+            .api_client()
+            .ok()
+            .filter(|_| startup.should_start(Subsystem::PlatformApi));
+        if let Some(client) = client {
+            startup.begin(Subsystem::PlatformApi);
             log::info!("Querying platform API for device list");
-            for info in client.get_devices().await? {
-                let mut device = state.device_mut(&info.sku, &info.device).await;
-                device.set_http_device_info(info);
-            }
+            load_platform_devices(&state, &client).await?;
 
+            let usage = args.api_args.api_key_usage()?;
+            if usage != PlatformApiUsage::All {
+                log::info!("The platform API will only be used for {usage:?}");
+            }
+            state.set_platform_api_usage(usage).await;
             state.set_platform_client(client).await;
         }
-        if let Ok(client) = args.undoc_args.api_client() {
+        let client = args
+            .undoc_args
+            .api_client()
+            .ok()
+            .filter(|_| startup.should_start(Subsystem::UndocApi));
+        if let Some(client) = client {
+            startup.begin(Subsystem::UndocApi);
             log::info!("Querying undocumented API for device + room list");
             let acct = client.login_account_cached().await?;
-            let info = client.get_device_list(&acct.token).await?;
-            let mut group_by_id = HashMap::new();
-            for group in info.groups {
-                group_by_id.insert(group.group_id, group.group_name);
-            }
-            for entry in info.devices {
-                let mut device = state.device_mut(&entry.sku, &entry.device).await;
-                let room_name = group_by_id.get(&entry.group_id).map(|name| name.as_str());
-                device.set_undoc_device_info(entry, room_name);
-            }
+            load_undoc_devices(&state, &client, &acct.token).await?;
 
             start_iot_client(args, state.clone(), Some(acct)).await?;
 
@@ -124,7 +538,8 @@ impl ServeCommand {
         // Now start discovery
 
         let options = args.lan_disco_args.to_disco_options()?;
-        if !options.is_empty() {
+        if !options.is_empty() && startup.should_start(Subsystem::LanDiscovery) {
+            startup.begin(Subsystem::LanDiscovery);
             log::info!("Starting LAN discovery");
             let state = state.clone();
             let (client, mut scan) = LanClient::new(options).await?;
@@ -142,11 +557,12 @@ impl ServeCommand {
                     let state = state.clone();
                     let client = client.clone();
                     tokio::spawn(async move {
+                        let freshness = Freshness::now();
                         if let Ok(status) = client.query_status(&lan_device).await {
                             state
                                 .device_mut(&lan_device.sku, &lan_device.device)
                                 .await
-                                .set_lan_device_status(status);
+                                .set_lan_device_status(status, freshness);
 
                             log::trace!("LAN disco: update and notify {}", lan_device.device);
                             state.notify_of_state_change(&lan_device.device).await.ok();
@@ -224,18 +640,26 @@ impl ServeCommand {
             log::info!("");
         }
 
-        // Start periodic status polling
-        {
-            let state = state.clone();
-            tokio::spawn(async move {
-                if let Err(err) = periodic_state_poll(state).await {
-                    log::error!("periodic_state_poll: {err:#}");
-                }
-            });
+        // Start periodic status polling, and the less frequent
+        // refreshes of the scenes and metadata
+        if !startup.is_safe_mode() {
+            tokio::spawn(periodic_state_poll(state.clone()));
+            tokio::spawn(periodic_scene_refresh(state.clone(), intervals.scenes));
+            tokio::spawn(periodic_metadata_refresh(state.clone(), intervals.metadata));
+            tokio::spawn(release_held_state(state.clone()));
         }
 
         // start advertising on local mqtt
-        spawn_hass_integration(state.clone(), &args.hass_args).await?;
+        if startup.should_start(Subsystem::Hass) {
+            startup.begin(Subsystem::Hass);
+            spawn_hass_integration(state.clone(), &args.hass_args).await?;
+        }
+
+        if let Some(path) = args.config_args.config_file()? {
+            tokio::spawn(watch_config_file(state.clone(), path));
+        }
+
+        startup.finished();
 
         run_http_server(state.clone(), self.http_port)
             .await
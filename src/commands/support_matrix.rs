@@ -0,0 +1,25 @@
+use crate::service::support_matrix::{format_support_matrix, support_matrix};
+
+/// Print the transports and entities that are provided for each
+/// of the SKUs that have specific handling.
+/// Devices that aren't listed may still work via the Platform API,
+/// and the capabilities that it reports for a device will add
+/// further entities to those shown here.
+#[derive(clap::Parser, Debug)]
+pub struct SupportMatrixCommand {
+    /// Print the matrix as JSON rather than as a markdown table
+    #[arg(long)]
+    json: bool,
+}
+
+impl SupportMatrixCommand {
+    pub async fn run(&self, _args: &crate::Args) -> anyhow::Result<()> {
+        let matrix = support_matrix().await?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&matrix)?);
+        } else {
+            print!("{}", format_support_matrix(&matrix));
+        }
+        Ok(())
+    }
+}
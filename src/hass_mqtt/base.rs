@@ -1,5 +1,7 @@
+use crate::service::composition::VirtualDevice;
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::topic_safe_id;
+use crate::service::hass::{device_availability_topic, topic_safe_id, topic_safe_id_str};
+use crate::service::identity::{bridge_id, bridge_name};
 use crate::version_info::govee_version;
 use serde::Serialize;
 
@@ -53,6 +55,10 @@ pub struct Device {
     pub identifiers: Vec<String>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub connections: Vec<(String, String)>,
+    /// Where we report whether the device is reachable; the
+    /// entities of the device reference it in their availability
+    #[serde(skip)]
+    pub availability_topic: Option<String>,
 }
 
 impl Device {
@@ -63,7 +69,10 @@ impl Device {
             model: device.sku.to_string(),
             sw_version: None,
             suggested_area: device.room_name().map(|s| s.to_string()),
-            via_device: Some("gv2mqtt".to_string()),
+            via_device: Some(match &device.gateway_id {
+                Some(gateway_id) => format!("gv2mqtt-{}", topic_safe_id_str(gateway_id)),
+                None => bridge_id(),
+            }),
             identifiers: vec![
                 format!("gv2mqtt-{}", topic_safe_id(device)),
                 /*
@@ -72,19 +81,49 @@ impl Device {
                 */
             ],
             connections: vec![],
+            availability_topic: Some(device_availability_topic(device)),
+        }
+    }
+
+    pub fn for_virtual_device(device: &VirtualDevice) -> Self {
+        Self {
+            name: device.name().to_string(),
+            manufacturer: "govee2mqtt".to_string(),
+            model: "Virtual Device".to_string(),
+            sw_version: None,
+            suggested_area: device.config.room.clone(),
+            via_device: Some(bridge_id()),
+            identifiers: vec![format!("gv2mqtt-virtual-{}", device.id())],
+            connections: vec![],
+            availability_topic: None,
+        }
+    }
+
+    pub fn for_room(room: &str, slug: &str) -> Self {
+        Self {
+            name: room.to_string(),
+            manufacturer: "govee2mqtt".to_string(),
+            model: "Room".to_string(),
+            sw_version: None,
+            suggested_area: Some(room.to_string()),
+            via_device: Some(bridge_id()),
+            identifiers: vec![format!("gv2mqtt-room-{slug}")],
+            connections: vec![],
+            availability_topic: None,
         }
     }
 
     pub fn this_service() -> Self {
         Self {
-            name: "Govee to MQTT".to_string(),
+            name: bridge_name(),
             manufacturer: "Wez Furlong".to_string(),
             model: "govee2mqtt".to_string(),
             sw_version: Some(govee_version().to_string()),
             suggested_area: None,
             via_device: None,
-            identifiers: vec!["gv2mqtt".to_string()],
+            identifiers: vec![bridge_id()],
             connections: vec![],
+            availability_topic: None,
         }
     }
 }
@@ -0,0 +1,557 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
+use crate::hass_mqtt::sensor::numeric_value;
+use crate::platform_api::DeviceCapability;
+use crate::service::alarm::AlarmKind;
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{availability_topic, topic_safe_id, HassClient};
+use crate::service::schedule::DeviceSchedule;
+use crate::service::state::StateHandle;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BinarySensorConfig {
+    #[serde(flatten)]
+    pub base: EntityConfig,
+
+    pub state_topic: String,
+    pub payload_on: &'static str,
+    pub payload_off: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_attributes_topic: Option<String>,
+}
+
+impl BinarySensorConfig {
+    pub async fn publish(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        publish_entity_config("binary_sensor", state, client, &self.base, self).await
+    }
+
+    pub async fn notify_state(&self, client: &HassClient, on: bool) -> anyhow::Result<()> {
+        client
+            .publish(
+                &self.state_topic,
+                if on {
+                    self.payload_on
+                } else {
+                    self.payload_off
+                },
+            )
+            .await
+    }
+}
+
+/// Reports whether a bluetooth gateway is connected to the Govee cloud
+pub struct GatewayConnectivity {
+    sensor: BinarySensorConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl GatewayConnectivity {
+    pub fn new(device: &ServiceDevice, state: &StateHandle) -> Self {
+        let unique_id = format!(
+            "binary-sensor-{id}-connectivity",
+            id = topic_safe_id(device)
+        );
+
+        Self {
+            sensor: BinarySensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Connectivity".to_string()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some("connectivity"),
+                    icon: None,
+                },
+                state_topic: format!("gv2mqtt/binary_sensor/{unique_id}/state"),
+                payload_on: "ON",
+                payload_off: "OFF",
+                json_attributes_topic: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for GatewayConnectivity {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        if let Some(online) = device.gateway_online() {
+            self.sensor.notify_state(client, online).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports the alarm of a leak or contact sensor, as pushed via IoT
+pub struct AlarmSensor {
+    sensor: BinarySensorConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl AlarmSensor {
+    pub fn new(device: &ServiceDevice, state: &StateHandle, kind: AlarmKind) -> Self {
+        let unique_id = format!(
+            "binary-sensor-{id}-{slug}",
+            id = topic_safe_id(device),
+            slug = kind.slug()
+        );
+
+        Self {
+            sensor: BinarySensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some(kind.name().to_string()),
+                    entity_category: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some(kind.device_class()),
+                    icon: None,
+                },
+                state_topic: format!("gv2mqtt/binary_sensor/{unique_id}/state"),
+                payload_on: "ON",
+                payload_off: "OFF",
+                json_attributes_topic: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for AlarmSensor {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        if let Some(active) = device.iot_alarm {
+            self.sensor.notify_state(client, active).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The event with which the platform API reports presence
+const BODY_APPEARED_INSTANCE: &str = "bodyAppearedEvent";
+
+/// Reports whether an mmWave presence sensor detects someone.
+/// Changes arrive via IoT as they happen; the platform API event
+/// is used until the first of those is received.
+pub struct OccupancySensor {
+    sensor: BinarySensorConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl OccupancySensor {
+    pub fn new(device: &ServiceDevice, state: &StateHandle) -> Self {
+        let unique_id = format!("binary-sensor-{id}-occupancy", id = topic_safe_id(device));
+
+        Self {
+            sensor: BinarySensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Occupancy".to_string()),
+                    entity_category: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some("occupancy"),
+                    icon: None,
+                },
+                state_topic: format!("gv2mqtt/binary_sensor/{unique_id}/state"),
+                payload_on: "ON",
+                payload_off: "OFF",
+                json_attributes_topic: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+/// Interprets the state of the platform API presence event, whose
+/// options distinguish "Presence" from "Absence"
+fn platform_presence(device: &ServiceDevice) -> Option<bool> {
+    let options: Vec<JsonValue> = device
+        .http_device_info
+        .as_ref()?
+        .capability_by_instance(BODY_APPEARED_INSTANCE)?
+        .event_state
+        .as_ref()?
+        .get("options")?
+        .as_array()?
+        .iter()
+        .filter(|option| option.get("name").and_then(|n| n.as_str()) == Some("Presence"))
+        .cloned()
+        .collect();
+    let cap = device.get_state_capability_by_instance(BODY_APPEARED_INSTANCE)?;
+    Some(event_is_active(&options, &cap.state))
+}
+
+#[async_trait]
+impl EntityInstance for OccupancySensor {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        if let Some(present) = device.iot_presence.or_else(|| platform_presence(&device)) {
+            self.sensor.notify_state(client, present).await?;
+        }
+        Ok(())
+    }
+}
+
+const LACK_WATER_INSTANCE: &str = "lackWaterEvent";
+const ICE_FULL_INSTANCE: &str = "iceFull";
+
+/// The name and unique id suffix of the problem reported by
+/// an event capability, if it is one that we recognize
+pub fn problem_event(instance: &str) -> Option<(&'static str, &'static str)> {
+    match instance {
+        LACK_WATER_INSTANCE => Some(("Water Tank Empty", "water-tank-empty")),
+        ICE_FULL_INSTANCE => Some(("Ice Basket Full", "ice-basket-full")),
+        _ => None,
+    }
+}
+
+/// Returns true if the reported state of an event capability
+/// matches one of the options that the event advertises.
+/// The event state may be reported as a bare value, as an
+/// `{"name", "value"}` object, or as a list of those.
+fn event_is_active(options: &[JsonValue], state: &JsonValue) -> bool {
+    let state = state.get("value").unwrap_or(state);
+    let matches = |item: &JsonValue| {
+        options.iter().any(|option| match item {
+            JsonValue::Object(_) => {
+                (item.get("name").is_some() && item.get("name") == option.get("name"))
+                    || (item.get("value").is_some() && item.get("value") == option.get("value"))
+            }
+            JsonValue::Null => false,
+            _ => Some(item) == option.get("value"),
+        })
+    };
+    match state {
+        JsonValue::Array(items) => items.iter().any(matches),
+        item => matches(item),
+    }
+}
+
+/// Reports a problem that the device signals with an event, such as
+/// the water tank of a humidifier running dry or the basket of an
+/// ice maker being full
+pub struct ProblemEvent {
+    sensor: BinarySensorConfig,
+    instance: String,
+    options: Vec<JsonValue>,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl ProblemEvent {
+    pub fn new(
+        device: &ServiceDevice,
+        state: &StateHandle,
+        event: &DeviceCapability,
+        name: &str,
+        slug: &str,
+    ) -> Self {
+        let unique_id = format!("binary-sensor-{id}-{slug}", id = topic_safe_id(device));
+
+        let options = event
+            .event_state
+            .as_ref()
+            .and_then(|s| s.get("options"))
+            .and_then(|o| o.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        Self {
+            sensor: BinarySensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some(name.to_string()),
+                    entity_category: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some("problem"),
+                    icon: None,
+                },
+                state_topic: format!("gv2mqtt/binary_sensor/{unique_id}/state"),
+                payload_on: "ON",
+                payload_off: "OFF",
+                json_attributes_topic: None,
+            },
+            instance: event.instance.to_string(),
+            options,
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for ProblemEvent {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance)
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        // The event is only reported while the problem persists
+        let active = device
+            .get_state_capability_by_instance(&self.instance)
+            .map(|cap| event_is_active(&self.options, &cap.state))
+            .unwrap_or(false);
+        self.sensor.notify_state(client, active).await
+    }
+}
+
+/// At or below this percentage of remaining filter life,
+/// the filter is reported as needing replacement
+const FILTER_REPLACEMENT_PERCENT: f64 = 5.0;
+
+/// Reports when the filter of a purifier or humidifier is due to
+/// be replaced, so that reminders can be automated
+pub struct FilterReplacement {
+    sensor: BinarySensorConfig,
+    instance: String,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl FilterReplacement {
+    pub fn new(device: &ServiceDevice, state: &StateHandle, instance: &str) -> Self {
+        let unique_id = format!(
+            "binary-sensor-{id}-filter-replacement",
+            id = topic_safe_id(device)
+        );
+
+        Self {
+            sensor: BinarySensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Filter Needs Replacement".to_string()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some("problem"),
+                    icon: None,
+                },
+                state_topic: format!("gv2mqtt/binary_sensor/{unique_id}/state"),
+                payload_on: "ON",
+                payload_off: "OFF",
+                json_attributes_topic: None,
+            },
+            instance: instance.to_string(),
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for FilterReplacement {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance)
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        let Some(life) = device
+            .get_state_capability_by_instance(&self.instance)
+            .and_then(|cap| numeric_value(&cap.state))
+        else {
+            return Ok(());
+        };
+        self.sensor
+            .notify_state(client, life <= FILTER_REPLACEMENT_PERCENT)
+            .await
+    }
+}
+
+/// Reports whether a timer or alarm that is stored on the device
+/// is enabled, with its details as attributes.
+/// When the app settings are writable, a ScheduleSwitch can turn
+/// it on and off.
+pub struct ScheduleSensor {
+    sensor: BinarySensorConfig,
+    slug: String,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl ScheduleSensor {
+    pub fn new(device: &ServiceDevice, state: &StateHandle, schedule: &DeviceSchedule) -> Self {
+        let unique_id = format!(
+            "binary-sensor-{id}-schedule-{slug}",
+            id = topic_safe_id(device),
+            slug = schedule.slug()
+        );
+
+        Self {
+            sensor: BinarySensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some(schedule.name()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: None,
+                    icon: Some("mdi:alarm".to_string()),
+                },
+                state_topic: format!("gv2mqtt/binary_sensor/{unique_id}/state"),
+                payload_on: "ON",
+                payload_off: "OFF",
+                json_attributes_topic: Some(format!(
+                    "gv2mqtt/binary_sensor/{unique_id}/attributes"
+                )),
+            },
+            slug: schedule.slug(),
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for ScheduleSensor {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        let Some(schedule) = device
+            .schedules()
+            .into_iter()
+            .find(|s| s.slug() == self.slug)
+        else {
+            return Ok(());
+        };
+
+        self.sensor.notify_state(client, schedule.enabled).await?;
+        if let Some(topic) = &self.sensor.json_attributes_topic {
+            client
+                .publish_obj(
+                    topic,
+                    &json!({
+                        "time": schedule.time(),
+                        "repeat": schedule.repeat,
+                        "raw": schedule.raw,
+                    }),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::service::device::Freshness;
+
+    #[test]
+    fn lack_of_water_event() {
+        let options = vec![json!({"name": "lack", "value": 1, "message": "Lack of Water"})];
+
+        assert!(event_is_active(
+            &options,
+            &json!({"value": [{"name": "lack", "value": 1, "message": "Lack of Water"}]})
+        ));
+        assert!(event_is_active(&options, &json!({"value": 1})));
+        assert!(event_is_active(&options, &json!({"name": "lack"})));
+
+        assert!(!event_is_active(&options, &json!({"value": ""})));
+        assert!(!event_is_active(&options, &json!({"value": 0})));
+        assert!(!event_is_active(&options, &json!({"value": []})));
+        assert!(!event_is_active(&options, &JsonValue::Null));
+    }
+
+    #[test]
+    fn presence_event() {
+        let mut device = ServiceDevice::new("H5127", "12:34:56:78:9A:BC:DE:F0");
+        device.set_http_device_info(
+            serde_json::from_value(json!({
+                "sku": "H5127",
+                "device": "12:34:56:78:9A:BC:DE:F0",
+                "capabilities": [{
+                    "type": "devices.capabilities.event",
+                    "instance": "bodyAppearedEvent",
+                    "eventState": {"options": [
+                        {"name": "Presence", "value": 1},
+                        {"name": "Absence", "value": 2}
+                    ]}
+                }]
+            }))
+            .unwrap(),
+        );
+        assert_eq!(platform_presence(&device), None);
+
+        let set_state = |device: &mut ServiceDevice, value: JsonValue| {
+            device.set_http_device_state(
+                serde_json::from_value(json!({
+                    "sku": "H5127",
+                    "device": "12:34:56:78:9A:BC:DE:F0",
+                    "capabilities": [{
+                        "type": "devices.capabilities.event",
+                        "instance": "bodyAppearedEvent",
+                        "state": value
+                    }]
+                }))
+                .unwrap(),
+                Freshness::now(),
+            )
+        };
+        set_state(&mut device, json!({"value": 1}));
+        assert_eq!(platform_presence(&device), Some(true));
+        set_state(&mut device, json!({"value": 2}));
+        assert_eq!(platform_presence(&device), Some(false));
+    }
+}
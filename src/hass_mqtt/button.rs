@@ -3,11 +3,14 @@ use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::platform_api::DeviceCapability;
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{
-    availability_topic, camel_case_to_space_separated, topic_safe_id, topic_safe_string, HassClient,
+    availability_topic, camel_case_to_space_separated, oneclick_topic, topic_safe_id,
+    topic_safe_string, HassClient,
 };
+use crate::service::identity::{bridge_id, global_unique_id};
 use crate::service::state::StateHandle;
 use async_trait::async_trait;
 use serde::Serialize;
+use uuid::Uuid;
 
 #[derive(Serialize, Clone, Debug)]
 pub struct ButtonConfig {
@@ -55,7 +58,7 @@ impl ButtonConfig {
 
     pub fn new<NAME: Into<String>, TOPIC: Into<String>>(name: NAME, topic: TOPIC) -> Self {
         let name = name.into();
-        let unique_id = format!("global-{}", topic_safe_string(&name));
+        let unique_id = global_unique_id(&topic_safe_string(&name));
         Self {
             base: EntityConfig {
                 availability_topic: availability_topic(),
@@ -72,6 +75,30 @@ impl ButtonConfig {
         }
     }
 
+    /// A button to run one of the one-click (tap-to-run) rules
+    /// from the Govee app
+    pub fn activate_one_click(name: &str) -> Self {
+        let unique_id = format!(
+            "{}-one-click-button-{}",
+            bridge_id(),
+            Uuid::new_v5(&Uuid::NAMESPACE_DNS, name.as_bytes()).simple()
+        );
+        Self {
+            base: EntityConfig {
+                availability_topic: availability_topic(),
+                name: Some(name.to_string()),
+                entity_category: None,
+                origin: Origin::default(),
+                device: Device::this_service(),
+                unique_id,
+                device_class: None,
+                icon: Some("mdi:gesture-tap-button".to_string()),
+            },
+            command_topic: oneclick_topic(),
+            payload_press: Some(name.to_string()),
+        }
+    }
+
     pub fn activate_work_mode_preset(
         device: &ServiceDevice,
         name: &str,
@@ -105,6 +132,73 @@ impl ButtonConfig {
         }
     }
 
+    /// A button that selects a preset via the work mode select's
+    /// command topic. This is used for modes whose values are
+    /// strings, which can't be sent via the number command topic.
+    pub fn activate_work_mode_by_name(device: &ServiceDevice, name: &str, preset: &str) -> Self {
+        let unique_id = format!(
+            "gv2mqtt-{id}-preset-{preset}",
+            id = topic_safe_id(device),
+            preset = topic_safe_string(preset),
+        );
+        let command_topic = format!("gv2mqtt/{id}/set-work-mode", id = topic_safe_id(device));
+        Self {
+            base: EntityConfig {
+                availability_topic: availability_topic(),
+                name: Some(name.to_string()),
+                entity_category: None,
+                origin: Origin::default(),
+                device: Device::for_device(device),
+                unique_id,
+                device_class: None,
+                icon: None,
+            },
+            command_topic,
+            payload_press: Some(preset.to_string()),
+        }
+    }
+
+    pub fn activate_favorite_scene(device: &ServiceDevice, scene: &str) -> Self {
+        let unique_id = format!(
+            "gv2mqtt-{id}-scene-{scene}",
+            id = topic_safe_id(device),
+            scene = topic_safe_string(scene),
+        );
+        let command_topic = format!("gv2mqtt/{id}/set-mode-scene", id = topic_safe_id(device));
+        Self {
+            base: EntityConfig {
+                availability_topic: availability_topic(),
+                name: Some(scene.to_string()),
+                entity_category: None,
+                origin: Origin::default(),
+                device: Device::for_device(device),
+                unique_id: unique_id.clone(),
+                device_class: None,
+                icon: Some("mdi:palette".to_string()),
+            },
+            command_topic,
+            payload_press: Some(scene.to_string()),
+        }
+    }
+
+    pub fn poll_now_for_device(device: &ServiceDevice) -> Self {
+        let id = topic_safe_id(device);
+        Self {
+            base: EntityConfig {
+                availability_topic: availability_topic(),
+                name: Some("Poll Now".to_string()),
+                entity_category: Some("diagnostic".to_string()),
+                origin: Origin::default(),
+                device: Device::for_device(device),
+                unique_id: format!("gv2mqtt-{id}-poll-now"),
+                device_class: None,
+                icon: Some("mdi:refresh".to_string()),
+            },
+            command_topic: format!("gv2mqtt/{id}/poll-now"),
+            payload_press: None,
+        }
+    }
+
     pub fn request_platform_data_for_device(device: &ServiceDevice) -> Self {
         let unique_id = format!(
             "gv2mqtt-{id}-request-platform-data",
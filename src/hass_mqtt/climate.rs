@@ -2,6 +2,7 @@ use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::EntityInstance;
 use crate::hass_mqtt::number::NumberConfig;
 use crate::platform_api::{DeviceCapability, DeviceParameters};
+use crate::service::config::SetpointLimitConfig;
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{availability_topic, topic_safe_id, topic_safe_string, HassClient};
 use crate::service::state::StateHandle;
@@ -27,15 +28,63 @@ pub struct TargetTemperatureEntity {
 pub struct TemperatureConstraints {
     pub min: TemperatureValue,
     pub max: TemperatureValue,
+    /// The increment between valid values, in the units of `min`
+    pub step: f32,
 }
 
 impl TemperatureConstraints {
     pub fn as_unit(&self, unit: TemperatureUnits) -> Self {
+        let min = self.min.as_unit(unit);
         Self {
-            min: self.min.as_unit(unit),
+            // A step in one scale doesn't translate into a sensible
+            // step in another, so fall back to whole degrees
+            step: if min.unit() == self.min.unit() {
+                self.step
+            } else {
+                1.0
+            },
+            min,
             max: self.max.as_unit(unit),
         }
     }
+
+    /// Narrow the range to the configured limits. Limits can only
+    /// make the range narrower than what the device accepts.
+    pub fn restrict(&self, limits: &SetpointLimitConfig) -> Self {
+        let unit = self.min.unit();
+        let lower = self.min.value();
+        let upper = self.max.value();
+        let min = limits
+            .min_value()
+            .ok()
+            .flatten()
+            .map(|v| v.as_unit(unit).value().clamp(lower, upper))
+            .unwrap_or(lower);
+        let max = limits
+            .max_value()
+            .ok()
+            .flatten()
+            .map(|v| v.as_unit(unit).value().clamp(min, upper))
+            .unwrap_or(upper);
+        Self {
+            min: TemperatureValue::new(min, unit),
+            max: TemperatureValue::new(max, unit),
+            step: self.step,
+        }
+    }
+
+    /// Bring a target temperature within the range, returning
+    /// it in the units of the range
+    pub fn clamp(&self, target: TemperatureValue) -> TemperatureValue {
+        let unit = self.min.unit();
+        TemperatureValue::new(
+            target
+                .as_unit(unit)
+                .value()
+                .clamp(self.min.value(), self.max.value()),
+            unit,
+        )
+    }
 }
 
 pub fn parse_temperature_constraints(
@@ -65,9 +114,11 @@ pub fn parse_temperature_constraints(
             let max = TemperatureValue::new(range.max.into(), range_units);
 
             Ok(TemperatureConstraints {
-                min: min.as_unit(units),
-                max: max.as_unit(units),
-            })
+                min,
+                max,
+                step: range.step(),
+            }
+            .as_unit(units))
         }
         _ => {
             anyhow::bail!("Unexpected temperature value in {instance:?}");
@@ -83,7 +134,10 @@ impl TargetTemperatureEntity {
     ) -> anyhow::Result<Self> {
         let units = state.get_temperature_scale().await;
 
-        let constraints = parse_temperature_constraints(instance)?.as_unit(units.into());
+        let constraints = state
+            .device_temperature_constraints(device, instance)
+            .await?
+            .as_unit(units.into());
         let unique_id = format!(
             "{id}-{inst}",
             id = topic_safe_id(device),
@@ -117,7 +171,7 @@ impl TargetTemperatureEntity {
                 command_topic,
                 min: Some(constraints.min.value().floor() as f32),
                 max: Some(constraints.max.value().ceil() as f32),
-                step: 1.0,
+                step: constraints.step,
                 unit_of_measurement: Some(units.unit_of_measurement()),
             },
             device_id: device.id.to_string(),
@@ -133,6 +187,10 @@ impl EntityInstance for TargetTemperatureEntity {
         self.number.publish(&state, &client).await
     }
 
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance_name)
+    }
+
     async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
         let device = self
             .state
@@ -140,42 +198,11 @@ impl EntityInstance for TargetTemperatureEntity {
             .await
             .expect("device to exist");
 
-        let quirk = device.resolve_quirk();
-
         log::debug!("notify_state for {device} {}", self.instance_name);
 
-        if let Some(cap) = device.get_state_capability_by_instance(&self.instance_name) {
-            log::debug!("have: {cap:?}");
-
-            let units = cap
-                .state
-                .pointer("/value/unit")
-                .and_then(|unit| {
-                    unit.as_str()
-                        .and_then(|s| TemperatureScale::from_str(s).map(Into::into).ok())
-                })
-                .or_else(|| quirk.and_then(|q| q.platform_temperature_sensor_units))
-                .unwrap_or(TemperatureUnits::Celsius);
-
-            log::debug!("units are reported as {units:?}");
-
-            let value = match cap
-                .state
-                .pointer("/value/targetTemperature")
-                .and_then(|v| v.as_f64())
-                .map(|v| TemperatureValue::new(v, units))
-            {
-                Some(v) => {
-                    let pref_units = self.state.get_temperature_scale().await;
-                    log::debug!("reported temp is {v}, pref_units: {pref_units}");
-                    let value = v.as_unit(pref_units.into()).value();
-                    format!("{value:.2}")
-                }
-                None => "".to_string(),
-            };
-
+        let scale = self.state.get_temperature_scale().await;
+        if let Some(value) = reported_target_temperature(&device, &self.instance_name, scale) {
             log::debug!("setting value to {value}");
-
             return self.number.notify_state(&client, &value).await;
         }
 
@@ -183,6 +210,47 @@ impl EntityInstance for TargetTemperatureEntity {
     }
 }
 
+/// Returns the target temperature reported by the device for the
+/// `instance` temperature setting, formatted in `scale`, or an empty
+/// string if it can't be interpreted.
+/// Returns None if the device hasn't reported that capability.
+pub fn reported_target_temperature(
+    device: &ServiceDevice,
+    instance: &str,
+    scale: TemperatureScale,
+) -> Option<String> {
+    let cap = device.get_state_capability_by_instance(instance)?;
+    log::debug!("have: {cap:?}");
+
+    let quirk = device.resolve_quirk();
+    let units = cap
+        .state
+        .pointer("/value/unit")
+        .and_then(|unit| {
+            unit.as_str()
+                .and_then(|s| TemperatureScale::from_str(s).map(Into::into).ok())
+        })
+        .or_else(|| quirk.and_then(|q| q.platform_temperature_sensor_units))
+        .unwrap_or(TemperatureUnits::Celsius);
+
+    log::debug!("units are reported as {units:?}");
+
+    let value = match cap
+        .state
+        .pointer("/value/targetTemperature")
+        .and_then(|v| v.as_f64())
+        .map(|v| TemperatureValue::new(v, units))
+    {
+        Some(v) => {
+            log::debug!("reported temp is {v}, pref_units: {scale}");
+            let value = v.as_unit(scale.into()).value();
+            format!("{value:.2}")
+        }
+        None => "".to_string(),
+    };
+    Some(value)
+}
+
 #[derive(Deserialize)]
 pub struct IdInstAndUnits {
     id: String,
@@ -211,3 +279,96 @@ pub async fn mqtt_set_temperature(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use TemperatureUnits::{Celsius, Farenheit};
+
+    fn kettle() -> TemperatureConstraints {
+        TemperatureConstraints {
+            min: TemperatureValue::new(40.0, Celsius),
+            max: TemperatureValue::new(100.0, Celsius),
+            step: 1.0,
+        }
+    }
+
+    fn limits(min: Option<&str>, max: Option<&str>) -> SetpointLimitConfig {
+        SetpointLimitConfig {
+            device: "Kettle".to_string(),
+            min: min.map(String::from),
+            max: max.map(String::from),
+        }
+    }
+
+    fn range(c: &TemperatureConstraints) -> (f64, f64) {
+        let round = |v: &TemperatureValue| (v.value() * 100.0).round() / 100.0;
+        (round(&c.min), round(&c.max))
+    }
+
+    #[test]
+    fn restrict() {
+        assert_eq!(
+            range(&kettle().restrict(&limits(None, None))),
+            (40.0, 100.0)
+        );
+        assert_eq!(
+            range(&kettle().restrict(&limits(Some("50"), Some("90C")))),
+            (50.0, 90.0)
+        );
+        // Converted into the units of the device
+        assert_eq!(
+            range(&kettle().restrict(&limits(None, Some("176F")))),
+            (40.0, 80.0)
+        );
+        // Limits can't widen the range
+        assert_eq!(
+            range(&kettle().restrict(&limits(Some("0C"), Some("250F")))),
+            (40.0, 100.0)
+        );
+        // Inverted limits, or ones outside of the range, collapse it
+        // to a single value rather than inverting it
+        assert_eq!(
+            range(&kettle().restrict(&limits(Some("80"), Some("60")))),
+            (80.0, 80.0)
+        );
+        assert_eq!(
+            range(&kettle().restrict(&limits(None, Some("20C")))),
+            (40.0, 40.0)
+        );
+        // Unparseable limits are ignored here; they are reported when
+        // the configuration is loaded
+        assert_eq!(
+            range(&kettle().restrict(&limits(Some("warm"), None))),
+            (40.0, 100.0)
+        );
+        assert_eq!(kettle().restrict(&limits(Some("50"), None)).step, 1.0);
+    }
+
+    #[test]
+    fn clamp() {
+        let c = kettle().restrict(&limits(Some("50"), Some("90")));
+        assert_eq!(
+            c.clamp(TemperatureValue::new(70.0, Celsius)),
+            TemperatureValue::new(70.0, Celsius)
+        );
+        assert_eq!(
+            c.clamp(TemperatureValue::new(120.0, Celsius)),
+            TemperatureValue::new(90.0, Celsius)
+        );
+        assert_eq!(
+            c.clamp(TemperatureValue::new(32.0, Farenheit)),
+            TemperatureValue::new(50.0, Celsius)
+        );
+        let target = c.clamp(TemperatureValue::new(158.0, Farenheit));
+        assert_eq!(target.unit(), Celsius);
+        assert!((target.value() - 70.0).abs() < 0.01);
+
+        let f = c.as_unit(Farenheit);
+        assert_eq!(range(&f), (122.0, 194.0));
+        assert_eq!(f.step, 1.0);
+        let target = f.clamp(TemperatureValue::new(95.0, Celsius));
+        assert_eq!(target.unit(), Farenheit);
+        assert!((target.value() - 194.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,916 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::EntityInstance;
+use crate::hass_mqtt::number::NumberConfig;
+use crate::hass_mqtt::select::SelectConfig;
+use crate::hass_mqtt::sensor::SensorConfig;
+use crate::hass_mqtt::switch::SwitchConfig;
+use crate::lan_api::truthy;
+use crate::service::config::AppSettings;
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{availability_topic, topic_safe_id, HassClient};
+use crate::service::schedule::set_schedule_enabled;
+use crate::service::state::StateHandle;
+use crate::temperature::TemperatureScale;
+use crate::undoc_api::DeviceSettings;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use mosquitto_rs::router::{Params, Payload, State};
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+
+/// A per-device setting that is made in the Govee app, and which
+/// we learn about via the undocumented device list API.
+/// These are reported as diagnostic sensors, or, when the app settings
+/// are writable, offered as config entities that can change them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceSetting {
+    /// Whether the device displays Celsius or Fahrenheit
+    TemperatureUnit,
+    /// The offset applied to the temperature reading
+    TemperatureCalibration,
+    /// The offset applied to the humidity reading
+    HumidityCalibration,
+}
+
+impl DeviceSetting {
+    const ALL: &'static [Self] = &[
+        Self::TemperatureUnit,
+        Self::TemperatureCalibration,
+        Self::HumidityCalibration,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::TemperatureUnit => "Temperature Unit",
+            Self::TemperatureCalibration => "Temperature Calibration",
+            Self::HumidityCalibration => "Humidity Calibration",
+        }
+    }
+
+    fn slug(&self) -> &'static str {
+        match self {
+            Self::TemperatureUnit => "temperature-unit",
+            Self::TemperatureCalibration => "temperature-calibration",
+            Self::HumidityCalibration => "humidity-calibration",
+        }
+    }
+
+    fn icon(&self) -> &'static str {
+        match self {
+            Self::TemperatureUnit => "mdi:temperature-celsius",
+            Self::TemperatureCalibration => "mdi:thermometer-plus",
+            Self::HumidityCalibration => "mdi:water-plus",
+        }
+    }
+
+    fn from_slug(slug: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|setting| setting.slug() == slug)
+    }
+
+    /// The name of the setting in the undocumented API
+    fn key(&self) -> &'static str {
+        match self {
+            Self::TemperatureUnit => "fahOpen",
+            Self::TemperatureCalibration => "temCali",
+            Self::HumidityCalibration => "humCali",
+        }
+    }
+
+    /// The range of the calibration offsets that the Govee app
+    /// offers, in degrees Celsius, or percent, respectively
+    fn range(&self) -> Option<(f64, f64)> {
+        match self {
+            Self::TemperatureUnit => None,
+            Self::TemperatureCalibration => Some((-3.0, 3.0)),
+            Self::HumidityCalibration => Some((-20.0, 20.0)),
+        }
+    }
+
+    fn is_present(&self, settings: &DeviceSettings) -> bool {
+        match self {
+            Self::TemperatureUnit => settings.fah_open.is_some(),
+            Self::TemperatureCalibration => settings.tem_cali.is_some(),
+            Self::HumidityCalibration => settings.hum_cali.is_some(),
+        }
+    }
+
+    fn unit_of_measurement(&self, scale: TemperatureScale) -> Option<&'static str> {
+        match self {
+            Self::TemperatureUnit => None,
+            Self::TemperatureCalibration => Some(scale.unit_of_measurement()),
+            Self::HumidityCalibration => Some("%"),
+        }
+    }
+
+    /// Format the value of this setting for display.
+    /// Calibration offsets are reported by the API in hundredths of
+    /// a degree Celsius, or of a percent, respectively.
+    fn value(&self, settings: &DeviceSettings, scale: TemperatureScale) -> Option<String> {
+        match self {
+            Self::TemperatureUnit => settings
+                .fah_open
+                .map(|fah| if fah { "°F" } else { "°C" }.to_string()),
+            Self::TemperatureCalibration => settings.tem_cali.map(|cali| {
+                let celsius = cali as f64 / 100.0;
+                // This is a difference between two temperatures,
+                // so there is no offset to apply when converting
+                let value = match scale {
+                    TemperatureScale::Celsius => celsius,
+                    TemperatureScale::Farenheit => celsius * 1.8,
+                };
+                format!("{value:.1}")
+            }),
+            Self::HumidityCalibration => settings
+                .hum_cali
+                .map(|cali| format!("{:.1}", cali as f64 / 100.0)),
+        }
+    }
+
+    /// Parse a value in the form produced by `value`, returning it
+    /// as it is represented in the undocumented API
+    fn encode(&self, value: &str, scale: TemperatureScale) -> anyhow::Result<JsonValue> {
+        if *self == Self::TemperatureUnit {
+            return match value.trim().trim_start_matches('°') {
+                "C" | "c" => Ok(json!(false)),
+                "F" | "f" => Ok(json!(true)),
+                _ => anyhow::bail!("invalid temperature unit {value}"),
+            };
+        }
+
+        let value: f64 = value
+            .trim()
+            .parse()
+            .map_err(|err| anyhow!("invalid {} {value}: {err:#}", self.name()))?;
+        let value = match (self, scale) {
+            (Self::TemperatureCalibration, TemperatureScale::Farenheit) => value / 1.8,
+            _ => value,
+        };
+        let (min, max) = self.range().unwrap_or((f64::MIN, f64::MAX));
+        // Allow for the rounding in converting between scales
+        anyhow::ensure!(
+            value >= min - 0.05 && value <= max + 0.05,
+            "{} must be between {min} and {max}",
+            self.name()
+        );
+        Ok(json!((value.clamp(min, max) * 100.0).round() as i64))
+    }
+
+    /// Apply a value produced by `encode` to our copy of the settings
+    fn apply(&self, settings: &mut DeviceSettings, value: &JsonValue) {
+        match self {
+            Self::TemperatureUnit => settings.fah_open = value.as_bool(),
+            Self::TemperatureCalibration => settings.tem_cali = value.as_i64(),
+            Self::HumidityCalibration => settings.hum_cali = value.as_i64(),
+        }
+    }
+}
+
+fn device_settings(device: &ServiceDevice) -> Option<&DeviceSettings> {
+    device
+        .undoc_device_info
+        .as_ref()
+        .map(|info| &info.entry.device_ext.device_settings)
+}
+
+/// Exposes a DeviceSetting as a read-only sensor, when the
+/// app settings are not writable
+pub struct DeviceSettingSensor {
+    sensor: SensorConfig,
+    setting: DeviceSetting,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl DeviceSettingSensor {
+    /// Returns sensors for each of the settings that the device has
+    pub async fn for_device(device: &ServiceDevice, state: &StateHandle) -> Vec<Self> {
+        if state.get_config().await.app_settings == AppSettings::Writable {
+            return vec![];
+        }
+        let Some(settings) = device_settings(device) else {
+            return vec![];
+        };
+        let scale = state.get_temperature_scale().await;
+
+        DeviceSetting::ALL
+            .iter()
+            .filter(|setting| setting.is_present(settings))
+            .map(|&setting| {
+                let unique_id = format!(
+                    "sensor-{id}-setting-{slug}",
+                    id = topic_safe_id(device),
+                    slug = setting.slug()
+                );
+                Self {
+                    sensor: SensorConfig {
+                        base: EntityConfig {
+                            availability_topic: availability_topic(),
+                            name: Some(setting.name().to_string()),
+                            entity_category: Some("diagnostic".to_string()),
+                            origin: Origin::default(),
+                            device: Device::for_device(device),
+                            unique_id: unique_id.clone(),
+                            device_class: None,
+                            icon: Some(setting.icon().to_string()),
+                        },
+                        state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                        state_class: None,
+                        unit_of_measurement: setting.unit_of_measurement(scale),
+                        json_attributes_topic: None,
+                        options: vec![],
+                        enabled_by_default: None,
+                    },
+                    setting,
+                    device_id: device.id.to_string(),
+                    state: state.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EntityInstance for DeviceSettingSensor {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        let scale = self.state.get_temperature_scale().await;
+        if let Some(value) =
+            device_settings(&device).and_then(|settings| self.setting.value(settings, scale))
+        {
+            self.sensor.notify_state(client, &value).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Exposes a DeviceSetting as a config entity that can change it:
+/// a select for the temperature unit, and numbers for the calibration
+/// offsets
+pub struct DeviceSettingControl {
+    config: DeviceSettingConfig,
+    setting: DeviceSetting,
+    device_id: String,
+    state: StateHandle,
+}
+
+enum DeviceSettingConfig {
+    Select(SelectConfig),
+    Number(NumberConfig),
+}
+
+impl DeviceSettingControl {
+    /// Returns controls for each of the settings that the device has,
+    /// if the app settings may be changed
+    pub async fn for_device(device: &ServiceDevice, state: &StateHandle) -> Vec<Self> {
+        if state.get_config().await.app_settings != AppSettings::Writable {
+            return vec![];
+        }
+        let Some(settings) = device_settings(device) else {
+            return vec![];
+        };
+        let scale = state.get_temperature_scale().await;
+        let id = topic_safe_id(device);
+
+        DeviceSetting::ALL
+            .iter()
+            .filter(|setting| setting.is_present(settings))
+            .map(|&setting| {
+                let slug = setting.slug();
+                let base = EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some(setting.name().to_string()),
+                    entity_category: Some("config".to_string()),
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("gv2mqtt-{id}-setting-{slug}"),
+                    device_class: None,
+                    icon: Some(setting.icon().to_string()),
+                };
+                let command_topic = format!("gv2mqtt/{id}/set-setting/{slug}");
+                let state_topic = format!("gv2mqtt/{id}/setting/{slug}/state");
+                let config = match setting.range() {
+                    None => DeviceSettingConfig::Select(SelectConfig {
+                        base,
+                        command_topic,
+                        options: vec!["°C".to_string(), "°F".to_string()],
+                        state_topic,
+                    }),
+                    Some((min, max)) => {
+                        let factor = match (setting, scale) {
+                            (
+                                DeviceSetting::TemperatureCalibration,
+                                TemperatureScale::Farenheit,
+                            ) => 1.8,
+                            _ => 1.0,
+                        };
+                        DeviceSettingConfig::Number(NumberConfig {
+                            base,
+                            command_topic,
+                            state_topic: Some(state_topic),
+                            min: Some((min * factor) as f32),
+                            max: Some((max * factor) as f32),
+                            step: 0.1,
+                            unit_of_measurement: setting.unit_of_measurement(scale),
+                        })
+                    }
+                };
+                Self {
+                    config,
+                    setting,
+                    device_id: device.id.to_string(),
+                    state: state.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EntityInstance for DeviceSettingControl {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        match &self.config {
+            DeviceSettingConfig::Select(select) => select.publish(state, client).await,
+            DeviceSettingConfig::Number(number) => number.publish(state, client).await,
+        }
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        let scale = self.state.get_temperature_scale().await;
+        let Some(value) =
+            device_settings(&device).and_then(|settings| self.setting.value(settings, scale))
+        else {
+            return Ok(());
+        };
+        match &self.config {
+            DeviceSettingConfig::Select(select) => client.publish(&select.state_topic, value).await,
+            DeviceSettingConfig::Number(number) => number.notify_state(client, &value).await,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IdAndSetting {
+    id: String,
+    setting: String,
+}
+
+/// Change a setting, such as the temperature unit or a calibration offset
+pub async fn mqtt_set_setting(
+    Payload(value): Payload<String>,
+    Params(IdAndSetting { id, setting }): Params<IdAndSetting>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("mqtt_set_setting: {id}: {setting} {value}");
+    let device = state.resolve_device_for_control(&id).await?;
+    let setting =
+        DeviceSetting::from_slug(&setting).ok_or_else(|| anyhow!("unknown setting {setting}"))?;
+    let value = setting.encode(&value, state.get_temperature_scale().await)?;
+
+    state
+        .device_set_app_settings(
+            &device,
+            json!({ setting.key(): value.clone() }),
+            |settings| setting.apply(settings, &value),
+        )
+        .await
+}
+
+/// An alert that the Govee cloud can push to the phone running the
+/// Govee app. These can be turned on and off from hass, so that the
+/// routing of alerts can be managed in one place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertSetting {
+    /// The temperature is outside of the configured range
+    Temperature,
+    /// The humidity is outside of the configured range
+    Humidity,
+    /// The device has gone offline
+    Offline,
+    /// The PM2.5 level is outside of the configured range
+    Pm25,
+}
+
+impl AlertSetting {
+    const ALL: &'static [Self] = &[Self::Temperature, Self::Humidity, Self::Offline, Self::Pm25];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Temperature => "Temperature Alerts",
+            Self::Humidity => "Humidity Alerts",
+            Self::Offline => "Offline Alerts",
+            Self::Pm25 => "PM2.5 Alerts",
+        }
+    }
+
+    fn slug(&self) -> &'static str {
+        match self {
+            Self::Temperature => "temperature",
+            Self::Humidity => "humidity",
+            Self::Offline => "offline",
+            Self::Pm25 => "pm25",
+        }
+    }
+
+    fn from_slug(slug: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|alert| alert.slug() == slug)
+    }
+
+    /// The name of the setting in the undocumented API
+    fn key(&self) -> &'static str {
+        match self {
+            Self::Temperature => "temWarning",
+            Self::Humidity => "humWarning",
+            // sic
+            Self::Offline => "netWaring",
+            Self::Pm25 => "pm25Warning",
+        }
+    }
+
+    fn get(&self, settings: &DeviceSettings) -> Option<bool> {
+        match self {
+            Self::Temperature => settings.tem_warning,
+            Self::Humidity => settings.hum_warning,
+            Self::Offline => settings.net_waring,
+            Self::Pm25 => settings.pm25_warning,
+        }
+    }
+
+    fn set(&self, settings: &mut DeviceSettings, on: bool) {
+        let value = match self {
+            Self::Temperature => &mut settings.tem_warning,
+            Self::Humidity => &mut settings.hum_warning,
+            Self::Offline => &mut settings.net_waring,
+            Self::Pm25 => &mut settings.pm25_warning,
+        };
+        value.replace(on);
+    }
+}
+
+/// Exposes an AlertSetting as a switch
+pub struct AlertSwitch {
+    switch: SwitchConfig,
+    alert: AlertSetting,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl AlertSwitch {
+    /// Returns switches for each of the alerts that the device has,
+    /// if the app settings may be changed
+    pub async fn for_device(device: &ServiceDevice, state: &StateHandle) -> Vec<Self> {
+        if state.get_config().await.app_settings != AppSettings::Writable {
+            return vec![];
+        }
+        let Some(settings) = device_settings(device) else {
+            return vec![];
+        };
+        let id = topic_safe_id(device);
+
+        AlertSetting::ALL
+            .iter()
+            .filter(|alert| alert.get(settings).is_some())
+            .map(|&alert| {
+                let slug = alert.slug();
+                Self {
+                    switch: SwitchConfig {
+                        base: EntityConfig {
+                            availability_topic: availability_topic(),
+                            name: Some(alert.name().to_string()),
+                            entity_category: Some("config".to_string()),
+                            origin: Origin::default(),
+                            device: Device::for_device(device),
+                            unique_id: format!("gv2mqtt-{id}-alert-{slug}"),
+                            device_class: None,
+                            icon: Some("mdi:bell-ring".to_string()),
+                        },
+                        command_topic: format!("gv2mqtt/{id}/set-alert/{slug}"),
+                        state_topic: format!("gv2mqtt/switch/{id}/alert-{slug}/state"),
+                    },
+                    alert,
+                    device_id: device.id.to_string(),
+                    state: state.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EntityInstance for AlertSwitch {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.switch.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        if let Some(on) = device_settings(&device).and_then(|settings| self.alert.get(settings)) {
+            client
+                .publish(&self.switch.state_topic, if on { "ON" } else { "OFF" })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IdAndAlert {
+    id: String,
+    alert: String,
+}
+
+/// Turn an alert on or off
+pub async fn mqtt_set_alert(
+    Payload(value): Payload<String>,
+    Params(IdAndAlert { id, alert }): Params<IdAndAlert>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("mqtt_set_alert: {id}: {alert} {value}");
+    let device = state.resolve_device_for_control(&id).await?;
+    let alert = AlertSetting::from_slug(&alert).ok_or_else(|| anyhow!("unknown alert {alert}"))?;
+    let on = truthy(&value)?;
+
+    state
+        .device_set_app_settings(&device, json!({ alert.key(): on }), |settings| {
+            alert.set(settings, on)
+        })
+        .await
+}
+
+/// Turns a timer or alarm that is stored on the device on and off,
+/// when the app settings are writable. The details of the schedule
+/// are reported by the accompanying ScheduleSensor.
+pub struct ScheduleSwitch {
+    switch: SwitchConfig,
+    slug: String,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl ScheduleSwitch {
+    pub async fn for_device(device: &ServiceDevice, state: &StateHandle) -> Vec<Self> {
+        if state.get_config().await.app_settings != AppSettings::Writable {
+            return vec![];
+        }
+        let id = topic_safe_id(device);
+
+        device
+            .schedules()
+            .into_iter()
+            .map(|schedule| {
+                let slug = schedule.slug();
+                Self {
+                    switch: SwitchConfig {
+                        base: EntityConfig {
+                            availability_topic: availability_topic(),
+                            name: Some(schedule.name()),
+                            entity_category: Some("config".to_string()),
+                            origin: Origin::default(),
+                            device: Device::for_device(device),
+                            unique_id: format!("gv2mqtt-{id}-schedule-{slug}"),
+                            device_class: None,
+                            icon: Some("mdi:alarm".to_string()),
+                        },
+                        command_topic: format!("gv2mqtt/{id}/set-schedule/{slug}"),
+                        state_topic: format!("gv2mqtt/switch/{id}/schedule-{slug}/state"),
+                    },
+                    slug,
+                    device_id: device.id.to_string(),
+                    state: state.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EntityInstance for ScheduleSwitch {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.switch.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        if let Some(schedule) = device
+            .schedules()
+            .into_iter()
+            .find(|s| s.slug() == self.slug)
+        {
+            client
+                .publish(
+                    &self.switch.state_topic,
+                    if schedule.enabled { "ON" } else { "OFF" },
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IdAndSchedule {
+    id: String,
+    schedule: String,
+}
+
+/// Turn a timer or alarm on or off
+pub async fn mqtt_set_schedule(
+    Payload(value): Payload<String>,
+    Params(IdAndSchedule { id, schedule }): Params<IdAndSchedule>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("mqtt_set_schedule: {id}: {schedule} {value}");
+    let device = state.resolve_device_for_control(&id).await?;
+    let on = truthy(&value)?;
+    let schedule = device
+        .schedules()
+        .into_iter()
+        .find(|s| s.slug() == schedule)
+        .ok_or_else(|| anyhow!("{device} has no schedule {schedule}"))?;
+    let kind = schedule.kind;
+    let mut settings = device_settings(&device)
+        .cloned()
+        .ok_or_else(|| anyhow!("{device} has no settings"))?;
+    let updated = kind
+        .setting_mut(&mut settings)
+        .as_ref()
+        .and_then(|value| set_schedule_enabled(value, schedule.index, on))
+        .ok_or_else(|| anyhow!("unable to change {} of {device}", schedule.name()))?;
+
+    state
+        .device_set_app_settings(
+            &device,
+            json!({ kind.key(): updated.clone() }),
+            |settings| *kind.setting_mut(settings) = Some(updated),
+        )
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform_api::from_json;
+    use crate::service::config::ServiceConfig;
+    use crate::service::state::State;
+    use crate::undoc_api::DevicesResponse;
+    use std::sync::Arc;
+
+    async fn load_state(config: &str) -> StateHandle {
+        let state = Arc::new(State::new());
+        state
+            .set_config(ServiceConfig::parse(config).unwrap())
+            .await;
+        let list: DevicesResponse = from_json(include_str!(
+            "../../test-data/undoc-device-list-issue-21.json"
+        ))
+        .unwrap();
+        for entry in list.devices {
+            let mut device = state.device_mut(&entry.sku, &entry.device).await;
+            device.set_undoc_device_info(entry, None);
+        }
+        state
+    }
+
+    #[tokio::test]
+    async fn alert_switches() {
+        let state = load_state("{}").await;
+        for device in state.devices().await {
+            assert!(AlertSwitch::for_device(&device, &state).await.is_empty());
+        }
+        assert!(mqtt_set_alert(
+            Payload("OFF".to_string()),
+            Params(IdAndAlert {
+                id: state.devices().await[0].id.to_string(),
+                alert: "temperature".to_string(),
+            }),
+            State(state.clone()),
+        )
+        .await
+        .unwrap_err()
+        .to_string()
+        .contains("app_settings"));
+
+        let state = load_state(r#"{"app_settings": "writable"}"#).await;
+        let mut alerts = vec![];
+        for device in state.devices().await {
+            for switch in AlertSwitch::for_device(&device, &state).await {
+                alerts.push((
+                    switch.alert,
+                    switch.alert.get(device_settings(&device).unwrap()),
+                ));
+            }
+        }
+        assert!(alerts.contains(&(AlertSetting::Temperature, Some(true))));
+        assert!(alerts.contains(&(AlertSetting::Temperature, Some(false))));
+        assert!(alerts.contains(&(AlertSetting::Offline, Some(true))));
+    }
+
+    #[tokio::test]
+    async fn setting_entities() {
+        let state = load_state("{}").await;
+        let device = state
+            .devices()
+            .await
+            .into_iter()
+            .find(|d| d.sku == "H5100")
+            .unwrap();
+        assert_eq!(
+            DeviceSettingSensor::for_device(&device, &state).await.len(),
+            3
+        );
+        assert!(DeviceSettingControl::for_device(&device, &state)
+            .await
+            .is_empty());
+        assert!(mqtt_set_setting(
+            Payload("1.5".to_string()),
+            Params(IdAndSetting {
+                id: device.id.to_string(),
+                setting: "temperature-calibration".to_string(),
+            }),
+            State(state.clone()),
+        )
+        .await
+        .unwrap_err()
+        .to_string()
+        .contains("app_settings"));
+
+        let state = load_state(r#"{"app_settings": "writable"}"#).await;
+        assert!(DeviceSettingSensor::for_device(&device, &state)
+            .await
+            .is_empty());
+        let controls = DeviceSettingControl::for_device(&device, &state).await;
+        assert_eq!(controls.len(), 3);
+        for control in &controls {
+            let (category, topic) = match &control.config {
+                DeviceSettingConfig::Select(s) => (&s.base.entity_category, &s.command_topic),
+                DeviceSettingConfig::Number(n) => (&n.base.entity_category, &n.command_topic),
+            };
+            assert_eq!(category.as_deref(), Some("config"));
+            assert!(topic.ends_with(&format!("/set-setting/{}", control.setting.slug())));
+        }
+    }
+
+    #[test]
+    fn setting_values() {
+        use TemperatureScale::{Celsius, Farenheit};
+        let mut settings: DeviceSettings = serde_json::from_value(json!({})).unwrap();
+
+        for (setting, input, scale, encoded, shown) in [
+            (
+                DeviceSetting::TemperatureUnit,
+                "°F",
+                Celsius,
+                json!(true),
+                "°F",
+            ),
+            (
+                DeviceSetting::TemperatureUnit,
+                "C",
+                Celsius,
+                json!(false),
+                "°C",
+            ),
+            (
+                DeviceSetting::TemperatureCalibration,
+                "-1.5",
+                Celsius,
+                json!(-150),
+                "-1.5",
+            ),
+            (
+                DeviceSetting::TemperatureCalibration,
+                "1.8",
+                Farenheit,
+                json!(100),
+                "1.8",
+            ),
+            (
+                DeviceSetting::TemperatureCalibration,
+                "5.4",
+                Farenheit,
+                json!(300),
+                "5.4",
+            ),
+            (
+                DeviceSetting::HumidityCalibration,
+                "12.3",
+                Celsius,
+                json!(1230),
+                "12.3",
+            ),
+        ] {
+            let value = setting.encode(input, scale).unwrap();
+            assert_eq!(value, encoded, "{setting:?} {input}");
+            setting.apply(&mut settings, &value);
+            assert_eq!(setting.value(&settings, scale).as_deref(), Some(shown));
+        }
+
+        for (setting, input) in [
+            (DeviceSetting::TemperatureUnit, "K"),
+            (DeviceSetting::TemperatureCalibration, "3.5"),
+            (DeviceSetting::HumidityCalibration, "-21"),
+            (DeviceSetting::HumidityCalibration, "damp"),
+        ] {
+            assert!(
+                setting.encode(input, Celsius).is_err(),
+                "{setting:?} {input}"
+            );
+        }
+        for &setting in DeviceSetting::ALL {
+            assert_eq!(DeviceSetting::from_slug(setting.slug()), Some(setting));
+        }
+    }
+
+    async fn load_alarm_clock(config: &str) -> (StateHandle, ServiceDevice) {
+        let state = load_state(config).await;
+        let device = state
+            .devices()
+            .await
+            .into_iter()
+            .find(|d| d.sku == "H5100")
+            .unwrap();
+        {
+            let mut device = state.device_mut(&device.sku, &device.id).await;
+            let info = device.undoc_device_info.as_mut().unwrap();
+            info.entry.device_ext.device_settings.wake_up_setting =
+                Some(json!({"hour": 6, "minute": 45, "onOff": 1}));
+        }
+        let device = state.device_by_id(&device.id).await.unwrap();
+        (state, device)
+    }
+
+    async fn set_schedule(state: &StateHandle, id: &str, schedule: &str) -> anyhow::Result<()> {
+        mqtt_set_schedule(
+            Payload("OFF".to_string()),
+            Params(IdAndSchedule {
+                id: id.to_string(),
+                schedule: schedule.to_string(),
+            }),
+            State(state.clone()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn schedule_switches() {
+        let (state, device) = load_alarm_clock("{}").await;
+        assert!(ScheduleSwitch::for_device(&device, &state).await.is_empty());
+        assert!(set_schedule(&state, &device.id, "wake-up-0")
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("app_settings"));
+
+        let (state, device) = load_alarm_clock(r#"{"app_settings": "writable"}"#).await;
+        let switches = ScheduleSwitch::for_device(&device, &state).await;
+        assert_eq!(switches.len(), 1);
+        assert_eq!(switches[0].slug, "wake-up-0");
+        assert_eq!(
+            switches[0].switch.base.entity_category.as_deref(),
+            Some("config")
+        );
+        assert!(set_schedule(&state, &device.id, "timer-0")
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("no schedule timer-0"));
+        // Without a Govee account, the change is computed, but can't be made
+        assert!(set_schedule(&state, &device.id, "wake-up-0")
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("Govee account"));
+    }
+
+    #[test]
+    fn alert_settings() {
+        let mut settings: DeviceSettings = serde_json::from_value(json!({})).unwrap();
+        for &alert in AlertSetting::ALL {
+            assert_eq!(AlertSetting::from_slug(alert.slug()), Some(alert));
+            assert_eq!(alert.get(&settings), None);
+            alert.set(&mut settings, true);
+            assert_eq!(alert.get(&settings), Some(true));
+        }
+        assert_eq!(AlertSetting::from_slug("bogus"), None);
+        assert_eq!(AlertSetting::Offline.key(), "netWaring");
+    }
+}
@@ -1,21 +1,50 @@
 use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::binary_sensor::{
+    problem_event, AlarmSensor, FilterReplacement, GatewayConnectivity, OccupancySensor,
+    ProblemEvent, ScheduleSensor,
+};
 use crate::hass_mqtt::button::ButtonConfig;
 use crate::hass_mqtt::climate::TargetTemperatureEntity;
-use crate::hass_mqtt::humidifier::Humidifier;
+use crate::hass_mqtt::device_settings::{
+    AlertSwitch, DeviceSettingControl, DeviceSettingSensor, ScheduleSwitch,
+};
+use crate::hass_mqtt::event::ButtonPressEvent;
+use crate::hass_mqtt::fan::Fan;
+use crate::hass_mqtt::humidifier::{humidity_setpoint_mode, Humidifier};
 use crate::hass_mqtt::instance::EntityList;
 use crate::hass_mqtt::light::DeviceLight;
-use crate::hass_mqtt::number::WorkModeNumber;
+use crate::hass_mqtt::music::{MusicModeSelect, MusicSensitivityNumber, ParsedMusicMode};
+use crate::hass_mqtt::number::{
+    is_countdown_instance, CountdownNumber, DisplayBrightnessNumber, WorkModeNumber,
+};
+use crate::hass_mqtt::room::enumerate_room_entities;
 use crate::hass_mqtt::scene::SceneConfig;
 use crate::hass_mqtt::select::{SceneModeSelect, WorkModeSelect};
-use crate::hass_mqtt::sensor::{CapabilitySensor, DeviceStatusDiagnostic, GlobalFixedDiagnostic};
+use crate::hass_mqtt::sensor::{
+    is_filter_life_instance, ActiveWorkModeSensor, BridgeStatisticsDiagnostic, CapabilitySensor,
+    CountdownRemainingSensor, DeviceInfoDiagnostic, DeviceStatusDiagnostic, GlobalFixedDiagnostic,
+    PlatformApiUsageDiagnostic, PowerStateSensor, ScheduleSummarySensor, ThermometerSensor,
+    WifiSignalDiagnostic,
+};
 use crate::hass_mqtt::switch::CapabilitySwitch;
-use crate::hass_mqtt::work_mode::ParsedWorkMode;
+use crate::hass_mqtt::text::SceneNameText;
+use crate::hass_mqtt::update::FirmwareUpdate;
+use crate::hass_mqtt::virtual_device::VirtualLight;
+use crate::hass_mqtt::water_heater::WaterHeater;
+use crate::hass_mqtt::work_mode::{
+    work_mode_override, ParsedWorkMode, WorkMode, WorkModePresentation,
+};
 use crate::platform_api::{DeviceCapability, DeviceCapabilityKind, DeviceType};
-use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, oneclick_topic, purge_cache_topic};
+use crate::service::composition::virtual_devices;
+use crate::service::config::{
+    KettleEntities, OneClickEntities, RoomControls, SegmentLights, WorkModePresets,
+};
+use crate::service::device::{Device as ServiceDevice, NIGHTLIGHT_INSTANCE};
+use crate::service::hass::{availability_topic, oneclick_topic, purge_cache_topic, HassClient};
+use crate::service::identity::bridge_id;
+use crate::service::quirks::{is_child_lock_instance, is_display_brightness_instance};
 use crate::service::state::StateHandle;
 use crate::version_info::govee_version;
-use anyhow::Context;
 
 use uuid::Uuid;
 
@@ -27,51 +56,86 @@ pub async fn enumerate_all_entites(state: &StateHandle) -> anyhow::Result<Entity
 
     let devices = state.devices().await;
 
-    for d in &devices {
-        enumerate_entities_for_device(d, state, &mut entities)
-            .await
-            .with_context(|| format!("Config::for_device({d})"))?;
+    for d in devices.iter() {
+        // A failure, which may be transient, shouldn't prevent
+        // the other devices from being registered
+        let mut device_entities = EntityList::new();
+        if let Err(err) = enumerate_entities_for_device(d, state, &mut device_entities).await {
+            log::error!("While enumerating entities for {d}: {err:#}");
+            device_entities.mark_incomplete();
+        }
+        entities.extend_for_device(&d.id, device_entities);
+    }
+
+    for v in virtual_devices(state).await {
+        entities.add(VirtualLight::new(v, state).await);
+    }
+
+    if state.get_config().await.room_controls == RoomControls::Lights {
+        enumerate_room_entities(state, &mut entities).await;
     }
 
     Ok(entities)
 }
 
 async fn enumerate_global_entities(
-    _state: &StateHandle,
+    state: &StateHandle,
     entities: &mut EntityList,
 ) -> anyhow::Result<()> {
     entities.add(GlobalFixedDiagnostic::new("Version", govee_version()));
+    if let Some(startup) = state.get_startup_tracker().await {
+        if startup.is_safe_mode() {
+            entities.add(GlobalFixedDiagnostic::new(
+                "Safe Mode",
+                startup.status().reason(),
+            ));
+        }
+    }
+    if state.get_platform_client().await.is_some() {
+        entities.add(PlatformApiUsageDiagnostic::new(state));
+    }
+    entities.add(BridgeStatisticsDiagnostic::new(state));
     entities.add(ButtonConfig::new("Purge Caches", purge_cache_topic()));
     Ok(())
 }
 
 async fn enumerate_scenes(state: &StateHandle, entities: &mut EntityList) -> anyhow::Result<()> {
     if let Some(undoc) = state.get_undoc_client().await {
+        let kind = state.get_config().await.one_click_entities;
         match undoc.parse_one_clicks().await {
             Ok(items) => {
                 for oc in items {
-                    let unique_id = format!(
-                        "gv2mqtt-one-click-{}",
-                        Uuid::new_v5(&Uuid::NAMESPACE_DNS, oc.name.as_bytes()).simple()
-                    );
-                    entities.add(SceneConfig {
-                        base: EntityConfig {
-                            availability_topic: availability_topic(),
-                            name: Some(oc.name.to_string()),
-                            entity_category: None,
-                            origin: Origin::default(),
-                            device: Device::this_service(),
-                            unique_id: unique_id.clone(),
-                            device_class: None,
-                            icon: None,
-                        },
-                        command_topic: oneclick_topic(),
-                        payload_on: oc.name,
-                    });
+                    match kind {
+                        OneClickEntities::Scene => {
+                            let unique_id = format!(
+                                "{}-one-click-{}",
+                                bridge_id(),
+                                Uuid::new_v5(&Uuid::NAMESPACE_DNS, oc.name.as_bytes()).simple()
+                            );
+                            entities.add(SceneConfig {
+                                base: EntityConfig {
+                                    availability_topic: availability_topic(),
+                                    name: Some(oc.name.to_string()),
+                                    entity_category: None,
+                                    origin: Origin::default(),
+                                    device: Device::this_service(),
+                                    unique_id: unique_id.clone(),
+                                    device_class: None,
+                                    icon: None,
+                                },
+                                command_topic: oneclick_topic(),
+                                payload_on: oc.name,
+                            });
+                        }
+                        OneClickEntities::Button => {
+                            entities.add(ButtonConfig::activate_one_click(&oc.name));
+                        }
+                    }
                 }
             }
             Err(err) => {
                 log::warn!("Failed to parse one-clicks: {err:#}");
+                entities.mark_incomplete();
             }
         }
     }
@@ -79,6 +143,36 @@ async fn enumerate_scenes(state: &StateHandle, entities: &mut EntityList) -> any
     Ok(())
 }
 
+/// Add a button to activate each of the values of a work mode
+fn add_work_mode_value_presets(
+    d: &ServiceDevice,
+    work_mode: &WorkMode,
+    mode_num: Option<i64>,
+    entities: &mut EntityList,
+) {
+    for value in &work_mode.values {
+        match (mode_num, value.value.as_i64()) {
+            (Some(mode_num), Some(mode_value)) => {
+                entities.add(ButtonConfig::activate_work_mode_preset(
+                    d,
+                    &value.computed_label,
+                    &work_mode.name,
+                    mode_num,
+                    mode_value,
+                ));
+            }
+            _ if value.value.is_string() || work_mode.value.is_string() => {
+                entities.add(ButtonConfig::activate_work_mode_by_name(
+                    d,
+                    &value.computed_label,
+                    &value.computed_label,
+                ));
+            }
+            _ => {}
+        }
+    }
+}
+
 async fn entities_for_work_mode<'a>(
     d: &ServiceDevice,
     state: &StateHandle,
@@ -86,46 +180,66 @@ async fn entities_for_work_mode<'a>(
     entities: &mut EntityList,
 ) -> anyhow::Result<()> {
     let mut work_modes = ParsedWorkMode::with_capability(cap)?;
-    work_modes.adjust_for_device(&d.sku);
+    work_modes.adjust_for_device(d);
 
     let quirk = d.resolve_quirk();
+    let presets = state.get_config().await.work_mode_presets;
+    let is_humidifier = matches!(
+        d.device_type(),
+        DeviceType::Humidifier | DeviceType::Dehumidifier
+    );
+    let setpoint_mode = humidity_setpoint_mode(d, &work_modes)
+        .filter(|_| is_humidifier)
+        .map(|mode| mode.name.clone());
 
     for work_mode in work_modes.modes.values() {
-        let Some(mode_num) = work_mode.value.as_i64() else {
+        // Newer devices may use strings rather than numbers
+        let mode_num = work_mode.value.as_i64();
+        if mode_num.is_none() && !work_mode.value.is_string() {
             continue;
-        };
+        }
 
         let range = work_mode.contiguous_value_range();
 
-        let show_as_preset = work_mode.should_show_as_preset()
-            || quirk
-                .as_ref()
-                .map(|q| q.should_show_mode_as_preset(&work_mode.name))
-                .unwrap_or(false);
+        let show_as_preset = match work_mode_override(d, &work_mode.name).presentation {
+            Some(WorkModePresentation::Presets) => true,
+            Some(WorkModePresentation::Slider) => false,
+            None => {
+                work_mode.should_show_as_preset()
+                    || quirk
+                        .as_ref()
+                        .map(|q| q.should_show_mode_as_preset(&work_mode.name))
+                        .unwrap_or(false)
+            }
+        };
 
         if show_as_preset {
             if work_mode.values.is_empty() {
-                entities.add(ButtonConfig::activate_work_mode_preset(
-                    d,
-                    &format!("Activate Mode: {}", work_mode.label()),
-                    &work_mode.name,
-                    mode_num,
-                    work_mode.default_value(),
-                ));
-            } else {
-                for value in &work_mode.values {
-                    if let Some(mode_value) = value.value.as_i64() {
-                        entities.add(ButtonConfig::activate_work_mode_preset(
-                            d,
-                            &value.computed_label,
-                            &work_mode.name,
-                            mode_num,
-                            mode_value,
-                        ));
-                    }
+                if presets == WorkModePresets::Select {
+                    // Activated via the work mode select instead
+                    continue;
                 }
+                let label = format!("Activate Mode: {}", work_mode.label());
+                match mode_num {
+                    Some(mode_num) => entities.add(ButtonConfig::activate_work_mode_preset(
+                        d,
+                        &label,
+                        &work_mode.name,
+                        mode_num,
+                        work_mode.default_value(),
+                    )),
+                    None => entities.add(ButtonConfig::activate_work_mode_by_name(
+                        d,
+                        &label,
+                        &work_mode.name,
+                    )),
+                }
+            } else {
+                add_work_mode_value_presets(d, work_mode, mode_num, entities);
             }
-        } else {
+        } else if setpoint_mode.as_deref() == Some(work_mode.name.as_str()) {
+            // This is the target humidity of the humidifier entity
+        } else if let Some(range) = range.filter(|_| mode_num.is_some()) {
             let label = work_mode.label().to_string();
 
             entities.add(WorkModeNumber::new(
@@ -136,66 +250,262 @@ async fn entities_for_work_mode<'a>(
                 work_mode.value.clone(),
                 range,
             ));
+        } else {
+            // The values are discrete options rather than a range
+            // that can be represented by a slider
+            add_work_mode_value_presets(d, work_mode, mode_num, entities);
+        }
+    }
+
+    entities.add(WorkModeSelect::new(
+        d,
+        &work_modes,
+        presets == WorkModePresets::Select,
+        state,
+    ));
+    entities.add(ActiveWorkModeSensor::new(d, state, &work_modes));
+
+    Ok(())
+}
+
+/// Enumerate the entities of a device, setting aside those
+/// suppressed by the `disabled_entities` configuration
+pub async fn enumerate_entities_for_device(
+    d: &ServiceDevice,
+    state: &StateHandle,
+    entities: &mut EntityList,
+) -> anyhow::Result<()> {
+    if d.removed {
+        // Enumerated regardless, so that any configs left
+        // behind by an earlier run can be removed. That is only
+        // a courtesy, so a failure mustn't affect other devices;
+        // the configs that we published during this run are
+        // removed either way, as they are no longer current.
+        let mut device_entities = EntityList::new();
+        if let Err(err) = enumerate_device_entities(d, state, &mut device_entities).await {
+            log::warn!("While enumerating entities for removed {d}: {err:#}");
+        }
+        for entity in device_entities.iter() {
+            entities.push_disabled(entity.clone());
         }
+        return Ok(());
+    }
+
+    let disabled = state.device_disabled_entities(d).await;
+    if disabled.is_empty() {
+        return enumerate_device_entities(d, state, entities).await;
     }
 
-    entities.add(WorkModeSelect::new(d, &work_modes, state));
+    let mut device_entities = EntityList::new();
+    enumerate_device_entities(d, state, &mut device_entities).await?;
 
+    // Unique ids are only known by asking the entity for its config
+    let (recorder, recorded) = HassClient::detached().manifest_recording();
+    for entity in device_entities.iter() {
+        entity.publish_config(state, &recorder).await?;
+        let configs = std::mem::take(&mut *recorded.lock());
+        let capability = entity.source_capability();
+        let is_disabled = configs.iter().any(|entry| {
+            disabled
+                .iter()
+                .any(|d| d.matches(capability, &entry.unique_id))
+        });
+        if is_disabled {
+            entities.push_disabled(entity.clone());
+        } else {
+            entities.push(entity.clone());
+        }
+    }
+    if !device_entities.is_complete() {
+        entities.mark_incomplete();
+    }
     Ok(())
 }
 
-pub async fn enumerate_entities_for_device<'a>(
-    d: &'a ServiceDevice,
+async fn enumerate_device_entities(
+    d: &ServiceDevice,
     state: &StateHandle,
     entities: &mut EntityList,
 ) -> anyhow::Result<()> {
+    if d.is_gateway() {
+        // A gateway has nothing to control; it exists in the device
+        // registry so that its sensors can be grouped beneath it
+        entities.add(GatewayConnectivity::new(d, state));
+        return Ok(());
+    }
+
+    if d.is_presence_sensor() {
+        entities.add(OccupancySensor::new(d, state));
+    }
+    if let Some(kind) = d.alarm_kind() {
+        entities.add(AlarmSensor::new(d, state, kind));
+    }
+    if d.has_button_events() {
+        entities.add(ButtonPressEvent::new(d));
+    }
+
+    // Thermo-hygrometers are often BLE devices that can't be
+    // controlled, but they still have readings to report
+    for sensor in ThermometerSensor::for_device(d, state).await {
+        entities.add(sensor);
+    }
+
     if !d.is_controllable() {
         return Ok(());
     }
 
     entities.add(DeviceStatusDiagnostic::new(d, state));
+    if d.wifi_rssi().is_some() {
+        entities.add(WifiSignalDiagnostic::new(d, state));
+    }
+    for diagnostic in DeviceInfoDiagnostic::for_device(d, state) {
+        entities.add(diagnostic);
+    }
+    if let Some(update) = FirmwareUpdate::new(d, state) {
+        entities.add(update);
+    }
+    entities.add(ButtonConfig::poll_now_for_device(d));
     entities.add(ButtonConfig::request_platform_data_for_device(d));
 
-    if d.supports_rgb() || d.get_color_temperature_range().is_some() || d.supports_brightness() {
+    if d.supports_rgb()
+        || d.get_color_temperature_range().is_some()
+        || d.supports_brightness()
+        || d.has_night_light()
+    {
         entities.add(DeviceLight::for_device(&d, state, None).await?);
     }
 
-    if matches!(
+    let is_humidifier = matches!(
         d.device_type(),
         DeviceType::Humidifier | DeviceType::Dehumidifier
-    ) {
+    );
+    if is_humidifier {
         entities.add(Humidifier::new(&d, state).await?);
     }
 
-    if d.device_type() != DeviceType::Light {
-        if let Some(scenes) = SceneModeSelect::new(d, state).await? {
-            entities.add(scenes);
+    let is_water_heater = d.device_type() == DeviceType::Kettle
+        && state.get_config().await.kettle_entities == KettleEntities::WaterHeater;
+
+    if d.device_type() == DeviceType::Fan {
+        entities.add(Fan::new(d, state).await?);
+    }
+
+    if let Some(scenes) = SceneModeSelect::new(d, state).await? {
+        entities.add(scenes);
+    }
+    match SceneModeSelect::diy(d, state).await {
+        Ok(Some(scenes)) => entities.add(scenes),
+        Ok(None) => {}
+        Err(err) => {
+            log::error!("Unable to list DIY scenes for {d}: {err:#}");
+            entities.mark_incomplete();
+        }
+    }
+    match SceneNameText::new(d, state).await {
+        Ok(Some(text)) => entities.add(text),
+        Ok(None) => {}
+        Err(err) => {
+            log::error!("Unable to list scenes for {d}: {err:#}");
+            entities.mark_incomplete();
+        }
+    }
+
+    if let Some(favorites) = state.device_favorite_scenes(d).await {
+        match state.device_list_scenes(d).await {
+            Ok(catalog) => {
+                let scenes = favorites.resolve(&catalog);
+                if scenes.len() < favorites.scenes.len() {
+                    log::warn!(
+                        "{d}: some of the favorite scenes {:?} are not in its catalog",
+                        favorites.scenes
+                    );
+                }
+                for scene in scenes {
+                    entities.add(ButtonConfig::activate_favorite_scene(d, &scene));
+                }
+            }
+            Err(err) => {
+                log::error!("Unable to list scenes for {d}: {err:#}");
+                entities.mark_incomplete();
+            }
         }
     }
 
     if let Some(info) = &d.http_device_info {
+        // Appliances may be off but waiting for a timer to turn them on,
+        // which is worth distinguishing from being idle
+        if d.device_type() != DeviceType::Light
+            && info.capability_by_instance("powerSwitch").is_some()
+        {
+            entities.add(PowerStateSensor::new(d, state));
+        }
+
         for cap in &info.capabilities {
             match &cap.kind {
+                // Some devices advertise their child lock as a mode with
+                // on and off options rather than as a toggle, but it
+                // should appear the same way regardless
+                _ if is_child_lock_instance(&d.sku, &cap.instance) => {
+                    entities.add(CapabilitySwitch::new(d, state, cap).await?);
+                }
+                // The humidifier entity provides the power control
+                DeviceCapabilityKind::OnOff if is_humidifier && cap.instance == "powerSwitch" => {}
+                // The water heater entity provides the power control
+                DeviceCapabilityKind::OnOff if is_water_heater && cap.instance == "powerSwitch" => {
+                }
+                // The night light entity provides its power control
+                DeviceCapabilityKind::Toggle
+                    if cap.instance == NIGHTLIGHT_INSTANCE && d.has_night_light() => {}
                 DeviceCapabilityKind::Toggle | DeviceCapabilityKind::OnOff => {
                     entities.add(CapabilitySwitch::new(&d, state, cap).await?);
                 }
+                DeviceCapabilityKind::Event => {
+                    // Other events aren't surfaced
+                    if let Some((name, slug)) = problem_event(&cap.instance) {
+                        entities.add(ProblemEvent::new(d, state, cap, name, slug));
+                    }
+                }
+                DeviceCapabilityKind::MusicSetting => {
+                    if let Some(music) = ParsedMusicMode::with_capability(cap) {
+                        entities.add(MusicModeSelect::new(d, state, &music));
+                        entities.add(MusicSensitivityNumber::new(d, state, &music));
+                    }
+                }
                 DeviceCapabilityKind::ColorSetting
                 | DeviceCapabilityKind::SegmentColorSetting
-                | DeviceCapabilityKind::MusicSetting
-                | DeviceCapabilityKind::Event
                 | DeviceCapabilityKind::Mode
                 | DeviceCapabilityKind::DynamicScene => {}
 
                 DeviceCapabilityKind::Range if cap.instance == "brightness" => {}
                 DeviceCapabilityKind::Range if cap.instance == "humidity" => {}
+                DeviceCapabilityKind::Range if is_display_brightness_instance(&cap.instance) => {
+                    entities.add(DisplayBrightnessNumber::new(d, state, cap)?);
+                }
+                DeviceCapabilityKind::Range if is_countdown_instance(&cap.instance) => {
+                    match CountdownNumber::new(d, state, cap) {
+                        Ok(number) => {
+                            entities.add(number);
+                            entities.add(CountdownRemainingSensor::new(d, state, cap));
+                        }
+                        Err(err) => {
+                            log::warn!("Skipping {} for {d}: {err:#}", cap.instance);
+                        }
+                    }
+                }
                 DeviceCapabilityKind::WorkMode => {
                     entities_for_work_mode(d, state, cap, entities).await?;
                 }
 
                 DeviceCapabilityKind::Property => {
                     entities.add(CapabilitySensor::new(&d, state, cap).await?);
+                    if is_filter_life_instance(&cap.instance) {
+                        entities.add(FilterReplacement::new(d, state, &cap.instance));
+                    }
                 }
 
+                DeviceCapabilityKind::TemperatureSetting if is_water_heater => {
+                    entities.add(WaterHeater::new(d, state, cap).await?);
+                }
                 DeviceCapabilityKind::TemperatureSetting => {
                     entities.add(TargetTemperatureEntity::new(&d, state, cap).await?);
                 }
@@ -209,11 +519,364 @@ pub async fn enumerate_entities_for_device<'a>(
             }
         }
 
-        if let Some(segments) = info.supports_segmented_rgb() {
+        let segment_lights = state.get_config().await.segment_lights;
+        if let Some(segments) = info
+            .supports_segmented_rgb()
+            .filter(|_| segment_lights == SegmentLights::All)
+        {
             for n in segments {
                 entities.add(DeviceLight::for_device(&d, state, Some(n)).await?);
             }
         }
     }
+
+    for setting in DeviceSettingSensor::for_device(d, state).await {
+        entities.add(setting);
+    }
+    for control in DeviceSettingControl::for_device(d, state).await {
+        entities.add(control);
+    }
+    for alert in AlertSwitch::for_device(d, state).await {
+        entities.add(alert);
+    }
+
+    if d.reports_schedules() {
+        entities.add(ScheduleSummarySensor::new(d, state));
+    }
+    for schedule in d.schedules() {
+        entities.add(ScheduleSensor::new(d, state, &schedule));
+    }
+    for schedule in ScheduleSwitch::for_device(d, state).await {
+        entities.add(schedule);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform_api::{from_json, HttpDeviceInfo};
+    use crate::service::hass::HassClient;
+    use crate::service::state::State;
+    use crate::undoc_api::DevicesResponse;
+    use serde::Deserialize;
+    use serde_json::Value as JsonValue;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[derive(Deserialize)]
+    struct PlatformDeviceList {
+        data: Vec<HttpDeviceInfo>,
+    }
+
+    /// Populate the state from a fixture.
+    /// Returns false if the fixture isn't a device list.
+    async fn load_fixture(state: &StateHandle, text: &str) -> bool {
+        if let Ok(list) = from_json::<PlatformDeviceList, _>(text) {
+            for info in list.data {
+                let mut device = state.device_mut(&info.sku, &info.device).await;
+                device.set_http_device_info(info);
+            }
+            return true;
+        }
+        if let Ok(list) = from_json::<DevicesResponse, _>(text) {
+            for entry in list.devices {
+                let mut device = state.device_mut(&entry.sku, &entry.device).await;
+                device.set_undoc_device_info(entry, None);
+            }
+            state.link_gateway_devices().await;
+            return true;
+        }
+        false
+    }
+
+    /// Generates the discovery configs for every device in each of the
+    /// device list fixtures, so that the effect of a change to any of
+    /// the entity builders can be seen across all of the known devices
+    #[tokio::test]
+    async fn fixture_discovery_configs() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data");
+        let mut paths: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map(|ext| ext == "json").unwrap_or(false))
+            .collect();
+        paths.sort();
+
+        let mut report = String::new();
+        for path in paths {
+            let text = std::fs::read_to_string(&path).unwrap();
+            let state = Arc::new(State::new());
+            if !load_fixture(&state, &text).await {
+                continue;
+            }
+            state
+                .set_hass_disco_prefix("homeassistant".to_string())
+                .await;
+            let client = HassClient::for_test();
+
+            let mut devices = state.devices().await;
+            devices.sort_by(|a, b| a.id.cmp(&b.id));
+            for d in &devices {
+                let mut entities = EntityList::new();
+                enumerate_entities_for_device(d, &state, &mut entities)
+                    .await
+                    .unwrap();
+                for entity in entities.iter() {
+                    entity.publish_config(&state, &client).await.unwrap();
+                }
+            }
+
+            let name = path.file_name().unwrap().to_string_lossy();
+            report.push_str(&format!("# {name}\n"));
+            for (topic, payload) in client.take_published() {
+                let mut config: JsonValue = serde_json::from_slice(&payload).unwrap();
+                // Don't churn the snapshot on every release
+                if let Some(origin) = config.get_mut("origin") {
+                    origin["sw_version"] = "<version>".into();
+                }
+                report.push_str(&format!(
+                    "{topic}\n{}\n",
+                    serde_json::to_string_pretty(&config).unwrap()
+                ));
+            }
+        }
+
+        k9::assert_matches_snapshot!(report);
+    }
+
+    #[tokio::test]
+    async fn kettle_water_heater() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/list_devices_kettle.json");
+        let text = std::fs::read_to_string(path).unwrap();
+
+        async fn topics(state: &StateHandle) -> Vec<String> {
+            let device = state.device_by_id("99:88:77:66:55:44:33:22").await.unwrap();
+            let client = HassClient::for_test();
+            let mut entities = EntityList::new();
+            enumerate_entities_for_device(&device, state, &mut entities)
+                .await
+                .unwrap();
+            for entity in entities.iter() {
+                entity.publish_config(state, &client).await.unwrap();
+            }
+            client
+                .take_published()
+                .into_iter()
+                .map(|(topic, _)| topic)
+                .collect()
+        }
+
+        let state = Arc::new(State::new());
+        assert!(load_fixture(&state, &text).await);
+        let topics_by_default = topics(&state).await;
+        assert!(topics_by_default.iter().any(|t| t.contains("powerSwitch")));
+        assert!(topics_by_default.iter().any(|t| t.contains("/number/")));
+        assert!(!topics_by_default
+            .iter()
+            .any(|t| t.contains("/water_heater/")));
+
+        state
+            .set_config(
+                crate::service::config::ServiceConfig::parse(
+                    r#"{"kettle_entities": "water_heater"}"#,
+                )
+                .unwrap(),
+            )
+            .await;
+        let topics = topics(&state).await;
+        assert!(!topics.iter().any(|t| t.contains("powerSwitch")));
+        assert!(topics.iter().any(|t| t.contains("/water_heater/")));
+    }
+
+    /// Returns the discovery configs for a device described by
+    /// a platform API device info document
+    async fn configs_for_device(info: &str) -> Vec<(String, JsonValue)> {
+        let state = Arc::new(State::new());
+        let info: HttpDeviceInfo = from_json(info).unwrap();
+        let id = info.device.clone();
+        state
+            .device_mut(&info.sku, &info.device)
+            .await
+            .set_http_device_info(info);
+        let device = state.device_by_id(&id).await.unwrap();
+
+        let client = HassClient::for_test();
+        let mut entities = EntityList::new();
+        enumerate_entities_for_device(&device, &state, &mut entities)
+            .await
+            .unwrap();
+        for entity in entities.iter() {
+            entity.publish_config(&state, &client).await.unwrap();
+        }
+
+        client
+            .take_published()
+            .into_iter()
+            .map(|(topic, payload)| (topic, serde_json::from_slice(&payload).unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn night_light_entity() {
+        let published = configs_for_device(
+            r#"{
+                "sku": "H7126",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "deviceName": "Purifier",
+                "type": "devices.types.air_purifier",
+                "capabilities": [
+                    {"type": "devices.capabilities.toggle", "instance": "nightlightToggle",
+                     "parameters": {"dataType": "ENUM", "options": [
+                        {"name": "on", "value": 1}, {"name": "off", "value": 0}]}}
+                ]
+            }"#,
+        )
+        .await;
+
+        let (_, light) = published
+            .iter()
+            .find(|(topic, _)| topic.contains("/light/"))
+            .expect("a light entity");
+        assert_eq!(light["name"], "Night Light");
+        assert_eq!(light["supported_color_modes"], serde_json::json!(["onoff"]));
+
+        // The light replaces the switch
+        assert!(!published
+            .iter()
+            .any(|(topic, _)| topic.contains("nightlightToggle")));
+    }
+
+    #[tokio::test]
+    async fn child_lock_switch() {
+        let published = configs_for_device(
+            r#"{
+                "sku": "H7131",
+                "device": "AA:BB:CC:DD:EE:FF:42:2B",
+                "deviceName": "Heater",
+                "type": "devices.types.heater",
+                "capabilities": [
+                    {"type": "devices.capabilities.mode", "instance": "controlLock",
+                     "parameters": {"dataType": "ENUM", "options": [
+                        {"name": "on", "value": 1}, {"name": "off", "value": 0}]}}
+                ]
+            }"#,
+        )
+        .await;
+
+        let (_, switch) = published
+            .iter()
+            .find(|(topic, _)| topic.contains("/switch/") && topic.contains("controlLock"))
+            .expect("a child lock switch");
+        assert_eq!(switch["name"], "Child Lock");
+        assert_eq!(switch["entity_category"], "config");
+        assert_eq!(
+            switch["command_topic"],
+            "gv2mqtt/switch/AABBCCDDEEFF422B/command/controlLock"
+        );
+    }
+
+    #[tokio::test]
+    async fn display_switch_and_brightness() {
+        let published = configs_for_device(
+            r#"{
+                "sku": "H5112",
+                "device": "AA:BB:CC:DD:EE:FF:42:2C",
+                "deviceName": "Thermometer",
+                "type": "devices.types.thermometer",
+                "capabilities": [
+                    {"type": "devices.capabilities.toggle", "instance": "displayToggle",
+                     "parameters": {"dataType": "ENUM", "options": [
+                        {"name": "on", "value": 1}, {"name": "off", "value": 0}]}},
+                    {"type": "devices.capabilities.range", "instance": "displayBrightness",
+                     "parameters": {"dataType": "INTEGER", "unit": "unit.percent",
+                        "range": {"min": 1, "max": 100, "precision": 1}}}
+                ]
+            }"#,
+        )
+        .await;
+
+        let (_, switch) = published
+            .iter()
+            .find(|(topic, _)| topic.contains("/switch/") && topic.contains("displayToggle"))
+            .expect("a display switch");
+        assert_eq!(switch["name"], "Display");
+        assert_eq!(switch["entity_category"], "config");
+
+        let (_, number) = published
+            .iter()
+            .find(|(topic, _)| topic.contains("/number/"))
+            .expect("a display brightness number");
+        assert_eq!(number["name"], "Display Brightness");
+        assert_eq!(number["entity_category"], "config");
+        assert_eq!(number["min"], 1.0);
+        assert_eq!(number["max"], 100.0);
+        assert_eq!(number["unit_of_measurement"], "%");
+        assert_eq!(
+            number["command_topic"],
+            "gv2mqtt/number/AABBCCDDEEFF422C/range/displayBrightness"
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_entities() {
+        let state = Arc::new(State::new());
+        let info: HttpDeviceInfo = from_json(
+            r#"{
+                "sku": "H5112",
+                "device": "AA:BB:CC:DD:EE:FF:42:2C",
+                "deviceName": "Thermometer",
+                "type": "devices.types.thermometer",
+                "capabilities": [
+                    {"type": "devices.capabilities.toggle", "instance": "displayToggle",
+                     "parameters": {"dataType": "ENUM", "options": [
+                        {"name": "on", "value": 1}, {"name": "off", "value": 0}]}}
+                ]
+            }"#,
+        )
+        .unwrap();
+        state
+            .device_mut(&info.sku, &info.device)
+            .await
+            .set_http_device_info(info);
+        state
+            .set_config(
+                crate::service::config::ServiceConfig::parse(
+                    r#"{"disabled_entities": [
+                        {"device": "Thermometer", "instance": "displayToggle"},
+                        {"device": "AA:BB:CC:DD:EE:FF:42:2C",
+                         "instance": "gv2mqtt-AABBCCDDEEFF422C-poll-now"}
+                    ]}"#,
+                )
+                .unwrap(),
+            )
+            .await;
+        let device = state.device_by_id("AA:BB:CC:DD:EE:FF:42:2C").await.unwrap();
+
+        let mut entities = EntityList::new();
+        enumerate_entities_for_device(&device, &state, &mut entities)
+            .await
+            .unwrap();
+        assert_eq!(entities.disabled().count(), 2);
+
+        let client = HassClient::for_test();
+        for entity in entities.iter() {
+            entity.publish_config(&state, &client).await.unwrap();
+        }
+        let published = client.take_published();
+        assert!(!published.is_empty());
+        assert!(!published
+            .iter()
+            .any(|(topic, _)| topic.contains("displayToggle") || topic.contains("poll-now")));
+
+        // All of the entities of a removed device are disabled
+        let mut removed = device;
+        removed.removed = true;
+        let mut entities = EntityList::new();
+        enumerate_entities_for_device(&removed, &state, &mut entities)
+            .await
+            .unwrap();
+        assert_eq!(entities.len(), 0);
+        assert!(entities.disabled().count() > 2);
+    }
+}
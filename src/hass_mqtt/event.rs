@@ -0,0 +1,64 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
+use crate::service::button_press::{button_event_topic, EVENT_TYPES};
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{availability_topic, topic_safe_id, HassClient};
+use crate::service::state::StateHandle;
+use async_trait::async_trait;
+use serde::Serialize;
+
+/// <https://www.home-assistant.io/integrations/event.mqtt>
+#[derive(Serialize, Clone, Debug)]
+pub struct EventConfig {
+    #[serde(flatten)]
+    pub base: EntityConfig,
+
+    pub state_topic: String,
+    pub event_types: Vec<&'static str>,
+}
+
+impl EventConfig {
+    pub async fn publish(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        publish_entity_config("event", state, client, &self.base, self).await
+    }
+}
+
+/// Fires when a button on a scene switch or remote is pressed.
+/// The presses are published by the IoT client as they arrive,
+/// so there is no state to report here.
+pub struct ButtonPressEvent {
+    event: EventConfig,
+}
+
+impl ButtonPressEvent {
+    pub fn new(device: &ServiceDevice) -> Self {
+        Self {
+            event: EventConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Button".to_string()),
+                    entity_category: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("event-{id}-button", id = topic_safe_id(device)),
+                    device_class: Some("button"),
+                    icon: None,
+                },
+                state_topic: button_event_topic(device),
+                event_types: EVENT_TYPES.to_vec(),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for ButtonPressEvent {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.event.publish(state, client).await
+    }
+
+    async fn notify_state(&self, _client: &HassClient) -> anyhow::Result<()> {
+        // Events have no state
+        Ok(())
+    }
+}
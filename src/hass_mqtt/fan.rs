@@ -0,0 +1,271 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
+use crate::hass_mqtt::work_mode::{ParsedWorkMode, WorkMode};
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{
+    availability_topic, switch_instance_state_topic, topic_safe_id, HassClient, IdAndScale,
+    IdParameter,
+};
+use crate::service::scale::CommandScale;
+use crate::service::state::StateHandle;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use mosquitto_rs::router::{Params, Payload, State};
+use serde::Serialize;
+use std::ops::Range;
+
+const OSCILLATION_INSTANCE: &str = "oscillationToggle";
+
+/// Sent by hass as the preset mode state when no preset is active
+const NO_PRESET: &str = "None";
+
+/// <https://www.home-assistant.io/integrations/fan.mqtt>
+#[derive(Serialize, Clone, Debug)]
+pub struct FanConfig {
+    #[serde(flatten)]
+    pub base: EntityConfig,
+
+    pub command_topic: String,
+    pub state_topic: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentage_command_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentage_state_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_range_min: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_range_max: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset_mode_command_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset_mode_state_topic: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub preset_modes: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oscillation_command_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oscillation_state_topic: Option<String>,
+    pub payload_oscillation_on: &'static str,
+    pub payload_oscillation_off: &'static str,
+
+    pub optimistic: bool,
+}
+
+/// Returns the work mode that represents the speed of the fan,
+/// along with its range of speeds
+fn speed_mode(work_modes: &ParsedWorkMode) -> Option<(&WorkMode, Range<i64>)> {
+    work_modes.modes.values().find_map(|mode| {
+        if !mode.name.to_ascii_lowercase().contains("speed") {
+            return None;
+        }
+        let range = mode.contiguous_value_range()?;
+        Some((mode, range))
+    })
+}
+
+pub struct Fan {
+    fan: FanConfig,
+    /// The workMode value of the speed mode
+    speed_mode: Option<i64>,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl Fan {
+    pub async fn new(device: &ServiceDevice, state: &StateHandle) -> anyhow::Result<Self> {
+        let id = topic_safe_id(device);
+        let use_iot = device.iot_api_supported() && state.get_iot_client().await.is_some();
+
+        let work_modes = ParsedWorkMode::with_device(device).ok();
+        let speed = work_modes.as_ref().and_then(speed_mode);
+        let speed_mode_name = speed.as_ref().map(|(mode, _)| mode.name.as_str());
+
+        let preset_modes: Vec<String> = work_modes
+            .as_ref()
+            .map(|wm| {
+                wm.get_mode_names()
+                    .into_iter()
+                    .filter(|name| Some(name.as_str()) != speed_mode_name)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let has_oscillation = device
+            .http_device_info
+            .as_ref()
+            .map(|info| info.capability_by_instance(OSCILLATION_INSTANCE).is_some())
+            .unwrap_or(false);
+
+        Ok(Self {
+            fan: FanConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: None,
+                    device_class: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("gv2mqtt-{id}-fan"),
+                    entity_category: None,
+                    icon: None,
+                },
+                // Power is handled by the general power switch handler
+                command_topic: format!("gv2mqtt/switch/{id}/command/powerSwitch"),
+                state_topic: format!("gv2mqtt/fan/{id}/state"),
+                percentage_command_topic: speed
+                    .as_ref()
+                    .map(|_| format!("gv2mqtt/fan/{id}/set-speed")),
+                percentage_state_topic: speed
+                    .as_ref()
+                    .map(|_| format!("gv2mqtt/fan/{id}/notify-speed")),
+                speed_range_min: speed.as_ref().map(|(_, range)| range.start),
+                speed_range_max: speed.as_ref().map(|(_, range)| range.end - 1),
+                preset_mode_command_topic: (!preset_modes.is_empty())
+                    .then(|| format!("gv2mqtt/{id}/set-work-mode")),
+                preset_mode_state_topic: (!preset_modes.is_empty())
+                    .then(|| format!("gv2mqtt/fan/{id}/notify-preset")),
+                preset_modes,
+                oscillation_command_topic: has_oscillation
+                    .then(|| format!("gv2mqtt/switch/{id}/command/{OSCILLATION_INSTANCE}")),
+                oscillation_state_topic: has_oscillation
+                    .then(|| switch_instance_state_topic(device, OSCILLATION_INSTANCE)),
+                payload_oscillation_on: "ON",
+                payload_oscillation_off: "OFF",
+                optimistic: !use_iot,
+            },
+            speed_mode: speed.and_then(|(mode, _)| mode.value.as_i64()),
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl EntityInstance for Fan {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        publish_entity_config("fan", state, client, &self.fan.base, &self.fan).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+
+        let is_on = device.device_state().map(|s| s.on).unwrap_or(false);
+        client
+            .publish(&self.fan.state_topic, if is_on { "ON" } else { "OFF" })
+            .await?;
+
+        let Some(cap) = device.get_state_capability_by_instance("workMode") else {
+            return Ok(());
+        };
+        let Some(mode_num) = cap
+            .state
+            .pointer("/value/workMode")
+            .and_then(|v| v.as_i64())
+        else {
+            return Ok(());
+        };
+
+        let in_speed_mode = self.speed_mode == Some(mode_num);
+
+        if in_speed_mode {
+            if let (Some(topic), Some(value)) = (
+                &self.fan.percentage_state_topic,
+                cap.state
+                    .pointer("/value/modeValue")
+                    .and_then(|v| v.as_i64()),
+            ) {
+                client.publish(topic, value.to_string()).await?;
+            }
+        }
+
+        if let Some(topic) = &self.fan.preset_mode_state_topic {
+            let preset = if in_speed_mode {
+                None
+            } else {
+                ParsedWorkMode::with_device(&device)
+                    .ok()
+                    .and_then(|wm| wm.mode_for_value(&mode_num.into()).map(|m| m.name.clone()))
+            };
+            client
+                .publish(topic, preset.as_deref().unwrap_or(NO_PRESET))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// HASS is setting the speed of the fan.
+/// The value has already been scaled by hass into the
+/// speed_range_min..=speed_range_max range that we advertised.
+pub async fn mqtt_fan_set_speed(
+    Payload(speed): Payload<i64>,
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("mqtt_fan_set_speed: {id}: {speed}");
+    let device = state.resolve_device_for_control(&id).await?;
+    set_fan_speed(&state, &device, CommandScale::Native, speed as f64).await
+}
+
+/// Something other than hass is setting the speed of the fan,
+/// in the scale given by the topic
+pub async fn mqtt_fan_set_level(
+    Payload(value): Payload<f64>,
+    Params(params): Params<IdAndScale>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    let device = state.resolve_device_for_control(&params.id).await?;
+    let scale = params.resolve_scale(&state).await;
+    log::info!("mqtt_fan_set_level: {device}: {value} as {scale:?}");
+    set_fan_speed(&state, &device, scale, value).await
+}
+
+async fn set_fan_speed(
+    state: &StateHandle,
+    device: &ServiceDevice,
+    scale: CommandScale,
+    value: f64,
+) -> anyhow::Result<()> {
+    let work_modes = ParsedWorkMode::with_device(device)?;
+    let (mode, range) =
+        speed_mode(&work_modes).ok_or_else(|| anyhow!("{device} has no fan speed mode"))?;
+
+    if scale.is_zero(value, &range) {
+        // Below the range is how hass represents 0%
+        return state.device_power_on(device, false).await;
+    }
+
+    let mode_num = mode
+        .value
+        .as_i64()
+        .ok_or_else(|| anyhow!("expected workMode to be a number"))?;
+    let speed = scale.to_native(value, &range);
+
+    state
+        .humidifier_set_parameter(device, mode_num, speed)
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform_api::{from_json, DeviceCapability};
+
+    #[test]
+    fn fan_speed_mode() {
+        let cap: DeviceCapability =
+            from_json(include_str!("../../test-data/work-mode-issue-93.json")).unwrap();
+        let wm = ParsedWorkMode::with_capability(&cap).unwrap();
+
+        let (mode, range) = speed_mode(&wm).unwrap();
+        assert_eq!(mode.name, "FanSpeed");
+        assert_eq!(range, 1..9);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::ble::TargetHumidity;
 use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
-use crate::hass_mqtt::work_mode::ParsedWorkMode;
+use crate::hass_mqtt::work_mode::{work_mode_override, ParsedWorkMode, WorkMode};
 use crate::platform_api::{DeviceParameters, DeviceType, IntegerRange};
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{availability_topic, topic_safe_id, HassClient, IdParameter};
@@ -11,9 +11,42 @@ use async_trait::async_trait;
 use mosquitto_rs::router::{Params, Payload, State};
 use serde::Serialize;
 use serde_json::json;
+use std::ops::Range;
 
 pub const DEVICE_CLASS_HUMIDITY: &str = "humidity";
 
+/// The work mode in which the device maintains a target humidity
+pub const AUTO_MODE: &str = "Auto";
+
+/// Returns true if the values of a work mode look like a range of
+/// relative humidity, rather than, say, a handful of levels
+fn is_humidity_range(range: &Range<i64>) -> bool {
+    range.start >= 20 && range.end <= 101 && range.end - range.start > 10
+}
+
+/// Returns the work mode whose value is the target humidity, if the
+/// device has one. That mode is represented by the target humidity
+/// of the humidifier entity, rather than by a number of its own.
+/// The work mode overrides can pick a different mode, or rule one out;
+/// otherwise the Auto mode is the one that targets a humidity.
+pub fn humidity_setpoint_mode<'a>(
+    device: &ServiceDevice,
+    work_modes: &'a ParsedWorkMode,
+) -> Option<&'a WorkMode> {
+    let setpoint_override =
+        |mode: &WorkMode| work_mode_override(device, &mode.name).humidity_setpoint;
+    if let Some(mode) = work_modes
+        .modes
+        .values()
+        .find(|mode| setpoint_override(mode) == Some(true))
+    {
+        return Some(mode);
+    }
+    work_modes
+        .mode_by_name(AUTO_MODE)
+        .filter(|mode| setpoint_override(mode) != Some(false))
+}
+
 /// <https://www.home-assistant.io/integrations/humidifier.mqtt>
 #[derive(Serialize, Clone, Debug)]
 pub struct HumidifierConfig {
@@ -101,6 +134,19 @@ impl Humidifier {
             .map(|wm| wm.get_mode_names())
             .unwrap_or(vec![]);
 
+        // The range of values of the setpoint mode is the
+        // range of humidity that can be targeted, if it looks
+        // like one
+        if let Some(range) = work_mode
+            .as_ref()
+            .and_then(|wm| humidity_setpoint_mode(device, wm))
+            .and_then(|mode| mode.contiguous_value_range())
+            .filter(is_humidity_range)
+        {
+            min_humidity.replace(range.start as u8);
+            max_humidity.replace(range.end.saturating_sub(1) as u8);
+        }
+
         if let Some(info) = &device.http_device_info {
             if let Some(cap) = info.capability_by_instance("humidity") {
                 match &cap.parameters {
@@ -191,7 +237,10 @@ impl EntityInstance for Humidifier {
             }
         }
 
-        if let Some(humidity) = device.target_humidity_percent {
+        if let Some(humidity) = device
+            .target_humidity_percent
+            .or_else(|| reported_setpoint(&device))
+        {
             client
                 .publish(
                     &self.humidifier.target_humidity_state_topic,
@@ -242,6 +291,21 @@ impl EntityInstance for Humidifier {
     }
 }
 
+/// Returns the target humidity reported via the platform API,
+/// if the device is in its setpoint mode
+fn reported_setpoint(device: &ServiceDevice) -> Option<u8> {
+    let work_modes = ParsedWorkMode::with_device(device).ok()?;
+    let setpoint = humidity_setpoint_mode(device, &work_modes)?;
+    let cap = device.get_state_capability_by_instance("workMode")?;
+    if cap.state.pointer("/value/workMode")? != &setpoint.value {
+        return None;
+    }
+    cap.state
+        .pointer("/value/modeValue")?
+        .as_u64()
+        .and_then(|v| u8::try_from(v).ok())
+}
+
 pub async fn mqtt_device_set_work_mode(
     Payload(mode): Payload<String>,
     Params(IdParameter { id }): Params<IdParameter>,
@@ -251,18 +315,12 @@ pub async fn mqtt_device_set_work_mode(
     let device = state.resolve_device_for_control(&id).await?;
 
     let work_modes = ParsedWorkMode::with_device(&device)?;
-    let work_mode = work_modes
-        .mode_by_name(&mode)
+    let (work_mode, value) = work_modes
+        .resolve_preset(&mode)
         .ok_or_else(|| anyhow!("mode {mode} not found"))?;
-    let mode_num = work_mode
-        .value
-        .as_i64()
-        .ok_or_else(|| anyhow::anyhow!("expected workMode to be a number"))?;
-
-    let value = work_mode.default_value();
 
     state
-        .humidifier_set_parameter(&device, mode_num, value)
+        .device_set_work_mode(&device, work_mode, value)
         .await?;
 
     Ok(())
@@ -307,14 +365,31 @@ pub async fn mqtt_humidifier_set_target(
     }
 
     let work_modes = ParsedWorkMode::with_device(&device)?;
-    let work_mode = work_modes
-        .mode_by_name("Auto")
-        .ok_or_else(|| anyhow!("mode Auto not found"))?;
+    let work_mode = humidity_setpoint_mode(&device, &work_modes)
+        .ok_or_else(|| anyhow!("{device} has no work mode for its target humidity"))?;
     let mode_num = work_mode
         .value
         .as_i64()
         .ok_or_else(|| anyhow::anyhow!("expected workMode to be a number"))?;
 
+    if !use_iot {
+        // The platform API takes the percentage as-is for the
+        // Auto mode value
+        if let (Some(client), Some(info)) = (
+            state.get_platform_client_for_control().await,
+            &device.http_device_info,
+        ) {
+            client
+                .set_work_mode(info, mode_num.into(), percent.into())
+                .await?;
+            state
+                .device_mut(&device.sku, &device.id)
+                .await
+                .set_target_humidity(percent as u8);
+            return Ok(());
+        }
+    }
+
     let value = TargetHumidity::from_percent(percent as u8);
 
     state
@@ -323,3 +398,44 @@ pub async fn mqtt_humidifier_set_target(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform_api::{from_json, DeviceCapability};
+
+    fn device(sku: &str) -> ServiceDevice {
+        ServiceDevice::new(sku, "AA:BB:CC:DD:EE:FF:42:2A")
+    }
+
+    fn work_modes(fixture: &str) -> ParsedWorkMode {
+        let cap: DeviceCapability = from_json(fixture).unwrap();
+        ParsedWorkMode::with_capability(&cap).unwrap()
+    }
+
+    #[test]
+    fn setpoint_mode() {
+        // Auto takes a humidity between 40 and 80
+        let wm = work_modes(include_str!("../../test-data/work-mode-issue-81.json"));
+        assert_eq!(
+            humidity_setpoint_mode(&device("H7141"), &wm).map(|m| m.name.as_str()),
+            Some(AUTO_MODE)
+        );
+
+        // Auto is a mode without a range, but it is still the one
+        // that targets a humidity
+        let wm = work_modes(include_str!("../../test-data/work-mode-issue-93.json"));
+        assert_eq!(
+            humidity_setpoint_mode(&device("H7141"), &wm).map(|m| m.name.as_str()),
+            Some(AUTO_MODE)
+        );
+        assert_eq!(
+            humidity_setpoint_mode(&device("H7160"), &wm).map(|m| m.name.as_str()),
+            Some(AUTO_MODE)
+        );
+
+        assert!(is_humidity_range(&(30..81)));
+        assert!(!is_humidity_range(&(1..4)));
+        assert!(!is_humidity_range(&(0..101)));
+    }
+}
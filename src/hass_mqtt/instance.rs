@@ -1,15 +1,31 @@
 use crate::hass_mqtt::base::EntityConfig;
+use crate::hass_mqtt::manifest::ManifestEntry;
+use crate::service::config::EntityNamesConfig;
 use crate::service::hass::HassClient;
 use crate::service::state::StateHandle;
 use anyhow::Context;
 use async_trait::async_trait;
 use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
 
 #[async_trait]
 pub trait EntityInstance: Send + Sync {
     async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()>;
     async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()>;
+
+    /// The platform API capability instance that this entity
+    /// represents, if it maps to exactly one
+    fn source_capability(&self) -> Option<&str> {
+        None
+    }
+
+    /// Diagnostic entities are not updated during the quiet hours
+    fn is_diagnostic(&self) -> bool {
+        false
+    }
 }
 
 pub async fn publish_entity_config<T: Serialize>(
@@ -19,20 +35,108 @@ pub async fn publish_entity_config<T: Serialize>(
     base: &EntityConfig,
     config: &T,
 ) -> anyhow::Result<()> {
-    // TODO: remember all published topics for future GC
+    if let Some(manifest) = client.manifest_recorder() {
+        manifest.lock().push(ManifestEntry {
+            unique_id: base.unique_id.clone(),
+            platform: integration.to_string(),
+            capability: None,
+        });
+        return Ok(());
+    }
 
     let disco = state.get_hass_disco_prefix().await;
-    let topic = format!(
-        "{disco}/{integration}/{unique_id}/config",
-        unique_id = base.unique_id
-    );
+    let topic = config_topic(&disco, integration, &base.unique_id);
+
+    let mut config = serde_json::to_value(config)?;
+    with_device_availability(&mut config, base);
+    with_entity_names(&mut config, base, &state.get_config().await.entity_names);
+
+    client.publish_config_obj(topic, config).await
+}
+
+/// Make the entity available only while both the bridge and its
+/// device are online. Diagnostics are left available, as they
+/// help to explain why a device is unreachable.
+fn with_device_availability(config: &mut JsonValue, base: &EntityConfig) {
+    let Some(device_topic) = &base.device.availability_topic else {
+        return;
+    };
+    if base.entity_category.as_deref() == Some("diagnostic") {
+        return;
+    }
+    let Some(config) = config.as_object_mut() else {
+        return;
+    };
+    if let Some(bridge_topic) = config.remove("availability_topic") {
+        config.insert(
+            "availability".to_string(),
+            json!([{"topic": bridge_topic}, {"topic": device_topic}]),
+        );
+        config.insert("availability_mode".to_string(), json!("all"));
+    }
+}
 
-    client.publish_obj(topic, config).await
+/// Apply the `entity_names` templates from the configuration file
+fn with_entity_names(config: &mut JsonValue, base: &EntityConfig, names: &EntityNamesConfig) {
+    let Some(config) = config.as_object_mut() else {
+        return;
+    };
+    let render = |template: &str| {
+        EntityNamesConfig::render(
+            template,
+            base.device.suggested_area.as_deref(),
+            &base.device.name,
+            base.name.as_deref(),
+        )
+    };
+    // An entity without a name is the main feature of its device,
+    // and is named for the device by hass
+    if let (Some(template), Some(_)) = (&names.name, &base.name) {
+        config.insert("name".to_string(), render(template).into());
+    }
+    if let Some(template) = &names.object_id {
+        let object_id = EntityNamesConfig::object_id(&render(template));
+        if !object_id.is_empty() {
+            config.insert("object_id".to_string(), object_id.into());
+        }
+    }
+}
+
+pub fn config_topic(disco: &str, integration: &str, unique_id: &str) -> String {
+    format!("{disco}/{integration}/{unique_id}/config")
+}
+
+/// Controls how quickly entity configs are sent to hass
+#[derive(Clone, Copy, Debug)]
+pub struct DiscoveryPacing {
+    /// The maximum number of configs to publish per second
+    pub rate: f64,
+    /// The number of configs to publish before waiting for
+    /// them to be sent
+    pub batch_size: usize,
+}
+
+impl DiscoveryPacing {
+    /// The minimum time between the start of successive batches
+    pub fn batch_interval(&self) -> Duration {
+        Duration::from_secs_f64(self.batch_size as f64 / self.rate)
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct EntityList {
     entities: Vec<Arc<dyn EntityInstance + Send + Sync + 'static>>,
+    /// The id of the device to which each of `entities` belongs,
+    /// for those that belong to a single device
+    devices: Vec<Option<String>>,
+    /// Entities that were suppressed by the `disabled_entities`
+    /// configuration; their configs are removed from hass
+    /// rather than published
+    disabled: Vec<Arc<dyn EntityInstance + Send + Sync + 'static>>,
+    /// Set when some of the entities could not be enumerated, such
+    /// as when a device's scenes couldn't be fetched, in which case
+    /// the list can't be used to decide what no longer exists
+    incomplete: bool,
 }
 
 impl EntityList {
@@ -41,29 +145,95 @@ impl EntityList {
     }
 
     pub fn add<E: EntityInstance + Send + Sync + 'static>(&mut self, e: E) {
-        self.entities.push(Arc::new(e));
+        self.push(Arc::new(e));
+    }
+
+    pub fn push(&mut self, e: Arc<dyn EntityInstance + Send + Sync + 'static>) {
+        self.entities.push(e);
+        self.devices.push(None);
+    }
+
+    /// Add the entities of the device whose id is `id`
+    pub fn extend_for_device(&mut self, id: &str, other: EntityList) {
+        self.devices
+            .extend(other.entities.iter().map(|_| Some(id.to_string())));
+        self.entities.extend(other.entities);
+        self.disabled.extend(other.disabled);
+        self.incomplete |= other.incomplete;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn EntityInstance + Send + Sync + 'static>> {
+        self.entities.iter()
+    }
+
+    pub fn push_disabled(&mut self, e: Arc<dyn EntityInstance + Send + Sync + 'static>) {
+        self.disabled.push(e);
+    }
+
+    pub fn disabled(
+        &self,
+    ) -> impl Iterator<Item = &Arc<dyn EntityInstance + Send + Sync + 'static>> {
+        self.disabled.iter()
+    }
+
+    /// Record that some entities may be missing from the list
+    pub fn mark_incomplete(&mut self) {
+        self.incomplete = true;
+    }
+
+    pub fn is_complete(&self) -> bool {
+        !self.incomplete
     }
 
     pub fn len(&self) -> usize {
         self.entities.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
     pub async fn publish_config(
         &self,
         state: &StateHandle,
         client: &HassClient,
     ) -> anyhow::Result<()> {
-        // Allow HASS time to process each entity before registering the next
-        let delay = tokio::time::Duration::from_millis(100);
-        for e in &self.entities {
-            e.publish_config(state, client)
-                .await
-                .context("EntityList::publish_config")?;
-            tokio::time::sleep(delay).await;
+        let pacing = client.discovery_pacing();
+        for batch in self.entities.chunks(pacing.batch_size) {
+            let started = Instant::now();
+            for e in batch {
+                e.publish_config(state, client)
+                    .await
+                    .context("EntityList::publish_config")?;
+            }
+            client.wait_for_configs().await;
+            // Allow HASS time to process the batch before sending the next
+            tokio::time::sleep_until(started + pacing.batch_interval()).await;
         }
         Ok(())
     }
 
+    fn retain(&mut self, keep: impl Fn(&dyn EntityInstance, Option<&str>) -> bool) {
+        let (entities, devices) = std::mem::take(&mut self.entities)
+            .into_iter()
+            .zip(std::mem::take(&mut self.devices))
+            .filter(|(e, device)| keep(e.as_ref(), device.as_deref()))
+            .unzip();
+        self.entities = entities;
+        self.devices = devices;
+    }
+
+    /// Drop the diagnostic entities from the list
+    pub fn retain_non_diagnostic(&mut self) {
+        self.retain(|e, _| !e.is_diagnostic());
+    }
+
+    /// Drop the entities of the devices for which `keep` returns
+    /// false. Entities that don't belong to a device are kept.
+    pub fn retain_devices(&mut self, keep: impl Fn(&str) -> bool) {
+        self.retain(|_, device| device.map(&keep).unwrap_or(true));
+    }
+
     pub async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
         for e in &self.entities {
             e.notify_state(client)
@@ -4,8 +4,9 @@ use crate::platform_api::DeviceType;
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{
     availability_topic, kelvin_to_mired, light_segment_state_topic, light_state_topic,
-    topic_safe_id, HassClient,
+    topic_safe_id, ColorTempUnits, HassClient,
 };
+use crate::service::quirks::NIGHTLIGHT;
 use crate::service::state::StateHandle;
 use async_trait::async_trait;
 use serde::Serialize;
@@ -40,12 +41,47 @@ pub struct LightConfig {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub effect_list: Vec<String>,
 
+    #[serde(flatten)]
+    pub color_temp: ColorTempRange,
+
+    pub payload_available: String,
+}
+
+/// The range of color temperatures that a light supports,
+/// expressed in the units that hass has been configured to use
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ColorTempRange {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub color_temp_kelvin: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_kelvin: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_kelvin: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub min_mireds: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_mireds: Option<u32>,
+}
 
-    pub payload_available: String,
+impl ColorTempRange {
+    pub fn new(units: ColorTempUnits, kelvin_range: Option<(u32, u32)>) -> Self {
+        match (units, kelvin_range) {
+            (_, None) => Self::default(),
+            (ColorTempUnits::Kelvin, Some((min, max))) => Self {
+                color_temp_kelvin: true,
+                min_kelvin: Some(min),
+                max_kelvin: Some(max),
+                ..Self::default()
+            },
+            // Note that min and max are swapped by the translation
+            // from kelvin to mired
+            (ColorTempUnits::Mired, Some((min, max))) => Self {
+                min_mireds: Some(kelvin_to_mired(max)),
+                max_mireds: Some(kelvin_to_mired(min)),
+                ..Self::default()
+            },
+        }
+    }
 }
 
 impl LightConfig {
@@ -102,7 +138,11 @@ impl EntityInstance for DeviceLight {
                             "state": "ON",
                             "color_mode": "color_temp",
                             "brightness": device_state.brightness,
-                            "color_temp": kelvin_to_mired(device_state.kelvin),
+                            "color_temp": self
+                                .state
+                                .get_color_temp_units()
+                                .await
+                                .kelvin_in_units(device_state.kelvin),
                             "effect": device_state.scene,
                         })
                     }
@@ -143,9 +183,12 @@ impl DeviceLight {
             ),
         };
 
+        let is_night_light = segment.is_none() && device.has_night_light();
+
         let icon = match segment {
             Some(_) => None,
             None if device_type == DeviceType::Light => quirk.as_ref().map(|q| q.icon.to_string()),
+            None if is_night_light => Some(NIGHTLIGHT.to_string()),
             None => None,
         };
 
@@ -163,7 +206,7 @@ impl DeviceLight {
         let effect_list = if segment.is_some() {
             vec![]
         } else {
-            match state.device_list_scenes(device).await {
+            match state.device_offered_scenes(device).await {
                 Ok(scenes) => scenes,
                 Err(err) => {
                     log::error!("Unable to list scenes for {device}: {err:#}");
@@ -180,17 +223,16 @@ impl DeviceLight {
             color_mode = true;
         }
 
-        let (min_mireds, max_mireds) = if segment.is_some() {
-            (None, None)
-        } else if let Some((min, max)) = device.get_color_temperature_range() {
-            supported_color_modes.push("color_temp".to_string());
-            color_mode = true;
-            // Note that min and max are swapped by the translation
-            // from kelvin to mired
-            (Some(kelvin_to_mired(max)), Some(kelvin_to_mired(min)))
+        let kelvin_range = if segment.is_some() {
+            None
         } else {
-            (None, None)
+            state.device_color_temperature_range(device).await
         };
+        if kelvin_range.is_some() {
+            supported_color_modes.push("color_temp".to_string());
+            color_mode = true;
+        }
+        let color_temp = ColorTempRange::new(state.get_color_temp_units().await, kelvin_range);
 
         let brightness = segment.is_some()
             || quirk
@@ -203,9 +245,17 @@ impl DeviceLight {
                 .map(|info| info.supports_brightness())
                 .unwrap_or(false);
 
+        if is_night_light && supported_color_modes.is_empty() {
+            // Some night lights are simply on or off
+            supported_color_modes.push(if brightness { "brightness" } else { "onoff" }.to_string());
+            color_mode = true;
+        }
+
         let name = match segment {
             Some(n) => Some(format!("Segment {:03}", n + 1)),
-            None if device_type == DeviceType::Humidifier => Some("Night Light".to_string()),
+            None if is_night_light || device_type == DeviceType::Humidifier => {
+                Some("Night Light".to_string())
+            }
             None => None,
         };
 
@@ -228,11 +278,10 @@ impl DeviceLight {
                 color_mode,
                 brightness,
                 brightness_scale: 100,
-                effect: true,
+                effect: !effect_list.is_empty(),
                 effect_list,
                 payload_available: "online".to_string(),
-                max_mireds,
-                min_mireds,
+                color_temp,
                 optimistic: segment.is_some(),
                 icon,
             },
@@ -241,3 +290,37 @@ impl DeviceLight {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn color_temp_range() {
+        assert_eq!(
+            serde_json::to_value(ColorTempRange::new(
+                ColorTempUnits::Kelvin,
+                Some((2000, 9000))
+            ))
+            .unwrap(),
+            json!({"color_temp_kelvin": true, "min_kelvin": 2000, "max_kelvin": 9000})
+        );
+        assert_eq!(
+            serde_json::to_value(ColorTempRange::new(
+                ColorTempUnits::Mired,
+                Some((2000, 9000))
+            ))
+            .unwrap(),
+            json!({"min_mireds": 111, "max_mireds": 500})
+        );
+        assert_eq!(
+            serde_json::to_value(ColorTempRange::new(ColorTempUnits::Kelvin, None)).unwrap(),
+            json!({})
+        );
+
+        assert_eq!(ColorTempUnits::Kelvin.to_kelvin(2700), 2700);
+        assert_eq!(ColorTempUnits::Mired.to_kelvin(370), 2702);
+        assert_eq!(ColorTempUnits::Mired.kelvin_in_units(2700), 370);
+    }
+}
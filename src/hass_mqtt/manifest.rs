@@ -0,0 +1,135 @@
+//! A retained JSON document per device that lists each of the entities
+//! that we created for it, so that other tools can discover the mapping
+//! between devices, capabilities and entities without having to scrape
+//! and correlate the discovery topics.
+use crate::hass_mqtt::enumerator::enumerate_entities_for_device;
+use crate::hass_mqtt::instance::EntityList;
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{topic_safe_id, HassClient};
+use crate::service::state::StateHandle;
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    pub unique_id: String,
+    /// The hass integration, such as `light` or `sensor`
+    pub platform: String,
+    /// The platform API capability instance that the entity
+    /// represents, if it maps to exactly one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capability: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceManifest {
+    pub device: String,
+    pub sku: String,
+    pub name: String,
+    pub entities: Vec<ManifestEntry>,
+}
+
+pub fn manifest_topic(device: &ServiceDevice) -> String {
+    format!("gv2mqtt/{id}/manifest", id = topic_safe_id(device))
+}
+
+/// Compute the manifest for a device by asking each of its entities
+/// to publish its config to a client that records them instead
+pub async fn device_manifest(
+    device: &ServiceDevice,
+    state: &StateHandle,
+    client: &HassClient,
+) -> anyhow::Result<DeviceManifest> {
+    let mut entities = EntityList::new();
+    enumerate_entities_for_device(device, state, &mut entities).await?;
+
+    let (recorder, recorded) = client.manifest_recording();
+    let mut manifest = vec![];
+    for entity in entities.iter() {
+        entity.publish_config(state, &recorder).await?;
+        for mut entry in std::mem::take(&mut *recorded.lock()) {
+            entry.capability = entity.source_capability().map(|s| s.to_string());
+            manifest.push(entry);
+        }
+    }
+
+    Ok(DeviceManifest {
+        device: device.id.to_string(),
+        sku: device.sku.to_string(),
+        name: device.name(),
+        entities: manifest,
+    })
+}
+
+pub async fn publish_device_manifests(
+    state: &StateHandle,
+    client: &HassClient,
+) -> anyhow::Result<()> {
+    for device in state.devices().await {
+        let manifest = device_manifest(&device, state, client).await?;
+        if manifest.entities.is_empty() {
+            continue;
+        }
+        client
+            .publish_retained_obj(manifest_topic(&device), &manifest)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform_api::{from_json, HttpDeviceInfo};
+    use crate::service::state::State;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn manifest_for_purifier() {
+        let state = Arc::new(State::new());
+        let info: HttpDeviceInfo = from_json(
+            r#"{
+                "sku": "H7122",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "deviceName": "Purifier",
+                "type": "devices.types.air_purifier",
+                "capabilities": [
+                    {"type": "devices.capabilities.on_off", "instance": "powerSwitch",
+                     "parameters": {"dataType": "ENUM", "options": [
+                        {"name": "on", "value": 1}, {"name": "off", "value": 0}]}},
+                    {"type": "devices.capabilities.property", "instance": "filterLifeTime"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        state
+            .device_mut(&info.sku, &info.device)
+            .await
+            .set_http_device_info(info);
+        let device = state.device_by_id("AA:BB:CC:DD:EE:FF:42:2A").await.unwrap();
+
+        let client = HassClient::for_test();
+        let manifest = device_manifest(&device, &state, &client).await.unwrap();
+
+        let find = |unique_id: &str| {
+            manifest
+                .entities
+                .iter()
+                .find(|e| e.unique_id == unique_id)
+                .cloned()
+        };
+        assert_eq!(
+            find("sensor-AABBCCDDEEFF422A-filterlifetime"),
+            Some(ManifestEntry {
+                unique_id: "sensor-AABBCCDDEEFF422A-filterlifetime".to_string(),
+                platform: "sensor".to_string(),
+                capability: Some("filterLifeTime".to_string()),
+            })
+        );
+        assert_eq!(
+            find("binary-sensor-AABBCCDDEEFF422A-filter-replacement").and_then(|e| e.capability),
+            Some("filterLifeTime".to_string())
+        );
+        // Nothing was actually published
+        assert!(client.take_published().is_empty());
+    }
+}
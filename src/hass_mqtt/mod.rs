@@ -1,14 +1,25 @@
 pub mod base;
+pub mod binary_sensor;
 pub mod button;
 pub mod climate;
 pub mod cover;
+pub mod device_settings;
 pub mod enumerator;
+pub mod event;
+pub mod fan;
 pub mod humidifier;
 pub mod instance;
 pub mod light;
+pub mod manifest;
+pub mod music;
 pub mod number;
+pub mod room;
 pub mod scene;
 pub mod select;
 pub mod sensor;
 pub mod switch;
+pub mod text;
+pub mod update;
+pub mod virtual_device;
+pub mod water_heater;
 pub mod work_mode;
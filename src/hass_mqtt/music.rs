@@ -0,0 +1,281 @@
+//! Lights that react to sound have a musicMode capability, which
+//! combines the style of the effect with how sensitive it is to
+//! the sound. These are offered as a select for the style and a
+//! number for the sensitivity; changing either one sends both.
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::EntityInstance;
+use crate::hass_mqtt::number::NumberConfig;
+use crate::hass_mqtt::select::SelectConfig;
+use crate::platform_api::{DeviceCapability, DeviceParameters};
+use crate::service::device::{Device as ServiceDevice, MusicSetting};
+use crate::service::hass::{availability_topic, topic_safe_id, HassClient, IdParameter};
+use crate::service::state::StateHandle;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use mosquitto_rs::router::{Params, Payload, State};
+
+const MUSIC_MODE_INSTANCE: &str = "musicMode";
+
+/// The sensitivity to use when none has been chosen yet
+const DEFAULT_SENSITIVITY: i64 = 100;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsedMusicMode {
+    /// The names and values of the styles
+    pub modes: Vec<(String, i64)>,
+    pub sensitivity_range: (i64, i64),
+}
+
+impl ParsedMusicMode {
+    pub fn with_device(device: &ServiceDevice) -> Option<Self> {
+        Self::with_capability(device.get_capability_by_instance(MUSIC_MODE_INSTANCE)?)
+    }
+
+    pub fn with_capability(cap: &DeviceCapability) -> Option<Self> {
+        let modes: Vec<(String, i64)> = match &cap.struct_field_by_name("musicMode")?.field_type {
+            DeviceParameters::Enum { options } => options
+                .iter()
+                .filter_map(|opt| Some((opt.name.to_string(), opt.value.as_i64()?)))
+                .collect(),
+            _ => return None,
+        };
+        if modes.is_empty() {
+            return None;
+        }
+
+        let sensitivity_range = match cap
+            .struct_field_by_name("sensitivity")
+            .map(|f| &f.field_type)
+        {
+            Some(DeviceParameters::Integer { range, .. }) => (range.min as i64, range.max as i64),
+            _ => (0, 100),
+        };
+
+        Some(Self {
+            modes,
+            sensitivity_range,
+        })
+    }
+
+    pub fn mode_by_name(&self, name: &str) -> Option<i64> {
+        self.modes
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+
+    pub fn name_for_mode(&self, value: i64) -> Option<&str> {
+        self.modes
+            .iter()
+            .find(|(_, v)| *v == value)
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn mode_names(&self) -> Vec<String> {
+        self.modes
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    /// The setting to send when only part of it has been chosen,
+    /// keeping the rest as it currently is
+    fn current_or_default(&self, current: Option<MusicSetting>) -> MusicSetting {
+        current.unwrap_or(MusicSetting {
+            mode: self.modes[0].1,
+            sensitivity: DEFAULT_SENSITIVITY
+                .clamp(self.sensitivity_range.0, self.sensitivity_range.1),
+        })
+    }
+}
+
+pub struct MusicModeSelect {
+    select: SelectConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl MusicModeSelect {
+    pub fn new(device: &ServiceDevice, state: &StateHandle, music: &ParsedMusicMode) -> Self {
+        let id = topic_safe_id(device);
+        Self {
+            select: SelectConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Music Mode".to_string()),
+                    device_class: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("gv2mqtt-{id}-music-mode"),
+                    entity_category: None,
+                    icon: Some("mdi:music".to_string()),
+                },
+                command_topic: format!("gv2mqtt/{id}/set-music-mode"),
+                state_topic: format!("gv2mqtt/{id}/notify-music-mode"),
+                options: music.mode_names(),
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for MusicModeSelect {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.select.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some(MUSIC_MODE_INSTANCE)
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+        let (Some(music), Some(setting)) = (
+            ParsedMusicMode::with_device(&device),
+            device.music_setting(),
+        ) else {
+            return Ok(());
+        };
+        match music.name_for_mode(setting.mode) {
+            Some(name) => client.publish(&self.select.state_topic, name).await,
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct MusicSensitivityNumber {
+    number: NumberConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl MusicSensitivityNumber {
+    pub fn new(device: &ServiceDevice, state: &StateHandle, music: &ParsedMusicMode) -> Self {
+        let id = topic_safe_id(device);
+        let (min, max) = music.sensitivity_range;
+        Self {
+            number: NumberConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Music Sensitivity".to_string()),
+                    device_class: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("gv2mqtt-{id}-music-sensitivity"),
+                    entity_category: None,
+                    icon: Some("mdi:microphone".to_string()),
+                },
+                command_topic: format!("gv2mqtt/number/{id}/music-sensitivity"),
+                state_topic: Some(format!("gv2mqtt/number/{id}/notify-music-sensitivity")),
+                min: Some(min as f32),
+                max: Some(max as f32),
+                step: 1f32,
+                unit_of_measurement: Some("%"),
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for MusicSensitivityNumber {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.number.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some(MUSIC_MODE_INSTANCE)
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+        match device.music_setting() {
+            Some(setting) => {
+                self.number
+                    .notify_state(client, &setting.sensitivity.to_string())
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+pub async fn mqtt_set_music_mode(
+    Payload(mode): Payload<String>,
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("mqtt_set_music_mode: {id}: {mode}");
+    let device = state.resolve_device_for_control(&id).await?;
+    let music = ParsedMusicMode::with_device(&device)
+        .ok_or_else(|| anyhow!("{device} has no music modes"))?;
+    let value = music
+        .mode_by_name(&mode)
+        .ok_or_else(|| anyhow!("music mode {mode} not found"))?;
+
+    let setting = MusicSetting {
+        mode: value,
+        ..music.current_or_default(device.music_setting())
+    };
+    state.device_set_music_mode(&device, setting).await
+}
+
+pub async fn mqtt_set_music_sensitivity(
+    Payload(sensitivity): Payload<i64>,
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("mqtt_set_music_sensitivity: {id}: {sensitivity}");
+    let device = state.resolve_device_for_control(&id).await?;
+    let music = ParsedMusicMode::with_device(&device)
+        .ok_or_else(|| anyhow!("{device} has no music modes"))?;
+
+    let setting = MusicSetting {
+        sensitivity,
+        ..music.current_or_default(device.music_setting())
+    };
+    state.device_set_music_mode(&device, setting).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value as JsonValue;
+
+    #[test]
+    fn parse_music_mode() {
+        let devices: JsonValue =
+            serde_json::from_str(include_str!("../../test-data/list_devices.json")).unwrap();
+        let cap: DeviceCapability = devices["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|info| info["capabilities"].as_array().unwrap())
+            .find(|cap| cap["instance"] == MUSIC_MODE_INSTANCE)
+            .map(|cap| serde_json::from_value(cap.clone()).unwrap())
+            .unwrap();
+        let music = ParsedMusicMode::with_capability(&cap).unwrap();
+
+        assert_eq!(music.mode_by_name("rhythm"), Some(3));
+        assert_eq!(music.name_for_mode(6), Some("Spectrum"));
+        assert_eq!(music.sensitivity_range, (0, 100));
+        assert_eq!(
+            music.current_or_default(None),
+            MusicSetting {
+                mode: 5,
+                sensitivity: 100
+            }
+        );
+    }
+}
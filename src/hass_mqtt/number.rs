@@ -1,7 +1,11 @@
 use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
+use crate::hass_mqtt::work_mode::ParsedWorkMode;
+use crate::platform_api::{DeviceCapability, DeviceParameters};
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, topic_safe_id, topic_safe_string, HassClient};
+use crate::service::hass::{
+    availability_topic, camel_case_to_space_separated, topic_safe_id, topic_safe_string, HassClient,
+};
 use crate::service::state::StateHandle;
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -29,7 +33,16 @@ pub struct NumberConfig {
 
 impl NumberConfig {
     pub async fn publish(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
-        publish_entity_config("number", state, client, &self.base, self).await
+        match state.get_config().await.number_step(&self.base.unique_id) {
+            Some(step) => {
+                let config = Self {
+                    step,
+                    ..self.clone()
+                };
+                publish_entity_config("number", state, client, &config.base, &config).await
+            }
+            None => publish_entity_config("number", state, client, &self.base, self).await,
+        }
     }
 
     pub async fn notify_state(&self, client: &HassClient, value: &str) -> anyhow::Result<()> {
@@ -59,7 +72,7 @@ impl WorkModeNumber {
         label: String,
         mode_name: &str,
         work_mode: JsonValue,
-        range: Option<Range<i64>>,
+        range: Range<i64>,
     ) -> Self {
         let command_topic = format!(
             "gv2mqtt/number/{id}/command/{mode}/{mode_num}",
@@ -97,11 +110,8 @@ impl WorkModeNumber {
                 },
                 command_topic,
                 state_topic: Some(state_topic),
-                min: range.as_ref().map(|r| r.start as f32).or(Some(0.)),
-                max: range
-                    .as_ref()
-                    .map(|r| r.end.saturating_sub(1) as f32)
-                    .or(Some(255.)),
+                min: Some(range.start as f32),
+                max: Some(range.end.saturating_sub(1) as f32),
                 step: 1f32,
                 unit_of_measurement: None,
             },
@@ -119,6 +129,10 @@ impl EntityInstance for WorkModeNumber {
         self.number.publish(&state, &client).await
     }
 
+    fn source_capability(&self) -> Option<&str> {
+        Some("workMode")
+    }
+
     async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
         let state_topic = self
             .number
@@ -167,6 +181,253 @@ impl EntityInstance for WorkModeNumber {
     }
 }
 
+/// Returns the inclusive range, the step and the unit of an
+/// integer capability
+fn integer_range(cap: &DeviceCapability) -> Option<(i64, i64, f32, Option<&'static str>)> {
+    match &cap.parameters {
+        Some(DeviceParameters::Integer { unit, range }) => Some((
+            range.min.into(),
+            range.max.into(),
+            range.step(),
+            match unit.as_deref() {
+                Some("unit.percent") => Some("%"),
+                _ => None,
+            },
+        )),
+        _ => None,
+    }
+}
+
+/// The brightness of the display on the device itself, as
+/// distinct from the brightness of any light that it has
+pub struct DisplayBrightnessNumber {
+    number: NumberConfig,
+    device_id: String,
+    state: StateHandle,
+    instance_name: String,
+}
+
+impl DisplayBrightnessNumber {
+    pub fn new(
+        device: &ServiceDevice,
+        state: &StateHandle,
+        cap: &DeviceCapability,
+    ) -> anyhow::Result<Self> {
+        let (min, max, step, unit) = integer_range(cap)
+            .ok_or_else(|| anyhow!("{} is not an integer range", cap.instance))?;
+        let id = topic_safe_id(device);
+        let instance = &cap.instance;
+
+        Ok(Self {
+            number: NumberConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Display Brightness".to_string()),
+                    device_class: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("gv2mqtt-{id}-{instance}-number"),
+                    entity_category: Some("config".to_string()),
+                    icon: Some("mdi:brightness-6".to_string()),
+                },
+                command_topic: format!("gv2mqtt/number/{id}/range/{instance}"),
+                state_topic: Some(format!("gv2mqtt/number/{id}/state/{instance}")),
+                min: Some(min as f32),
+                max: Some(max as f32),
+                step,
+                unit_of_measurement: unit,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+            instance_name: instance.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl EntityInstance for DisplayBrightnessNumber {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.number.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance_name)
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+
+        if let Some(value) = device
+            .get_state_capability_by_instance(&self.instance_name)
+            .and_then(|cap| cap.state.pointer("/value")?.as_i64())
+        {
+            self.number.notify_state(client, &value.to_string()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The range capabilities that turn the device off after a delay,
+/// such as the auto-off timer of heaters and fans
+const COUNTDOWN_INSTANCES: &[&str] = &["autoOffTimer"];
+
+pub fn is_countdown_instance(instance: &str) -> bool {
+    COUNTDOWN_INSTANCES.contains(&instance)
+}
+
+/// The number of seconds in each unit of a countdown capability.
+/// Countdowns are taken to be in minutes unless they say otherwise.
+pub fn countdown_unit_seconds(cap: &DeviceCapability) -> i64 {
+    match &cap.parameters {
+        Some(DeviceParameters::Integer {
+            unit: Some(unit), ..
+        }) => match unit.as_str() {
+            "unit.second" | "unit.seconds" => 1,
+            "unit.hour" | "unit.hours" => 3600,
+            _ => 60,
+        },
+        _ => 60,
+    }
+}
+
+/// Converts a number of minutes to the units of a countdown capability,
+/// rounding up so that the device doesn't turn off sooner than asked
+fn minutes_to_countdown(minutes: i64, unit_seconds: i64) -> anyhow::Result<i64> {
+    anyhow::ensure!(minutes >= 0, "{minutes} is not a valid number of minutes");
+    let seconds = minutes * 60;
+    Ok((seconds + unit_seconds - 1) / unit_seconds)
+}
+
+/// How long until the device turns itself off, in minutes
+pub struct CountdownNumber {
+    number: NumberConfig,
+    device_id: String,
+    state: StateHandle,
+    instance_name: String,
+    unit_seconds: i64,
+}
+
+impl CountdownNumber {
+    pub fn new(
+        device: &ServiceDevice,
+        state: &StateHandle,
+        cap: &DeviceCapability,
+    ) -> anyhow::Result<Self> {
+        let (min, max, _step, _unit) = integer_range(cap)
+            .ok_or_else(|| anyhow!("{} is not an integer range", cap.instance))?;
+        let unit_seconds = countdown_unit_seconds(cap);
+        let to_minutes = |value: i64| (value * unit_seconds) as f32 / 60.;
+        let id = topic_safe_id(device);
+        let instance = &cap.instance;
+
+        Ok(Self {
+            number: NumberConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some(format!(
+                        "{} Minutes",
+                        camel_case_to_space_separated(instance)
+                    )),
+                    device_class: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("gv2mqtt-{id}-{instance}-countdown"),
+                    entity_category: Some("config".to_string()),
+                    icon: Some("mdi:timer-cog-outline".to_string()),
+                },
+                command_topic: format!("gv2mqtt/number/{id}/countdown/{instance}"),
+                state_topic: Some(format!("gv2mqtt/number/{id}/countdown-state/{instance}")),
+                min: Some(to_minutes(min)),
+                max: Some(to_minutes(max)),
+                step: 1.,
+                unit_of_measurement: Some("min"),
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+            instance_name: instance.to_string(),
+            unit_seconds,
+        })
+    }
+}
+
+#[async_trait]
+impl EntityInstance for CountdownNumber {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.number.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance_name)
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+
+        if let Some(value) = device
+            .get_state_capability_by_instance(&self.instance_name)
+            .and_then(|cap| cap.state.pointer("/value")?.as_i64())
+        {
+            let minutes = (value * self.unit_seconds) as f64 / 60.;
+            self.number
+                .notify_state(client, &format!("{minutes:.0}"))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Sets a countdown capability from a number of minutes
+pub async fn mqtt_countdown_command(
+    Payload(minutes): Payload<i64>,
+    Params(IdAndInstance { id, instance }): Params<IdAndInstance>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("{instance} for {id}: {minutes} minutes");
+    let device = state.resolve_device_for_control(&id).await?;
+    let cap = device
+        .get_capability_by_instance(&instance)
+        .ok_or_else(|| anyhow!("{device} has no {instance}"))?;
+
+    let value = minutes_to_countdown(minutes, countdown_unit_seconds(cap))?;
+    // The platform API rejects values outside of the range
+    let value = cap.clamp_value(value.into());
+
+    state.device_control(&device, cap, value).await
+}
+
+#[derive(Deserialize)]
+pub struct IdAndInstance {
+    id: String,
+    instance: String,
+}
+
+/// Sets the value of an integer range capability, such as
+/// the brightness of the display
+pub async fn mqtt_range_command(
+    Payload(value): Payload<i64>,
+    Params(IdAndInstance { id, instance }): Params<IdAndInstance>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("{instance} for {id}: {value}");
+    let device = state.resolve_device_for_control(&id).await?;
+    let cap = device
+        .get_capability_by_instance(&instance)
+        .ok_or_else(|| anyhow!("{device} has no {instance}"))?;
+
+    // The platform API rejects values outside of the range
+    let value = cap.clamp_value(value.into());
+
+    state.device_control(&device, cap, value).await
+}
+
 #[derive(Deserialize)]
 pub struct IdAndModeName {
     id: String,
@@ -187,9 +448,101 @@ pub async fn mqtt_number_command(
     let work_mode: i64 = work_mode.parse()?;
     let device = state.resolve_device_for_control(&id).await?;
 
+    // Keep the value within the range that the device advertises,
+    // as the platform API rejects the whole command otherwise
+    let range = ParsedWorkMode::with_device(&device).ok().and_then(|modes| {
+        modes
+            .mode_for_value(&work_mode.into())
+            .and_then(|mode| mode.contiguous_value_range())
+    });
+    let value = match range {
+        Some(range) if !range.is_empty() => value.clamp(range.start, range.end - 1),
+        _ => value,
+    };
+
     state
         .humidifier_set_parameter(&device, work_mode, value)
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::service::config::ServiceConfig;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn step_from_precision_and_config() {
+        let state = Arc::new(crate::service::state::State::new());
+        state
+            .set_hass_disco_prefix("homeassistant".to_string())
+            .await;
+        let device = ServiceDevice::new("H5112", "AA:BB:CC:DD:EE:FF:42:2A");
+        let cap: DeviceCapability = serde_json::from_value(json!({
+            "type": "devices.capabilities.range",
+            "instance": "displayBrightness",
+            "parameters": {"dataType": "INTEGER", "unit": "unit.percent",
+                "range": {"min": 0, "max": 100, "precision": 5}}
+        }))
+        .unwrap();
+        let number = DisplayBrightnessNumber::new(&device, &state, &cap).unwrap();
+        assert_eq!(number.number.step, 5.0);
+
+        state
+            .set_config(
+                ServiceConfig::parse(
+                    r#"{"number_steps": [
+                        {"unique_id": "gv2mqtt-AABBCCDDEEFF422A-displayBrightness-number",
+                         "step": 10}]}"#,
+                )
+                .unwrap(),
+            )
+            .await;
+        let client = HassClient::for_test();
+        number.publish_config(&state, &client).await.unwrap();
+        let published = client.take_published();
+        let config: JsonValue = serde_json::from_slice(&published[0].1).unwrap();
+        assert_eq!(config["step"], json!(10.0));
+    }
+
+    #[test]
+    fn countdown() {
+        let state = Arc::new(crate::service::state::State::new());
+        let device = ServiceDevice::new("H7130", "AA:BB:CC:DD:EE:FF:42:2A");
+        let cap: DeviceCapability = serde_json::from_value(json!({
+            "type": "devices.capabilities.range",
+            "instance": "autoOffTimer",
+            "parameters": {"dataType": "INTEGER", "unit": "unit.hour",
+                "range": {"min": 0, "max": 12, "precision": 1}}
+        }))
+        .unwrap();
+        assert!(is_countdown_instance(&cap.instance));
+        assert!(!is_countdown_instance("displayBrightness"));
+        assert!(!is_countdown_instance("timerEnabled"));
+        assert_eq!(countdown_unit_seconds(&cap), 3600);
+
+        let number = CountdownNumber::new(&device, &state, &cap).unwrap();
+        assert_eq!(number.number.max, Some(720.0));
+        assert_eq!(
+            number.number.base.name.as_deref(),
+            Some("Auto Off Timer Minutes")
+        );
+    }
+
+    #[test]
+    fn countdown_conversion() {
+        // Hours
+        assert_eq!(minutes_to_countdown(0, 3600).unwrap(), 0);
+        assert_eq!(minutes_to_countdown(60, 3600).unwrap(), 1);
+        assert_eq!(minutes_to_countdown(90, 3600).unwrap(), 2);
+        assert_eq!(minutes_to_countdown(1, 3600).unwrap(), 1);
+        // Minutes
+        assert_eq!(minutes_to_countdown(45, 60).unwrap(), 45);
+        // Seconds
+        assert_eq!(minutes_to_countdown(2, 1).unwrap(), 120);
+        assert!(minutes_to_countdown(-5, 60).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance, EntityList};
+use crate::hass_mqtt::number::NumberConfig;
+use crate::hass_mqtt::switch::SwitchConfig;
+use crate::service::composition::{room_lights, room_slugs, VirtualDevice};
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{
+    apply_light_command, availability_topic, HassClient, HassLightCommand, IdParameter,
+};
+use crate::service::state::StateHandle;
+use async_trait::async_trait;
+use mosquitto_rs::router::{Params, Payload, State};
+
+fn room_topic(slug: &str, entity: &str, kind: &str) -> String {
+    format!("gv2mqtt/room/{slug}/{entity}/{kind}")
+}
+
+fn room_entity_config(
+    room: &str,
+    slug: &str,
+    entity: &str,
+    name: &str,
+    icon: &str,
+) -> EntityConfig {
+    EntityConfig {
+        availability_topic: availability_topic(),
+        name: Some(name.to_string()),
+        device_class: None,
+        origin: Origin::default(),
+        device: Device::for_room(room, slug),
+        unique_id: format!("gv2mqtt-room-{slug}-{entity}"),
+        entity_category: None,
+        icon: Some(icon.to_string()),
+    }
+}
+
+/// A switch that turns all of the lights in a room on or off
+pub struct RoomLightsSwitch {
+    switch: SwitchConfig,
+    room: String,
+    state: StateHandle,
+}
+
+impl RoomLightsSwitch {
+    pub fn new(room: &str, slug: &str, state: &StateHandle) -> Self {
+        Self {
+            switch: SwitchConfig {
+                base: room_entity_config(room, slug, "power", "All Lights", "mdi:lightbulb-group"),
+                command_topic: room_topic(slug, "power", "command"),
+                state_topic: room_topic(slug, "power", "state"),
+            },
+            room: room.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for RoomLightsSwitch {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.switch.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let lights = room_lights(&self.state)
+            .await
+            .remove(&self.room)
+            .unwrap_or_default();
+        let composite = VirtualDevice::composite_state(&lights);
+        client
+            .publish(
+                &self.switch.state_topic,
+                if composite.on { "ON" } else { "OFF" },
+            )
+            .await
+    }
+}
+
+/// A number that sets the brightness of all of the lights in a room
+pub struct RoomBrightness {
+    number: NumberConfig,
+    room: String,
+    state: StateHandle,
+}
+
+impl RoomBrightness {
+    pub fn new(room: &str, slug: &str, state: &StateHandle) -> Self {
+        Self {
+            number: NumberConfig {
+                base: room_entity_config(
+                    room,
+                    slug,
+                    "brightness",
+                    "Brightness",
+                    "mdi:brightness-6",
+                ),
+                command_topic: room_topic(slug, "brightness", "command"),
+                state_topic: Some(room_topic(slug, "brightness", "state")),
+                min: Some(1.),
+                max: Some(100.),
+                step: 1.,
+                unit_of_measurement: Some("%"),
+            },
+            room: room.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for RoomBrightness {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        publish_entity_config("number", state, client, &self.number.base, &self.number).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let lights = room_lights(&self.state)
+            .await
+            .remove(&self.room)
+            .unwrap_or_default();
+        let composite = VirtualDevice::composite_state(&lights);
+        if composite.on {
+            self.number
+                .notify_state(client, &composite.brightness.to_string())
+                .await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Adds the entities for each room that has more than one light
+pub async fn enumerate_room_entities(state: &StateHandle, entities: &mut EntityList) {
+    let rooms = room_lights(state).await;
+    let slugs = room_slugs(&rooms);
+    for (room, lights) in &rooms {
+        let slug = &slugs[room];
+        entities.add(RoomLightsSwitch::new(room, slug, state));
+        if VirtualDevice::supports_brightness(lights) {
+            entities.add(RoomBrightness::new(room, slug, state));
+        }
+    }
+}
+
+/// Publish the state of the room entities for the room
+/// in which the specified device is located
+pub async fn notify_room_of_state_change(
+    state: &StateHandle,
+    client: &HassClient,
+    device: &ServiceDevice,
+) -> anyhow::Result<()> {
+    let Some(room) = device.room_name() else {
+        return Ok(());
+    };
+    let rooms = room_lights(state).await;
+    let (Some(lights), Some(slug)) = (rooms.get(room), room_slugs(&rooms).remove(room)) else {
+        return Ok(());
+    };
+    RoomLightsSwitch::new(room, &slug, state)
+        .notify_state(client)
+        .await?;
+    if VirtualDevice::supports_brightness(lights) {
+        RoomBrightness::new(room, &slug, state)
+            .notify_state(client)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Apply a light command to each of the lights in the room
+/// whose slug is `slug`, concurrently. Each light
+/// is controlled via the LAN API where it is available.
+async fn apply_room_command(
+    state: &StateHandle,
+    slug: &str,
+    command: HassLightCommand,
+) -> anyhow::Result<()> {
+    let mut rooms = room_lights(state).await;
+    let room = room_slugs(&rooms)
+        .into_iter()
+        .find_map(|(room, room_slug)| (room_slug == slug).then_some(room))
+        .ok_or_else(|| anyhow::anyhow!("room {slug} not found"))?;
+    let lights: Vec<ServiceDevice> = rooms.remove(&room).unwrap_or_default();
+
+    let results = futures_util::future::join_all(lights.iter().map(|light| {
+        let state = state.clone();
+        let command = command.clone();
+        async move {
+            let light = state.resolve_device_for_control(&light.id).await?;
+            apply_light_command(&state, &light, &command).await
+        }
+    }))
+    .await;
+
+    let mut failures = vec![];
+    for (light, result) in lights.iter().zip(results) {
+        if let Err(err) = result {
+            failures.push(format!("{light}: {err:#}"));
+        }
+    }
+    if !failures.is_empty() {
+        anyhow::bail!("room {room} partially failed: {}", failures.join(", "));
+    }
+
+    Ok(())
+}
+
+/// HASS is turning all of the lights in a room on or off
+pub async fn mqtt_room_power_command(
+    Payload(payload): Payload<String>,
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("Power command for room {id}: {payload}");
+    let on = match payload.as_str() {
+        "ON" | "on" => true,
+        "OFF" | "off" => false,
+        _ => anyhow::bail!("invalid {payload} for room {id}"),
+    };
+    apply_room_command(
+        &state,
+        &id,
+        HassLightCommand {
+            state: if on { "ON" } else { "OFF" }.to_string(),
+            color_temp: None,
+            color: None,
+            effect: None,
+            brightness: None,
+            transition: None,
+        },
+    )
+    .await
+}
+
+/// HASS is setting the brightness of all of the lights in a room
+pub async fn mqtt_room_brightness_command(
+    Payload(brightness): Payload<f64>,
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("Brightness command for room {id}: {brightness}");
+    apply_room_command(
+        &state,
+        &id,
+        HassLightCommand {
+            state: "ON".to_string(),
+            color_temp: None,
+            color: None,
+            effect: None,
+            brightness: Some(brightness.round().clamp(0., 100.) as u8),
+            transition: None,
+        },
+    )
+    .await
+}
@@ -1,6 +1,7 @@
 use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
-use crate::hass_mqtt::work_mode::ParsedWorkMode;
+use crate::hass_mqtt::work_mode::{ParsedWorkMode, WorkMode};
+use crate::platform_api::DeviceType;
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{availability_topic, topic_safe_id, HassClient, IdParameter};
 use crate::service::state::StateHandle;
@@ -28,22 +29,36 @@ impl SelectConfig {
 
 pub struct WorkModeSelect {
     select: SelectConfig,
+    /// Whether the options are the labels of the modes, rather than
+    /// their names
+    use_labels: bool,
     device_id: String,
     state: StateHandle,
 }
 
 impl WorkModeSelect {
-    pub fn new(device: &ServiceDevice, work_modes: &ParsedWorkMode, state: &StateHandle) -> Self {
+    pub fn new(
+        device: &ServiceDevice,
+        work_modes: &ParsedWorkMode,
+        use_labels: bool,
+        state: &StateHandle,
+    ) -> Self {
         let command_topic = format!("gv2mqtt/{id}/set-work-mode", id = topic_safe_id(device),);
         let state_topic = format!("gv2mqtt/{id}/notify-work-mode", id = topic_safe_id(device));
         let availability_topic = availability_topic();
         let unique_id = format!("gv2mqtt-{id}-workMode", id = topic_safe_id(device),);
+        // The work modes of an ice maker are the sizes of ice it makes
+        let name = if device.device_type() == DeviceType::IceMaker {
+            "Nugget Size"
+        } else {
+            "Mode"
+        };
 
         Self {
             select: SelectConfig {
                 base: EntityConfig {
                     availability_topic,
-                    name: Some("Mode".to_string()),
+                    name: Some(name.to_string()),
                     device_class: None,
                     origin: Origin::default(),
                     device: Device::for_device(device),
@@ -53,20 +68,39 @@ impl WorkModeSelect {
                 },
                 command_topic,
                 state_topic,
-                options: work_modes.get_mode_names(),
+                options: if use_labels {
+                    work_modes.get_mode_labels()
+                } else {
+                    work_modes.get_mode_names()
+                },
             },
+            use_labels,
             device_id: device.id.to_string(),
             state: state.clone(),
         }
     }
 }
 
+impl WorkModeSelect {
+    fn option_for(&self, mode: &WorkMode) -> String {
+        if self.use_labels {
+            mode.label().to_string()
+        } else {
+            mode.name.to_string()
+        }
+    }
+}
+
 #[async_trait]
 impl EntityInstance for WorkModeSelect {
     async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
         self.select.publish(&state, &client).await
     }
 
+    fn source_capability(&self) -> Option<&str> {
+        Some("workMode")
+    }
+
     async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
         let device = self
             .state
@@ -79,7 +113,7 @@ impl EntityInstance for WorkModeSelect {
                 let mode_value_json = json!(mode_value);
                 if let Some(mode) = work_mode.mode_for_value(&mode_value_json) {
                     client
-                        .publish(&self.select.state_topic, mode.name.to_string())
+                        .publish(&self.select.state_topic, self.option_for(mode))
                         .await?;
                 }
             }
@@ -90,7 +124,7 @@ impl EntityInstance for WorkModeSelect {
                 if let Some(mode_num) = cap.state.pointer("/value/workMode") {
                     if let Some(mode) = work_modes.mode_for_value(mode_num) {
                         return client
-                            .publish(&self.select.state_topic, mode.name.to_string())
+                            .publish(&self.select.state_topic, self.option_for(mode))
                             .await;
                     }
                 }
@@ -108,21 +142,61 @@ pub struct SceneModeSelect {
 
 impl SceneModeSelect {
     pub async fn new(device: &ServiceDevice, state: &StateHandle) -> anyhow::Result<Option<Self>> {
-        let scenes = state.device_list_scenes(device).await?;
+        let scenes = state.device_offered_scenes(device).await?;
+        // Lights also offer their scenes as effects, but a select is
+        // easier to drive from dashboards and automations
+        let name = if device.device_type() == DeviceType::Light {
+            "Scene"
+        } else {
+            "Mode/Scene"
+        };
+        Ok(Self::with_options(
+            device,
+            state,
+            name,
+            "mode-scene",
+            scenes,
+        ))
+    }
+
+    /// The DIY scenes that the user created in the Govee app, which
+    /// would otherwise be hard to find amongst the stock scenes
+    pub async fn diy(device: &ServiceDevice, state: &StateHandle) -> anyhow::Result<Option<Self>> {
+        let mut scenes = state.device_list_diy_scenes(device).await?;
+        if !scenes.is_empty() {
+            // Reported when some other scene is active
+            scenes.insert(0, String::new());
+        }
+        Ok(Self::with_options(
+            device,
+            state,
+            "DIY Scene",
+            "diy-scene",
+            scenes,
+        ))
+    }
+
+    fn with_options(
+        device: &ServiceDevice,
+        state: &StateHandle,
+        name: &str,
+        slug: &str,
+        scenes: Vec<String>,
+    ) -> Option<Self> {
         if scenes.is_empty() {
-            return Ok(None);
+            return None;
         }
 
         let command_topic = format!("gv2mqtt/{id}/set-mode-scene", id = topic_safe_id(device));
-        let state_topic = format!("gv2mqtt/{id}/notify-mode-scene", id = topic_safe_id(device));
+        let state_topic = format!("gv2mqtt/{id}/notify-{slug}", id = topic_safe_id(device));
         let availability_topic = availability_topic();
-        let unique_id = format!("gv2mqtt-{id}-mode-scene", id = topic_safe_id(device));
+        let unique_id = format!("gv2mqtt-{id}-{slug}", id = topic_safe_id(device));
 
-        Ok(Some(Self {
+        Some(Self {
             select: SelectConfig {
                 base: EntityConfig {
                     availability_topic,
-                    name: Some("Mode/Scene".to_string()),
+                    name: Some(name.to_string()),
                     device_class: None,
                     origin: Origin::default(),
                     device: Device::for_device(device),
@@ -136,7 +210,7 @@ impl SceneModeSelect {
             },
             device_id: device.id.to_string(),
             state: state.clone(),
-        }))
+        })
     }
 }
 
@@ -154,12 +228,12 @@ impl EntityInstance for SceneModeSelect {
             .expect("device to exist");
 
         if let Some(device_state) = device.device_state() {
-            client
-                .publish(
-                    &self.select.state_topic,
-                    device_state.scene.as_deref().unwrap_or(""),
-                )
-                .await?;
+            let scene = device_state
+                .scene
+                .as_deref()
+                .filter(|scene| self.select.options.iter().any(|s| s == scene))
+                .unwrap_or("");
+            client.publish(&self.select.state_topic, scene).await?;
         }
 
         Ok(())
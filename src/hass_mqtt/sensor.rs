@@ -1,17 +1,24 @@
-use crate::commands::serve::POLL_INTERVAL;
 use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
 use crate::hass_mqtt::humidifier::DEVICE_CLASS_HUMIDITY;
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
-use crate::platform_api::DeviceCapability;
-use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, topic_safe_id, topic_safe_string, HassClient};
+use crate::hass_mqtt::number::countdown_unit_seconds;
+use crate::hass_mqtt::work_mode::ParsedWorkMode;
+use crate::platform_api::{DeviceCapability, DeviceParameters, PLATFORM_API_DAILY_QUOTA};
+use crate::service::device::{Device as ServiceDevice, PowerState};
+use crate::service::hass::{
+    availability_topic, camel_case_to_space_separated, topic_safe_id, topic_safe_string, HassClient,
+};
+use crate::service::identity::global_unique_id;
+use crate::service::polling::{device_poll_interval, state_poll_interval};
 use crate::service::quirks::HumidityUnits;
 use crate::service::state::StateHandle;
-use crate::temperature::{TemperatureUnits, TemperatureValue, DEVICE_CLASS_TEMPERATURE};
+use crate::temperature::{
+    TemperatureScale, TemperatureUnits, TemperatureValue, DEVICE_CLASS_TEMPERATURE,
+};
 use async_trait::async_trait;
 use chrono::Utc;
 use serde::Serialize;
-use serde_json::json;
+use serde_json::{json, Value as JsonValue};
 
 #[derive(Serialize, Clone, Debug)]
 pub struct SensorConfig {
@@ -25,6 +32,74 @@ pub struct SensorConfig {
     pub unit_of_measurement: Option<&'static str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub json_attributes_topic: Option<String>,
+    /// The possible states of an `enum` sensor
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+    /// Whether hass should enable the entity when it is first
+    /// discovered; hass assumes that it should when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled_by_default: Option<bool>,
+}
+
+pub const DEVICE_CLASS_PM25: &str = "pm25";
+pub const DEVICE_CLASS_ENUM: &str = "enum";
+pub const DEVICE_CLASS_SIGNAL_STRENGTH: &str = "signal_strength";
+
+/// The qualitative air quality level reported by purifiers
+const AIR_QUALITY_INSTANCE: &str = "airQuality";
+
+fn is_pm25_instance(instance: &str) -> bool {
+    matches!(instance, "sensorPM25" | "pm25")
+}
+
+/// The remaining life of the filter, as a percentage
+pub fn is_filter_life_instance(instance: &str) -> bool {
+    matches!(instance, "filterLifeTime" | "filterLife")
+}
+
+pub const DEVICE_CLASS_POWER: &str = "power";
+pub const DEVICE_CLASS_VOLTAGE: &str = "voltage";
+pub const DEVICE_CLASS_CURRENT: &str = "current";
+
+/// The name, device class and unit of the instantaneous electrical
+/// readings reported by plugs that monitor their consumption
+fn electrical_reading(instance: &str) -> Option<(&'static str, &'static str, &'static str)> {
+    match instance {
+        "power" | "electricPower" | "sensorPower" => Some(("Power", DEVICE_CLASS_POWER, "W")),
+        "voltage" | "electricVoltage" | "sensorVoltage" => {
+            Some(("Voltage", DEVICE_CLASS_VOLTAGE, "V"))
+        }
+        "current" | "electricCurrent" | "sensorCurrent" => {
+            Some(("Current", DEVICE_CLASS_CURRENT, "A"))
+        }
+        _ => None,
+    }
+}
+
+/// The temperature reported by the sensorTemperature capability,
+/// such as the water temperature of a kettle, in `scale`
+pub fn sensor_temperature(device: &ServiceDevice, scale: TemperatureScale) -> Option<f64> {
+    let cap = device.get_state_capability_by_instance("sensorTemperature")?;
+    let units = device
+        .resolve_quirk()
+        .and_then(|q| q.platform_temperature_sensor_units)
+        .unwrap_or(TemperatureUnits::Celsius);
+    let value = cap.state.pointer("/value").and_then(|v| v.as_f64())?;
+    Some(
+        TemperatureValue::new(value, units)
+            .as_unit(scale.into())
+            .value(),
+    )
+}
+
+/// Extract a numeric reading from a capability state, which may either
+/// be the value itself, or an object holding the value
+pub fn numeric_value(state: &JsonValue) -> Option<f64> {
+    match state.pointer("/value")? {
+        JsonValue::Number(n) => n.as_f64(),
+        JsonValue::Object(map) => map.values().find_map(|v| v.as_f64()),
+        _ => None,
+    }
 }
 
 #[allow(unused)]
@@ -68,7 +143,7 @@ impl EntityInstance for GlobalFixedDiagnostic {
 impl GlobalFixedDiagnostic {
     pub fn new<NAME: Into<String>, VALUE: Into<String>>(name: NAME, value: VALUE) -> Self {
         let name = name.into();
-        let unique_id = format!("global-{}", topic_safe_string(&name));
+        let unique_id = global_unique_id(&topic_safe_string(&name));
 
         Self {
             sensor: SensorConfig {
@@ -86,12 +161,182 @@ impl GlobalFixedDiagnostic {
                 state_class: None,
                 unit_of_measurement: None,
                 json_attributes_topic: None,
+                options: vec![],
+                enabled_by_default: None,
             },
             value: value.into(),
         }
     }
 }
 
+/// How many platform API requests were made in the last 24 hours,
+/// so that users can see how close they are to the daily quota
+#[derive(Clone)]
+pub struct PlatformApiUsageDiagnostic {
+    sensor: SensorConfig,
+    state: StateHandle,
+}
+
+impl PlatformApiUsageDiagnostic {
+    pub fn new(state: &StateHandle) -> Self {
+        let unique_id = global_unique_id("platform-api-requests");
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Platform API Requests (24h)".to_string()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::this_service(),
+                    unique_id: unique_id.clone(),
+                    device_class: None,
+                    icon: Some("mdi:api".to_string()),
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: Some(StateClass::Measurement),
+                unit_of_measurement: Some("requests"),
+                json_attributes_topic: Some(format!("gv2mqtt/sensor/{unique_id}/attributes")),
+                options: vec![],
+                enabled_by_default: None,
+            },
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for PlatformApiUsageDiagnostic {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let requests = self.state.platform_api_requests_last_day();
+        self.sensor
+            .notify_state(client, &requests.to_string())
+            .await?;
+        if let Some(topic) = &self.sensor.json_attributes_topic {
+            client
+                .publish_obj(
+                    topic,
+                    json!({
+                        "daily_quota": PLATFORM_API_DAILY_QUOTA,
+                        "quota_used_percent":
+                            (requests * 100) as f64 / PLATFORM_API_DAILY_QUOTA as f64,
+                    }),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the devices that are reachable via each of the ways
+/// that we can talk to them; a device may be counted under several.
+/// Devices count towards IoT when the Govee device list gives them
+/// an IoT topic.
+pub fn devices_by_transport(devices: &[ServiceDevice]) -> JsonValue {
+    let count = |f: &dyn Fn(&ServiceDevice) -> bool| devices.iter().filter(|d| f(d)).count();
+    json!({
+        "lan": count(&|d| d.lan_device.is_some()),
+        "iot": count(&|d| {
+            d.undoc_device_info
+                .as_ref()
+                .is_some_and(|info| info.entry.device_topic().is_ok())
+        }),
+        "platform_api": count(&|d| d.http_device_info.is_some()),
+        "undocumented_api": count(&|d| d.undoc_device_info.is_some()),
+        "ble_only": count(&|d| d.is_ble_only_device() == Some(true)),
+    })
+}
+
+/// A summary of the health of the bridge, for those who don't
+/// run Prometheus. The state is the number of devices, and the
+/// details are in the attributes.
+pub struct BridgeStatisticsDiagnostic {
+    sensor: SensorConfig,
+    state: StateHandle,
+}
+
+impl BridgeStatisticsDiagnostic {
+    pub fn new(state: &StateHandle) -> Self {
+        let unique_id = global_unique_id("statistics");
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Statistics".to_string()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::this_service(),
+                    unique_id: unique_id.clone(),
+                    device_class: None,
+                    icon: Some("mdi:chart-box-outline".to_string()),
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: Some(StateClass::Measurement),
+                unit_of_measurement: Some("devices"),
+                json_attributes_topic: Some(format!("gv2mqtt/sensor/{unique_id}/attributes")),
+                options: vec![],
+                enabled_by_default: None,
+            },
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for BridgeStatisticsDiagnostic {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let devices = self.state.devices().await;
+        self.sensor
+            .notify_state(client, &devices.len().to_string())
+            .await?;
+        if let Some(topic) = &self.sensor.json_attributes_topic {
+            let publish = client.publish_stats();
+            client
+                .publish_obj(
+                    topic,
+                    json!({
+                        "devices_by_transport": devices_by_transport(&devices),
+                        "platform_api_requests_last_hour":
+                            self.state.platform_api_requests_last_hour(),
+                        "platform_api_requests_last_day":
+                            self.state.platform_api_requests_last_day(),
+                        "errors": {
+                            "platform_api": self.state.platform_api_errors(),
+                            "mqtt_publish_failed": publish.failed,
+                            "mqtt_publish_dropped": publish.dropped,
+                        },
+                        "queues": {
+                            "mqtt_publish": publish.queued,
+                        },
+                    }),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Thermo-hygrometers, whose temperature and humidity readings are
+/// their primary purpose
+const THERMO_HYGROMETER_SKUS: &[&str] = &["H5075", "H5100", "H5179"];
+
 #[derive(Clone)]
 pub struct CapabilitySensor {
     sensor: SensorConfig,
@@ -112,21 +357,40 @@ impl CapabilitySensor {
             inst = topic_safe_string(&instance.instance)
         );
 
+        // If the air quality is reported as one of a set of named
+        // levels, present it as an enum sensor with those levels
+        let air_quality_levels: Vec<String> = match &instance.parameters {
+            Some(DeviceParameters::Enum { options })
+                if instance.instance == AIR_QUALITY_INSTANCE =>
+            {
+                options.iter().map(|opt| opt.name.to_string()).collect()
+            }
+            _ => vec![],
+        };
+
         let unit_of_measurement = match instance.instance.as_str() {
             "sensorTemperature" => Some(state.get_temperature_scale().await.unit_of_measurement()),
             "sensorHumidity" => Some("%"),
-            _ => None,
+            inst if is_pm25_instance(inst) => Some("µg/m³"),
+            inst if is_filter_life_instance(inst) => Some("%"),
+            inst => electrical_reading(inst).map(|(_, _, unit)| unit),
         };
 
         let device_class = match instance.instance.as_str() {
             "sensorTemperature" => Some(DEVICE_CLASS_TEMPERATURE),
             "sensorHumidity" => Some(DEVICE_CLASS_HUMIDITY),
-            _ => None,
+            inst if is_pm25_instance(inst) => Some(DEVICE_CLASS_PM25),
+            AIR_QUALITY_INSTANCE if !air_quality_levels.is_empty() => Some(DEVICE_CLASS_ENUM),
+            inst => electrical_reading(inst).map(|(_, class, _)| class),
         };
 
         let state_class = match instance.instance.as_str() {
             "sensorTemperature" => Some(StateClass::Measurement),
             "sensorHumidity" => Some(StateClass::Measurement),
+            inst if is_pm25_instance(inst) => Some(StateClass::Measurement),
+            inst if is_filter_life_instance(inst) => Some(StateClass::Measurement),
+            AIR_QUALITY_INSTANCE if air_quality_levels.is_empty() => Some(StateClass::Measurement),
+            inst if electrical_reading(inst).is_some() => Some(StateClass::Measurement),
             _ => None,
         };
 
@@ -134,7 +398,36 @@ impl CapabilitySensor {
             "sensorTemperature" => "Temperature".to_string(),
             "sensorHumidity" => "Humidity".to_string(),
             "online" => "Connected to Govee Cloud".to_string(),
-            _ => instance.instance.to_string(),
+            inst if is_pm25_instance(inst) => "PM2.5".to_string(),
+            AIR_QUALITY_INSTANCE => "Air Quality".to_string(),
+            inst if is_filter_life_instance(inst) => "Filter Life".to_string(),
+            inst => match electrical_reading(inst) {
+                Some((name, _, _)) => name.to_string(),
+                None => inst.to_string(),
+            },
+        };
+
+        let icon = if is_filter_life_instance(&instance.instance) {
+            Some("mdi:air-filter".to_string())
+        } else {
+            None
+        };
+
+        // Air quality is the primary purpose of a purifier, as are the
+        // readings of a thermo-hygrometer or a power monitoring plug, so
+        // they shouldn't be tucked away with the diagnostics. Other
+        // devices report temperature and humidity incidentally.
+        let is_primary = instance.instance == AIR_QUALITY_INSTANCE
+            || (matches!(
+                instance.instance.as_str(),
+                "sensorTemperature" | "sensorHumidity"
+            ) && THERMO_HYGROMETER_SKUS.contains(&device.sku.as_str()))
+            || is_pm25_instance(&instance.instance)
+            || electrical_reading(&instance.instance).is_some();
+        let entity_category = if is_primary {
+            None
+        } else {
+            Some("diagnostic".to_string())
         };
 
         Ok(Self {
@@ -142,17 +435,19 @@ impl CapabilitySensor {
                 base: EntityConfig {
                     availability_topic: availability_topic(),
                     name: Some(name),
-                    entity_category: Some("diagnostic".to_string()),
+                    entity_category,
                     origin: Origin::default(),
                     device: Device::for_device(device),
                     unique_id: unique_id.clone(),
                     device_class,
-                    icon: None,
+                    icon,
                 },
                 state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
                 state_class: state_class,
                 unit_of_measurement,
                 json_attributes_topic: None,
+                options: air_quality_levels,
+                enabled_by_default: None,
             },
             device_id: device.id.to_string(),
             state: state.clone(),
@@ -163,10 +458,18 @@ impl CapabilitySensor {
 
 #[async_trait]
 impl EntityInstance for CapabilitySensor {
+    fn is_diagnostic(&self) -> bool {
+        self.sensor.base.entity_category.as_deref() == Some("diagnostic")
+    }
+
     async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
         self.sensor.publish(&state, &client).await
     }
 
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance_name)
+    }
+
     async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
         let device = self
             .state
@@ -179,22 +482,8 @@ impl EntityInstance for CapabilitySensor {
         if let Some(cap) = device.get_state_capability_by_instance(&self.instance_name) {
             let value = match self.instance_name.as_str() {
                 "sensorTemperature" => {
-                    let units = quirk
-                        .and_then(|q| q.platform_temperature_sensor_units)
-                        .unwrap_or(TemperatureUnits::Celsius);
-
-                    match cap
-                        .state
-                        .pointer("/value")
-                        .and_then(|v| v.as_f64())
-                        .map(|v| TemperatureValue::new(v, units))
-                    {
-                        Some(v) => {
-                            let value = v
-                                .as_unit(self.state.get_temperature_scale().await.into())
-                                .value();
-                            format!("{value:.2}")
-                        }
+                    match sensor_temperature(&device, self.state.get_temperature_scale().await) {
+                        Some(value) => format!("{value:.2}"),
                         None => "".to_string(),
                     }
                 }
@@ -212,6 +501,36 @@ impl EntityInstance for CapabilitySensor {
                         None => "".to_string(),
                     }
                 }
+                inst if is_pm25_instance(inst) || is_filter_life_instance(inst) => {
+                    match numeric_value(&cap.state) {
+                        Some(v) => format!("{v:.0}"),
+                        None => "".to_string(),
+                    }
+                }
+                AIR_QUALITY_INSTANCE if !self.sensor.options.is_empty() => {
+                    let level = device
+                        .get_capability_by_instance(AIR_QUALITY_INSTANCE)
+                        .and_then(|info| match &info.parameters {
+                            Some(DeviceParameters::Enum { options }) => cap
+                                .state
+                                .pointer("/value")
+                                .and_then(|value| options.iter().find(|opt| opt.value == *value))
+                                .map(|opt| opt.name.to_string()),
+                            _ => None,
+                        });
+                    match level {
+                        Some(level) => level,
+                        None => return Ok(()),
+                    }
+                }
+                AIR_QUALITY_INSTANCE => match numeric_value(&cap.state) {
+                    Some(v) => format!("{v:.0}"),
+                    None => "".to_string(),
+                },
+                inst if electrical_reading(inst).is_some() => match numeric_value(&cap.state) {
+                    Some(v) => format!("{v:.2}"),
+                    None => "".to_string(),
+                },
                 _ => cap.state.to_string(),
             };
 
@@ -225,6 +544,588 @@ impl EntityInstance for CapabilitySensor {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ThermometerQuantity {
+    Temperature,
+    Humidity,
+}
+
+impl ThermometerQuantity {
+    /// The platform API capability that carries the same reading.
+    /// We share its unique id so that the entity is retained if the
+    /// device later becomes available via the platform API.
+    fn instance(&self) -> &'static str {
+        match self {
+            Self::Temperature => "sensorTemperature",
+            Self::Humidity => "sensorHumidity",
+        }
+    }
+}
+
+/// A reading from a thermo-hygrometer that we learn about via the
+/// device list or IoT, rather than via the platform API. These are
+/// often BLE devices that relay their readings via a gateway or the
+/// phone app.
+pub struct ThermometerSensor {
+    sensor: SensorConfig,
+    device_id: String,
+    state: StateHandle,
+    quantity: ThermometerQuantity,
+}
+
+impl ThermometerSensor {
+    pub async fn for_device(device: &ServiceDevice, state: &StateHandle) -> Vec<Self> {
+        let Some(reading) = device.thermometer_state() else {
+            return vec![];
+        };
+
+        let mut sensors = vec![];
+        for (quantity, present) in [
+            (ThermometerQuantity::Temperature, reading.celsius.is_some()),
+            (ThermometerQuantity::Humidity, reading.humidity.is_some()),
+        ] {
+            if !present
+                || device
+                    .get_capability_by_instance(quantity.instance())
+                    .is_some()
+            {
+                continue;
+            }
+            sensors.push(Self::new(device, state, quantity).await);
+        }
+        sensors
+    }
+
+    async fn new(
+        device: &ServiceDevice,
+        state: &StateHandle,
+        quantity: ThermometerQuantity,
+    ) -> Self {
+        let unique_id = format!(
+            "sensor-{id}-{inst}",
+            id = topic_safe_id(device),
+            inst = topic_safe_string(quantity.instance())
+        );
+        let (name, device_class, unit_of_measurement) = match quantity {
+            ThermometerQuantity::Temperature => (
+                "Temperature",
+                DEVICE_CLASS_TEMPERATURE,
+                state.get_temperature_scale().await.unit_of_measurement(),
+            ),
+            ThermometerQuantity::Humidity => ("Humidity", DEVICE_CLASS_HUMIDITY, "%"),
+        };
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some(name.to_string()),
+                    entity_category: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some(device_class),
+                    icon: None,
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: Some(StateClass::Measurement),
+                unit_of_measurement: Some(unit_of_measurement),
+                json_attributes_topic: None,
+                options: vec![],
+                enabled_by_default: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+            quantity,
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for ThermometerSensor {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+        let Some(reading) = device.thermometer_state() else {
+            return Ok(());
+        };
+
+        let value = match self.quantity {
+            ThermometerQuantity::Temperature => match reading.celsius {
+                Some(c) => TemperatureValue::with_celsius(c)
+                    .as_unit(self.state.get_temperature_scale().await.into())
+                    .value(),
+                None => return Ok(()),
+            },
+            ThermometerQuantity::Humidity => match reading.humidity {
+                Some(h) => h,
+                None => return Ok(()),
+            },
+        };
+        self.sensor
+            .notify_state(client, &format!("{value:.2}"))
+            .await
+    }
+}
+
+/// Reports the work mode that the device is currently in, by its
+/// label, so that automations can react to the mode being changed
+/// from the Govee app or from the device itself
+pub struct ActiveWorkModeSensor {
+    sensor: SensorConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl ActiveWorkModeSensor {
+    pub fn new(device: &ServiceDevice, state: &StateHandle, work_modes: &ParsedWorkMode) -> Self {
+        let unique_id = format!("sensor-{id}-active-work-mode", id = topic_safe_id(device));
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Active Mode".to_string()),
+                    entity_category: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some(DEVICE_CLASS_ENUM),
+                    icon: Some("mdi:tune-variant".to_string()),
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: None,
+                unit_of_measurement: None,
+                json_attributes_topic: None,
+                options: work_modes.get_mode_labels(),
+                enabled_by_default: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for ActiveWorkModeSensor {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some("workMode")
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+        let work_modes = ParsedWorkMode::with_device(&device)?;
+        match work_modes.active_mode(&device) {
+            Some(mode) => self.sensor.notify_state(client, mode.label()).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reports whether an appliance is running, in standby, or in standby
+/// with a timer or alarm that will turn it on
+pub struct PowerStateSensor {
+    sensor: SensorConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl PowerStateSensor {
+    pub fn new(device: &ServiceDevice, state: &StateHandle) -> Self {
+        let unique_id = format!("sensor-{id}-power-state", id = topic_safe_id(device));
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Power State".to_string()),
+                    entity_category: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some(DEVICE_CLASS_ENUM),
+                    icon: Some("mdi:power-settings".to_string()),
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: None,
+                unit_of_measurement: None,
+                json_attributes_topic: None,
+                options: PowerState::ALL
+                    .iter()
+                    .map(|p| p.as_str().to_string())
+                    .collect(),
+                enabled_by_default: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for PowerStateSensor {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some("powerSwitch")
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+        match device.power_state() {
+            Some(power) => self.sensor.notify_state(client, power.as_str()).await,
+            None => Ok(()),
+        }
+    }
+}
+
+pub const DEVICE_CLASS_DURATION: &str = "duration";
+
+/// How long is left on a countdown, such as an auto-off timer,
+/// as of when the device last reported it
+pub struct CountdownRemainingSensor {
+    sensor: SensorConfig,
+    device_id: String,
+    state: StateHandle,
+    instance_name: String,
+    unit_seconds: i64,
+}
+
+impl CountdownRemainingSensor {
+    pub fn new(device: &ServiceDevice, state: &StateHandle, cap: &DeviceCapability) -> Self {
+        let unique_id = format!(
+            "sensor-{id}-{instance}-remaining",
+            id = topic_safe_id(device),
+            instance = topic_safe_string(&cap.instance)
+        );
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some(format!(
+                        "{} Remaining",
+                        camel_case_to_space_separated(&cap.instance)
+                    )),
+                    entity_category: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some(DEVICE_CLASS_DURATION),
+                    icon: Some("mdi:timer-sand".to_string()),
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: None,
+                unit_of_measurement: Some("min"),
+                json_attributes_topic: None,
+                options: vec![],
+                enabled_by_default: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+            instance_name: cap.instance.to_string(),
+            unit_seconds: countdown_unit_seconds(cap),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for CountdownRemainingSensor {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance_name)
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+        let Some(value) = device
+            .get_state_capability_by_instance(&self.instance_name)
+            .and_then(|cap| cap.state.pointer("/value")?.as_i64())
+        else {
+            return Ok(());
+        };
+        let minutes = (value * self.unit_seconds) as f64 / 60.;
+        self.sensor
+            .notify_state(client, &format!("{minutes:.1}"))
+            .await
+    }
+}
+
+/// The number of enabled timers and alarms that are configured in the
+/// Govee app, listing all of them as attributes. Unlike the per-schedule
+/// binary sensors, this picks up schedules that are added after the
+/// entities were registered.
+pub struct ScheduleSummarySensor {
+    sensor: SensorConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl ScheduleSummarySensor {
+    pub fn new(device: &ServiceDevice, state: &StateHandle) -> Self {
+        let unique_id = format!("sensor-{id}-schedules", id = topic_safe_id(device));
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Schedules".to_string()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: None,
+                    icon: Some("mdi:calendar-clock".to_string()),
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: None,
+                unit_of_measurement: None,
+                json_attributes_topic: Some(format!("gv2mqtt/sensor/{unique_id}/attributes")),
+                options: vec![],
+                enabled_by_default: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for ScheduleSummarySensor {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let Some(device) = self.state.device_by_id(&self.device_id).await else {
+            return Ok(());
+        };
+        let schedules = device.schedules();
+        let enabled = schedules.iter().filter(|s| s.enabled).count();
+
+        self.sensor
+            .notify_state(client, &enabled.to_string())
+            .await?;
+        if let Some(topic) = &self.sensor.json_attributes_topic {
+            let summaries: Vec<JsonValue> = schedules.iter().map(|s| s.summary()).collect();
+            client
+                .publish_obj(topic, json!({"schedules": summaries}))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// The Wi-Fi signal strength reported via the Govee app, which
+/// helps when diagnosing devices that keep dropping off the network
+pub struct WifiSignalDiagnostic {
+    sensor: SensorConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl WifiSignalDiagnostic {
+    pub fn new(device: &ServiceDevice, state: &StateHandle) -> Self {
+        let unique_id = format!("sensor-{id}-wifi-rssi", id = topic_safe_id(device));
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Wi-Fi Signal".to_string()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class: Some(DEVICE_CLASS_SIGNAL_STRENGTH),
+                    icon: None,
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: Some(StateClass::Measurement),
+                unit_of_measurement: Some("dBm"),
+                json_attributes_topic: None,
+                options: vec![],
+                enabled_by_default: Some(false),
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for WifiSignalDiagnostic {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+        match device.wifi_rssi() {
+            Some(rssi) => self.sensor.notify_state(client, &rssi.to_string()).await,
+            None => Ok(()),
+        }
+    }
+}
+
+pub const DEVICE_CLASS_TIMESTAMP: &str = "timestamp";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceInfoKind {
+    IpAddress,
+    Firmware,
+    LastSeen,
+}
+
+impl DeviceInfoKind {
+    /// The kinds of information that are known for `device`
+    fn available_for(device: &ServiceDevice) -> Vec<Self> {
+        let mut kinds = vec![];
+        if device.ip_addr().is_some() {
+            kinds.push(Self::IpAddress);
+        }
+        if device.firmware_version().is_some() {
+            kinds.push(Self::Firmware);
+        }
+        kinds.push(Self::LastSeen);
+        kinds
+    }
+}
+
+/// Details of the device that help with troubleshooting its
+/// connectivity from hass, without needing to read the logs
+pub struct DeviceInfoDiagnostic {
+    sensor: SensorConfig,
+    device_id: String,
+    state: StateHandle,
+    kind: DeviceInfoKind,
+}
+
+impl DeviceInfoDiagnostic {
+    pub fn for_device(device: &ServiceDevice, state: &StateHandle) -> Vec<Self> {
+        DeviceInfoKind::available_for(device)
+            .into_iter()
+            .map(|kind| Self::new(device, state, kind))
+            .collect()
+    }
+
+    fn new(device: &ServiceDevice, state: &StateHandle, kind: DeviceInfoKind) -> Self {
+        let (name, slug, icon, device_class) = match kind {
+            DeviceInfoKind::IpAddress => ("IP Address", "ip-address", Some("mdi:ip-network"), None),
+            DeviceInfoKind::Firmware => ("Firmware", "firmware", Some("mdi:chip"), None),
+            DeviceInfoKind::LastSeen => {
+                ("Last Seen", "last-seen", None, Some(DEVICE_CLASS_TIMESTAMP))
+            }
+        };
+        let unique_id = format!("sensor-{id}-{slug}", id = topic_safe_id(device));
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some(name.to_string()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    device_class,
+                    icon: icon.map(|icon| icon.to_string()),
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: None,
+                unit_of_measurement: None,
+                json_attributes_topic: match kind {
+                    DeviceInfoKind::Firmware => {
+                        Some(format!("gv2mqtt/sensor/{unique_id}/attributes"))
+                    }
+                    _ => None,
+                },
+                options: vec![],
+                enabled_by_default: None,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+            kind,
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for DeviceInfoDiagnostic {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+
+        let value = match self.kind {
+            DeviceInfoKind::IpAddress => device.ip_addr().map(|ip| ip.to_string()),
+            DeviceInfoKind::Firmware => device.firmware_version(),
+            DeviceInfoKind::LastSeen => device.last_seen().map(|t| t.to_rfc3339()),
+        };
+        let Some(value) = value else {
+            return Ok(());
+        };
+        self.sensor.notify_state(client, &value).await?;
+
+        if let Some(topic) = &self.sensor.json_attributes_topic {
+            client
+                .publish_obj(topic, device.firmware_versions())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
 pub struct DeviceStatusDiagnostic {
     sensor: SensorConfig,
     device_id: String,
@@ -251,6 +1152,8 @@ impl DeviceStatusDiagnostic {
                 state_class: None,
                 json_attributes_topic: Some(format!("gv2mqtt/sensor/{unique_id}/attributes")),
                 unit_of_measurement: None,
+                options: vec![],
+                enabled_by_default: None,
             },
             device_id: device.id.to_string(),
             state: state.clone(),
@@ -260,6 +1163,10 @@ impl DeviceStatusDiagnostic {
 
 #[async_trait]
 impl EntityInstance for DeviceStatusDiagnostic {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
     async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
         self.sensor.publish(&state, &client).await
     }
@@ -280,7 +1187,8 @@ impl EntityInstance for DeviceStatusDiagnostic {
 
         let now = Utc::now();
 
-        let threshold = *POLL_INTERVAL + chrono::Duration::seconds(30);
+        let threshold =
+            device_poll_interval(&device.id, state_poll_interval()) + chrono::Duration::seconds(30);
 
         let summary = match &device_state {
             Some(state) => {
@@ -309,3 +1217,124 @@ impl EntityInstance for DeviceStatusDiagnostic {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::service::state::State;
+    use std::sync::Arc;
+
+    #[test]
+    fn transports() {
+        let mut lan = ServiceDevice::new("H6072", "AA:BB:CC:DD:EE:FF:42:2A");
+        lan.set_lan_device(
+            serde_json::from_value(json!({
+                "ip": "10.0.0.2",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "sku": "H6072",
+                "bleVersionHard": "",
+                "bleVersionSoft": "",
+                "wifiVersionHard": "",
+                "wifiVersionSoft": "",
+            }))
+            .unwrap(),
+        );
+        let thermometer = ServiceDevice::new("H5075", "AA:BB:CC:DD:EE:FF:42:2B");
+        let list: crate::undoc_api::DevicesResponse = crate::platform_api::from_json(include_str!(
+            "../../test-data/undoc-device-list-issue-21.json"
+        ))
+        .unwrap();
+        let entry = list.devices[0].clone();
+        let mut cloud = ServiceDevice::new(&entry.sku, &entry.device);
+        cloud.set_undoc_device_info(entry, None);
+        assert_eq!(
+            devices_by_transport(&[lan, thermometer, cloud]),
+            json!({
+                "lan": 1,
+                "iot": 1,
+                "platform_api": 0,
+                "undocumented_api": 1,
+                "ble_only": 1,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn air_quality_sensors() {
+        let state = Arc::new(State::new());
+        let device = ServiceDevice::new("H7122", "AA:BB:CC:DD:EE:FF:42:2A");
+
+        let pm25: DeviceCapability = serde_json::from_value(json!({
+            "type": "devices.capabilities.property",
+            "instance": "sensorPM25",
+        }))
+        .unwrap();
+        let sensor = CapabilitySensor::new(&device, &state, &pm25).await.unwrap();
+        assert_eq!(sensor.sensor.base.device_class, Some(DEVICE_CLASS_PM25));
+        assert_eq!(sensor.sensor.unit_of_measurement, Some("µg/m³"));
+        assert_eq!(sensor.sensor.base.entity_category, None);
+        assert_eq!(numeric_value(&json!({"value": 12})), Some(12.0));
+        assert_eq!(numeric_value(&json!({"value": {"pm25": 7}})), Some(7.0));
+
+        let level: DeviceCapability = serde_json::from_value(json!({
+            "type": "devices.capabilities.property",
+            "instance": "airQuality",
+            "parameters": {
+                "dataType": "ENUM",
+                "options": [
+                    {"name": "Good", "value": 1},
+                    {"name": "Moderate", "value": 2},
+                ]
+            }
+        }))
+        .unwrap();
+        let sensor = CapabilitySensor::new(&device, &state, &level)
+            .await
+            .unwrap();
+        assert_eq!(sensor.sensor.base.device_class, Some(DEVICE_CLASS_ENUM));
+        assert_eq!(sensor.sensor.options, vec!["Good", "Moderate"]);
+        assert_eq!(sensor.sensor.state_class, None);
+    }
+
+    #[tokio::test]
+    async fn filter_life_sensor() {
+        let state = Arc::new(State::new());
+        let device = ServiceDevice::new("H7122", "AA:BB:CC:DD:EE:FF:42:2A");
+
+        let cap: DeviceCapability = serde_json::from_value(json!({
+            "type": "devices.capabilities.property",
+            "instance": "filterLifeTime",
+        }))
+        .unwrap();
+        let sensor = CapabilitySensor::new(&device, &state, &cap).await.unwrap();
+        assert_eq!(sensor.sensor.base.name.as_deref(), Some("Filter Life"));
+        assert_eq!(sensor.sensor.unit_of_measurement, Some("%"));
+        assert_eq!(
+            sensor.sensor.base.entity_category.as_deref(),
+            Some("diagnostic")
+        );
+    }
+
+    #[tokio::test]
+    async fn power_monitoring_sensors() {
+        let state = Arc::new(State::new());
+        let device = ServiceDevice::new("H5086", "AA:BB:CC:DD:EE:FF:42:2A");
+
+        for (instance, class, unit) in [
+            ("electricPower", DEVICE_CLASS_POWER, "W"),
+            ("voltage", DEVICE_CLASS_VOLTAGE, "V"),
+            ("current", DEVICE_CLASS_CURRENT, "A"),
+        ] {
+            let cap: DeviceCapability = serde_json::from_value(json!({
+                "type": "devices.capabilities.property",
+                "instance": instance,
+            }))
+            .unwrap();
+            let sensor = CapabilitySensor::new(&device, &state, &cap).await.unwrap();
+            assert_eq!(sensor.sensor.base.device_class, Some(class));
+            assert_eq!(sensor.sensor.unit_of_measurement, Some(unit));
+            assert_eq!(sensor.sensor.state_class, Some(StateClass::Measurement));
+            assert_eq!(sensor.sensor.base.entity_category, None);
+        }
+    }
+}
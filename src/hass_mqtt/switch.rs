@@ -6,6 +6,7 @@ use crate::service::hass::{
     availability_topic, camel_case_to_space_separated, switch_instance_state_topic, topic_safe_id,
     HassClient,
 };
+use crate::service::quirks::{is_child_lock_instance, is_display_instance, resolve_toggle_label};
 use crate::service::state::StateHandle;
 use async_trait::async_trait;
 use serde::Serialize;
@@ -37,16 +38,28 @@ impl SwitchConfig {
             inst = instance.instance
         );
 
+        let label = resolve_toggle_label(&device.sku, &instance.instance);
+
         Ok(Self {
             base: EntityConfig {
                 availability_topic,
-                name: Some(camel_case_to_space_separated(&instance.instance)),
+                name: Some(
+                    label
+                        .map(|l| l.name.to_string())
+                        .unwrap_or_else(|| camel_case_to_space_separated(&instance.instance)),
+                ),
                 device_class: None,
                 origin: Origin::default(),
                 device: Device::for_device(device),
                 unique_id,
-                entity_category: None,
-                icon: None,
+                entity_category: if is_child_lock_instance(&device.sku, &instance.instance)
+                    || is_display_instance(&instance.instance)
+                {
+                    Some("config".to_string())
+                } else {
+                    None
+                },
+                icon: label.and_then(|l| l.icon).map(|icon| icon.to_string()),
             },
             command_topic,
             state_topic,
@@ -87,6 +100,10 @@ impl EntityInstance for CapabilitySwitch {
         self.switch.publish(&state, &client).await
     }
 
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance_name)
+    }
+
     async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
         let device = self
             .state
@@ -95,11 +112,11 @@ impl EntityInstance for CapabilitySwitch {
             .expect("device to exist");
 
         if self.instance_name == "powerSwitch" {
-            if let Some(state) = device.device_state() {
+            if let Some(power) = device.power_state() {
                 client
                     .publish(
                         &self.switch.state_topic,
-                        if state.on { "ON" } else { "OFF" },
+                        if power.is_on() { "ON" } else { "OFF" },
                     )
                     .await?;
             }
@@ -0,0 +1,171 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{availability_topic, topic_safe_id, HassClient, IdParameter};
+use crate::service::state::StateHandle;
+use anyhow::Context;
+use async_trait::async_trait;
+use mosquitto_rs::router::{Params, Payload, State};
+use serde::Serialize;
+
+/// <https://www.home-assistant.io/integrations/text.mqtt>
+#[derive(Serialize, Clone, Debug)]
+pub struct TextConfig {
+    #[serde(flatten)]
+    pub base: EntityConfig,
+
+    pub command_topic: String,
+    pub state_topic: String,
+    pub max: usize,
+}
+
+impl TextConfig {
+    pub async fn publish(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        publish_entity_config("text", state, client, &self.base, self).await
+    }
+}
+
+/// Activates the scene whose name best matches the text entered,
+/// for devices whose scene catalog is too large to pick from a select
+pub struct SceneNameText {
+    text: TextConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl SceneNameText {
+    pub async fn new(device: &ServiceDevice, state: &StateHandle) -> anyhow::Result<Option<Self>> {
+        if state.device_list_scenes(device).await?.is_empty() {
+            return Ok(None);
+        }
+
+        let id = topic_safe_id(device);
+        Ok(Some(Self {
+            text: TextConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Scene Name".to_string()),
+                    device_class: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("gv2mqtt-{id}-scene-name"),
+                    entity_category: None,
+                    icon: Some("mdi:text-search".to_string()),
+                },
+                command_topic: format!("gv2mqtt/{id}/set-scene-name"),
+                state_topic: format!("gv2mqtt/{id}/notify-scene-name"),
+                max: 255,
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        }))
+    }
+}
+
+#[async_trait]
+impl EntityInstance for SceneNameText {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.text.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+
+        if let Some(device_state) = device.device_state() {
+            let scene = device_state.scene.as_deref().unwrap_or("");
+            client.publish(&self.text.state_topic, scene).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reduce a scene name to its lowercase letters and digits, so that
+/// spacing, punctuation and case don't get in the way of matching
+fn normalize_scene_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Find the scene that best matches `query`: an exact match is
+/// preferred, then the shortest scene that starts with it, then the
+/// shortest scene that contains it
+pub fn match_scene_name<'a>(query: &str, scenes: &'a [String]) -> Option<&'a str> {
+    let query = normalize_scene_name(query);
+    if query.is_empty() {
+        return None;
+    }
+
+    let candidates: Vec<(String, &String)> = scenes
+        .iter()
+        .map(|scene| (normalize_scene_name(scene), scene))
+        .collect();
+
+    let best = |accept: &dyn Fn(&str) -> bool| {
+        candidates
+            .iter()
+            .filter(|(name, _)| accept(name))
+            .min_by_key(|(name, _)| name.len())
+            .map(|(_, scene)| scene.as_str())
+    };
+
+    best(&|name| name == query)
+        .or_else(|| best(&|name| name.starts_with(&query)))
+        .or_else(|| best(&|name| name.contains(&query)))
+}
+
+pub async fn mqtt_set_scene_name(
+    Payload(query): Payload<String>,
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    let device = state.resolve_device_for_control(&id).await?;
+
+    let mut catalog = state.device_list_scenes(&device).await?;
+    catalog.extend(state.device_list_diy_scenes(&device).await?);
+
+    let Some(scene) = match_scene_name(&query, &catalog) else {
+        anyhow::bail!("{device} has no scene matching '{query}'");
+    };
+    log::info!("mqtt_set_scene_name: {device}: '{query}' matched {scene}");
+
+    state
+        .device_set_scene(&device, scene)
+        .await
+        .context("mqtt_set_scene_name: state.device_set_scene")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scene_matching() {
+        let scenes: Vec<String> = ["Sunrise", "Sunset", "Sunset Glow", "Aurora", "Forest Rain"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(match_scene_name("sunset", &scenes), Some("Sunset"));
+        assert_eq!(
+            match_scene_name("SUNSET glow", &scenes),
+            Some("Sunset Glow")
+        );
+        assert_eq!(match_scene_name("sunr", &scenes), Some("Sunrise"));
+        assert_eq!(match_scene_name("rain", &scenes), Some("Forest Rain"));
+        assert_eq!(
+            match_scene_name("forest-rain", &scenes),
+            Some("Forest Rain")
+        );
+        assert_eq!(match_scene_name("ocean", &scenes), None);
+        assert_eq!(match_scene_name("  ", &scenes), None);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{availability_topic, topic_safe_id, HassClient};
+use crate::service::state::StateHandle;
+use async_trait::async_trait;
+use serde::Serialize;
+
+/// <https://www.home-assistant.io/integrations/update.mqtt>
+#[derive(Serialize, Clone, Debug)]
+pub struct UpdateConfig {
+    #[serde(flatten)]
+    pub base: EntityConfig,
+
+    pub state_topic: String,
+}
+
+impl UpdateConfig {
+    pub async fn publish(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        publish_entity_config("update", state, client, &self.base, self).await
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct UpdateState {
+    pub installed_version: String,
+    pub latest_version: String,
+}
+
+impl UpdateState {
+    /// When Govee hasn't told us about a newer version, the installed
+    /// version is reported as the latest so that hass shows the device
+    /// as being up to date
+    pub fn new(installed: String, latest: Option<String>) -> Self {
+        Self {
+            latest_version: latest.unwrap_or_else(|| installed.clone()),
+            installed_version: installed,
+        }
+    }
+}
+
+/// Shows whether the Govee app has a firmware update for the device.
+/// The update has to be installed from the app; this entity is read-only
+pub struct FirmwareUpdate {
+    update: UpdateConfig,
+    device_id: String,
+    state: StateHandle,
+}
+
+impl FirmwareUpdate {
+    pub fn new(device: &ServiceDevice, state: &StateHandle) -> Option<Self> {
+        device.firmware_version()?;
+
+        let unique_id = format!("update-{id}-firmware", id = topic_safe_id(device));
+        Some(Self {
+            update: UpdateConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: Some("Firmware Update".to_string()),
+                    device_class: Some("firmware"),
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: unique_id.clone(),
+                    entity_category: Some("diagnostic".to_string()),
+                    icon: None,
+                },
+                state_topic: format!("gv2mqtt/update/{unique_id}/state"),
+            },
+            device_id: device.id.to_string(),
+            state: state.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl EntityInstance for FirmwareUpdate {
+    fn is_diagnostic(&self) -> bool {
+        true
+    }
+
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.update.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+
+        let Some(installed) = device.firmware_version() else {
+            return Ok(());
+        };
+        let update = UpdateState::new(installed, device.latest_firmware_version());
+        client.publish_obj(&self.update.state_topic, update).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn update_state() {
+        assert_eq!(
+            UpdateState::new("1.02.03".to_string(), None),
+            UpdateState {
+                installed_version: "1.02.03".to_string(),
+                latest_version: "1.02.03".to_string(),
+            }
+        );
+        assert_eq!(
+            UpdateState::new("1.02.03".to_string(), Some("1.04.00".to_string())),
+            UpdateState {
+                installed_version: "1.02.03".to_string(),
+                latest_version: "1.04.00".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::instance::EntityInstance;
+use crate::hass_mqtt::light::{ColorTempRange, LightConfig};
+use crate::service::composition::{virtual_device_by_id, VirtualDevice};
+use crate::service::hass::{
+    apply_light_command, availability_topic, HassClient, HassLightCommand, IdParameter,
+};
+use crate::service::state::StateHandle;
+use async_trait::async_trait;
+use mosquitto_rs::router::{Params, Payload, State};
+use serde_json::json;
+
+pub fn virtual_light_state_topic(id: &str) -> String {
+    format!("gv2mqtt/virtual/{id}/state")
+}
+
+pub fn virtual_light_command_topic(id: &str) -> String {
+    format!("gv2mqtt/virtual/{id}/command")
+}
+
+/// A light that controls all of the members of a virtual device
+pub struct VirtualLight {
+    light: LightConfig,
+    device: VirtualDevice,
+    state: StateHandle,
+}
+
+impl VirtualLight {
+    pub async fn new(device: VirtualDevice, state: &StateHandle) -> Self {
+        let members = device.members(state).await;
+        let id = device.id();
+
+        let mut supported_color_modes = vec![];
+        if VirtualDevice::supports_rgb(&members) {
+            supported_color_modes.push("rgb".to_string());
+        }
+        let kelvin_range = VirtualDevice::color_temperature_range(state, &members).await;
+        if kelvin_range.is_some() {
+            supported_color_modes.push("color_temp".to_string());
+        }
+        let color_temp = ColorTempRange::new(state.get_color_temp_units().await, kelvin_range);
+
+        Self {
+            light: LightConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: None,
+                    device_class: None,
+                    origin: Origin::default(),
+                    device: Device::for_virtual_device(&device),
+                    unique_id: format!("gv2mqtt-virtual-{id}"),
+                    entity_category: None,
+                    icon: None,
+                },
+                schema: "json".to_string(),
+                command_topic: virtual_light_command_topic(&id),
+                state_topic: virtual_light_state_topic(&id),
+                optimistic: false,
+                color_mode: !supported_color_modes.is_empty(),
+                supported_color_modes,
+                brightness: VirtualDevice::supports_brightness(&members),
+                brightness_scale: 100,
+                icon: Some("mdi:lightbulb-group".to_string()),
+                // Scene names are specific to each member device,
+                // so there isn't a meaningful common list to offer
+                effect: false,
+                effect_list: vec![],
+                color_temp,
+                payload_available: "online".to_string(),
+            },
+            device,
+            state: state.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for VirtualLight {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.light.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let members = self.device.members(&self.state).await;
+        let composite = VirtualDevice::composite_state(&members);
+
+        let light_state = if !composite.on {
+            json!({"state": "OFF"})
+        } else if composite.kelvin == 0 {
+            json!({
+                "state": "ON",
+                "color_mode": "rgb",
+                "color": {
+                    "r": composite.color.r,
+                    "g": composite.color.g,
+                    "b": composite.color.b,
+                },
+                "brightness": composite.brightness,
+            })
+        } else {
+            json!({
+                "state": "ON",
+                "color_mode": "color_temp",
+                "brightness": composite.brightness,
+                "color_temp": self
+                    .state
+                    .get_color_temp_units()
+                    .await
+                    .kelvin_in_units(composite.kelvin),
+            })
+        };
+
+        client
+            .publish_obj(&self.light.state_topic, &light_state)
+            .await
+    }
+}
+
+/// HASS is sending a command to a virtual light; apply it to each
+/// of the members concurrently
+pub async fn mqtt_virtual_light_command(
+    Payload(payload): Payload<String>,
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    let device = virtual_device_by_id(&state, &id)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("virtual device {id} not found"))?;
+    let command: HassLightCommand = serde_json::from_str(&payload)?;
+    log::info!("Command for virtual device {}: {payload}", device.name());
+
+    let results = futures_util::future::join_all(device.config.members.iter().map(|label| {
+        let state = state.clone();
+        let command = command.clone();
+        async move {
+            let member = state.resolve_device_for_control(label).await?;
+            apply_light_command(&state, &member, &command).await
+        }
+    }))
+    .await;
+
+    let mut failures = vec![];
+    for (label, result) in device.config.members.iter().zip(results) {
+        if let Err(err) = result {
+            failures.push(format!("{label}: {err:#}"));
+        }
+    }
+    if !failures.is_empty() {
+        anyhow::bail!(
+            "virtual device {} partially failed: {}",
+            device.name(),
+            failures.join(", ")
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::climate::reported_target_temperature;
+use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
+use crate::hass_mqtt::sensor::sensor_temperature;
+use crate::platform_api::DeviceCapability;
+use crate::service::device::Device as ServiceDevice;
+use crate::service::hass::{
+    availability_topic, topic_safe_id, topic_safe_string, HassClient, IdParameter,
+};
+use crate::service::state::StateHandle;
+use crate::temperature::TemperatureScale;
+use async_trait::async_trait;
+use mosquitto_rs::router::{Params, Payload, State};
+use serde::Serialize;
+
+/// The mode reported while the device is heating; hass only
+/// accepts a fixed set of mode names for water heaters
+const MODE_HEATING: &str = "electric";
+const MODE_OFF: &str = "off";
+
+/// <https://www.home-assistant.io/integrations/water_heater.mqtt>
+#[derive(Serialize, Clone, Debug)]
+pub struct WaterHeaterConfig {
+    #[serde(flatten)]
+    pub base: EntityConfig,
+
+    /// HASS will publish ON/OFF here to control the power
+    pub power_command_topic: String,
+
+    /// HASS will publish here to change the current mode
+    pub mode_command_topic: String,
+    /// we will publish the current mode here
+    pub mode_state_topic: String,
+    pub modes: Vec<&'static str>,
+
+    /// HASS will publish here to change the target temperature
+    pub temperature_command_topic: String,
+    /// we will publish the target temperature here
+    pub temperature_state_topic: String,
+    /// we will publish the current water temperature here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_temperature_topic: Option<String>,
+
+    pub min_temp: f64,
+    pub max_temp: f64,
+    pub precision: f64,
+    pub temperature_unit: &'static str,
+}
+
+/// Presents a kettle as a water heater, so that it can be switched
+/// on to heat to a target temperature from a single control
+#[derive(Clone)]
+pub struct WaterHeater {
+    water_heater: WaterHeaterConfig,
+    state: StateHandle,
+    device_id: String,
+    instance_name: String,
+}
+
+impl WaterHeater {
+    pub async fn new(
+        device: &ServiceDevice,
+        state: &StateHandle,
+        instance: &DeviceCapability,
+    ) -> anyhow::Result<Self> {
+        let id = topic_safe_id(device);
+        let scale = state.get_temperature_scale().await;
+        let constraints = state
+            .device_temperature_constraints(device, instance)
+            .await?
+            .as_unit(scale.into());
+
+        // Power is routed to the general power switch handler, and the
+        // target temperature to the general set-temperature handler
+        let power_command_topic = format!("gv2mqtt/switch/{id}/command/powerSwitch");
+        let temperature_command_topic = format!(
+            "gv2mqtt/{id}/set-temperature/{inst}/{scale}",
+            inst = topic_safe_string(&instance.instance)
+        );
+
+        let current_temperature_topic = device
+            .http_device_info
+            .as_ref()
+            .and_then(|info| info.capability_by_instance("sensorTemperature"))
+            .map(|_| format!("gv2mqtt/water_heater/{id}/current-temperature"));
+
+        Ok(Self {
+            water_heater: WaterHeaterConfig {
+                base: EntityConfig {
+                    availability_topic: availability_topic(),
+                    name: None,
+                    device_class: None,
+                    origin: Origin::default(),
+                    device: Device::for_device(device),
+                    unique_id: format!("gv2mqtt-{id}-water-heater"),
+                    entity_category: None,
+                    icon: None,
+                },
+                power_command_topic,
+                mode_command_topic: format!("gv2mqtt/water_heater/{id}/set-mode"),
+                mode_state_topic: format!("gv2mqtt/water_heater/{id}/mode"),
+                modes: vec![MODE_OFF, MODE_HEATING],
+                temperature_command_topic,
+                temperature_state_topic: format!("gv2mqtt/water_heater/{id}/target-temperature"),
+                current_temperature_topic,
+                min_temp: constraints.min.value().floor(),
+                max_temp: constraints.max.value().ceil(),
+                precision: constraints.step.into(),
+                temperature_unit: match scale {
+                    TemperatureScale::Celsius => "C",
+                    TemperatureScale::Farenheit => "F",
+                },
+            },
+            state: state.clone(),
+            device_id: device.id.to_string(),
+            instance_name: instance.instance.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl EntityInstance for WaterHeater {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        publish_entity_config(
+            "water_heater",
+            state,
+            client,
+            &self.water_heater.base,
+            &self.water_heater,
+        )
+        .await
+    }
+
+    fn source_capability(&self) -> Option<&str> {
+        Some(&self.instance_name)
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let device = self
+            .state
+            .device_by_id(&self.device_id)
+            .await
+            .expect("device to exist");
+
+        let is_on = device.device_state().map(|s| s.on).unwrap_or(false);
+        client
+            .publish(
+                &self.water_heater.mode_state_topic,
+                if is_on { MODE_HEATING } else { MODE_OFF },
+            )
+            .await?;
+
+        let scale = self.state.get_temperature_scale().await;
+        if let Some(value) = reported_target_temperature(&device, &self.instance_name, scale) {
+            client
+                .publish(&self.water_heater.temperature_state_topic, value)
+                .await?;
+        }
+
+        if let Some(topic) = &self.water_heater.current_temperature_topic {
+            if let Some(value) = sensor_temperature(&device, scale) {
+                client.publish(topic, format!("{value:.2}")).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn mqtt_water_heater_set_mode(
+    Payload(mode): Payload<String>,
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    log::info!("mqtt_water_heater_set_mode: {id}: {mode}");
+    let device = state.resolve_device_for_control(&id).await?;
+    state.device_power_on(&device, mode != MODE_OFF).await?;
+    Ok(())
+}
@@ -1,10 +1,169 @@
-use crate::platform_api::{DeviceCapability, DeviceParameters, EnumOption};
-use crate::service::device::Device as ServiceDevice;
-use anyhow::anyhow;
-use serde::Deserialize;
+use crate::platform_api::{from_json, DeviceCapability, DeviceParameters, EnumOption};
+use crate::service::device::{normalize_device_id, Device as ServiceDevice};
+use anyhow::{anyhow, Context};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Range;
+use std::path::Path;
+
+/// How the values of a work mode are offered in hass
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkModePresentation {
+    /// A number entity covering the range of values
+    Slider,
+    /// A button for each value, or to activate the mode
+    Presets,
+}
+
+/// Adjusts how a work mode of a particular SKU or device is presented
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WorkModeOverride {
+    /// Shown in place of the name of the mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The value of the mode is the target humidity of a humidifier,
+    /// rather than a level of some kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humidity_setpoint: Option<bool>,
+    /// The mode isn't offered at all, such as when the firmware
+    /// of a device doesn't actually support it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presentation: Option<WorkModePresentation>,
+}
+
+impl WorkModeOverride {
+    /// Take the fields that are set in `other`
+    fn merge_from(&mut self, other: &Self) {
+        if other.label.is_some() {
+            self.label.clone_from(&other.label);
+        }
+        if other.humidity_setpoint.is_some() {
+            self.humidity_setpoint = other.humidity_setpoint;
+        }
+        if other.hidden.is_some() {
+            self.hidden = other.hidden;
+        }
+        if other.presentation.is_some() {
+            self.presentation = other.presentation;
+        }
+    }
+}
+
+/// Maps SKU or device id -> work mode name -> override
+pub type WorkModeOverrides = HashMap<String, HashMap<String, WorkModeOverride>>;
+
+/// The devices whose work modes we know better than their metadata does
+fn builtin_work_mode_overrides() -> WorkModeOverrides {
+    let mut overrides = WorkModeOverrides::new();
+    let mut add = |sku: &str, mode: &str, o: WorkModeOverride| {
+        overrides
+            .entry(sku.to_string())
+            .or_default()
+            .insert(mode.to_string(), o);
+    };
+    for sku in ["H7160", "H7143"] {
+        add(
+            sku,
+            "Manual",
+            WorkModeOverride {
+                label: Some("Manual: Mist Level".to_string()),
+                ..Default::default()
+            },
+        );
+        add(
+            sku,
+            "Auto",
+            WorkModeOverride {
+                humidity_setpoint: Some(true),
+                ..Default::default()
+            },
+        );
+    }
+    for sku in ["H7131", "H7173"] {
+        add(
+            sku,
+            "gearMode",
+            WorkModeOverride {
+                label: Some("Heat".to_string()),
+                ..Default::default()
+            },
+        );
+    }
+    overrides
+}
+
+/// Merge `user` over `builtin`; fields that are set in `user`
+/// take precedence over those of the same mode in `builtin`
+fn merge_work_mode_overrides(
+    mut builtin: WorkModeOverrides,
+    user: WorkModeOverrides,
+) -> WorkModeOverrides {
+    for (key, modes) in user {
+        let entry = builtin.entry(override_key(&key)).or_default();
+        for (name, o) in modes {
+            entry.entry(name).or_default().merge_from(&o);
+        }
+    }
+    builtin
+}
+
+/// SKUs and device ids are matched regardless of case,
+/// and device ids regardless of their separators
+fn override_key(key: &str) -> String {
+    normalize_device_id(key).to_ascii_uppercase()
+}
+
+/// Resolve the override for the named mode of a device;
+/// that for its id takes precedence over that for its SKU
+fn resolve_override(
+    overrides: &WorkModeOverrides,
+    sku: &str,
+    id: &str,
+    mode: &str,
+) -> WorkModeOverride {
+    let mut result = WorkModeOverride::default();
+    for key in [sku, id] {
+        if let Some(o) = overrides.get(&override_key(key)).and_then(|m| m.get(mode)) {
+            result.merge_from(o);
+        }
+    }
+    result
+}
+
+static WORK_MODE_OVERRIDES: OnceCell<WorkModeOverrides> = OnceCell::new();
+
+/// Load the work mode overrides file, and merge it over
+/// the built-in overrides for the remainder of the process
+pub fn load_work_mode_overrides(path: &Path) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading work mode overrides {}", path.display()))?;
+    let user: WorkModeOverrides = from_json(&text)
+        .with_context(|| format!("parsing work mode overrides {}", path.display()))?;
+    let count: usize = user.values().map(|modes| modes.len()).sum();
+    WORK_MODE_OVERRIDES
+        .set(merge_work_mode_overrides(
+            builtin_work_mode_overrides(),
+            user,
+        ))
+        .map_err(|_| anyhow!("work mode overrides have already been set"))?;
+    log::info!("Loaded {count} work mode overrides from {}", path.display());
+    Ok(())
+}
+
+fn work_mode_overrides() -> &'static WorkModeOverrides {
+    WORK_MODE_OVERRIDES.get_or_init(builtin_work_mode_overrides)
+}
+
+/// Returns the override for the named work mode of a device
+pub fn work_mode_override(device: &ServiceDevice, mode: &str) -> WorkModeOverride {
+    resolve_override(work_mode_overrides(), &device.sku, &device.id, mode)
+}
 
 #[derive(Default, Debug)]
 pub struct ParsedWorkMode {
@@ -13,15 +172,20 @@ pub struct ParsedWorkMode {
 
 impl ParsedWorkMode {
     pub fn with_device(device: &ServiceDevice) -> anyhow::Result<Self> {
-        let info = device
-            .http_device_info
-            .as_ref()
-            .ok_or_else(|| anyhow!("no platform state, so no known work mode"))?;
-        let cap = info
-            .capability_by_instance("workMode")
+        if device.lacks_capability("workMode") {
+            anyhow::bail!("device has no usable workMode capability");
+        }
+        let cap = device
+            .get_capability_by_instance("workMode")
             .ok_or_else(|| anyhow!("device has no workMode capability"))?;
-        let mut parsed = Self::with_capability(cap)?;
-        parsed.adjust_for_device(&device.sku);
+        let mut parsed = match Self::with_capability(cap) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                device.mark_capability_unusable("workMode");
+                return Err(err);
+            }
+        };
+        parsed.adjust_for_device(device);
         Ok(parsed)
     }
 
@@ -53,6 +217,22 @@ impl ParsedWorkMode {
                 }
                 _ => {}
             }
+
+            // The capability may also specify a default for the
+            // modeValue field as a whole, which applies to those
+            // modes that don't have a default of their own
+            if let Some(default) = mv.default_value.as_ref().filter(|v| is_mode_value(v)) {
+                for work_mode in work_modes.modes.values_mut() {
+                    let has_default = work_mode
+                        .default_value
+                        .as_ref()
+                        .map(is_mode_value)
+                        .unwrap_or(false);
+                    if !has_default {
+                        work_mode.default_value.replace(default.clone());
+                    }
+                }
+            }
         }
         Ok(work_modes)
     }
@@ -72,45 +252,78 @@ impl ParsedWorkMode {
         self.modes.get_mut(mode)
     }
 
-    pub fn adjust_for_device(&mut self, sku: &str) {
-        match sku {
-            "H7160" | "H7143" => {
-                self.modes
-                    .get_mut("Manual")
-                    .map(|m| m.label = "Manual: Mist Level".to_string());
-            }
-            "H7131" => {
-                self.modes.get_mut("gearMode").map(|m| {
-                    m.label = "Heat".to_string();
-                });
-            }
-            "H7173" => {
-                self.modes.get_mut("gearMode").map(|m| {
-                    m.label = "Heat".to_string();
-                });
-            }
-            _ => {
-                for mode in self.modes.values_mut() {
-                    mode.label = mode.name.clone();
-                }
+    pub fn adjust_for_device(&mut self, device: &ServiceDevice) {
+        self.apply_overrides(&device.sku, &device.id, work_mode_overrides());
+    }
+
+    fn apply_overrides(&mut self, sku: &str, id: &str, overrides: &WorkModeOverrides) {
+        if sku == "H7172" {
+            // The work modes are the nugget sizes
+            for mode in self.modes.values_mut() {
+                mode.label = mode.name.trim_end_matches("Ice").to_string();
             }
         }
+        self.modes.retain(|name, mode| {
+            let o = resolve_override(overrides, sku, id, name);
+            if let Some(label) = o.label {
+                mode.label = label;
+            }
+            o.hidden != Some(true)
+        });
     }
 
     pub fn mode_for_value(&self, value: &JsonValue) -> Option<&WorkMode> {
         for mode in self.modes.values() {
-            if mode.value == *value {
+            if mode_values_equal(&mode.value, value) {
                 return Some(mode);
             }
         }
         None
     }
 
+    /// Returns the mode that the device last reported being in,
+    /// whether it was set by us or by some other means such as
+    /// the Govee app
+    pub fn active_mode(&self, device: &ServiceDevice) -> Option<&WorkMode> {
+        match device.humidifier_work_mode {
+            Some(mode_value) => self.mode_for_value(&mode_value.into()),
+            None => {
+                let cap = device.get_state_capability_by_instance("workMode")?;
+                self.mode_for_value(cap.state.pointer("/value/workMode")?)
+            }
+        }
+    }
+
+    /// Resolve a preset that was selected in hass to the workMode
+    /// and modeValue pair that activates it. The preset may be the
+    /// name or label of a mode, in which case the default value of
+    /// the mode is used, or it may be one of the values of a mode.
+    /// Sending both halves of the pair matters, as some firmware
+    /// ignores a workMode that arrives without a usable modeValue.
+    /// Either half may be a string rather than a number.
+    pub fn resolve_preset(&self, preset: &str) -> Option<(JsonValue, JsonValue)> {
+        if let Some(mode) = self.mode_by_name_or_label(preset) {
+            return Some((mode.value.clone(), mode.default_mode_value()));
+        }
+        self.modes.values().find_map(|mode| {
+            let value = mode
+                .values
+                .iter()
+                .find(|v| v.computed_label == preset || v.name.as_deref() == Some(preset))?;
+            Some((mode.value.clone(), value.value.clone()))
+        })
+    }
+
     pub fn mode_by_name(&self, name: &str) -> Option<&WorkMode> {
         self.modes.get(name)
     }
 
-    #[allow(unused)]
+    /// Resolve a mode that was selected in hass, which may
+    /// be either its name or its label
+    pub fn mode_by_name_or_label(&self, name: &str) -> Option<&WorkMode> {
+        self.mode_by_name(name).or_else(|| self.mode_by_label(name))
+    }
+
     pub fn mode_by_label(&self, name: &str) -> Option<&WorkMode> {
         for mode in self.modes.values() {
             if mode.label() == name {
@@ -130,7 +343,6 @@ impl ParsedWorkMode {
         names
     }
 
-    #[allow(unused)]
     pub fn get_mode_labels(&self) -> Vec<String> {
         let mut names: Vec<_> = self
             .modes
@@ -153,6 +365,30 @@ impl ParsedWorkMode {
     }
 }
 
+/// Some newer devices use string enum values for workMode and
+/// modeValue rather than integers
+fn is_mode_value(value: &JsonValue) -> bool {
+    value.is_i64() || value.is_string()
+}
+
+/// Compares two workMode or modeValue values. A device may report
+/// a value as a string that it advertises as a number, or vice versa,
+/// so those are considered to be equal when their text matches.
+pub fn mode_values_equal(a: &JsonValue, b: &JsonValue) -> bool {
+    fn as_text(value: &JsonValue) -> Option<String> {
+        match value {
+            JsonValue::String(s) => Some(s.to_string()),
+            JsonValue::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+    a == b
+        || match (as_text(a), as_text(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+}
+
 #[derive(Default, Debug)]
 pub struct WorkMode {
     pub name: String,
@@ -172,7 +408,7 @@ pub struct WorkModeValue {
 
 impl WorkMode {
     pub fn add_values(&mut self, opt: &EnumOption) {
-        self.default_value = opt.extras.get("defaultValue").cloned();
+        self.default_value = opt.extra("defaultValue");
 
         #[derive(Deserialize)]
         struct ModeRange {
@@ -180,31 +416,26 @@ impl WorkMode {
             max: i64,
         }
 
-        if let Some(range) = opt.extras.get("range") {
-            if let Ok(range) = serde_json::from_value::<ModeRange>(range.clone()) {
-                self.value_range = Some(range.min..range.max + 1);
-                return;
-            }
+        if let Some(range) = opt.extra::<ModeRange>("range") {
+            self.value_range = Some(range.min..range.max + 1);
+            return;
         }
 
         #[derive(Deserialize)]
-        struct ModeOption {
-            name: Option<String>,
+        struct ModeOption<'a> {
+            #[serde(borrow)]
+            name: Option<&'a str>,
             value: JsonValue,
         }
 
-        let Some(options) = opt.extras.get("options") else {
-            return;
-        };
-
-        let Ok(options) = serde_json::from_value::<Vec<ModeOption>>(options.clone()) else {
+        let Some(options) = opt.extra::<Vec<ModeOption>>("options") else {
             return;
         };
 
         for opt in options {
             self.values.push(WorkModeValue {
                 value: opt.value,
-                name: opt.name,
+                name: opt.name.map(|name| name.to_string()),
                 computed_label: String::new(),
             });
         }
@@ -242,6 +473,20 @@ impl WorkMode {
             .unwrap_or(0)
     }
 
+    /// Like default_value, but allows for string values
+    pub fn default_mode_value(&self) -> JsonValue {
+        self.default_value
+            .clone()
+            .filter(is_mode_value)
+            .or_else(|| {
+                self.values
+                    .first()
+                    .map(|wmv| wmv.value.clone())
+                    .filter(is_mode_value)
+            })
+            .unwrap_or_else(|| self.default_value().into())
+    }
+
     pub fn contiguous_value_range(&self) -> Option<Range<i64>> {
         if let Some(range) = &self.value_range {
             return Some(range.clone());
@@ -281,9 +526,8 @@ impl WorkMode {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::platform_api::{from_json, DeviceCapabilityKind, StructField};
+    use crate::platform_api::{DeviceCapabilityKind, StructField};
     use serde_json::json;
-    use std::collections::HashMap;
 
     #[test]
     fn test_work_mode_parser() {
@@ -596,7 +840,7 @@ ParsedWorkMode {
             from_json(&include_str!("../../test-data/work-mode-issue-100.json")).unwrap();
 
         let mut wm = ParsedWorkMode::with_capability(&cap).unwrap();
-        wm.adjust_for_device("H7173");
+        wm.adjust_for_device(&ServiceDevice::new("H7173", "AA:BB:CC:DD:EE:FF:42:2A"));
 
         k9::snapshot!(
             &wm,
@@ -651,4 +895,206 @@ ParsedWorkMode {
         assert_eq!(wm.mode_by_name("Boiling").unwrap().default_value(), 0);
         assert_eq!(wm.mode_by_name("DIY").unwrap().default_value(), 1);
     }
+
+    #[test]
+    fn active_mode() {
+        let cap: DeviceCapability =
+            from_json(include_str!("../../test-data/work-mode-issue-93.json")).unwrap();
+        let wm = ParsedWorkMode::with_capability(&cap).unwrap();
+
+        let mut device = ServiceDevice::new("H7106", "AA:BB:CC:DD:EE:FF:00:01");
+        assert!(wm.active_mode(&device).is_none());
+
+        // As reported via IoT after changing the mode in the app
+        device.set_humidifier_work_mode_and_param(3, 0);
+        assert_eq!(wm.active_mode(&device).unwrap().name, "Auto");
+    }
+
+    #[test]
+    fn preset_resolution() {
+        let cap: DeviceCapability =
+            from_json(include_str!("../../test-data/work-mode-issue-81.json")).unwrap();
+        let wm = ParsedWorkMode::with_capability(&cap).unwrap();
+        assert_eq!(wm.resolve_preset("Custom"), Some((json!(2), json!(0))));
+        assert_eq!(wm.resolve_preset("Manual"), Some((json!(1), json!(1))));
+        assert_eq!(wm.resolve_preset("Auto"), Some((json!(3), json!(40))));
+        assert_eq!(wm.resolve_preset("Turbo"), None);
+
+        // A default for the modeValue field as a whole, and
+        // presets that are values of a mode
+        let cap: DeviceCapability = serde_json::from_value(json!({
+            "type": "devices.capabilities.work_mode",
+            "instance": "workMode",
+            "parameters": {
+                "dataType": "STRUCT",
+                "fields": [
+                    {
+                        "fieldName": "workMode",
+                        "dataType": "ENUM",
+                        "options": [{"name": "Normal", "value": 1}, {"name": "Sleep", "value": 2}],
+                    },
+                    {
+                        "fieldName": "modeValue",
+                        "dataType": "ENUM",
+                        "defaultValue": 3,
+                        "options": [
+                            {"name": "Normal", "options": [
+                                {"name": "Low", "value": 1},
+                                {"name": "High", "value": 4},
+                            ]},
+                            {"name": "Sleep", "defaultValue": 0},
+                        ],
+                    },
+                ],
+            },
+        }))
+        .unwrap();
+        let wm = ParsedWorkMode::with_capability(&cap).unwrap();
+        assert_eq!(wm.resolve_preset("Normal"), Some((json!(1), json!(3))));
+        assert_eq!(wm.resolve_preset("Sleep"), Some((json!(2), json!(0))));
+        assert_eq!(wm.resolve_preset("High"), Some((json!(1), json!(4))));
+        assert_eq!(
+            wm.resolve_preset("Activate Normal Preset Low"),
+            Some((json!(1), json!(1)))
+        );
+    }
+
+    #[test]
+    fn string_values() {
+        let cap: DeviceCapability = serde_json::from_value(json!({
+            "type": "devices.capabilities.work_mode",
+            "instance": "workMode",
+            "parameters": {
+                "dataType": "STRUCT",
+                "fields": [
+                    {
+                        "fieldName": "workMode",
+                        "dataType": "ENUM",
+                        "options": [
+                            {"name": "Brew", "value": "brew"},
+                            {"name": "KeepWarm", "value": "keep_warm"},
+                            {"name": "Manual", "value": 1},
+                        ],
+                    },
+                    {
+                        "fieldName": "modeValue",
+                        "dataType": "ENUM",
+                        "options": [
+                            {"name": "Brew", "options": [
+                                {"name": "Green Tea", "value": "green"},
+                                {"name": "Black Tea", "value": "black"},
+                            ]},
+                            {"name": "KeepWarm", "defaultValue": "normal"},
+                            {"name": "Manual", "options": [
+                                {"value": 1}, {"value": 2}, {"value": 3},
+                            ]},
+                        ],
+                    },
+                ],
+            },
+        }))
+        .unwrap();
+        let wm = ParsedWorkMode::with_capability(&cap).unwrap();
+
+        let brew = wm.mode_by_name("Brew").unwrap();
+        assert!(brew.contiguous_value_range().is_none());
+        assert_eq!(brew.values.len(), 2);
+        assert_eq!(
+            wm.mode_by_name("Manual").unwrap().contiguous_value_range(),
+            Some(1..4)
+        );
+
+        assert_eq!(
+            wm.mode_for_value(&json!("keep_warm")).unwrap().name,
+            "KeepWarm"
+        );
+        // Reported with a different type than advertised
+        assert_eq!(wm.mode_for_value(&json!("1")).unwrap().name, "Manual");
+        assert!(wm.mode_for_value(&json!("steep")).is_none());
+
+        assert_eq!(
+            wm.resolve_preset("Brew"),
+            Some((json!("brew"), json!("green")))
+        );
+        assert_eq!(
+            wm.resolve_preset("KeepWarm"),
+            Some((json!("keep_warm"), json!("normal")))
+        );
+        assert_eq!(
+            wm.resolve_preset("Activate Brew Preset Black Tea"),
+            Some((json!("brew"), json!("black")))
+        );
+        assert_eq!(wm.resolve_preset("Manual"), Some((json!(1), json!(1))));
+    }
+
+    #[test]
+    fn overrides() {
+        let user: WorkModeOverrides = from_json(
+            r#"{
+                "h7160": {"Manual": {"label": "Mist"}, "Auto": {"label": "Target"}},
+                "H7141": {"Auto": {"humidity_setpoint": false}}
+            }"#,
+        )
+        .unwrap();
+        let overrides = merge_work_mode_overrides(builtin_work_mode_overrides(), user);
+
+        // The user's label replaces the built-in one, while
+        // the built-in setpoint is kept
+        let auto = &overrides["H7160"]["Auto"];
+        assert_eq!(auto.label.as_deref(), Some("Target"));
+        assert_eq!(auto.humidity_setpoint, Some(true));
+        assert_eq!(overrides["H7160"]["Manual"].label.as_deref(), Some("Mist"));
+        assert_eq!(
+            overrides["H7131"]["gearMode"].label.as_deref(),
+            Some("Heat")
+        );
+
+        let cap: DeviceCapability =
+            from_json(include_str!("../../test-data/work-mode-issue-81.json")).unwrap();
+        let mut wm = ParsedWorkMode::with_capability(&cap).unwrap();
+        wm.apply_overrides("H7160", "AA:BB:CC:DD:EE:FF:42:2A", &overrides);
+        assert_eq!(wm.mode_by_name("Manual").unwrap().label(), "Mist");
+        assert_eq!(wm.mode_by_name("Auto").unwrap().label(), "Target");
+
+        assert!(
+            from_json::<WorkModeOverrides, _>(r#"{"H7160": {"Auto": {"lable": "x"}}}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn device_overrides() {
+        let user: WorkModeOverrides = from_json(
+            r#"{
+                "H7160": {"Custom": {"label": "Program"}},
+                "aabbccddeeff422a": {
+                    "Custom": {"hidden": true},
+                    "Manual": {"label": "Mist", "presentation": "presets"}
+                }
+            }"#,
+        )
+        .unwrap();
+        let overrides = merge_work_mode_overrides(builtin_work_mode_overrides(), user);
+
+        // The device's own override is merged over that of its SKU
+        let manual = resolve_override(&overrides, "H7160", "AA:BB:CC:DD:EE:FF:42:2A", "Manual");
+        assert_eq!(manual.label.as_deref(), Some("Mist"));
+        assert_eq!(manual.presentation, Some(WorkModePresentation::Presets));
+
+        let cap: DeviceCapability =
+            from_json(include_str!("../../test-data/work-mode-issue-81.json")).unwrap();
+
+        let mut wm = ParsedWorkMode::with_capability(&cap).unwrap();
+        wm.apply_overrides("H7160", "AA:BB:CC:DD:EE:FF:42:2A", &overrides);
+        assert!(wm.mode_by_name("Custom").is_none());
+        assert_eq!(wm.mode_by_name("Manual").unwrap().label(), "Mist");
+
+        // Another device of the same SKU is unaffected
+        let mut wm = ParsedWorkMode::with_capability(&cap).unwrap();
+        wm.apply_overrides("H7160", "11:22:33:44:55:66:77:88", &overrides);
+        assert_eq!(wm.mode_by_name("Custom").unwrap().label(), "Program");
+        assert_eq!(
+            wm.mode_by_name("Manual").unwrap().label(),
+            "Manual: Mist Level"
+        );
+    }
 }
@@ -1,6 +1,7 @@
 use crate::ble::{Base64HexBytes, SetSceneCode};
 use crate::opt_env_var;
 use crate::platform_api::from_json;
+use crate::service::command_result::{note_transport, Transport};
 use crate::undoc_api::GoveeUndocumentedApi;
 use anyhow::Context;
 use if_addrs::IfAddr;
@@ -194,6 +195,7 @@ pub struct LanDevice {
 impl LanDevice {
     pub async fn send_request(&self, msg: Request) -> anyhow::Result<()> {
         log::trace!("LanDevice::send_request to {:?} {msg:?}", self.ip);
+        note_transport(Transport::Lan);
         let client = udp_socket_for_target(self.ip).await?;
         let data = serde_json::to_string(&RequestMessage { msg })?;
         client.send_to(data.as_bytes(), (self.ip, CMD_PORT)).await?;
@@ -309,6 +311,14 @@ struct ResponseWrapper {
     msg: Response,
 }
 
+/// Parse a datagram received from a device. Devices with buggy firmware
+/// can send all sorts of junk, so this must never panic, whatever
+/// the input; see `fuzz/fuzz_targets/lan_packet.rs`.
+pub fn parse_response(data: &[u8]) -> anyhow::Result<Response> {
+    let response: ResponseWrapper = from_json(data)?;
+    Ok(response.msg)
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum AccountTopic {
     #[serde(rename = "reserve")]
@@ -455,18 +465,25 @@ async fn lan_disco(
             String::from_utf8_lossy(data)
         );
 
-        let response: ResponseWrapper = from_json(data)
-            .with_context(|| format!("Parsing: {}", String::from_utf8_lossy(data)))?;
+        let response = match parse_response(data) {
+            Ok(response) => response,
+            Err(err) => {
+                // Skip it, rather than letting one misbehaving
+                // device interfere with the rest of the scan
+                log::warn!("Ignoring unparseable packet from {addr}: {err:#}");
+                return Ok(());
+            }
+        };
 
         let mut mux = inner.mux.lock().await;
         mux.retain(|l| !l.tx.is_closed());
         for l in mux.iter() {
             if l.addr == addr.ip() {
-                l.tx.send(response.msg.clone()).await.ok();
+                l.tx.send(response.clone()).await.ok();
             }
         }
 
-        if let Response::Scan(info) = response.msg {
+        if let Response::Scan(info) = response {
             tx.send(info).await?;
         }
 
@@ -578,3 +595,114 @@ impl Client {
         anyhow::bail!("timed out waiting for status");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCAN: &str = r#"{"msg":{"cmd":"scan","data":{"ip":"10.0.0.2","device":"AA:BB:CC:DD:EE:FF:42:2A","sku":"H6072","bleVersionHard":"3.01.01","bleVersionSoft":"1.03.01","wifiVersionHard":"1.00.10","wifiVersionSoft":"1.02.03"}}}"#;
+    const STATUS: &str = r#"{"msg":{"cmd":"devStatus","data":{"onOff":1,"brightness":100,"color":{"r":255,"g":0,"b":0},"colorTemInKelvin":7200}}}"#;
+
+    /// A small deterministic generator, so that failures can be reproduced
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn parse_valid_packets() {
+        match parse_response(SCAN.as_bytes()).unwrap() {
+            Response::Scan(info) => {
+                assert_eq!(info.sku, "H6072");
+                assert_eq!(info.ip, "10.0.0.2".parse::<IpAddr>().unwrap());
+            }
+            other => panic!("expected scan, got {other:?}"),
+        }
+        match parse_response(STATUS.as_bytes()).unwrap() {
+            Response::DevStatus(status) => {
+                assert!(status.on);
+                assert_eq!(status.color, DeviceColor { r: 255, g: 0, b: 0 });
+            }
+            other => panic!("expected devStatus, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn status_round_trip() {
+        let mut rng = XorShift(0x5eed);
+        for _ in 0..1000 {
+            let status = DeviceStatus {
+                on: rng.next() & 1 == 1,
+                brightness: rng.next() as u8,
+                color: DeviceColor {
+                    r: rng.next() as u8,
+                    g: rng.next() as u8,
+                    b: rng.next() as u8,
+                },
+                color_temperature_kelvin: rng.next() as u32,
+            };
+            let data = serde_json::to_vec(&ResponseWrapper {
+                msg: Response::DevStatus(status.clone()),
+            })
+            .unwrap();
+            match parse_response(&data).unwrap() {
+                Response::DevStatus(parsed) => assert_eq!(parsed, status),
+                other => panic!("expected devStatus, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected() {
+        for packet in [SCAN, STATUS] {
+            let packet = packet.as_bytes();
+            for len in 0..packet.len() {
+                assert!(parse_response(&packet[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_packets_do_not_panic() {
+        let mut rng = XorShift(0xbad_f00d);
+        let interesting = b"{}[]\":,0-1e9\\ \xff\x00";
+        for _ in 0..5000 {
+            let mut packet = if rng.next() & 1 == 0 { SCAN } else { STATUS }
+                .as_bytes()
+                .to_vec();
+            for _ in 0..1 + rng.below(4) {
+                let pos = rng.below(packet.len());
+                match rng.below(3) {
+                    0 => packet[pos] = rng.next() as u8,
+                    1 => packet.insert(pos, interesting[rng.below(interesting.len())]),
+                    _ => {
+                        packet.remove(pos);
+                    }
+                }
+            }
+            let _ = parse_response(&packet);
+        }
+
+        // Values that are out of range for their fields
+        for data in [
+            r#"{"msg":{"cmd":"devStatus","data":{"onOff":1,"brightness":256,"color":{"r":0,"g":0,"b":0},"colorTemInKelvin":0}}}"#,
+            r#"{"msg":{"cmd":"devStatus","data":{"onOff":1e400,"brightness":1,"color":{"r":0,"g":0,"b":0},"colorTemInKelvin":0}}}"#,
+            r#"{"msg":{"cmd":"devStatus","data":{"onOff":"yes","brightness":1,"color":{"r":0,"g":0,"b":0},"colorTemInKelvin":-1}}}"#,
+            r#"{"msg":{"cmd":"scan","data":{"ip":"999.0.0.1"}}}"#,
+            r#"{"msg":{"cmd":"unknown","data":{}}}"#,
+            r#"{"msg":null}"#,
+        ] {
+            assert!(parse_response(data.as_bytes()).is_err(), "{data}");
+        }
+    }
+}
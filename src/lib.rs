@@ -0,0 +1,125 @@
+use crate::lan_api::LanDiscoArguments;
+use crate::platform_api::GoveeApiArguments;
+use crate::service::config::ConfigArguments;
+use crate::service::hass::HassArguments;
+use crate::undoc_api::UndocApiArguments;
+use std::str::FromStr;
+
+pub(crate) mod api_log;
+pub(crate) mod ble;
+pub(crate) mod cache;
+pub(crate) mod commands;
+pub(crate) mod hass_mqtt;
+pub(crate) mod lan_api;
+#[macro_use]
+pub(crate) mod platform_api;
+pub(crate) mod rest_api;
+pub(crate) mod service;
+pub(crate) mod temperature;
+pub(crate) mod undoc_api;
+pub(crate) mod version_info;
+
+// Used by the benchmarks
+pub use crate::hass_mqtt::work_mode::ParsedWorkMode;
+pub use crate::platform_api::{from_json, DeviceCapability, HttpDeviceInfo};
+
+#[derive(clap::Parser, Debug)]
+#[command(version = version_info::govee_version(),  propagate_version=true)]
+pub struct Args {
+    #[command(flatten)]
+    api_args: GoveeApiArguments,
+    #[command(flatten)]
+    lan_disco_args: LanDiscoArguments,
+    #[command(flatten)]
+    undoc_args: UndocApiArguments,
+    #[command(flatten)]
+    hass_args: HassArguments,
+    #[command(flatten)]
+    config_args: ConfigArguments,
+
+    /// Log the bodies of the requests made to, and the responses
+    /// received from, the Govee APIs, with credentials redacted.
+    /// You may also set GOVEE_LOG_API=true via the environment.
+    #[arg(long, global = true)]
+    log_api: bool,
+
+    /// Fail when a document from the Govee platform API has malformed
+    /// entries, rather than skipping them with a warning. This is
+    /// intended for development.
+    /// You may also set GOVEE_STRICT_PARSING=true via the environment.
+    #[arg(long, global = true)]
+    strict_parsing: bool,
+
+    #[command(subcommand)]
+    cmd: SubCommand,
+}
+
+#[derive(clap::Parser, Debug)]
+pub enum SubCommand {
+    Backfill(commands::backfill::BackfillCommand),
+    CaptureFixture(commands::capture_fixture::CaptureFixtureCommand),
+    Export(commands::export::ExportCommand),
+    LanControl(commands::lan_control::LanControlCommand),
+    LanDisco(commands::lan_disco::LanDiscoCommand),
+    ListHttp(commands::list_http::ListHttpCommand),
+    List(commands::list::ListCommand),
+    Loadtest(commands::loadtest::LoadTestCommand),
+    HttpControl(commands::http_control::HttpControlCommand),
+    Serve(commands::serve::ServeCommand),
+    SupportMatrix(commands::support_matrix::SupportMatrixCommand),
+    Undoc(commands::undoc::UndocCommand),
+}
+
+impl Args {
+    fn log_api(&self) -> anyhow::Result<bool> {
+        match opt_env_var::<String>("GOVEE_LOG_API")? {
+            Some(v) => lan_api::truthy(&v),
+            None => Ok(self.log_api),
+        }
+    }
+
+    fn strict_parsing(&self) -> anyhow::Result<bool> {
+        match opt_env_var::<String>("GOVEE_STRICT_PARSING")? {
+            Some(v) => lan_api::truthy(&v),
+            None => Ok(self.strict_parsing),
+        }
+    }
+
+    pub async fn run(&self) -> anyhow::Result<()> {
+        if self.log_api()? {
+            api_log::enable();
+        }
+        if self.strict_parsing()? {
+            platform_api::enable_strict_parsing();
+        }
+        match &self.cmd {
+            SubCommand::Backfill(cmd) => cmd.run(self).await,
+            SubCommand::CaptureFixture(cmd) => cmd.run(self).await,
+            SubCommand::Export(cmd) => cmd.run(self).await,
+            SubCommand::LanControl(cmd) => cmd.run(self).await,
+            SubCommand::LanDisco(cmd) => cmd.run(self).await,
+            SubCommand::ListHttp(cmd) => cmd.run(self).await,
+            SubCommand::HttpControl(cmd) => cmd.run(self).await,
+            SubCommand::List(cmd) => cmd.run(self).await,
+            SubCommand::Loadtest(cmd) => cmd.run(self).await,
+            SubCommand::Serve(cmd) => cmd.run(self).await,
+            SubCommand::SupportMatrix(cmd) => cmd.run(self).await,
+            SubCommand::Undoc(cmd) => cmd.run(self).await,
+        }
+    }
+}
+
+pub fn opt_env_var<T: FromStr>(name: &str) -> anyhow::Result<Option<T>>
+where
+    <T as FromStr>::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(p) => {
+            Ok(Some(p.parse().map_err(|err| {
+                anyhow::anyhow!("parsing ${name}: {err:#}")
+            })?))
+        }
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => anyhow::bail!("${name} is invalid: {err:#}"),
+    }
+}
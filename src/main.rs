@@ -1,78 +1,5 @@
-use crate::lan_api::LanDiscoArguments;
-use crate::platform_api::GoveeApiArguments;
-use crate::service::hass::HassArguments;
-use crate::undoc_api::UndocApiArguments;
 use clap::Parser;
-use std::str::FromStr;
-
-mod ble;
-mod cache;
-mod commands;
-mod hass_mqtt;
-mod lan_api;
-#[macro_use]
-mod platform_api;
-mod rest_api;
-mod service;
-mod temperature;
-mod undoc_api;
-mod version_info;
-
-#[derive(clap::Parser, Debug)]
-#[command(version = version_info::govee_version(),  propagate_version=true)]
-pub struct Args {
-    #[command(flatten)]
-    api_args: GoveeApiArguments,
-    #[command(flatten)]
-    lan_disco_args: LanDiscoArguments,
-    #[command(flatten)]
-    undoc_args: UndocApiArguments,
-    #[command(flatten)]
-    hass_args: HassArguments,
-
-    #[command(subcommand)]
-    cmd: SubCommand,
-}
-
-#[derive(clap::Parser, Debug)]
-pub enum SubCommand {
-    LanControl(commands::lan_control::LanControlCommand),
-    LanDisco(commands::lan_disco::LanDiscoCommand),
-    ListHttp(commands::list_http::ListHttpCommand),
-    List(commands::list::ListCommand),
-    HttpControl(commands::http_control::HttpControlCommand),
-    Serve(commands::serve::ServeCommand),
-    Undoc(commands::undoc::UndocCommand),
-}
-
-impl Args {
-    pub async fn run(&self) -> anyhow::Result<()> {
-        match &self.cmd {
-            SubCommand::LanControl(cmd) => cmd.run(self).await,
-            SubCommand::LanDisco(cmd) => cmd.run(self).await,
-            SubCommand::ListHttp(cmd) => cmd.run(self).await,
-            SubCommand::HttpControl(cmd) => cmd.run(self).await,
-            SubCommand::List(cmd) => cmd.run(self).await,
-            SubCommand::Serve(cmd) => cmd.run(self).await,
-            SubCommand::Undoc(cmd) => cmd.run(self).await,
-        }
-    }
-}
-
-pub fn opt_env_var<T: FromStr>(name: &str) -> anyhow::Result<Option<T>>
-where
-    <T as FromStr>::Err: std::fmt::Display,
-{
-    match std::env::var(name) {
-        Ok(p) => {
-            Ok(Some(p.parse().map_err(|err| {
-                anyhow::anyhow!("parsing ${name}: {err:#}")
-            })?))
-        }
-        Err(std::env::VarError::NotPresent) => Ok(None),
-        Err(err) => anyhow::bail!("${name} is invalid: {err:#}"),
-    }
-}
+use govee::Args;
 
 fn setup_logger() {
     fn resolve_timezone() -> chrono_tz::Tz {
@@ -1,15 +1,21 @@
-use crate::cache::{cache_get, CacheComputeResult, CacheGetOptions};
+use crate::api_log::LoggedJson;
+use crate::cache::{cache_get, invalidate_key, CacheComputeResult, CacheGetOptions};
 use crate::hass_mqtt::climate::parse_temperature_constraints;
 use crate::opt_env_var;
+use crate::service::command_result::{note_transport, Transport};
 use crate::service::state::sort_and_dedup_scenes;
 use crate::temperature::{TemperatureUnits, TemperatureValue};
 use crate::undoc_api::GoveeUndocumentedApi;
 use anyhow::Context;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
 use reqwest::Method;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{json, Value as JsonValue};
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 // This file implements the Govee Platform API V1 as described at:
@@ -22,6 +28,12 @@ use thiserror::Error;
 const SERVER: &str = "https://openapi.api.govee.com";
 pub const ONE_WEEK: Duration = Duration::from_secs(86400 * 7);
 pub const FIVE_MINUTES: Duration = Duration::from_secs(5 * 60);
+const ONE_DAY: Duration = Duration::from_secs(86400);
+const ONE_HOUR: Duration = Duration::from_secs(3600);
+
+/// The number of requests that Govee permits each account to make
+/// to the platform API per day
+pub const PLATFORM_API_DAILY_QUOTA: usize = 10000;
 
 fn endpoint(url: &str) -> String {
     format!("{SERVER}{url}")
@@ -33,9 +45,60 @@ pub struct GoveeApiArguments {
     /// the GOVEE_API_KEY environment variable.
     #[arg(long, global = true)]
     pub api_key: Option<String>,
+
+    /// What the API key is used for: `all`, `control` or `state`.
+    /// The device list and scenes are fetched via the API key in
+    /// any case. If not passed here, it will be read from the
+    /// GOVEE_API_KEY_USAGE environment variable. The default is `all`.
+    #[arg(long, global = true)]
+    pub api_key_usage: Option<PlatformApiUsage>,
+}
+
+/// What the platform API may be used for. Rationing the quota by
+/// leaving control, or the polling of state, to the LAN and IoT
+/// APIs only makes sense for devices that they support.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlatformApiUsage {
+    #[default]
+    All,
+    /// Only to control devices; their state comes from LAN and IoT
+    Control,
+    /// Only to poll the state of devices
+    State,
+}
+
+impl PlatformApiUsage {
+    pub fn allows_control(self) -> bool {
+        matches!(self, Self::All | Self::Control)
+    }
+
+    pub fn allows_state(self) -> bool {
+        matches!(self, Self::All | Self::State)
+    }
+}
+
+impl std::str::FromStr for PlatformApiUsage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "control" => Ok(Self::Control),
+            "state" => Ok(Self::State),
+            _ => anyhow::bail!("invalid API key usage {s:?}; expected all, control or state"),
+        }
+    }
 }
 
 impl GoveeApiArguments {
+    pub fn api_key_usage(&self) -> anyhow::Result<PlatformApiUsage> {
+        match self.api_key_usage {
+            Some(usage) => Ok(usage),
+            None => Ok(opt_env_var("GOVEE_API_KEY_USAGE")?.unwrap_or_default()),
+        }
+    }
+
     pub fn opt_api_key(&self) -> anyhow::Result<Option<String>> {
         match &self.api_key {
             Some(key) => Ok(Some(key.to_string())),
@@ -58,14 +121,88 @@ impl GoveeApiArguments {
     }
 }
 
+/// Remembers when the platform API requests of the last 24 hours
+/// were made, so that we can tell how much of the quota is in use
+#[derive(Default, Debug)]
+pub struct RequestTally {
+    times: Mutex<VecDeque<Instant>>,
+    /// The number of requests that have failed since startup
+    errors: AtomicU64,
+}
+
+impl RequestTally {
+    pub fn record(&self) {
+        self.record_at(Instant::now());
+    }
+
+    /// The number of requests made in the last 24 hours
+    pub fn last_day(&self) -> usize {
+        self.last_day_at(Instant::now())
+    }
+
+    /// The number of requests made in the last hour
+    pub fn last_hour(&self) -> usize {
+        self.last_hour_at(Instant::now())
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of requests that have failed since startup
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    fn record_at(&self, now: Instant) {
+        let mut times = self.times.lock();
+        times.push_back(now);
+        Self::expire(&mut times, now);
+    }
+
+    fn last_day_at(&self, now: Instant) -> usize {
+        let mut times = self.times.lock();
+        Self::expire(&mut times, now);
+        times.len()
+    }
+
+    fn last_hour_at(&self, now: Instant) -> usize {
+        let mut times = self.times.lock();
+        Self::expire(&mut times, now);
+        times
+            .iter()
+            .rev()
+            .take_while(|t| now.saturating_duration_since(**t) < ONE_HOUR)
+            .count()
+    }
+
+    fn expire(times: &mut VecDeque<Instant>, now: Instant) {
+        while let Some(oldest) = times.front() {
+            if now.saturating_duration_since(*oldest) < ONE_DAY {
+                break;
+            }
+            times.pop_front();
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct GoveeApiClient {
     key: String,
+    tally: Arc<RequestTally>,
 }
 
 impl GoveeApiClient {
     pub fn new<K: Into<String>>(key: K) -> Self {
-        Self { key: key.into() }
+        Self {
+            key: key.into(),
+            tally: Arc::default(),
+        }
+    }
+
+    /// Count the requests made by this client in `tally`
+    pub fn with_request_tally(self, tally: Arc<RequestTally>) -> Self {
+        Self { tally, ..self }
     }
 
     pub async fn get_devices(&self) -> anyhow::Result<Vec<HttpDeviceInfo>> {
@@ -105,6 +242,7 @@ impl GoveeApiClient {
         value: V,
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let url = endpoint("/router/api/v1/device/control");
+        note_transport(Transport::PlatformApi);
         let request = ControlDeviceRequest {
             request_id: "uuid".to_string(),
             payload: ControlDevicePayload {
@@ -113,7 +251,7 @@ impl GoveeApiClient {
                 capability: ControlDeviceCapability {
                     kind: capability.kind.clone(),
                     instance: capability.instance.to_string(),
-                    value: value.into(),
+                    value: capability.clamp_value(value.into()),
                 },
             },
         };
@@ -223,6 +361,19 @@ impl GoveeApiClient {
         .await
     }
 
+    /// Discard the cached scene catalogs for a device, so that
+    /// they are fetched afresh when next needed
+    pub fn invalidate_scene_caches(device: &HttpDeviceInfo) -> anyhow::Result<()> {
+        invalidate_key(
+            "http-api",
+            &format!("scene-list-{}-{}", device.sku, device.device),
+        )?;
+        invalidate_key(
+            "http-api",
+            &format!("scene-list-diy-{}-{}", device.sku, device.device),
+        )
+    }
+
     pub async fn get_scene_caps(
         &self,
         device: &HttpDeviceInfo,
@@ -323,6 +474,21 @@ impl GoveeApiClient {
         Ok(sort_and_dedup_scenes(result))
     }
 
+    /// Returns the names of the DIY scenes that the user has
+    /// created for the device in the Govee app
+    pub async fn list_diy_scene_names(
+        &self,
+        device: &HttpDeviceInfo,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut result = vec![];
+        for cap in self.get_device_diy_scenes(device).await? {
+            if let Some(DeviceParameters::Enum { options }) = &cap.parameters {
+                result.extend(options.iter().map(|opt| opt.name.to_string()));
+            }
+        }
+        Ok(sort_and_dedup_scenes(result))
+    }
+
     pub async fn set_scene_by_name(
         &self,
         device: &HttpDeviceInfo,
@@ -337,12 +503,7 @@ impl GoveeApiClient {
             if let Some(cap) = device.capability_by_instance("musicMode") {
                 if let Some(field) = cap.struct_field_by_name("musicMode") {
                     if let Some(value) = field.field_type.enum_parameter_by_name(music_mode) {
-                        let value = serde_json::json!({
-                            "musicMode": value,
-                            "sensitivity": 100,
-                            "autoColor": 1,
-                        });
-                        return self.control_device(&device, &cap, value).await;
+                        return self.set_music_mode(device, value.into(), 100).await;
                     }
                 }
             }
@@ -364,6 +525,25 @@ impl GoveeApiClient {
         anyhow::bail!("Scene '{scene}' is not available for this device");
     }
 
+    pub async fn set_music_mode(
+        &self,
+        device: &HttpDeviceInfo,
+        music_mode: i64,
+        sensitivity: i64,
+    ) -> anyhow::Result<ControlDeviceResponseCapability> {
+        let cap = device
+            .capability_by_instance("musicMode")
+            .ok_or_else(|| anyhow::anyhow!("device has no musicMode"))?;
+
+        let value = json!({
+            "musicMode": music_mode,
+            "sensitivity": sensitivity,
+            "autoColor": 1,
+        });
+
+        self.control_device(device, cap, value).await
+    }
+
     pub async fn set_target_temperature(
         &self,
         device: &HttpDeviceInfo,
@@ -378,17 +558,17 @@ impl GoveeApiClient {
 
         let min = constraints.min.as_celsius();
         let max = constraints.max.as_celsius();
-        let celsius = target.as_celsius().max(min).min(max);
+        let celsius = target.as_celsius();
         let clamped = celsius.max(min).min(max);
         if clamped != celsius {
-            log::info!(
+            log::warn!(
                 "set_target_temperature: constraining requested {celsius} to \
                        {clamped} because min={min} and max={max}"
             );
         }
 
         let value = json!({
-            "temperature": celsius,
+            "temperature": clamped,
             "unit": "Celsius",
         });
 
@@ -398,8 +578,8 @@ impl GoveeApiClient {
     pub async fn set_work_mode(
         &self,
         device: &HttpDeviceInfo,
-        work_mode: i64,
-        value: i64,
+        work_mode: JsonValue,
+        value: JsonValue,
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance("workMode")
@@ -446,14 +626,7 @@ impl GoveeApiClient {
         let cap = device
             .capability_by_instance("brightness")
             .ok_or_else(|| anyhow::anyhow!("device has no brightness"))?;
-        let value = match &cap.parameters {
-            Some(DeviceParameters::Integer {
-                range: IntegerRange { min, max, .. },
-                ..
-            }) => (percent as u32).max(*min).min(*max),
-            _ => anyhow::bail!("unexpected parameter type for brightness"),
-        };
-        self.control_device(&device, &cap, value).await
+        self.control_device(device, cap, percent).await
     }
 
     pub async fn set_color_temperature(
@@ -464,14 +637,7 @@ impl GoveeApiClient {
         let cap = device
             .capability_by_instance("colorTemperatureK")
             .ok_or_else(|| anyhow::anyhow!("device has no colorTemperatureK"))?;
-        let value = match &cap.parameters {
-            Some(DeviceParameters::Integer {
-                range: IntegerRange { min, max, .. },
-                ..
-            }) => (kelvin).max(*min).min(*max),
-            _ => anyhow::bail!("unexpected parameter type for colorTemperatureK"),
-        };
-        self.control_device(&device, &cap, value).await
+        self.control_device(device, cap, kelvin).await
     }
 
     pub async fn set_color_rgb(
@@ -555,6 +721,7 @@ struct GetDeviceScenesResponse {
 struct GetDeviceScenesResponsePayload {
     pub sku: String,
     pub device: String,
+    #[serde(deserialize_with = "lenient_list")]
     pub capabilities: Vec<DeviceCapability>,
 }
 
@@ -644,6 +811,7 @@ struct GetDeviceStateResponse {
 pub struct HttpDeviceState {
     pub sku: String,
     pub device: String,
+    #[serde(deserialize_with = "lenient_list")]
     pub capabilities: Vec<DeviceCapabilityState>,
 }
 
@@ -670,6 +838,7 @@ pub struct DeviceCapabilityState {
 struct GetDevicesResponse {
     pub code: u32,
     pub message: String,
+    #[serde(deserialize_with = "lenient_list")]
     pub data: Vec<HttpDeviceInfo>,
 }
 
@@ -682,6 +851,7 @@ pub struct HttpDeviceInfo {
     pub device_name: String,
     #[serde(default, rename = "type")]
     pub device_type: DeviceType,
+    #[serde(deserialize_with = "lenient_list")]
     pub capabilities: Vec<DeviceCapability>,
 }
 
@@ -879,6 +1049,90 @@ impl DeviceCapability {
             _ => None,
         }
     }
+
+    /// Constrain the numbers in a value that is about to be sent to
+    /// the device to the ranges declared by the capability, as the
+    /// API rejects out of range values with unhelpful errors.
+    /// Clamping is logged, as it indicates either a quirk of the
+    /// device or a bug in how we computed the value.
+    pub fn clamp_value(&self, mut value: JsonValue) -> JsonValue {
+        match &self.parameters {
+            Some(DeviceParameters::Integer { range, .. }) => {
+                clamp_number(
+                    &self.instance,
+                    &mut value,
+                    range.min as i64,
+                    range.max as i64,
+                );
+            }
+            Some(DeviceParameters::Struct { fields }) => {
+                // A temperature may be given in a different unit from
+                // the one that the range is declared in
+                if value.get("unit").is_some() {
+                    return value;
+                }
+                for field in fields {
+                    if let DeviceParameters::Integer { range, .. } = &field.field_type {
+                        if let Some(v) = value.get_mut(&field.field_name) {
+                            clamp_number(
+                                &format!("{}.{}", self.instance, field.field_name),
+                                v,
+                                range.min as i64,
+                                range.max as i64,
+                            );
+                        }
+                    }
+                }
+                if let Some((min, max)) = self.mode_value_range(&value) {
+                    if let Some(v) = value.get_mut("modeValue") {
+                        clamp_number(&format!("{}.modeValue", self.instance), v, min, max);
+                    }
+                }
+            }
+            _ => {}
+        }
+        value
+    }
+
+    /// The range declared for the modeValue of the workMode in `value`
+    fn mode_value_range(&self, value: &JsonValue) -> Option<(i64, i64)> {
+        #[derive(Deserialize)]
+        struct ModeRange {
+            min: i64,
+            max: i64,
+        }
+
+        let work_mode = value.get("workMode")?;
+        let mode_name = match &self.struct_field_by_name("workMode")?.field_type {
+            DeviceParameters::Enum { options } => {
+                &options.iter().find(|opt| opt.value == *work_mode)?.name
+            }
+            _ => return None,
+        };
+        match &self.struct_field_by_name("modeValue")?.field_type {
+            DeviceParameters::Enum { options } => {
+                let range: ModeRange = options
+                    .iter()
+                    .find(|opt| opt.name == *mode_name)?
+                    .extra("range")?;
+                Some((range.min, range.max))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn clamp_number(label: &str, value: &mut JsonValue, min: i64, max: i64) {
+    let Some(n) = value.as_i64() else {
+        return;
+    };
+    let clamped = n.clamp(min, max);
+    if clamped != n {
+        log::warn!(
+            "{label}: constraining requested {n} to {clamped} because min={min} and max={max}"
+        );
+        *value = clamped.into();
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -953,7 +1207,20 @@ pub struct ArraySize {
 pub struct IntegerRange {
     pub min: u32,
     pub max: u32,
-    pub precision: u32,
+    /// The increment between valid values
+    pub precision: f64,
+}
+
+impl IntegerRange {
+    /// The step for a number entity; a missing or bogus
+    /// precision is treated as whole numbers
+    pub fn step(&self) -> f32 {
+        if self.precision > 0.0 {
+            self.precision as f32
+        } else {
+            1.0
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -965,14 +1232,91 @@ pub struct EnumOption {
     pub extras: HashMap<String, JsonValue>,
 }
 
+impl EnumOption {
+    /// Deserialize the named extra field directly from the parsed
+    /// document, borrowing from it rather than cloning it first.
+    /// Returns None if the field is absent or doesn't match `T`.
+    pub fn extra<'a, T: Deserialize<'a>>(&'a self, name: &str) -> Option<T> {
+        T::deserialize(self.extras.get(name)?).ok()
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
 pub struct ArrayOption {
     pub value: u32,
 }
 
+static STRICT_PARSING: OnceCell<()> = OnceCell::new();
+
+/// Treat malformed entries in platform API documents as errors, rather
+/// than skipping them, for the remainder of the process. This is meant
+/// for development, where a loud failure is more useful than a device
+/// that quietly lacks an entity.
+pub fn enable_strict_parsing() {
+    if STRICT_PARSING.set(()).is_ok() {
+        log::info!("Strict parsing of platform API documents is enabled");
+    }
+}
+
+pub fn is_strict_parsing() -> bool {
+    STRICT_PARSING.get().is_some()
+}
+
+/// A short description of a list entry for use in warnings,
+/// naming the device or capability where possible
+fn describe_entry(value: &JsonValue, index: usize) -> String {
+    for field in ["instance", "device"] {
+        if let Some(name) = value.get(field).and_then(|v| v.as_str()) {
+            return format!("{field} {name}");
+        }
+    }
+    format!("entry {index}")
+}
+
+/// Parse each of `values` as a `T`. Entries that fail to parse are
+/// skipped with a warning, unless `strict` is set, in which case the
+/// first of them is returned as an error.
+fn salvage_list<T: serde::de::DeserializeOwned>(
+    values: Vec<JsonValue>,
+    strict: bool,
+) -> anyhow::Result<Vec<T>> {
+    let type_name = std::any::type_name::<T>();
+    let mut result = Vec::with_capacity(values.len());
+    for (index, value) in values.into_iter().enumerate() {
+        let label = describe_entry(&value, index);
+        let text = value.to_string();
+        match serde_json_path_to_error::from_value(value) {
+            Ok(item) => result.push(item),
+            Err(err) if strict => anyhow::bail!("{type_name} {label}: {err}. Input: {text}"),
+            Err(err) => {
+                log::warn!(
+                    "Skipping malformed {type_name} {label}: {err}. Input: {text}. \
+                     Use --strict-parsing to treat this as an error"
+                );
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// A `deserialize_with` helper for lists in platform API documents,
+/// so that one malformed entry doesn't cost us the rest of them
+fn lenient_list<'de, D: Deserializer<'de>, T: serde::de::DeserializeOwned>(
+    deserializer: D,
+) -> Result<Vec<T>, D::Error> {
+    let values = Vec::<JsonValue>::deserialize(deserializer)?;
+    salvage_list(values, is_strict_parsing()).map_err(serde::de::Error::custom)
+}
+
 pub fn from_json<T: serde::de::DeserializeOwned, S: AsRef<[u8]>>(text: S) -> anyhow::Result<T> {
     let text = text.as_ref();
+    // Tracking the path through the document is comparatively expensive,
+    // and large accounts parse a lot of capability data at startup, so
+    // only pay for it when we need to report an error.
+    if let Ok(value) = serde_json::from_slice(text) {
+        return Ok(value);
+    }
     serde_json_path_to_error::from_slice(text).map_err(|err| {
         anyhow::anyhow!(
             "{} {err}. Input: {}",
@@ -1008,10 +1352,12 @@ pub async fn json_body<T: serde::de::DeserializeOwned>(
     response: reqwest::Response,
 ) -> anyhow::Result<T> {
     let url = response.url().clone();
+    let status = response.status();
     let data = response
         .bytes()
         .await
         .with_context(|| format!("read {url} response body"))?;
+    crate::api_log::log_response(&url, status, &data);
 
     if let Ok(status) = from_json::<EmbeddedRequestStatus, _>(&data) {
         if status.status != reqwest::StatusCode::OK.as_u16() {
@@ -1053,6 +1399,7 @@ pub async fn http_response_body<R: serde::de::DeserializeOwned>(
                 status.canonical_reason().unwrap_or("")
             )
         })?;
+        crate::api_log::log_response(&url, status, &body_bytes);
 
         anyhow::bail!(
             "request {url} status {}: {}. Response body: {}",
@@ -1075,15 +1422,23 @@ impl GoveeApiClient {
         &self,
         url: T,
     ) -> anyhow::Result<R> {
-        let response = reqwest::Client::builder()
-            .timeout(Duration::from_secs(60))
-            .build()?
-            .request(Method::GET, url)
-            .header("Govee-API-Key", &self.key)
-            .send()
-            .await?;
-
-        http_response_body(response).await
+        self.tally.record();
+        let result = async {
+            let response = reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()?
+                .request(Method::GET, url)
+                .header("Govee-API-Key", &self.key)
+                .send()
+                .await?;
+
+            http_response_body(response).await
+        }
+        .await;
+        if result.is_err() {
+            self.tally.record_error();
+        }
+        result
     }
 
     async fn request_with_json_response<
@@ -1096,16 +1451,24 @@ impl GoveeApiClient {
         url: T,
         body: &B,
     ) -> anyhow::Result<R> {
-        let response = reqwest::Client::builder()
-            .timeout(Duration::from_secs(60))
-            .build()?
-            .request(method, url)
-            .header("Govee-API-Key", &self.key)
-            .json(body)
-            .send()
-            .await?;
-
-        http_response_body(response).await
+        self.tally.record();
+        let result = async {
+            let response = reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()?
+                .request(method, url)
+                .header("Govee-API-Key", &self.key)
+                .logged_json(body)
+                .send()
+                .await?;
+
+            http_response_body(response).await
+        }
+        .await;
+        if result.is_err() {
+            self.tally.record_error();
+        }
+        result
     }
 }
 
@@ -1113,6 +1476,29 @@ impl GoveeApiClient {
 mod test {
     use super::*;
 
+    #[test]
+    fn request_tally() {
+        let tally = RequestTally::default();
+        let start = Instant::now();
+        tally.record_at(start);
+        tally.record_at(start + Duration::from_secs(3600));
+        assert_eq!(tally.last_day_at(start + Duration::from_secs(7200)), 2);
+        assert_eq!(tally.last_day_at(start + ONE_DAY), 1);
+        assert_eq!(
+            tally.last_day_at(start + ONE_DAY + Duration::from_secs(3600)),
+            0
+        );
+
+        let tally = RequestTally::default();
+        tally.record_at(start);
+        tally.record_at(start + Duration::from_secs(1800));
+        assert_eq!(tally.last_hour_at(start + Duration::from_secs(1800)), 2);
+        assert_eq!(tally.last_hour_at(start + ONE_HOUR), 1);
+        assert_eq!(tally.errors(), 0);
+        tally.record_error();
+        assert_eq!(tally.errors(), 1);
+    }
+
     const SCENE_LIST: &str = include_str!("../test-data/scenes.json");
 
     #[test]
@@ -1123,6 +1509,33 @@ mod test {
 
     const GET_DEVICE_STATE_EXAMPLE: &str = include_str!("../test-data/get_device_state.json");
 
+    #[test]
+    fn malformed_entries() {
+        let values = vec![
+            json!({"type": "devices.capabilities.online", "instance": "online", "state": {"value": true}}),
+            json!({"type": 5, "instance": "powerSwitch", "state": {"value": 1}}),
+        ];
+        let salvaged: Vec<DeviceCapabilityState> = salvage_list(values.clone(), false).unwrap();
+        assert_eq!(salvaged.len(), 1);
+        assert_eq!(salvaged[0].instance, "online");
+
+        let err = salvage_list::<DeviceCapabilityState>(values, true).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("instance powerSwitch"),
+            "{err:#}"
+        );
+
+        // The rest of the document is kept when lenient
+        let state: HttpDeviceState = from_json(
+            r#"{"sku": "H6072", "device": "AA:BB", "capabilities": [
+                {"type": "devices.capabilities.online", "instance": "online", "state": {"value": true}},
+                {"type": 5, "instance": "powerSwitch", "state": {"value": 1}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(state.capabilities.len(), 1);
+    }
+
     #[test]
     fn get_device_state() {
         let resp: GetDeviceStateResponse = from_json(&GET_DEVICE_STATE_EXAMPLE).unwrap();
@@ -1162,4 +1575,41 @@ mod test {
             "\"something\""
         );
     }
+
+    #[test]
+    fn clamping() {
+        let brightness: DeviceCapability = serde_json::from_value(json!({
+            "type": "devices.capabilities.range",
+            "instance": "brightness",
+            "parameters": {"dataType": "INTEGER", "unit": "unit.percent", "range": {"min": 1, "max": 100, "precision": 1}},
+        }))
+        .unwrap();
+        assert_eq!(brightness.clamp_value(json!(0)), json!(1));
+        assert_eq!(brightness.clamp_value(json!(50)), json!(50));
+        assert_eq!(brightness.clamp_value(json!(250)), json!(100));
+
+        let work_mode: DeviceCapability =
+            from_json(include_str!("../test-data/work-mode-issue-81.json")).unwrap();
+        // Auto has a range of 40-80, but Manual has discrete values
+        assert_eq!(
+            work_mode.clamp_value(json!({"workMode": 3, "modeValue": 95})),
+            json!({"workMode": 3, "modeValue": 80})
+        );
+        assert_eq!(
+            work_mode.clamp_value(json!({"workMode": 1, "modeValue": 95})),
+            json!({"workMode": 1, "modeValue": 95})
+        );
+    }
+
+    #[test]
+    fn api_key_usage() {
+        assert_eq!(
+            "Control".parse::<PlatformApiUsage>().unwrap(),
+            PlatformApiUsage::Control
+        );
+        assert!(!PlatformApiUsage::Control.allows_state());
+        assert!(!PlatformApiUsage::State.allows_control());
+        assert!(PlatformApiUsage::All.allows_control() && PlatformApiUsage::All.allows_state());
+        assert!("sometimes".parse::<PlatformApiUsage>().is_err());
+    }
 }
@@ -0,0 +1,129 @@
+//! Alarms that are pushed via IoT by sensors that have no state to
+//! poll, such as water leak detectors and door contact sensors, which
+//! are usually paired with a bluetooth gateway.
+//!
+//! The layout of these messages isn't documented, so parsing is
+//! lenient in the same way as for schedules: we accept the various
+//! spellings of the alarm field in the state of the message.
+use crate::service::device::Device;
+use crate::service::hass::topic_safe_id;
+use crate::service::schedule::{as_bool, first_field};
+use serde_json::Value as JsonValue;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlarmKind {
+    Leak,
+    Contact,
+}
+
+impl AlarmKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Leak => "Water Leak",
+            Self::Contact => "Door",
+        }
+    }
+
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Self::Leak => "leak",
+            Self::Contact => "opening",
+        }
+    }
+
+    /// The hass binary_sensor device class
+    pub fn device_class(&self) -> &'static str {
+        match self {
+            Self::Leak => "moisture",
+            Self::Contact => "opening",
+        }
+    }
+
+    fn fields(&self) -> &'static [&'static str] {
+        match self {
+            Self::Leak => &["leak", "waterLeak", "leakage", "warning"],
+            Self::Contact => &["open", "doorOpen", "opened"],
+        }
+    }
+}
+
+/// Returns whether the alarm is active, if the message says
+pub fn parse_alarm(kind: AlarmKind, state: &JsonValue) -> Option<bool> {
+    first_field(state, kind.fields()).and_then(as_bool)
+}
+
+/// Returns the state of the alarm reported by the raw IoT message,
+/// if the message reports one that differs from `previous`
+pub fn alarm_change(kind: AlarmKind, previous: Option<bool>, raw: &JsonValue) -> Option<bool> {
+    let active = raw
+        .get("state")
+        .and_then(|state| parse_alarm(kind, state))?;
+    (previous != Some(active)).then_some(active)
+}
+
+/// The topic on which we republish the alarm messages as received
+/// when the alarm changes, for those that want to act on details
+/// that we don't interpret
+pub fn alarm_topic(device: &Device) -> String {
+    format!("gv2mqtt/{id}/alarm", id = topic_safe_id(device))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lenient_parsing() {
+        assert_eq!(
+            parse_alarm(AlarmKind::Leak, &json!({"leak": 1})),
+            Some(true)
+        );
+        assert_eq!(
+            parse_alarm(AlarmKind::Leak, &json!({"waterLeak": false})),
+            Some(false)
+        );
+        assert_eq!(parse_alarm(AlarmKind::Leak, &json!({"open": 1})), None);
+
+        assert_eq!(
+            parse_alarm(AlarmKind::Contact, &json!({"doorOpen": true})),
+            Some(true)
+        );
+        assert_eq!(
+            parse_alarm(AlarmKind::Contact, &json!({"open": 0})),
+            Some(false)
+        );
+        assert_eq!(
+            parse_alarm(AlarmKind::Contact, &json!({"battery": 90})),
+            None
+        );
+    }
+
+    #[test]
+    fn changes() {
+        let wet = json!({
+            "sku": "H5054",
+            "device": "12:34:56:78:9A:BC:DE:F0",
+            "cmd": "status",
+            "state": {"leak": 1},
+        });
+        let dry = json!({
+            "sku": "H5054",
+            "device": "12:34:56:78:9A:BC:DE:F0",
+            "cmd": "status",
+            "state": {"leak": 0},
+        });
+        let battery = json!({
+            "sku": "H5054",
+            "device": "12:34:56:78:9A:BC:DE:F0",
+            "cmd": "status",
+            "state": {"battery": 90},
+        });
+
+        assert_eq!(alarm_change(AlarmKind::Leak, None, &wet), Some(true));
+        assert_eq!(alarm_change(AlarmKind::Leak, Some(true), &wet), None);
+        assert_eq!(alarm_change(AlarmKind::Leak, Some(true), &dry), Some(false));
+        assert_eq!(alarm_change(AlarmKind::Leak, None, &dry), Some(false));
+        assert_eq!(alarm_change(AlarmKind::Leak, Some(true), &battery), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Button presses that are pushed via IoT by devices such as scene
+//! switches and remotes, so that they can trigger automations in hass.
+//!
+//! The layout of these messages isn't documented, and hasn't yet been
+//! confirmed against a captured message, so parsing is lenient in the
+//! same way as for alarms: we accept the various spellings of the
+//! fields in the state of the message. Only press types that describe
+//! themselves, such as "doublePress", are recognized; the meaning of
+//! numeric codes would be a guess.
+use crate::service::device::Device;
+use crate::service::hass::topic_safe_id;
+use crate::service::schedule::first_field;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+pub const SINGLE_PRESS: &str = "single_press";
+pub const DOUBLE_PRESS: &str = "double_press";
+pub const LONG_PRESS: &str = "long_press";
+
+/// The event types offered by the hass event entity
+pub const EVENT_TYPES: &[&str] = &[SINGLE_PRESS, DOUBLE_PRESS, LONG_PRESS];
+
+const PRESS_FIELDS: &[&str] = &["pressType", "keyType", "clickType", "action"];
+const BUTTON_FIELDS: &[&str] = &["keyCode", "keyId", "key", "buttonId", "button"];
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ButtonPress {
+    pub event_type: &'static str,
+    /// Which of the buttons was pressed, for devices with several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub button: Option<u64>,
+}
+
+fn press_type(value: &JsonValue) -> Option<&'static str> {
+    let s = value.as_str()?.to_ascii_lowercase();
+    if s.contains("double") {
+        Some(DOUBLE_PRESS)
+    } else if s.contains("long") || s.contains("hold") {
+        Some(LONG_PRESS)
+    } else if s.contains("single") || s.contains("short") || s.contains("click") {
+        Some(SINGLE_PRESS)
+    } else {
+        None
+    }
+}
+
+/// Returns the button press described by the message, if any
+pub fn parse_button_press(state: &JsonValue) -> Option<ButtonPress> {
+    let event_type = first_field(state, PRESS_FIELDS).and_then(press_type)?;
+    let button = first_field(state, BUTTON_FIELDS).and_then(|v| {
+        v.as_u64()
+            .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+    });
+    Some(ButtonPress { event_type, button })
+}
+
+/// The topic on which we publish the presses for the event entity
+pub fn button_event_topic(device: &Device) -> String {
+    format!("gv2mqtt/event/{id}/button", id = topic_safe_id(device))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lenient_parsing() {
+        let raw = json!({
+            "sku": "H5126",
+            "device": "12:34:56:78:9A:BC:DE:F0",
+            "cmd": "status",
+            "state": {"pressType": "doublePress", "keyCode": 3},
+        });
+        assert_eq!(
+            raw.get("state").and_then(parse_button_press),
+            Some(ButtonPress {
+                event_type: DOUBLE_PRESS,
+                button: Some(3)
+            })
+        );
+        assert_eq!(
+            parse_button_press(&json!({"action": "LongPress", "button": "1"})),
+            Some(ButtonPress {
+                event_type: LONG_PRESS,
+                button: Some(1)
+            })
+        );
+        assert_eq!(
+            parse_button_press(&json!({"clickType": "click"})),
+            Some(ButtonPress {
+                event_type: SINGLE_PRESS,
+                button: None
+            })
+        );
+        assert_eq!(parse_button_press(&json!({"keyCode": 1})), None);
+        // We don't know what the numeric codes mean
+        assert_eq!(parse_button_press(&json!({"pressType": 2})), None);
+        assert_eq!(
+            serde_json::to_value(parse_button_press(&json!({"pressType": "single"}))).unwrap(),
+            json!({"event_type": "single_press"})
+        );
+    }
+}
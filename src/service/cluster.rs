@@ -0,0 +1,397 @@
+//! Support for running multiple bridge replicas against the same broker.
+//!
+//! When a shared subscription group is configured, each replica subscribes
+//! to the command topics via `$share/<group>/...` so that the broker
+//! delivers any given command to just one of them. Publishing discovery
+//! and state is left to a single leader, which is elected by way of a
+//! retained lease on the `gv2mqtt/leader` topic: the current leader
+//! renews its lease periodically, and any other replica will claim
+//! the lease for itself once it has expired.
+//!
+//! A simpler arrangement is a `--standby` instance: it uses regular
+//! subscriptions but ignores commands and publishes nothing while the
+//! primary instance keeps renewing its lease. A primary started with
+//! `--heartbeat` publishes the lease as a heartbeat, and the standby
+//! takes over once that heartbeat has lapsed, stepping back down when
+//! the primary returns.
+use crate::service::identity::bridge_id;
+use crate::service::state::StateHandle;
+use mosquitto_rs::router::{Payload, State};
+use mosquitto_rs::{Client, MessageId, QoS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a leader lease remains valid without being renewed
+const LEASE_DURATION: Duration = Duration::from_secs(30);
+/// How often we renew or attempt to claim the lease
+const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(10);
+/// A command carrying the same message id as one received within this
+/// window is considered to be a redelivery and is ignored
+const COMMAND_DEDUP_WINDOW: Duration = Duration::from_secs(2);
+
+pub fn leader_topic() -> String {
+    format!("{}/leader", bridge_id())
+}
+
+/// Returns the topic without the `$share/<group>/` prefix that
+/// rewrite_incoming_topic may have added
+pub fn unshared_topic(topic: &str) -> &str {
+    topic
+        .strip_prefix("$share/")
+        .and_then(|t| t.split_once('/'))
+        .map(|(_group, t)| t)
+        .unwrap_or(topic)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LeaderLease {
+    pub instance: String,
+    /// Unix timestamp in milliseconds after which the lease is void
+    pub expires: u64,
+}
+
+impl LeaderLease {
+    fn is_valid(&self) -> bool {
+        self.expires > now_millis()
+    }
+}
+
+fn role_elects_leader(role: &ClusterRole) -> bool {
+    matches!(role, ClusterRole::Standby | ClusterRole::Shared { .. })
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The role that this instance plays relative to any other replicas
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClusterRole {
+    /// The primary (or only) instance. It always considers itself the
+    /// leader.
+    Primary,
+    /// A primary instance that also publishes the lease as a heartbeat
+    /// for a standby
+    Heartbeat,
+    /// Dormant until the primary's heartbeat stops
+    Standby,
+    /// One of several peers using a shared subscription group
+    Shared { group: String },
+}
+
+pub struct Cluster {
+    instance_id: String,
+    role: ClusterRole,
+    started: Instant,
+    is_leader: AtomicBool,
+    lease: parking_lot::Mutex<Option<LeaderLease>>,
+    recent_commands: parking_lot::Mutex<HashMap<(String, MessageId), Instant>>,
+}
+
+impl Cluster {
+    pub fn new(instance_id: String, role: ClusterRole) -> Self {
+        let is_leader = !role_elects_leader(&role);
+        Self {
+            instance_id,
+            role,
+            started: Instant::now(),
+            is_leader: AtomicBool::new(is_leader),
+            lease: parking_lot::Mutex::new(None),
+            recent_commands: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true if this instance participates in leader election,
+    /// rather than unconditionally acting as the leader
+    pub fn elects_leader(&self) -> bool {
+        role_elects_leader(&self.role)
+    }
+
+    /// Returns true if this instance publishes the lease, either to
+    /// take part in the election or as a heartbeat for a standby
+    pub fn publishes_lease(&self) -> bool {
+        self.role != ClusterRole::Primary
+    }
+
+    /// Returns true if commands are received via a shared subscription
+    pub fn is_shared(&self) -> bool {
+        matches!(self.role, ClusterRole::Shared { .. })
+    }
+
+    /// Returns true if this instance should act upon the commands
+    /// that it receives. A standby leaves that to the primary.
+    pub fn should_handle_commands(&self) -> bool {
+        self.role != ClusterRole::Standby || self.is_leader()
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::SeqCst)
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Returns the topic filter that should be used to subscribe
+    /// to `topic`, taking the shared group into account
+    pub fn command_route(&self, topic: &str) -> String {
+        match &self.role {
+            ClusterRole::Shared { group } => format!("$share/{group}/{topic}"),
+            _ => topic.to_string(),
+        }
+    }
+
+    /// The broker strips the `$share/<group>/` prefix from messages
+    /// delivered via a shared subscription; put it back so that the
+    /// topic matches the route that was registered for it.
+    pub fn rewrite_incoming_topic(&self, topic: &str) -> String {
+        match &self.role {
+            ClusterRole::Shared { group }
+                if topic.starts_with("gv2mqtt") && topic != leader_topic() =>
+            {
+                format!("$share/{group}/{topic}")
+            }
+            _ => topic.to_string(),
+        }
+    }
+
+    /// Returns true if the broker very recently delivered a command with
+    /// the same message id; it is being redelivered after a reconnect.
+    /// Commands are idempotent, but replaying them can cause visible
+    /// flicker or duplicate scene activations, so we drop them.
+    /// Messages sent at QoS 0 have no id and are never redelivered,
+    /// and a repeat of the same command with a different id is one that
+    /// the user sent again, so neither is considered a duplicate.
+    pub fn is_duplicate_command(&self, topic: &str, qos: QoS, mid: MessageId) -> bool {
+        if qos == QoS::AtMostOnce {
+            return false;
+        }
+        let now = Instant::now();
+        let mut recent = self.recent_commands.lock();
+        recent.retain(|_, when| now.duration_since(*when) < COMMAND_DEDUP_WINDOW);
+        recent.insert((topic.to_string(), mid), now).is_some()
+    }
+
+    /// Update our view of the lease from a message received on the
+    /// leader topic. Returns true if we just became the leader.
+    fn observe_lease(&self, lease: Option<LeaderLease>) -> bool {
+        if !self.elects_leader() {
+            return false;
+        }
+        let leader = lease
+            .as_ref()
+            .map(|l| l.instance == self.instance_id && l.is_valid())
+            .unwrap_or(false);
+        *self.lease.lock() = lease;
+        let was_leader = self.is_leader.swap(leader, Ordering::SeqCst);
+        if was_leader != leader {
+            log::info!(
+                "Instance {} is {} the leader",
+                self.instance_id,
+                if leader { "now" } else { "no longer" }
+            );
+        }
+        leader && !was_leader
+    }
+
+    /// Decide whether we should publish a claim on the lease: either
+    /// because we hold it and need to renew it, or because it has lapsed.
+    fn should_claim(&self) -> bool {
+        if !self.elects_leader() {
+            // The primary always publishes its heartbeat
+            return true;
+        }
+        if self.role == ClusterRole::Standby && self.started.elapsed() < LEASE_DURATION {
+            // Give the retained lease from the primary a chance to
+            // arrive before deciding that it is absent
+            return false;
+        }
+        match &*self.lease.lock() {
+            Some(lease) => lease.instance == self.instance_id || !lease.is_valid(),
+            None => true,
+        }
+    }
+
+    async fn claim(&self, client: &Client) -> anyhow::Result<()> {
+        let lease = LeaderLease {
+            instance: self.instance_id.clone(),
+            expires: now_millis() + LEASE_DURATION.as_millis() as u64,
+        };
+        client
+            .publish(
+                leader_topic(),
+                serde_json::to_string(&lease)?,
+                QoS::AtLeastOnce,
+                true,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Periodically renew or claim the lease
+    pub async fn run_election(&self, client: Client) {
+        loop {
+            // If the previous lease has lapsed while we were holding it,
+            // step down until we see our renewal echoed back
+            let expired = self
+                .lease
+                .lock()
+                .as_ref()
+                .map(|l| !l.is_valid())
+                .unwrap_or(true);
+            if expired && self.is_leader() && self.elects_leader() {
+                self.observe_lease(None);
+            }
+
+            if self.should_claim() {
+                if let Err(err) = self.claim(&client).await {
+                    log::error!("Failed to claim leader lease: {err:#}");
+                }
+            }
+            tokio::time::sleep(LEASE_RENEW_INTERVAL).await;
+        }
+    }
+}
+
+/// Another replica (or ourselves) has updated the leader lease
+pub async fn mqtt_leader_lease(
+    Payload(payload): Payload<String>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    let client = state
+        .get_hass_client()
+        .await
+        .expect("hass client to be present");
+
+    let lease: Option<LeaderLease> = if payload.is_empty() {
+        None
+    } else {
+        Some(serde_json::from_str(&payload)?)
+    };
+
+    if client.cluster().observe_lease(lease) {
+        // We just took over; make sure that hass has current
+        // configs and state from us
+        client.register_with_hass(&state).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lease_takeover() {
+        let cluster = Cluster::new(
+            "a".to_string(),
+            ClusterRole::Shared {
+                group: "govee".to_string(),
+            },
+        );
+        assert!(!cluster.is_leader());
+        assert!(cluster.should_claim());
+
+        let ours = LeaderLease {
+            instance: "a".to_string(),
+            expires: now_millis() + 10_000,
+        };
+        assert!(cluster.observe_lease(Some(ours)));
+        assert!(cluster.is_leader());
+
+        let theirs = LeaderLease {
+            instance: "b".to_string(),
+            expires: now_millis() + 10_000,
+        };
+        assert!(!cluster.observe_lease(Some(theirs)));
+        assert!(!cluster.is_leader());
+        assert!(!cluster.should_claim());
+
+        let stale = LeaderLease {
+            instance: "b".to_string(),
+            expires: now_millis() - 1,
+        };
+        cluster.observe_lease(Some(stale));
+        assert!(cluster.should_claim());
+    }
+
+    #[test]
+    fn shared_topics() {
+        let cluster = Cluster::new(
+            "a".to_string(),
+            ClusterRole::Shared {
+                group: "govee".to_string(),
+            },
+        );
+        assert_eq!(
+            cluster.command_route("gv2mqtt/light/:id/command"),
+            "$share/govee/gv2mqtt/light/:id/command"
+        );
+        assert_eq!(
+            cluster.rewrite_incoming_topic("gv2mqtt/light/abc/command"),
+            "$share/govee/gv2mqtt/light/abc/command"
+        );
+        assert_eq!(
+            cluster.rewrite_incoming_topic("gv2mqtt/leader"),
+            "gv2mqtt/leader"
+        );
+        assert_eq!(
+            cluster.rewrite_incoming_topic("homeassistant/status"),
+            "homeassistant/status"
+        );
+
+        assert_eq!(
+            unshared_topic("$share/govee/gv2mqtt/light/abc/command"),
+            "gv2mqtt/light/abc/command"
+        );
+        assert_eq!(unshared_topic("gv2mqtt/leader"), "gv2mqtt/leader");
+
+        assert!(!cluster.is_duplicate_command("t", QoS::AtLeastOnce, 7));
+        assert!(cluster.is_duplicate_command("t", QoS::AtLeastOnce, 7));
+        // The same command sent again is not a redelivery
+        assert!(!cluster.is_duplicate_command("t", QoS::AtLeastOnce, 8));
+        assert!(!cluster.is_duplicate_command("t", QoS::AtMostOnce, 0));
+        assert!(!cluster.is_duplicate_command("t", QoS::AtMostOnce, 0));
+    }
+
+    #[test]
+    fn standby_defers_to_primary() {
+        assert!(!Cluster::new("p".to_string(), ClusterRole::Primary).publishes_lease());
+        let primary = Cluster::new("p".to_string(), ClusterRole::Heartbeat);
+        assert!(primary.publishes_lease());
+        assert!(!primary.elects_leader());
+        assert!(primary.is_leader());
+        assert!(primary.should_claim());
+        let lease = LeaderLease {
+            instance: "s".to_string(),
+            expires: now_millis() + 10_000,
+        };
+        assert!(!primary.observe_lease(Some(lease)));
+        assert!(primary.is_leader());
+
+        let standby = Cluster::new("s".to_string(), ClusterRole::Standby);
+        assert!(!standby.is_leader());
+        assert!(!standby.should_handle_commands());
+        // Still within the startup grace period
+        assert!(!standby.should_claim());
+
+        let heartbeat = LeaderLease {
+            instance: "p".to_string(),
+            expires: now_millis() + 10_000,
+        };
+        assert!(!standby.observe_lease(Some(heartbeat)));
+
+        let ours = LeaderLease {
+            instance: "s".to_string(),
+            expires: now_millis() + 10_000,
+        };
+        assert!(standby.observe_lease(Some(ours)));
+        assert!(standby.should_handle_commands());
+    }
+}
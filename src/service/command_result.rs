@@ -0,0 +1,169 @@
+//! Structured results for the commands received via MQTT.
+//!
+//! Each command is run with a small context that records the devices
+//! that it resolved for control, and the transports that were used to
+//! control them. Once the command completes, a result message is
+//! published to `gv2mqtt/<device>/command_result` for each of those
+//! devices, or to `gv2mqtt/command_result` for commands that didn't
+//! involve a particular device, so that automations can retry or
+//! alert when a command fails.
+use crate::service::hass::{topic_safe_id_str, HassClient};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::future::Future;
+use tokio::time::Instant;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Lan,
+    Iot,
+    PlatformApi,
+    UndocApi,
+}
+
+#[derive(Default)]
+struct CommandContext {
+    devices: Vec<String>,
+    transports: Vec<Transport>,
+}
+
+tokio::task_local! {
+    static CONTEXT: RefCell<CommandContext>;
+}
+
+/// Record that the command being run is controlling the specified device
+pub fn note_device(id: &str) {
+    let _ = CONTEXT.try_with(|context| {
+        let mut context = context.borrow_mut();
+        if !context.devices.iter().any(|d| d == id) {
+            context.devices.push(id.to_string());
+        }
+    });
+}
+
+/// Record the transport via which the command being run is sent
+pub fn note_transport(transport: Transport) {
+    let _ = CONTEXT.try_with(|context| {
+        let mut context = context.borrow_mut();
+        if !context.transports.contains(&transport) {
+            context.transports.push(transport);
+        }
+    });
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CommandResult {
+    pub topic: String,
+    /// The payload of the command
+    pub requested: String,
+    pub applied: bool,
+    pub error: Option<String>,
+    pub latency_ms: u64,
+    /// The transports used to send the command, in the order
+    /// that they were used. Commands that fan out over several
+    /// devices may use more than one.
+    pub transport: Vec<Transport>,
+}
+
+pub struct TrackedCommand {
+    pub devices: Vec<String>,
+    pub result: CommandResult,
+}
+
+impl TrackedCommand {
+    pub fn result_topics(&self) -> Vec<String> {
+        if self.devices.is_empty() {
+            return vec!["gv2mqtt/command_result".to_string()];
+        }
+        self.devices
+            .iter()
+            .map(|id| format!("gv2mqtt/{}/command_result", topic_safe_id_str(id)))
+            .collect()
+    }
+
+    pub async fn publish(&self, client: &HassClient) -> anyhow::Result<()> {
+        for topic in self.result_topics() {
+            client.publish_event_obj(topic, &self.result).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a command, recording the facts needed to describe its result
+pub async fn track_command<F>(
+    topic: &str,
+    payload: &[u8],
+    command: F,
+) -> (anyhow::Result<()>, TrackedCommand)
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    let started = Instant::now();
+    let (result, context) = CONTEXT
+        .scope(RefCell::new(CommandContext::default()), async {
+            let result = command.await;
+            (result, CONTEXT.with(|context| context.take()))
+        })
+        .await;
+
+    let tracked = TrackedCommand {
+        devices: context.devices,
+        result: CommandResult {
+            topic: topic.to_string(),
+            requested: String::from_utf8_lossy(payload).to_string(),
+            applied: result.is_ok(),
+            error: result.as_ref().err().map(|err| format!("{err:#}")),
+            latency_ms: started.elapsed().as_millis() as u64,
+            transport: context.transports,
+        },
+    };
+    (result, tracked)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn tracked() {
+        let (result, tracked) = track_command("gv2mqtt/room/office/power/command", b"ON", async {
+            note_device("AA:BB:CC:DD:EE:FF:42:2A");
+            note_transport(Transport::Lan);
+            note_device("11:22:33:44:55:66:77:88");
+            note_transport(Transport::Iot);
+            note_transport(Transport::Lan);
+            anyhow::bail!("11:22:33:44:55:66:77:88 timed out");
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(
+            tracked.result_topics(),
+            vec![
+                "gv2mqtt/AABBCCDDEEFF422A/command_result".to_string(),
+                "gv2mqtt/1122334455667788/command_result".to_string(),
+            ]
+        );
+        assert!(!tracked.result.applied);
+        assert_eq!(tracked.result.requested, "ON");
+        assert_eq!(
+            tracked.result.error.as_deref(),
+            Some("11:22:33:44:55:66:77:88 timed out")
+        );
+        assert_eq!(
+            serde_json::to_value(&tracked.result.transport).unwrap(),
+            serde_json::json!(["lan", "iot"])
+        );
+
+        // Outside of a tracked command, these have no effect
+        note_device("AA:BB:CC:DD:EE:FF:42:2A");
+        let (result, tracked) = track_command("gv2mqtt/purge-caches", b"", async { Ok(()) }).await;
+        assert!(result.is_ok());
+        assert!(tracked.result.applied);
+        assert!(tracked.result.transport.is_empty());
+        assert_eq!(
+            tracked.result_topics(),
+            vec!["gv2mqtt/command_result".to_string()]
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Virtual devices: user-defined devices that are composed from a
+//! number of physical devices, and which are controlled as a unit.
+//! The lights in each room can also be controlled as a unit.
+use crate::lan_api::DeviceColor;
+use crate::platform_api::DeviceType;
+use crate::service::config::VirtualDeviceConfig;
+use crate::service::device::{Device, DeviceState};
+use crate::service::hass::topic_safe_string;
+use crate::service::state::StateHandle;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+pub struct VirtualDevice {
+    pub config: VirtualDeviceConfig,
+}
+
+/// The state of a virtual device, derived from its members
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositeState {
+    /// true if any member light is on
+    pub on: bool,
+    /// The brightest of the members that are on
+    pub brightness: u8,
+    pub color: DeviceColor,
+    pub kelvin: u32,
+}
+
+impl VirtualDevice {
+    pub fn new(config: VirtualDeviceConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn id(&self) -> String {
+        self.config.id()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Resolve the configured member labels to devices.
+    /// Members that cannot (yet) be resolved are omitted.
+    pub async fn members(&self, state: &StateHandle) -> Vec<Device> {
+        let mut result = vec![];
+        for label in &self.config.members {
+            match state.resolve_device(label).await {
+                Some(device) => result.push(device),
+                None => {
+                    log::trace!("virtual device {}: member {label} not found", self.name());
+                }
+            }
+        }
+        result
+    }
+
+    pub async fn has_member(&self, state: &StateHandle, device_id: &str) -> bool {
+        self.members(state)
+            .await
+            .iter()
+            .any(|device| device.id == device_id)
+    }
+
+    pub fn supports_rgb(members: &[Device]) -> bool {
+        members.iter().any(|d| d.supports_rgb())
+    }
+
+    pub fn supports_brightness(members: &[Device]) -> bool {
+        members.iter().any(|d| {
+            d.resolve_quirk()
+                .map(|q| q.supports_brightness)
+                .unwrap_or(false)
+                || d.http_device_info
+                    .as_ref()
+                    .map(|info| info.supports_brightness())
+                    .unwrap_or(false)
+        })
+    }
+
+    /// Returns the range of color temperatures that all of the members
+    /// that support color temperature are able to reproduce
+    pub async fn color_temperature_range(
+        state: &StateHandle,
+        members: &[Device],
+    ) -> Option<(u32, u32)> {
+        let mut ranges = vec![];
+        for d in members {
+            ranges.extend(state.device_color_temperature_range(d).await);
+        }
+        ranges
+            .into_iter()
+            .reduce(|(a_min, a_max), (b_min, b_max)| (a_min.max(b_min), a_max.min(b_max)))
+            .filter(|(min, max)| min < max)
+    }
+
+    pub fn composite_state(members: &[Device]) -> CompositeState {
+        let states: Vec<DeviceState> = members.iter().filter_map(|d| d.device_state()).collect();
+        Self::combine_states(&states)
+    }
+
+    fn combine_states(states: &[DeviceState]) -> CompositeState {
+        let lit: Vec<&DeviceState> = states
+            .iter()
+            .filter(|s| s.light_on.unwrap_or(s.on))
+            .collect();
+
+        match lit.first() {
+            Some(first) => CompositeState {
+                on: true,
+                brightness: lit.iter().map(|s| s.brightness).max().unwrap_or(0),
+                color: first.color,
+                kelvin: first.kelvin,
+            },
+            None => CompositeState {
+                on: false,
+                brightness: 0,
+                color: DeviceColor::default(),
+                kelvin: 0,
+            },
+        }
+    }
+}
+
+pub async fn virtual_devices(state: &StateHandle) -> Vec<VirtualDevice> {
+    state
+        .get_config()
+        .await
+        .virtual_devices
+        .iter()
+        .cloned()
+        .map(VirtualDevice::new)
+        .collect()
+}
+
+/// Groups the lights by the room to which they are assigned in
+/// the Govee app. Rooms with fewer than two lights are omitted,
+/// as there would be little point controlling them as a unit.
+pub fn group_lights_by_room(devices: Vec<Device>) -> BTreeMap<String, Vec<Device>> {
+    let mut rooms: BTreeMap<String, Vec<Device>> = BTreeMap::new();
+    for device in devices {
+        if device.removed || device.device_type() != DeviceType::Light {
+            continue;
+        }
+        if let Some(room) = device.room_name() {
+            rooms.entry(room.to_string()).or_default().push(device);
+        }
+    }
+    rooms.retain(|_, lights| lights.len() > 1);
+    rooms
+}
+
+pub async fn room_lights(state: &StateHandle) -> BTreeMap<String, Vec<Device>> {
+    group_lights_by_room(state.devices().await)
+}
+
+/// Maps each room name to the slug used in its topics and unique ids.
+/// Distinct names can produce the same slug, such as "Living Room"
+/// and "living room"; those rooms have their Govee group id appended
+/// so that their entities don't clobber each other.
+pub fn room_slugs(rooms: &BTreeMap<String, Vec<Device>>) -> BTreeMap<String, String> {
+    let mut by_slug: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for room in rooms.keys() {
+        by_slug
+            .entry(topic_safe_string(room))
+            .or_default()
+            .push(room);
+    }
+
+    let mut result = BTreeMap::new();
+    for (slug, names) in by_slug {
+        if names.len() == 1 {
+            result.insert(names[0].to_string(), slug);
+            continue;
+        }
+        for (idx, name) in names.into_iter().enumerate() {
+            let group_id = rooms[name]
+                .iter()
+                .find_map(|d| d.undoc_device_info.as_ref())
+                .map(|info| info.entry.group_id.to_string())
+                .unwrap_or_else(|| idx.to_string());
+            result.insert(name.to_string(), format!("{slug}-{group_id}"));
+        }
+    }
+    result
+}
+
+pub async fn virtual_device_by_id(state: &StateHandle, id: &str) -> Option<VirtualDevice> {
+    virtual_devices(state)
+        .await
+        .into_iter()
+        .find(|v| v.id() == id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform_api::from_json;
+    use crate::undoc_api::DevicesResponse;
+
+    fn state(on: bool, brightness: u8, kelvin: u32) -> DeviceState {
+        DeviceState {
+            on,
+            light_on: Some(on),
+            online: Some(true),
+            kelvin,
+            color: DeviceColor { r: 1, g: 2, b: 3 },
+            brightness,
+            scene: None,
+            source: "test",
+            updated: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn combine() {
+        let combined = VirtualDevice::combine_states(&[
+            state(false, 100, 0),
+            state(true, 20, 3000),
+            state(true, 60, 0),
+        ]);
+        assert_eq!(
+            combined,
+            CompositeState {
+                on: true,
+                brightness: 60,
+                color: DeviceColor { r: 1, g: 2, b: 3 },
+                kelvin: 3000,
+            }
+        );
+
+        let combined = VirtualDevice::combine_states(&[state(false, 100, 0)]);
+        assert!(!combined.on);
+    }
+
+    /// Loads the undoc device list fixture, naming the rooms by group id
+    fn room_devices(room_name: impl Fn(usize, u64) -> Option<&'static str>) -> Vec<Device> {
+        let list: DevicesResponse =
+            from_json(include_str!("../../test-data/undoc-device-list.json")).unwrap();
+        list.devices
+            .into_iter()
+            .enumerate()
+            .map(|(idx, entry)| {
+                let mut device = Device::new(&entry.sku, &entry.device);
+                let room = room_name(idx, entry.group_id);
+                device.set_undoc_device_info(entry, room);
+                device
+            })
+            .collect()
+    }
+
+    fn room_ids(rooms: &BTreeMap<String, Vec<Device>>) -> Vec<(&str, Vec<&str>)> {
+        rooms
+            .iter()
+            .map(|(room, lights)| {
+                (
+                    room.as_str(),
+                    lights.iter().map(|d| d.id.as_str()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn group_by_room() {
+        let rooms = group_lights_by_room(room_devices(|_, group_id| match group_id {
+            1001 => Some("Living Room"),
+            1003 => Some("Office"),
+            _ => None,
+        }));
+        assert_eq!(
+            rooms.keys().map(String::as_str).collect::<Vec<_>>(),
+            vec!["Living Room", "Office"]
+        );
+        assert_eq!(rooms["Living Room"].len(), 4);
+        assert_eq!(rooms["Office"].len(), 4);
+    }
+
+    #[test]
+    fn group_by_room_omits_single_lights() {
+        // The only light in the Hall doesn't get a room of its own
+        let rooms = group_lights_by_room(room_devices(|idx, group_id| match (idx, group_id) {
+            (4, _) => Some("Hall"),
+            (_, 1001) => Some("Living Room"),
+            _ => None,
+        }));
+        assert_eq!(
+            room_ids(&rooms)
+                .into_iter()
+                .map(|(room, _)| room)
+                .collect::<Vec<_>>(),
+            vec!["Living Room"]
+        );
+    }
+
+    #[test]
+    fn group_by_room_omits_removed() {
+        let mut devices = room_devices(|_, group_id| match group_id {
+            0 => Some("Hall"),
+            1001 => Some("Living Room"),
+            _ => None,
+        });
+        let removed = devices[0].id.clone();
+        devices[0].removed = true;
+        // Leaves only one light in the Hall
+        devices[7].removed = true;
+
+        let rooms = group_lights_by_room(devices);
+        let ids = room_ids(&rooms);
+        assert_eq!(ids.len(), 1);
+        let (room, lights) = &ids[0];
+        assert_eq!(*room, "Living Room");
+        assert_eq!(lights.len(), 3);
+        assert!(!lights.contains(&removed.as_str()));
+    }
+
+    #[test]
+    fn colliding_room_slugs() {
+        let rooms = group_lights_by_room(room_devices(|_, group_id| match group_id {
+            0 => Some("Office"),
+            1001 => Some("Living Room"),
+            1003 => Some("living room"),
+            _ => None,
+        }));
+        k9::snapshot!(
+            room_slugs(&rooms),
+            r#"
+{
+    "Living Room": "living_room-1001",
+    "Office": "office",
+    "living room": "living_room-1003",
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,996 @@
+use crate::opt_env_var;
+use crate::service::device::normalize_device_id;
+use crate::service::scale::CommandScale;
+use crate::service::state::StateHandle;
+use crate::temperature::TemperatureValue;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// How often to check whether the configuration file has changed
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(clap::Parser, Debug)]
+pub struct ConfigArguments {
+    /// Path to an optional JSON file holding additional configuration,
+    /// such as virtual device definitions.
+    /// You may also set this via the GOVEE_CONFIG_FILE environment variable.
+    #[arg(long, global = true)]
+    config_file: Option<PathBuf>,
+}
+
+impl ConfigArguments {
+    pub fn config_file(&self) -> anyhow::Result<Option<PathBuf>> {
+        match &self.config_file {
+            Some(path) => Ok(Some(path.clone())),
+            None => opt_env_var("GOVEE_CONFIG_FILE"),
+        }
+    }
+
+    /// Load the configuration file, if one was specified.
+    /// Returns the default (empty) configuration otherwise.
+    pub fn load(&self) -> anyhow::Result<ServiceConfig> {
+        match self.config_file()? {
+            Some(path) => ServiceConfig::load(&path),
+            None => Ok(ServiceConfig::default()),
+        }
+    }
+}
+
+fn modified_time(path: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Watch the configuration file for changes. When it changes, the new
+/// configuration is applied and any entities that it adds are registered
+/// with hass, without needing to restart the service.
+/// A file that fails to load is reported and otherwise ignored, so that
+/// a half-edited file doesn't take down the running configuration.
+pub async fn watch_config_file(state: StateHandle, path: PathBuf) {
+    let mut last_modified = modified_time(&path);
+    loop {
+        tokio::time::sleep(CONFIG_WATCH_INTERVAL).await;
+
+        let modified = modified_time(&path);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        match ServiceConfig::load(&path) {
+            Ok(config) => {
+                log::info!("Configuration file {} changed; applying it", path.display());
+                state.set_config(config).await;
+            }
+            Err(err) => {
+                log::error!("{err:#}; continuing with the previous configuration");
+                continue;
+            }
+        }
+
+        if let Some(hass) = state.get_hass_client().await {
+            if let Err(err) = hass.register_new_entities(&state).await {
+                log::error!("register_new_entities: {err:#}");
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// Devices composed from several physical devices
+    #[serde(default)]
+    pub virtual_devices: Vec<VirtualDeviceConfig>,
+    /// Whether the lights in each room can be controlled as a unit
+    #[serde(default)]
+    pub room_controls: RoomControls,
+    /// How kettles are presented in Home Assistant
+    #[serde(default)]
+    pub kettle_entities: KettleEntities,
+    /// How work modes are presented in Home Assistant
+    #[serde(default)]
+    pub work_mode_presets: WorkModePresets,
+    /// The scale of the values sent to level command topics that
+    /// don't specify one
+    #[serde(default)]
+    pub command_scale: CommandScale,
+    /// Scenes to feature for particular devices
+    #[serde(default)]
+    pub favorite_scenes: Vec<FavoriteScenesConfig>,
+    /// Whether the segments of RGBIC lights are offered as lights
+    #[serde(default)]
+    pub segment_lights: SegmentLights,
+    /// Corrections to the color temperature range of lights
+    #[serde(default)]
+    pub color_temp_ranges: Vec<ColorTempRangeConfig>,
+    /// How the one-click (tap-to-run) rules from the Govee app are offered
+    #[serde(default)]
+    pub one_click_entities: OneClickEntities,
+    /// Devices whose state is taken only from the LAN and IoT APIs
+    #[serde(default)]
+    pub ignore_cloud_state: Vec<String>,
+    /// Devices whose firmware accepts scene commands via the LAN API
+    #[serde(default)]
+    pub lan_scenes: Vec<String>,
+    /// Overrides for the step size of particular number entities
+    #[serde(default)]
+    pub number_steps: Vec<NumberStepConfig>,
+    /// Additional brokers to which published state is copied
+    #[serde(default)]
+    pub mirror_brokers: Vec<MirrorBrokerConfig>,
+    /// Narrower target temperature limits for heaters and kettles
+    #[serde(default)]
+    pub setpoint_limits: Vec<SetpointLimitConfig>,
+    /// Entities that are not to be registered with hass
+    #[serde(default)]
+    pub disabled_entities: Vec<DisabledEntityConfig>,
+    /// Templates for the names and object ids of entities
+    #[serde(default)]
+    pub entity_names: EntityNamesConfig,
+    /// How device ids are formatted in the HTTP API and listings.
+    /// MQTT topics and unique ids always use the plain form.
+    #[serde(default)]
+    pub device_id_format: DeviceIdFormat,
+    /// Whether the settings made in the Govee app can be changed
+    #[serde(default)]
+    pub app_settings: AppSettings,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkModePresets {
+    /// A single select entity listing the work modes by label
+    #[default]
+    Select,
+    /// A button to activate each preset mode, alongside a select
+    /// listing the work modes by name
+    Buttons,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentLights {
+    /// A light entity for each segment, alongside the main light
+    #[default]
+    All,
+    /// Only the main light
+    None,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomControls {
+    /// No room-level entities
+    #[default]
+    None,
+    /// An "All Lights" switch and brightness number for each room
+    /// that has more than one light
+    Lights,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KettleEntities {
+    /// A power switch and a target temperature number
+    #[default]
+    Number,
+    /// A water heater entity combining the power, the target
+    /// temperature and the current water temperature
+    WaterHeater,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AppSettings {
+    /// The settings are reported, but can only be changed in the app
+    #[default]
+    ReadOnly,
+    /// The settings can be changed from hass too. This uses an
+    /// undocumented API whose requests haven't been verified against
+    /// those made by the Govee app.
+    Writable,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceIdFormat {
+    /// The upper-case, colon-separated form
+    #[default]
+    Colon,
+    /// The upper-case hex digits, without separators
+    Plain,
+}
+
+impl DeviceIdFormat {
+    /// Format the device id. Ids that don't look like a MAC
+    /// address are returned as-is.
+    pub fn format(&self, id: &str) -> String {
+        match self {
+            Self::Colon => normalize_device_id(id),
+            Self::Plain => {
+                let canonical = normalize_device_id(id);
+                if canonical.chars().all(|c| c.is_ascii_hexdigit() || c == ':') {
+                    canonical.replace(':', "")
+                } else {
+                    canonical
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OneClickEntities {
+    /// A scene entity for each one-click
+    #[default]
+    Scene,
+    /// A button entity for each one-click
+    Button,
+}
+
+impl ServiceConfig {
+    /// The configured step size for the number entity with
+    /// the specified unique id
+    pub fn number_step(&self, unique_id: &str) -> Option<f32> {
+        self.number_steps
+            .iter()
+            .find(|n| n.unique_id == unique_id)
+            .map(|n| n.step)
+    }
+
+    pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("parsing config file {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let config: Self = crate::platform_api::from_json(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut ids = std::collections::HashSet::new();
+        for v in &self.virtual_devices {
+            anyhow::ensure!(
+                !v.members.is_empty(),
+                "virtual device '{}' has no members",
+                v.name
+            );
+            anyhow::ensure!(
+                ids.insert(v.id()),
+                "virtual device '{}' has the same id as another virtual device",
+                v.name
+            );
+        }
+        for r in &self.color_temp_ranges {
+            anyhow::ensure!(
+                r.sku.is_some() != r.device.is_some(),
+                "each color_temp_ranges entry must specify either sku or device"
+            );
+            anyhow::ensure!(
+                r.min_kelvin < r.max_kelvin,
+                "color_temp_ranges: min_kelvin {} must be less than max_kelvin {}",
+                r.min_kelvin,
+                r.max_kelvin
+            );
+        }
+        for n in &self.number_steps {
+            anyhow::ensure!(
+                n.step > 0.0,
+                "number_steps: the step for {} must be greater than zero",
+                n.unique_id
+            );
+        }
+        for m in &self.mirror_brokers {
+            anyhow::ensure!(!m.host.is_empty(), "mirror_brokers: host must be set");
+            anyhow::ensure!(
+                m.username.is_some() == m.password.is_some(),
+                "mirror_brokers: username and password for {} must either \
+                 both be set, or both be unset",
+                m.host
+            );
+            anyhow::ensure!(
+                m.topics.iter().all(|t| !t.from.is_empty()),
+                "mirror_brokers: each topics entry for {} must have a non-empty from",
+                m.host
+            );
+        }
+        for l in &self.setpoint_limits {
+            let min = l.min_value()?;
+            let max = l.max_value()?;
+            anyhow::ensure!(
+                min.is_some() || max.is_some(),
+                "setpoint_limits for '{}' must specify min, max or both",
+                l.device
+            );
+            if let (Some(min), Some(max)) = (min, max) {
+                anyhow::ensure!(
+                    min.as_celsius() < max.as_celsius(),
+                    "setpoint_limits for '{}': min {min} must be less than max {max}",
+                    l.device
+                );
+            }
+        }
+        for d in &self.disabled_entities {
+            anyhow::ensure!(
+                !d.instance.is_empty() && d.instance != "*",
+                "disabled_entities for '{}' must specify an instance",
+                d.device
+            );
+        }
+        for template in [&self.entity_names.name, &self.entity_names.object_id]
+            .into_iter()
+            .flatten()
+        {
+            validate_name_template(template)?;
+        }
+        for f in &self.favorite_scenes {
+            anyhow::ensure!(
+                !f.scenes.is_empty(),
+                "favorite_scenes for '{}' lists no scenes",
+                f.device
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualDeviceConfig {
+    /// The name to show in Home Assistant
+    pub name: String,
+    /// The devices that make up this virtual device. Each entry may be
+    /// a device id, name, or ip address; anything that can be resolved
+    /// to a device in the same way as the HTTP API.
+    pub members: Vec<String>,
+    /// The area to suggest to Home Assistant
+    #[serde(default)]
+    pub room: Option<String>,
+}
+
+impl VirtualDeviceConfig {
+    /// A stable identifier derived from the name, suitable for use
+    /// in topics and unique ids
+    pub fn id(&self) -> String {
+        crate::service::hass::topic_safe_string(&self.name)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ColorTempRangeConfig {
+    /// The SKU of the lights to which this range applies
+    #[serde(default)]
+    pub sku: Option<String>,
+    /// The device id, name, or ip address of a particular light
+    /// to which this range applies. This takes precedence over
+    /// a range for its SKU.
+    #[serde(default)]
+    pub device: Option<String>,
+    pub min_kelvin: u32,
+    pub max_kelvin: u32,
+}
+
+impl ColorTempRangeConfig {
+    pub fn range(&self) -> (u32, u32) {
+        (self.min_kelvin, self.max_kelvin)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NumberStepConfig {
+    /// The unique id of the number entity, as listed by
+    /// `/api/device/<id>/entities`
+    pub unique_id: String,
+    pub step: f32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SetpointLimitConfig {
+    /// The device id, name, or ip address of the heater or kettle
+    pub device: String,
+    /// The lowest target temperature to allow, such as `"40C"` or
+    /// `"104F"`. A value without a scale is taken to be in Celsius.
+    #[serde(default)]
+    pub min: Option<String>,
+    /// The highest target temperature to allow
+    #[serde(default)]
+    pub max: Option<String>,
+}
+
+impl SetpointLimitConfig {
+    fn parse_limit(&self, limit: &Option<String>) -> anyhow::Result<Option<TemperatureValue>> {
+        limit
+            .as_deref()
+            .map(|s| {
+                TemperatureValue::parse_with_optional_scale(s, None).with_context(|| {
+                    format!("setpoint_limits for '{}': invalid limit '{s}'", self.device)
+                })
+            })
+            .transpose()
+    }
+
+    pub fn min_value(&self) -> anyhow::Result<Option<TemperatureValue>> {
+        self.parse_limit(&self.min)
+    }
+
+    pub fn max_value(&self) -> anyhow::Result<Option<TemperatureValue>> {
+        self.parse_limit(&self.max)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DisabledEntityConfig {
+    /// The device id, name, or ip address of the device
+    pub device: String,
+    /// The capability instance that the entities represent, such as
+    /// `workMode`, or the unique id of a particular entity, as listed
+    /// by `/api/device/<id>/entities`. A trailing `*` matches any
+    /// unique id that starts with what precedes it.
+    pub instance: String,
+}
+
+impl DisabledEntityConfig {
+    /// Whether this entry applies to an entity with the specified
+    /// capability and unique id
+    pub fn matches(&self, capability: Option<&str>, unique_id: &str) -> bool {
+        if capability == Some(self.instance.as_str()) {
+            return true;
+        }
+        match self.instance.strip_suffix('*') {
+            Some(prefix) => unique_id.starts_with(prefix),
+            None => unique_id == self.instance,
+        }
+    }
+}
+
+/// The placeholders that may appear in an entity name template
+const NAME_PLACEHOLDERS: &[&str] = &["{room}", "{device_name}", "{entity}"];
+
+fn validate_name_template(template: &str) -> anyhow::Result<()> {
+    let mut rest = template.to_string();
+    for placeholder in NAME_PLACEHOLDERS {
+        rest = rest.replace(placeholder, "");
+    }
+    anyhow::ensure!(
+        !rest.contains('{') && !rest.contains('}'),
+        "entity_names: template '{template}' may only use the placeholders {}",
+        NAME_PLACEHOLDERS.join(", ")
+    );
+    Ok(())
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct EntityNamesConfig {
+    /// Template for the name of each entity. When unset, entities
+    /// have the names that we give them.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Template from which hass derives the entity id of each entity
+    /// when it is first registered. When unset, hass derives it from
+    /// the device name and the entity name.
+    #[serde(default)]
+    pub object_id: Option<String>,
+}
+
+impl EntityNamesConfig {
+    /// Substitute the placeholders in `template`. Any that have no
+    /// value are left out, along with the whitespace around them.
+    pub fn render(
+        template: &str,
+        room: Option<&str>,
+        device_name: &str,
+        entity: Option<&str>,
+    ) -> String {
+        let rendered = template
+            .replace("{room}", room.unwrap_or(""))
+            .replace("{device_name}", device_name)
+            .replace("{entity}", entity.unwrap_or(""));
+        rendered.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Reduce a rendered template to the form of an object id
+    pub fn object_id(rendered: &str) -> String {
+        let mut result = String::new();
+        for c in rendered.chars().flat_map(char::to_lowercase) {
+            if c.is_alphanumeric() {
+                result.push(c);
+            } else if !result.is_empty() && !result.ends_with('_') {
+                result.push('_');
+            }
+        }
+        result.trim_end_matches('_').to_string()
+    }
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn serialize_masked<S: serde::Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    crate::api_log::mask_secret(value.clone()).serialize(serializer)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorBrokerConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default, serialize_with = "serialize_masked")]
+    pub password: Option<String>,
+    /// The topics to copy to this broker, and what to call them
+    /// there. When empty, every topic is copied as-is.
+    #[serde(default)]
+    pub topics: Vec<TopicMappingConfig>,
+}
+
+impl MirrorBrokerConfig {
+    /// Returns the name of `topic` on this broker, or None if
+    /// it is not to be copied there. The first matching entry wins.
+    pub fn map_topic(&self, topic: &str) -> Option<String> {
+        if self.topics.is_empty() {
+            return Some(topic.to_string());
+        }
+        self.topics.iter().find_map(|t| {
+            topic
+                .strip_prefix(&t.from)
+                .map(|rest| format!("{}{rest}", t.to))
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TopicMappingConfig {
+    /// A topic prefix, such as `gv2mqtt/sensor/`
+    pub from: String,
+    /// What to replace the prefix with on the mirror broker
+    pub to: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FavoriteScenesConfig {
+    /// The device id, name, or ip address; anything that can be
+    /// resolved to a device in the same way as the HTTP API.
+    pub device: String,
+    /// The names of the scenes, in the order in which they should
+    /// be presented
+    pub scenes: Vec<String>,
+    /// Whether to list the rest of the catalog after the favorites,
+    /// rather than only the favorites
+    #[serde(default)]
+    pub show_all: bool,
+}
+
+impl FavoriteScenesConfig {
+    /// Returns the favorites that are present in `catalog`, spelled
+    /// as they are in the catalog. Names are matched ignoring case.
+    pub fn resolve(&self, catalog: &[String]) -> Vec<String> {
+        let mut resolved: Vec<String> = vec![];
+        for name in &self.scenes {
+            if let Some(scene) = catalog.iter().find(|s| s.eq_ignore_ascii_case(name)) {
+                if !resolved.contains(scene) {
+                    resolved.push(scene.clone());
+                }
+            }
+        }
+        resolved
+    }
+
+    /// Returns the scenes to present for the device: the favorites,
+    /// followed by the remainder of the catalog when show_all is set
+    pub fn apply(&self, catalog: Vec<String>) -> Vec<String> {
+        let mut scenes = self.resolve(&catalog);
+        if self.show_all {
+            let rest: Vec<String> = catalog
+                .into_iter()
+                .filter(|s| !scenes.contains(s))
+                .collect();
+            scenes.extend(rest);
+        }
+        scenes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_virtual_devices() {
+        let config = ServiceConfig::parse(
+            r#"{
+                "virtual_devices": [
+                    {
+                        "name": "Bedroom Ambience",
+                        "members": ["Bedroom Strip", "AA:BB:CC:DD:EE:FF:00:11"],
+                        "room": "Bedroom"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.virtual_devices[0].id(), "bedroom_ambience");
+        assert_eq!(config.virtual_devices[0].members.len(), 2);
+
+        assert!(
+            ServiceConfig::parse(r#"{"virtual_devices": [{"name": "x", "members": []}]}"#).is_err()
+        );
+        assert!(ServiceConfig::parse(r#"{"bogus": true}"#).is_err());
+        assert!(ServiceConfig::parse("{}")
+            .unwrap()
+            .virtual_devices
+            .is_empty());
+    }
+
+    #[test]
+    fn parse_work_mode_presets() {
+        assert_eq!(
+            ServiceConfig::parse("{}").unwrap().work_mode_presets,
+            WorkModePresets::Select
+        );
+        assert_eq!(
+            ServiceConfig::parse(r#"{"work_mode_presets": "buttons"}"#)
+                .unwrap()
+                .work_mode_presets,
+            WorkModePresets::Buttons
+        );
+        assert!(ServiceConfig::parse(r#"{"work_mode_presets": "sliders"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_segment_lights() {
+        assert_eq!(
+            ServiceConfig::parse("{}").unwrap().segment_lights,
+            SegmentLights::All
+        );
+        assert_eq!(
+            ServiceConfig::parse(r#"{"segment_lights": "none"}"#)
+                .unwrap()
+                .segment_lights,
+            SegmentLights::None
+        );
+        assert!(ServiceConfig::parse(r#"{"segment_lights": "some"}"#).is_err());
+    }
+
+    #[test]
+    fn serialize_with_defaults() {
+        let config = ServiceConfig::parse(r#"{"segment_lights": "none"}"#).unwrap();
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["segment_lights"], "none");
+        assert_eq!(value["work_mode_presets"], "select");
+        assert_eq!(value["command_scale"], "percent");
+        assert_eq!(value["virtual_devices"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn parse_ignore_cloud_state() {
+        assert!(ServiceConfig::parse("{}")
+            .unwrap()
+            .ignore_cloud_state
+            .is_empty());
+        let config = ServiceConfig::parse(r#"{"ignore_cloud_state": ["Desk Lamp"]}"#).unwrap();
+        assert_eq!(config.ignore_cloud_state, vec!["Desk Lamp".to_string()]);
+
+        let config = ServiceConfig::parse(r#"{"lan_scenes": ["Desk Lamp"]}"#).unwrap();
+        assert_eq!(config.lan_scenes, vec!["Desk Lamp".to_string()]);
+    }
+
+    #[test]
+    fn parse_setpoint_limits() {
+        let config = ServiceConfig::parse(
+            r#"{"setpoint_limits": [
+                {"device": "Kettle", "min": "40", "max": "194F"},
+                {"device": "Heater", "max": "25C"}
+            ]}"#,
+        )
+        .unwrap();
+        let kettle = &config.setpoint_limits[0];
+        assert_eq!(
+            kettle.min_value().unwrap(),
+            Some(TemperatureValue::with_celsius(40.))
+        );
+        assert_eq!(
+            kettle.max_value().unwrap(),
+            Some(TemperatureValue::with_farenheit(194.))
+        );
+        assert_eq!(config.setpoint_limits[1].min_value().unwrap(), None);
+
+        assert!(ServiceConfig::parse(r#"{"setpoint_limits": [{"device": "Kettle"}]}"#).is_err());
+        assert!(ServiceConfig::parse(
+            r#"{"setpoint_limits": [{"device": "Kettle", "min": "warm"}]}"#
+        )
+        .is_err());
+        assert!(ServiceConfig::parse(
+            r#"{"setpoint_limits": [{"device": "Kettle", "min": "90C", "max": "104F"}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_disabled_entities() {
+        let config = ServiceConfig::parse(
+            r#"{"disabled_entities": [
+                {"device": "Fan", "instance": "workMode"},
+                {"device": "Fan", "instance": "gv2mqtt-AABBCCDDEEFF422A-preset-*"},
+                {"device": "Lamp", "instance": "gv2mqtt-AABBCCDDEEFF422A-poll-now"}
+            ]}"#,
+        )
+        .unwrap();
+        let [by_capability, by_prefix, by_id] = &config.disabled_entities[..] else {
+            panic!("expected three entries");
+        };
+        assert!(by_capability.matches(Some("workMode"), "gv2mqtt-AABBCCDDEEFF422A-workMode"));
+        assert!(!by_capability.matches(Some("powerSwitch"), "gv2mqtt-AABBCCDDEEFF422A-powerSwitch"));
+        assert!(by_prefix.matches(None, "gv2mqtt-AABBCCDDEEFF422A-preset-fanspeed-1-3"));
+        assert!(!by_prefix.matches(None, "gv2mqtt-AABBCCDDEEFF422A-poll-now"));
+        assert!(by_id.matches(None, "gv2mqtt-AABBCCDDEEFF422A-poll-now"));
+        assert!(!by_id.matches(None, "gv2mqtt-AABBCCDDEEFF422A-poll-now-2"));
+
+        assert!(ServiceConfig::parse(
+            r#"{"disabled_entities": [{"device": "Fan", "instance": "*"}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn entity_names() {
+        let config = ServiceConfig::parse(
+            r#"{"entity_names": {"object_id": "{room} {device_name} {entity}"}}"#,
+        )
+        .unwrap();
+        let template = config.entity_names.object_id.as_deref().unwrap();
+        assert_eq!(config.entity_names.name, None);
+
+        let rendered =
+            EntityNamesConfig::render(template, Some("Living Room"), "Lamp", Some("Brightness"));
+        assert_eq!(rendered, "Living Room Lamp Brightness");
+        assert_eq!(
+            EntityNamesConfig::object_id(&rendered),
+            "living_room_lamp_brightness"
+        );
+
+        let rendered = EntityNamesConfig::render(template, None, "H6008_422A", None);
+        assert_eq!(rendered, "H6008_422A");
+        assert_eq!(EntityNamesConfig::object_id(&rendered), "h6008_422a");
+        assert_eq!(
+            EntityNamesConfig::object_id("Bob's Fan: Mode!"),
+            "bob_s_fan_mode"
+        );
+
+        assert!(ServiceConfig::parse(r#"{"entity_names": {"name": "{area} {entity}"}}"#).is_err());
+    }
+
+    #[test]
+    fn parse_number_steps() {
+        let config = ServiceConfig::parse(
+            r#"{"number_steps": [{"unique_id": "AABBCCDDEEFF422A-slidertemperature", "step": 0.5}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.number_step("AABBCCDDEEFF422A-slidertemperature"),
+            Some(0.5)
+        );
+        assert_eq!(config.number_step("something-else"), None);
+
+        assert!(
+            ServiceConfig::parse(r#"{"number_steps": [{"unique_id": "x", "step": 0}]}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn parse_mirror_brokers() {
+        let config = ServiceConfig::parse(
+            r#"{"mirror_brokers": [{
+                "host": "remote.example.com",
+                "username": "me",
+                "password": "secret",
+                "topics": [
+                    {"from": "gv2mqtt/sensor/", "to": "home/govee/sensor/"},
+                    {"from": "gv2mqtt/availability", "to": "home/govee/availability"}
+                ]
+            }]}"#,
+        )
+        .unwrap();
+        let mirror = &config.mirror_brokers[0];
+        assert_eq!(mirror.port, 1883);
+        assert_eq!(
+            mirror.map_topic("gv2mqtt/sensor/sensor-AABB-battery/state"),
+            Some("home/govee/sensor/sensor-AABB-battery/state".to_string())
+        );
+        assert_eq!(
+            mirror.map_topic("gv2mqtt/availability").as_deref(),
+            Some("home/govee/availability")
+        );
+        assert_eq!(mirror.map_topic("gv2mqtt/light/AABB/state"), None);
+        assert_eq!(
+            serde_json::to_value(mirror).unwrap()["password"],
+            serde_json::json!("REDACTED")
+        );
+
+        let config =
+            ServiceConfig::parse(r#"{"mirror_brokers": [{"host": "remote", "port": 8883}]}"#)
+                .unwrap();
+        assert_eq!(
+            config.mirror_brokers[0].map_topic("gv2mqtt/light/AABB/state"),
+            Some("gv2mqtt/light/AABB/state".to_string())
+        );
+
+        assert!(ServiceConfig::parse(r#"{"mirror_brokers": [{"host": ""}]}"#).is_err());
+        assert!(ServiceConfig::parse(
+            r#"{"mirror_brokers": [{"host": "remote", "username": "me"}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_one_click_entities() {
+        assert_eq!(
+            ServiceConfig::parse("{}").unwrap().one_click_entities,
+            OneClickEntities::Scene
+        );
+        assert_eq!(
+            ServiceConfig::parse(r#"{"one_click_entities": "button"}"#)
+                .unwrap()
+                .one_click_entities,
+            OneClickEntities::Button
+        );
+    }
+
+    #[test]
+    fn parse_room_controls() {
+        assert_eq!(
+            ServiceConfig::parse("{}").unwrap().room_controls,
+            RoomControls::None
+        );
+        assert_eq!(
+            ServiceConfig::parse(r#"{"room_controls": "lights"}"#)
+                .unwrap()
+                .room_controls,
+            RoomControls::Lights
+        );
+        assert!(ServiceConfig::parse(r#"{"room_controls": "everything"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_kettle_entities() {
+        assert_eq!(
+            ServiceConfig::parse("{}").unwrap().kettle_entities,
+            KettleEntities::Number
+        );
+        assert_eq!(
+            ServiceConfig::parse(r#"{"kettle_entities": "water_heater"}"#)
+                .unwrap()
+                .kettle_entities,
+            KettleEntities::WaterHeater
+        );
+    }
+
+    #[test]
+    fn parse_app_settings() {
+        assert_eq!(
+            ServiceConfig::parse("{}").unwrap().app_settings,
+            AppSettings::ReadOnly
+        );
+        assert_eq!(
+            ServiceConfig::parse(r#"{"app_settings": "writable"}"#)
+                .unwrap()
+                .app_settings,
+            AppSettings::Writable
+        );
+    }
+
+    #[test]
+    fn device_id_format() {
+        let config = ServiceConfig::parse(r#"{"device_id_format": "plain"}"#).unwrap();
+        assert_eq!(config.device_id_format, DeviceIdFormat::Plain);
+        assert_eq!(
+            config.device_id_format.format("aa:bb:cc:dd:ee:ff:42:2a"),
+            "AABBCCDDEEFF422A"
+        );
+        assert_eq!(
+            DeviceIdFormat::Colon.format("aabbccddeeff422a"),
+            "AA:BB:CC:DD:EE:FF:42:2A"
+        );
+        assert_eq!(
+            ServiceConfig::parse("{}").unwrap().device_id_format,
+            DeviceIdFormat::Colon
+        );
+        assert_eq!(DeviceIdFormat::Plain.format("loadtest0001"), "loadtest0001");
+        assert!(ServiceConfig::parse(r#"{"device_id_format": "dashes"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_color_temp_ranges() {
+        let config = ServiceConfig::parse(
+            r#"{
+            "color_temp_ranges": [
+                {"sku": "H6008", "min_kelvin": 2700, "max_kelvin": 6500},
+                {"device": "Desk Lamp", "min_kelvin": 3000, "max_kelvin": 5000}
+            ]
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(config.color_temp_ranges[0].sku.as_deref(), Some("H6008"));
+        assert_eq!(config.color_temp_ranges[1].range(), (3000, 5000));
+
+        assert!(ServiceConfig::parse(
+            r#"{"color_temp_ranges": [{"min_kelvin": 2700, "max_kelvin": 6500}]}"#
+        )
+        .is_err());
+        assert!(ServiceConfig::parse(
+            r#"{"color_temp_ranges": [{"sku": "H6008", "device": "Lamp", "min_kelvin": 2700, "max_kelvin": 6500}]}"#
+        )
+        .is_err());
+        assert!(ServiceConfig::parse(
+            r#"{"color_temp_ranges": [{"sku": "H6008", "min_kelvin": 6500, "max_kelvin": 2700}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_command_scale() {
+        assert_eq!(
+            ServiceConfig::parse("{}").unwrap().command_scale,
+            CommandScale::Percent
+        );
+        assert_eq!(
+            ServiceConfig::parse(r#"{"command_scale": "byte"}"#)
+                .unwrap()
+                .command_scale,
+            CommandScale::Byte
+        );
+    }
+
+    #[test]
+    fn favorite_scenes() {
+        let config = ServiceConfig::parse(
+            r#"{
+                "favorite_scenes": [
+                    {"device": "Bedroom Strip", "scenes": ["sunrise", "Aurora", "Missing"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let favorites = &config.favorite_scenes[0];
+        assert!(!favorites.show_all);
+
+        let catalog = vec![
+            "Aurora".to_string(),
+            "Forest".to_string(),
+            "Sunrise".to_string(),
+        ];
+        assert_eq!(favorites.apply(catalog.clone()), vec!["Sunrise", "Aurora"]);
+
+        let favorites = FavoriteScenesConfig {
+            show_all: true,
+            ..favorites.clone()
+        };
+        assert_eq!(
+            favorites.apply(catalog),
+            vec!["Sunrise", "Aurora", "Forest"]
+        );
+
+        assert!(ServiceConfig::parse(
+            r#"{"favorite_scenes": [{"device": "Bedroom Strip", "scenes": []}]}"#
+        )
+        .is_err());
+    }
+}
@@ -1,15 +1,47 @@
 use crate::ble::NotifyHumidifierNightlightParams;
-use crate::commands::serve::POLL_INTERVAL;
 use crate::lan_api::{DeviceColor, DeviceStatus as LanDeviceStatus, LanDevice};
 use crate::platform_api::{
     DeviceCapability, DeviceCapabilityState, DeviceType, HttpDeviceInfo, HttpDeviceState,
 };
+use crate::service::alarm::AlarmKind;
+use crate::service::hass::topic_safe_id;
+use crate::service::polling::{device_poll_interval, state_poll_interval};
 use crate::service::quirks::{resolve_quirk, Quirk, BULB};
+use crate::service::schedule::{parse_schedules, DeviceSchedule, ScheduleKind};
 use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The toggle for the night light of a device that is
+/// not primarily a light
+pub const NIGHTLIGHT_INSTANCE: &str = "nightlightToggle";
+
+/// How long after a command we hold off from polling a device, as
+/// the cloud state can lag behind the command by a few seconds
+const COMMAND_SETTLE_SECONDS: i64 = 15;
+
+/// Orders the data received from each state source by when we asked
+/// for it, so that a slow response can't overwrite the data from a
+/// request that was made after it. These are taken from a counter
+/// rather than the clock, so that they only ever increase.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Freshness(u64);
+
+impl Freshness {
+    pub fn now() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// The LAN scan is repeated every minute; a device that hasn't
+/// answered for this long is no longer reachable via the LAN
+const LAN_REACHABLE_SECONDS: i64 = 300;
 
 #[derive(Default, Clone, Debug)]
 pub struct Device {
@@ -36,14 +68,176 @@ pub struct Device {
     pub iot_device_status: Option<LanDeviceStatus>,
     pub last_iot_device_status_update: Option<DateTime<Utc>>,
 
+    /// The freshness of the data most recently applied from each source
+    lan_status_freshness: Freshness,
+    http_state_freshness: Freshness,
+    iot_status_freshness: Freshness,
+    iot_thermometer_freshness: Freshness,
+
+    /// The readings of a thermo-hygrometer, as most recently
+    /// reported via IoT
+    pub iot_thermometer_state: Option<ThermometerState>,
+    /// Whether a presence sensor detects someone, as most
+    /// recently reported via IoT
+    pub iot_presence: Option<bool>,
+    /// Whether the alarm of a leak or contact sensor is active,
+    /// as most recently reported via IoT
+    pub iot_alarm: Option<bool>,
+
     pub nightlight_state: Option<NotifyHumidifierNightlightParams>,
     pub target_humidity_percent: Option<u8>,
     pub humidifier_work_mode: Option<u8>,
     pub humidifier_param_by_mode: HashMap<u8, u8>,
+    /// The music mode most recently set via the platform API
+    pub last_music_setting: Option<MusicSetting>,
 
     pub last_polled: Option<DateTime<Utc>>,
+    /// When a command was most recently sent to the device
+    pub last_command: Option<DateTime<Utc>>,
+    /// Incremented for each command, so that a poll that was in
+    /// flight while the device was commanded can be discarded
+    command_generation: u64,
+
+    /// The id of the gateway through which this device
+    /// reports its data, if any
+    pub gateway_id: Option<String>,
+
+    /// Other SKUs that have been reported for this same device
+    pub alternate_skus: Vec<String>,
 
     active_scene: Option<ActiveSceneInfo>,
+
+    missing_capabilities: MissingCapabilities,
+
+    /// Set from the configuration file for devices whose Platform
+    /// API state is unreliable; only LAN and IoT state is used
+    pub ignore_cloud_state: bool,
+
+    /// Set from the configuration file for devices whose firmware
+    /// accepts scene commands via the LAN API
+    pub lan_scenes: bool,
+
+    /// Set for devices that have been removed via the HTTP API;
+    /// they have no entities and are not polled
+    pub removed: bool,
+}
+
+/// The values of the musicMode capability of a light
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MusicSetting {
+    pub mode: i64,
+    pub sensitivity: i64,
+}
+
+/// The temperature and humidity reported by a thermo-hygrometer
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThermometerState {
+    pub celsius: Option<f64>,
+    /// Relative humidity, as a percentage
+    pub humidity: Option<f64>,
+    pub updated: DateTime<Utc>,
+}
+
+impl ThermometerState {
+    /// The app API and IoT both report hundredths of a degree
+    /// and of a percent
+    pub fn from_hundredths(tem: Option<i64>, hum: Option<i64>, updated: DateTime<Utc>) -> Self {
+        Self {
+            celsius: tem.map(|t| t as f64 / 100.0),
+            humidity: hum.map(|h| h as f64 / 100.0),
+            updated,
+        }
+    }
+}
+
+/// Remembers which capabilities a device has been found to lack, so
+/// that the entity code that runs on every state update can skip them
+/// without repeatedly searching for them and constructing errors.
+/// The set is shared between clones of the device, because most of
+/// those lookups happen on a snapshot of it; it is discarded when the
+/// device's capabilities are updated.
+#[derive(Default, Clone, Debug)]
+struct MissingCapabilities(Arc<Mutex<HashSet<String>>>);
+
+impl MissingCapabilities {
+    fn contains(&self, instance: &str) -> bool {
+        self.0.lock().contains(instance)
+    }
+
+    fn insert(&self, instance: &str) {
+        self.0.lock().insert(instance.to_string());
+    }
+}
+
+/// The capability instances that differ between two revisions of
+/// a device's platform API metadata, such as after a firmware update
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct CapabilityChanges {
+    pub device: String,
+    pub sku: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Instances whose type or parameters have changed
+    pub changed: Vec<String>,
+}
+
+impl CapabilityChanges {
+    pub fn between(old: &HttpDeviceInfo, new: &HttpDeviceInfo) -> Self {
+        let mut changes = Self {
+            device: new.device.to_string(),
+            sku: new.sku.to_string(),
+            ..Self::default()
+        };
+        for cap in &new.capabilities {
+            match old.capability_by_instance(&cap.instance) {
+                None => changes.added.push(cap.instance.to_string()),
+                Some(prior) => {
+                    if serde_json::to_value(prior).ok() != serde_json::to_value(cap).ok() {
+                        changes.changed.push(cap.instance.to_string());
+                    }
+                }
+            }
+        }
+        for cap in &old.capabilities {
+            if new.capability_by_instance(&cap.instance).is_none() {
+                changes.removed.push(cap.instance.to_string());
+            }
+        }
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Govee device ids are usually 8 bytes of hex, separated by colons,
+/// but depending on the transport (or the user typing them in!) they
+/// may be presented in a different case, with different separators,
+/// or with none at all.
+/// This returns the canonical, upper-case and colon-separated, form of
+/// such an id. Anything that doesn't look like one is returned as-is.
+/// Devices are created with this form of their id, so that the topics
+/// and unique ids derived from it don't depend upon which transport
+/// happened to report the device first.
+pub fn normalize_device_id(id: &str) -> String {
+    let is_mac_like = id
+        .chars()
+        .all(|c| c.is_ascii_hexdigit() || matches!(c, ':' | '-' | ' '));
+    let digits: Vec<char> = id
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if !is_mac_like || digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return id.to_string();
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| pair.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join(":")
 }
 
 impl std::fmt::Display for Device {
@@ -91,6 +285,46 @@ pub struct DeviceState {
     pub updated: DateTime<Utc>,
 }
 
+/// A richer view of the power state than on/off, for appliances
+/// that may be waiting for a timer or alarm to turn them on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    /// Powered on and operating
+    Running,
+    /// Powered off, but with an enabled timer or alarm
+    Scheduled,
+    /// Powered off
+    Standby,
+}
+
+impl PowerState {
+    pub const ALL: [Self; 3] = [Self::Running, Self::Scheduled, Self::Standby];
+
+    pub fn new(on: bool, schedules: &[DeviceSchedule]) -> Self {
+        if on {
+            Self::Running
+        } else if schedules.iter().any(|s| s.enabled) {
+            Self::Scheduled
+        } else {
+            Self::Standby
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Scheduled => "scheduled",
+            Self::Standby => "standby",
+        }
+    }
+
+    /// Only a running device is considered to be on; a scheduled
+    /// device is still off until its timer fires
+    pub fn is_on(&self) -> bool {
+        *self == Self::Running
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UndocDeviceInfo {
     pub room_name: Option<String>,
@@ -101,10 +335,10 @@ impl Device {
     /// Create a new device given just its sku and id.
     /// No other facts are known or reflected by it at this time;
     /// they will need to be added by the caller.
-    pub fn new<S: Into<String>, I: Into<String>>(sku: S, id: I) -> Self {
+    pub fn new<S: Into<String>, I: AsRef<str>>(sku: S, id: I) -> Self {
         Self {
             sku: sku.into(),
-            id: id.into(),
+            id: normalize_device_id(id.as_ref()),
             ..Self::default()
         }
     }
@@ -122,6 +356,19 @@ impl Device {
         self.computed_name()
     }
 
+    /// Returns true if `label` is the name, computed name, id
+    /// or IP address of this device, ignoring case
+    pub fn matches_label(&self, label: &str) -> bool {
+        self.name().eq_ignore_ascii_case(label)
+            || self.id.eq_ignore_ascii_case(label)
+            || topic_safe_id(self).eq_ignore_ascii_case(label)
+            || self
+                .ip_addr()
+                .map(|ip| ip.to_string().eq_ignore_ascii_case(label))
+                .unwrap_or(false)
+            || self.computed_name().eq_ignore_ascii_case(label)
+    }
+
     /// Returns the name defined for the device in the Govee App
     pub fn govee_name(&self) -> Option<&str> {
         if let Some(info) = &self.http_device_info {
@@ -162,10 +409,10 @@ impl Device {
                 if self.device_state().map(|s| s.on).unwrap_or(false) {
                     chrono::Duration::seconds(60)
                 } else {
-                    *POLL_INTERVAL
+                    device_poll_interval(&self.id, state_poll_interval())
                 }
             }
-            _ => *POLL_INTERVAL,
+            _ => device_poll_interval(&self.id, state_poll_interval()),
         }
     }
 
@@ -185,6 +432,23 @@ impl Device {
         self.target_humidity_percent.replace(percent);
     }
 
+    pub fn set_music_setting(&mut self, setting: MusicSetting) {
+        self.last_music_setting.replace(setting);
+    }
+
+    /// Returns the music mode as reported by the platform API,
+    /// falling back to the one that we most recently set
+    pub fn music_setting(&self) -> Option<MusicSetting> {
+        self.get_state_capability_by_instance("musicMode")
+            .and_then(|cap| {
+                Some(MusicSetting {
+                    mode: cap.state.pointer("/value/musicMode")?.as_i64()?,
+                    sensitivity: cap.state.pointer("/value/sensitivity")?.as_i64()?,
+                })
+            })
+            .or(self.last_music_setting)
+    }
+
     pub fn set_humidifier_work_mode_and_param(&mut self, mode: u8, param: u8) {
         self.humidifier_work_mode.replace(mode);
         self.humidifier_param_by_mode.insert(mode, param);
@@ -196,8 +460,14 @@ impl Device {
         self.last_lan_device_update.replace(Utc::now());
     }
 
-    /// Update the LAN device status information
-    pub fn set_lan_device_status(&mut self, status: LanDeviceStatus) -> bool {
+    /// Update the LAN device status information, unless we already hold
+    /// fresher information. Returns true if the status changed.
+    pub fn set_lan_device_status(&mut self, status: LanDeviceStatus, freshness: Freshness) -> bool {
+        if freshness < self.lan_status_freshness {
+            log::debug!("Ignoring out of date LAN status for {self}");
+            return false;
+        }
+        self.lan_status_freshness = freshness;
         let changed = self
             .lan_device_status
             .as_ref()
@@ -209,21 +479,149 @@ impl Device {
         changed
     }
 
-    pub fn set_iot_device_status(&mut self, status: LanDeviceStatus) {
+    pub fn set_iot_device_status(&mut self, status: LanDeviceStatus, freshness: Freshness) {
+        if freshness < self.iot_status_freshness {
+            log::debug!("Ignoring out of date IoT status for {self}");
+            return;
+        }
+        self.iot_status_freshness = freshness;
         self.iot_device_status.replace(status);
         self.last_iot_device_status_update.replace(Utc::now());
         self.clear_scene_if_color_changed();
     }
 
+    /// Record a thermometer reading received via IoT. A message may
+    /// carry only one of the readings, so the other is retained.
+    pub fn set_iot_thermometer_state(&mut self, tem: Option<i64>, hum: Option<i64>) {
+        let mut reading = ThermometerState::from_hundredths(tem, hum, Utc::now());
+        if let Some(prior) = &self.iot_thermometer_state {
+            reading.celsius = reading.celsius.or(prior.celsius);
+            reading.humidity = reading.humidity.or(prior.humidity);
+        }
+        self.iot_thermometer_state.replace(reading);
+        self.iot_thermometer_freshness = Freshness::now();
+    }
+
+    /// Returns the most recent thermometer reading, whether it
+    /// came via IoT or from the device list
+    pub fn thermometer_state(&self) -> Option<ThermometerState> {
+        let listed = self.undoc_device_info.as_ref().and_then(|info| {
+            let data = &info.entry.device_ext.last_device_data;
+            if data.tem.is_none() && data.hum.is_none() {
+                return None;
+            }
+            let updated = data
+                .last_time
+                .and_then(|t| DateTime::from_timestamp_millis(t as i64))
+                .or(self.last_undoc_device_info_update)?;
+            Some(ThermometerState::from_hundredths(
+                data.tem, data.hum, updated,
+            ))
+        });
+
+        match (listed, self.iot_thermometer_state.clone()) {
+            (Some(listed), Some(iot)) if listed.updated > iot.updated => Some(listed),
+            (_, Some(iot)) => Some(iot),
+            (listed, None) => listed,
+        }
+    }
+
+    /// Called when another source reports a SKU for this device
+    /// that differs from the one we have; LAN and cloud don't always
+    /// agree. We keep a single device and warn about the conflict,
+    /// the first time that we see it.
+    pub fn note_sku(&mut self, sku: &str) {
+        if sku == self.sku || self.alternate_skus.iter().any(|s| s == sku) {
+            return;
+        }
+        log::warn!(
+            "Device {} is reported with SKU {sku} as well as {}; \
+             treating them as the same device",
+            self.id,
+            self.sku
+        );
+        self.alternate_skus.push(sku.to_string());
+    }
+
+    /// The SKU reported by the Govee cloud is the one that the
+    /// user sees in the Govee app, so prefer it over any other
+    fn adopt_cloud_sku(&mut self, sku: &str) {
+        if sku != self.sku {
+            self.note_sku(sku);
+            let previous = std::mem::replace(&mut self.sku, sku.to_string());
+            self.alternate_skus.retain(|s| s != sku);
+            self.alternate_skus.push(previous);
+        }
+    }
+
     pub fn set_http_device_info(&mut self, info: HttpDeviceInfo) {
+        self.adopt_cloud_sku(&info.sku);
         self.http_device_info.replace(info);
+        self.missing_capabilities = MissingCapabilities::default();
         self.last_http_device_update.replace(Utc::now());
     }
 
-    pub fn set_http_device_state(&mut self, state: HttpDeviceState) {
+    /// Record that the device is being, or has just been, commanded
+    pub fn note_command(&mut self) {
+        self.command_generation += 1;
+        self.last_command.replace(Utc::now());
+    }
+
+    pub fn command_generation(&self) -> u64 {
+        self.command_generation
+    }
+
+    /// Returns true if state has been received from the device via the
+    /// LAN, IoT or Platform APIs since we started, including the readings
+    /// of thermometers. What we know from the undocumented API's device
+    /// list may have been cached.
+    pub fn has_fresh_reading(&self) -> bool {
+        [
+            self.lan_status_freshness,
+            self.http_state_freshness,
+            self.iot_status_freshness,
+            self.iot_thermometer_freshness,
+        ]
+        .iter()
+        .any(|freshness| *freshness != Freshness::default())
+    }
+
+    /// Returns true if the device was commanded recently enough that
+    /// a poll may return state from before the command took effect
+    pub fn recently_commanded(&self, now: DateTime<Utc>) -> bool {
+        self.last_command
+            .map(|last| now - last < chrono::Duration::seconds(COMMAND_SETTLE_SECONDS))
+            .unwrap_or(false)
+    }
+
+    /// Apply state polled from the platform API, unless the device was
+    /// commanded after the poll began, in which case the state may
+    /// predate the command. Returns true if the state was applied.
+    pub fn apply_polled_http_device_state(
+        &mut self,
+        state: HttpDeviceState,
+        generation: u64,
+        freshness: Freshness,
+    ) -> bool {
+        if generation != self.command_generation {
+            log::info!("Discarding polled state for {self}, as it was commanded during the poll");
+            return false;
+        }
+        self.set_http_device_state(state, freshness)
+    }
+
+    /// Update the platform API state, unless we already hold fresher
+    /// state. Returns true if the state was applied.
+    pub fn set_http_device_state(&mut self, state: HttpDeviceState, freshness: Freshness) -> bool {
+        if freshness < self.http_state_freshness {
+            log::debug!("Ignoring out of date platform API state for {self}");
+            return false;
+        }
+        self.http_state_freshness = freshness;
         self.http_device_state.replace(state);
         self.last_http_device_state_update.replace(Utc::now());
         self.clear_scene_if_color_changed();
+        true
     }
 
     pub fn set_undoc_device_info(
@@ -231,6 +629,7 @@ impl Device {
         entry: crate::undoc_api::DeviceEntry,
         room_name: Option<&str>,
     ) {
+        self.adopt_cloud_sku(&entry.sku);
         self.undoc_device_info.replace(UndocDeviceInfo {
             entry,
             room_name: room_name.map(|s| s.to_string()),
@@ -355,7 +754,10 @@ impl Device {
         if let Some(state) = self.compute_lan_device_state() {
             candidates.push(state);
         }
-        if let Some(state) = self.compute_http_device_state() {
+        if let Some(state) = self
+            .compute_http_device_state()
+            .filter(|_| !self.ignore_cloud_state)
+        {
             candidates.push(state);
         }
         if let Some(state) = self.compute_iot_device_state() {
@@ -367,6 +769,13 @@ impl Device {
         candidates.pop()
     }
 
+    /// Returns the power state, distinguishing a device that will be
+    /// turned on by one of its schedules from one that is merely off
+    pub fn power_state(&self) -> Option<PowerState> {
+        let state = self.device_state()?;
+        Some(PowerState::new(state.on, &self.schedules()))
+    }
+
     /// Records the active scene name
     pub fn set_active_scene(&mut self, scene: Option<&str>) {
         match scene {
@@ -446,6 +855,17 @@ impl Device {
         }
     }
 
+    /// Returns true if scenes should be activated via the LAN API
+    /// rather than via the cloud
+    pub fn has_lan_scenes(&self) -> bool {
+        self.lan_device.is_some()
+            && (self.lan_scenes
+                || self
+                    .resolve_quirk()
+                    .map(|quirk| quirk.lan_scenes)
+                    .unwrap_or(false))
+    }
+
     pub fn avoid_platform_api(&self) -> bool {
         if let Some(quirk) = self.resolve_quirk() {
             if quirk.avoid_platform_api {
@@ -490,6 +910,27 @@ impl Device {
             .and_then(|info| info.capability_by_instance(instance))
     }
 
+    /// Returns true if the device lacks the capability, or if it was
+    /// previously marked as unusable via `mark_capability_unusable`.
+    /// Absence is cached until the device's capabilities next change.
+    pub fn lacks_capability(&self, instance: &str) -> bool {
+        if self.missing_capabilities.contains(instance) {
+            return true;
+        }
+        if self.get_capability_by_instance(instance).is_none() {
+            self.missing_capabilities.insert(instance);
+            return true;
+        }
+        false
+    }
+
+    /// Record that a capability is present but that we are unable
+    /// to make sense of it, so that callers can skip it via
+    /// `lacks_capability` rather than failing to parse it again
+    pub fn mark_capability_unusable(&self, instance: &str) {
+        self.missing_capabilities.insert(instance);
+    }
+
     pub fn get_state_capability_by_instance(
         &self,
         instance: &str,
@@ -499,6 +940,15 @@ impl Device {
             .and_then(|info| info.capability_by_instance(instance))
     }
 
+    /// Returns true if this device is not primarily a light,
+    /// but has a secondary night light
+    pub fn has_night_light(&self) -> bool {
+        self.device_type() != DeviceType::Light
+            && self
+                .get_capability_by_instance(NIGHTLIGHT_INSTANCE)
+                .is_some()
+    }
+
     pub fn get_light_power_toggle_instance_name(&self) -> Option<&'static str> {
         match self.device_type() {
             DeviceType::Light => Some("powerSwitch"),
@@ -510,10 +960,10 @@ impl Device {
                 // We may need to expand this to other power toggles
                 // in the future.
                 if self
-                    .get_capability_by_instance("nightlightToggle")
+                    .get_capability_by_instance(NIGHTLIGHT_INSTANCE)
                     .is_some()
                 {
-                    Some("nightlightToggle")
+                    Some(NIGHTLIGHT_INSTANCE)
                 } else {
                     None
                 }
@@ -601,6 +1051,220 @@ impl Device {
             _ => true,
         }
     }
+
+    /// Returns the Wi-Fi signal strength in dBm, as last reported
+    /// by the Govee app. The app reports zero when it doesn't know.
+    pub fn wifi_rssi(&self) -> Option<i64> {
+        self.undoc_device_info
+            .as_ref()
+            .and_then(|info| info.entry.device_ext.device_settings.wifi_level)
+            .filter(|&rssi| rssi < 0)
+    }
+
+    /// The firmware versions reported for the device, keyed by the
+    /// part that they apply to. Versions that are blank are omitted.
+    pub fn firmware_versions(&self) -> BTreeMap<&'static str, String> {
+        let mut versions = BTreeMap::new();
+        // The first source to report a version of a part wins
+        let mut add = |part: &'static str, version: Option<&str>| {
+            if let Some(version) = version.filter(|v| !v.is_empty()) {
+                versions.entry(part).or_insert_with(|| version.to_string());
+            }
+        };
+
+        if let Some(info) = &self.undoc_device_info {
+            let settings = &info.entry.device_ext.device_settings;
+            add("hardware", Some(&info.entry.version_hard));
+            add("software", Some(&info.entry.version_soft));
+            add("wifi_hardware", settings.wifi_hard_version.as_deref());
+            add("wifi_software", settings.wifi_soft_version.as_deref());
+            add("mcu_hardware", settings.mcu_hard_version.as_deref());
+            add("mcu_software", settings.mcu_soft_version.as_deref());
+        }
+        if let Some(lan) = &self.lan_device {
+            add("ble_hardware", Some(&lan.ble_version_hard));
+            add("ble_software", Some(&lan.ble_version_soft));
+            add("wifi_hardware", Some(&lan.wifi_version_hard));
+            add("wifi_software", Some(&lan.wifi_version_soft));
+        }
+        versions
+    }
+
+    /// The firmware version that the Govee device list reports for the
+    /// device. The wifi and BLE versions from the LAN API are for other
+    /// parts of the device, so they aren't substituted when it is missing.
+    pub fn firmware_version(&self) -> Option<String> {
+        self.firmware_versions().remove("software")
+    }
+
+    /// The newest firmware that the Govee app is offering for the device,
+    /// if it has told us about one. It is compared with `firmware_version`.
+    pub fn latest_firmware_version(&self) -> Option<String> {
+        self.undoc_device_info
+            .as_ref()
+            .and_then(|info| {
+                info.entry
+                    .device_ext
+                    .device_settings
+                    .latest_version_soft
+                    .clone()
+            })
+            .filter(|v| !v.is_empty())
+    }
+
+    /// When we last heard from or about the device, by any means
+    pub fn last_seen(&self) -> Option<DateTime<Utc>> {
+        [
+            self.device_state().map(|state| state.updated),
+            self.last_lan_device_update,
+            self.iot_thermometer_state.as_ref().map(|t| t.updated),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+    }
+
+    pub fn is_gateway(&self) -> bool {
+        self.resolve_quirk().map(|q| q.gateway).unwrap_or(false)
+    }
+
+    pub fn alarm_kind(&self) -> Option<AlarmKind> {
+        self.resolve_quirk().and_then(|q| q.alarm)
+    }
+
+    pub fn has_button_events(&self) -> bool {
+        self.resolve_quirk()
+            .map(|q| q.button_events)
+            .unwrap_or(false)
+    }
+
+    pub fn is_presence_sensor(&self) -> bool {
+        self.resolve_quirk()
+            .map(|q| q.presence_sensor)
+            .unwrap_or(false)
+    }
+
+    /// Returns the devices that the Govee app reports as being
+    /// paired with this gateway
+    pub fn gateway_sub_devices(&self) -> Vec<GatewaySubDevice> {
+        self.undoc_device_info
+            .as_ref()
+            .and_then(|info| info.entry.device_ext.device_settings.sub_devices.as_ref())
+            .map(parse_sub_devices)
+            .unwrap_or_default()
+    }
+
+    /// Returns the timers and alarms that the Govee app reports
+    /// for this device
+    pub fn schedules(&self) -> Vec<DeviceSchedule> {
+        let Some(info) = &self.undoc_device_info else {
+            return vec![];
+        };
+        let settings = &info.entry.device_ext.device_settings;
+
+        let mut result = vec![];
+        if let Some(timer) = &settings.timer {
+            result.extend(parse_schedules(ScheduleKind::Timer, timer));
+        }
+        if let Some(wake_up) = &settings.wake_up_setting {
+            result.extend(parse_schedules(ScheduleKind::WakeUp, wake_up));
+        }
+        if let Some(sleep) = &settings.sleep_setting {
+            result.extend(parse_schedules(ScheduleKind::Sleep, sleep));
+        }
+        result
+    }
+
+    /// Whether the Govee app reports any kind of schedule settings
+    /// for this device, even if none are currently configured
+    pub fn reports_schedules(&self) -> bool {
+        self.undoc_device_info.as_ref().is_some_and(|info| {
+            let settings = &info.entry.device_ext.device_settings;
+            settings.timer.is_some()
+                || settings.wake_up_setting.is_some()
+                || settings.sleep_setting.is_some()
+        })
+    }
+
+    /// Whether the device can be reached, judged by whether it has
+    /// answered the LAN scan recently, and by what the IoT device list
+    /// and the platform API report of its connection. Any one source
+    /// that can reach it is enough; a device that nothing reports on
+    /// is assumed to be reachable.
+    pub fn is_reachable(&self, now: DateTime<Utc>) -> bool {
+        let lan = self.lan_device.as_ref().map(|_| {
+            self.last_lan_device_update
+                .map(|seen| now - seen < chrono::Duration::seconds(LAN_REACHABLE_SECONDS))
+                .unwrap_or(false)
+        });
+        let iot = self
+            .undoc_device_info
+            .as_ref()
+            .and_then(|info| info.entry.device_ext.last_device_data.online);
+        let platform = self
+            .compute_http_device_state()
+            .filter(|_| !self.ignore_cloud_state)
+            .and_then(|state| state.online);
+
+        let reports = [lan, iot, platform];
+        reports.contains(&Some(true)) || !reports.contains(&Some(false))
+    }
+
+    /// Whether the gateway itself is connected to the Govee cloud
+    pub fn gateway_online(&self) -> Option<bool> {
+        self.undoc_device_info
+            .as_ref()
+            .and_then(|info| info.entry.device_ext.last_device_data.online)
+            .or_else(|| self.device_state().and_then(|state| state.online))
+    }
+}
+
+/// An entry from the `subDevices` map of a gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GatewaySubDevice {
+    pub name: Option<String>,
+    pub sku: Option<String>,
+    pub device: Option<String>,
+}
+
+impl GatewaySubDevice {
+    pub fn matches(&self, device: &Device) -> bool {
+        if let Some(id) = &self.device {
+            return normalize_device_id(id) == device.id;
+        }
+        if let Some(sku) = &self.sku {
+            if *sku != device.sku {
+                return false;
+            }
+        }
+        // The name is the only thing that we can reliably expect
+        // to be present, so we match on that as a last resort
+        self.name.as_deref() == Some(device.name().as_str())
+    }
+}
+
+/// The app reports `{"sub_0": {"name": "Device Name"}, ...}`;
+/// some firmware also includes the sku and device id
+fn parse_sub_devices(value: &serde_json::Value) -> Vec<GatewaySubDevice> {
+    let Some(map) = value.as_object() else {
+        return vec![];
+    };
+
+    let field = |entry: &serde_json::Value, name: &str| {
+        entry
+            .get(name)
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    };
+
+    map.values()
+        .map(|entry| GatewaySubDevice {
+            name: field(entry, "name"),
+            sku: field(entry, "sku"),
+            device: field(entry, "device"),
+        })
+        .filter(|sub| sub.name.is_some() || sub.device.is_some())
+        .collect()
 }
 
 #[cfg(test)]
@@ -618,4 +1282,363 @@ mod test {
         let device = Device::new("H6127", "ce");
         assert_eq!(device.name(), "H6127_CE");
     }
+
+    #[test]
+    fn normalize_ids() {
+        for id in [
+            "aa:bb:cc:dd:ee:ff:42:2a",
+            "AABBCCDDEEFF422A",
+            "aa-bb-cc-dd-ee-ff-42-2a",
+        ] {
+            assert_eq!(normalize_device_id(id), "AA:BB:CC:DD:EE:FF:42:2A");
+        }
+        assert_eq!(normalize_device_id("loadtest0001"), "loadtest0001");
+        assert_eq!(normalize_device_id("abc"), "abc");
+    }
+
+    #[test]
+    fn thermometer_readings() {
+        let mut device = Device::new("H5179", "AA:BB:CC:DD:EE:FF:42:2A");
+        assert_eq!(device.thermometer_state(), None);
+        assert!(!device.has_fresh_reading());
+
+        device.set_iot_thermometer_state(Some(2150), Some(4525));
+        assert!(device.has_fresh_reading());
+        device.set_iot_thermometer_state(Some(2200), None);
+        let reading = device.thermometer_state().unwrap();
+        assert_eq!(reading.celsius, Some(22.0));
+        // Retained from the earlier message
+        assert_eq!(reading.humidity, Some(45.25));
+    }
+
+    #[test]
+    fn missing_capabilities() {
+        let mut device = Device::new("H7131", "AA:BB:CC:DD:EE:FF:42:2A");
+        assert!(device.lacks_capability("workMode"));
+
+        let snapshot = device.clone();
+        device.set_http_device_info(
+            serde_json::from_value(serde_json::json!({
+                "sku": "H7131",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "capabilities": [{
+                    "type": "devices.capabilities.work_mode",
+                    "instance": "workMode",
+                    "parameters": {"dataType": "STRUCT", "fields": []}
+                }]
+            }))
+            .unwrap(),
+        );
+        assert!(!device.lacks_capability("workMode"));
+        // The snapshot taken earlier still lacks it
+        assert!(snapshot.lacks_capability("workMode"));
+
+        // The capability has no workMode field, so can't be parsed;
+        // that is remembered across clones of the device
+        let clone = device.clone();
+        assert!(crate::hass_mqtt::work_mode::ParsedWorkMode::with_device(&clone).is_err());
+        assert!(device.lacks_capability("workMode"));
+    }
+
+    #[test]
+    fn ignore_cloud_state() {
+        let mut device = Device::new("H6072", "AA:BB:CC:DD:EE:FF:42:2A");
+        device.set_iot_device_status(
+            LanDeviceStatus {
+                on: true,
+                brightness: 50,
+                color: DeviceColor::default(),
+                color_temperature_kelvin: 3000,
+            },
+            Freshness::now(),
+        );
+        device.set_http_device_state(
+            HttpDeviceState {
+                sku: "H6072".to_string(),
+                device: device.id.clone(),
+                capabilities: vec![],
+            },
+            Freshness::now(),
+        );
+        assert_eq!(device.device_state().unwrap().source, "PLATFORM API");
+
+        device.ignore_cloud_state = true;
+        let state = device.device_state().unwrap();
+        assert_eq!(state.source, "AWS IoT API");
+        assert_eq!(state.brightness, 50);
+    }
+
+    #[test]
+    fn stale_poll_after_command() {
+        let mut device = Device::new("H6072", "AA:BB:CC:DD:EE:FF:42:2A");
+        let polled = || HttpDeviceState {
+            sku: "H6072".to_string(),
+            device: "AA:BB:CC:DD:EE:FF:42:2A".to_string(),
+            capabilities: vec![],
+        };
+        let now = Utc::now();
+        assert!(!device.recently_commanded(now));
+
+        let generation = device.command_generation();
+        device.note_command();
+        assert!(device.recently_commanded(Utc::now()));
+        assert!(!device.recently_commanded(now + chrono::Duration::seconds(60)));
+
+        assert!(!device.apply_polled_http_device_state(polled(), generation, Freshness::now()));
+        assert!(device.http_device_state.is_none());
+
+        let generation = device.command_generation();
+        assert!(device.apply_polled_http_device_state(polled(), generation, Freshness::now()));
+        assert!(device.http_device_state.is_some());
+    }
+
+    #[test]
+    fn reachability() {
+        let mut device = Device::new("H6072", "AA:BB:CC:DD:EE:FF:42:2A");
+        let now = Utc::now();
+        assert!(device.is_reachable(now));
+
+        device.set_http_device_state(
+            serde_json::from_value(serde_json::json!({
+                "sku": "H6072",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "capabilities": [{
+                    "type": "devices.capabilities.online",
+                    "instance": "online",
+                    "state": {"value": false},
+                }],
+            }))
+            .unwrap(),
+            Freshness::now(),
+        );
+        assert!(!device.is_reachable(now));
+
+        // Answering the LAN scan trumps the cloud
+        device.last_lan_device_update.replace(now);
+        device.lan_device.replace(
+            serde_json::from_value(serde_json::json!({
+                "ip": "10.0.0.2",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "sku": "H6072",
+                "bleVersionHard": "",
+                "bleVersionSoft": "",
+                "wifiVersionHard": "",
+                "wifiVersionSoft": "",
+            }))
+            .unwrap(),
+        );
+        assert!(device.is_reachable(now));
+        assert!(!device.is_reachable(now + chrono::Duration::seconds(600)));
+    }
+
+    #[test]
+    fn lan_scenes() {
+        let lan_device = |sku: &str| {
+            serde_json::from_value(serde_json::json!({
+                "ip": "10.0.0.2",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "sku": sku,
+                "bleVersionHard": "",
+                "bleVersionSoft": "",
+                "wifiVersionHard": "",
+                "wifiVersionSoft": "",
+            }))
+            .unwrap()
+        };
+
+        let mut device = Device::new("H6072", "AA:BB:CC:DD:EE:FF:42:2A");
+        // Scenes can't be sent via LAN until it has been discovered there
+        assert!(!device.has_lan_scenes());
+        device.set_lan_device(lan_device("H6072"));
+        assert!(device.has_lan_scenes());
+
+        let mut device = Device::new("H619C", "AA:BB:CC:DD:EE:FF:42:2A");
+        device.set_lan_device(lan_device("H619C"));
+        assert!(!device.has_lan_scenes());
+        device.lan_scenes = true;
+        assert!(device.has_lan_scenes());
+    }
+
+    #[test]
+    fn firmware_and_last_seen() {
+        let mut device = Device::new("H6072", "AA:BB:CC:DD:EE:FF:42:2A");
+        assert_eq!(device.firmware_version(), None);
+        assert_eq!(device.last_seen(), None);
+
+        device.set_lan_device(
+            serde_json::from_value(serde_json::json!({
+                "ip": "10.0.0.2",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "sku": "H6072",
+                "bleVersionHard": "3.01.01",
+                "bleVersionSoft": "1.03.01",
+                "wifiVersionHard": "1.00.10",
+                "wifiVersionSoft": "",
+            }))
+            .unwrap(),
+        );
+        // The LAN API versions are for the wifi and BLE parts
+        assert_eq!(device.firmware_version(), None);
+        assert_eq!(
+            device.firmware_versions().keys().collect::<Vec<_>>(),
+            vec![&"ble_hardware", &"ble_software", &"wifi_hardware"]
+        );
+        assert_eq!(device.last_seen(), device.last_lan_device_update);
+    }
+
+    #[test]
+    fn firmware_from_device_list() {
+        let list: crate::undoc_api::DevicesResponse = crate::platform_api::from_json(include_str!(
+            "../../test-data/undoc-device-list-issue-21.json"
+        ))
+        .unwrap();
+        let entry = list
+            .devices
+            .into_iter()
+            .find(|entry| entry.sku == "H5080")
+            .unwrap();
+        let mut device = Device::new(&entry.sku, &entry.device);
+        device.set_undoc_device_info(entry, None);
+
+        // The wifi module has its own, different, version
+        assert_eq!(device.firmware_version().as_deref(), Some("1.00.12"));
+        assert_eq!(
+            device
+                .firmware_versions()
+                .get("wifi_software")
+                .map(String::as_str),
+            Some("1.00.21")
+        );
+        assert_eq!(device.latest_firmware_version(), None);
+    }
+
+    #[test]
+    fn out_of_order_responses() {
+        let mut device = Device::new("H6072", "AA:BB:CC:DD:EE:FF:42:2A");
+        let status = |brightness| LanDeviceStatus {
+            on: true,
+            brightness,
+            color: DeviceColor::default(),
+            color_temperature_kelvin: 3000,
+        };
+
+        // The first request is answered after the second
+        let first = Freshness::now();
+        let second = Freshness::now();
+        assert!(!device.has_fresh_reading());
+        assert!(device.set_lan_device_status(status(80), second));
+        assert!(device.has_fresh_reading());
+        assert!(!device.set_lan_device_status(status(20), first));
+        assert_eq!(device.lan_device_status.as_ref().unwrap().brightness, 80);
+
+        let polled = HttpDeviceState {
+            sku: "H6072".to_string(),
+            device: device.id.clone(),
+            capabilities: vec![],
+        };
+        let first = Freshness::now();
+        assert!(device.set_http_device_state(polled.clone(), Freshness::now()));
+        assert!(!device.set_http_device_state(polled, first));
+    }
+
+    #[test]
+    fn power_states() {
+        let schedules = parse_schedules(
+            ScheduleKind::Timer,
+            &serde_json::json!([{"enable": false, "hour": 7, "minute": 0}]),
+        );
+        assert_eq!(PowerState::new(true, &schedules), PowerState::Running);
+        assert_eq!(PowerState::new(false, &schedules), PowerState::Standby);
+        assert_eq!(PowerState::new(false, &[]), PowerState::Standby);
+
+        let schedules = parse_schedules(
+            ScheduleKind::Timer,
+            &serde_json::json!([{"enable": true, "hour": 7, "minute": 0}]),
+        );
+        assert_eq!(PowerState::new(false, &schedules), PowerState::Scheduled);
+        assert!(!PowerState::Scheduled.is_on());
+        assert_eq!(PowerState::new(true, &schedules), PowerState::Running);
+    }
+
+    #[test]
+    fn capability_changes() {
+        let info = |caps: serde_json::Value| -> HttpDeviceInfo {
+            serde_json::from_value(serde_json::json!({
+                "sku": "H7131",
+                "device": "AA:BB:CC:DD:EE:FF:42:2A",
+                "capabilities": caps,
+            }))
+            .unwrap()
+        };
+        let old = info(serde_json::json!([
+            {"type": "devices.capabilities.on_off", "instance": "powerSwitch"},
+            {"type": "devices.capabilities.toggle", "instance": "oscillationToggle"},
+            {"type": "devices.capabilities.property", "instance": "sensorTemperature"},
+        ]));
+        let new = info(serde_json::json!([
+            {"type": "devices.capabilities.on_off", "instance": "powerSwitch"},
+            {"type": "devices.capabilities.on_off", "instance": "oscillationToggle"},
+            {"type": "devices.capabilities.toggle", "instance": "thermostatToggle"},
+        ]));
+
+        assert!(CapabilityChanges::between(&old, &old).is_empty());
+        assert_eq!(
+            CapabilityChanges::between(&old, &new),
+            CapabilityChanges {
+                device: "AA:BB:CC:DD:EE:FF:42:2A".to_string(),
+                sku: "H7131".to_string(),
+                added: vec!["thermostatToggle".to_string()],
+                removed: vec!["sensorTemperature".to_string()],
+                changed: vec!["oscillationToggle".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn canonical_ids() {
+        for reported in [
+            ["aa:bb:cc:dd:ee:ff:42:2a", "AABBCCDDEEFF422A"],
+            ["AABBCCDDEEFF422A", "aa:bb:cc:dd:ee:ff:42:2a"],
+        ] {
+            let state = crate::service::state::State::new();
+            for id in reported {
+                drop(state.device_mut("H6199", id).await);
+            }
+            assert_eq!(state.devices().await.len(), 1);
+
+            // The id is the same whichever format was reported first,
+            // as it is the basis of the topics and unique ids
+            let device = state.device_by_id("aabbccddeeff422a").await.unwrap();
+            assert_eq!(device.id, "AA:BB:CC:DD:EE:FF:42:2A");
+        }
+    }
+
+    #[test]
+    fn sku_conflict() {
+        let mut device = Device::new("H6199", "AA:BB:CC:DD:EE:FF:42:2A");
+        device.note_sku("H619A");
+        device.note_sku("H619A");
+        assert_eq!(device.alternate_skus, vec!["H619A".to_string()]);
+
+        device.adopt_cloud_sku("H619A");
+        assert_eq!(device.sku, "H619A");
+        assert_eq!(device.alternate_skus, vec!["H6199".to_string()]);
+    }
+
+    #[test]
+    fn sub_devices() {
+        let subs = parse_sub_devices(&serde_json::json!({
+            "sub_0": {"name": "H5179_1234"},
+            "sub_1": {"name": "Freezer", "sku": "H5054", "device": "aa:bb:cc:dd"},
+            "sub_2": {},
+        }));
+        assert_eq!(subs.len(), 2);
+
+        let thermo = Device::new("H5179", "11:22:33:44:55:66:12:34");
+        assert!(subs[0].matches(&thermo));
+        assert!(!subs[1].matches(&thermo));
+
+        let leak = Device::new("H5054", "AA:BB:CC:DD");
+        assert!(subs[1].matches(&leak));
+    }
 }
@@ -0,0 +1,92 @@
+use crate::service::device::DeviceState;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// How many events a slow subscriber may fall behind by before it
+/// starts to miss them
+const EVENT_BUS_CAPACITY: usize = 256;
+
+/// Something interesting happened to a device.
+/// Subsystems that need to react to changes, such as the MQTT publisher
+/// or the HTTP event stream, subscribe to these via the EventBus rather
+/// than polling the state or being called directly.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DeviceEvent {
+    /// The state of the device has changed
+    StateChanged {
+        id: String,
+        sku: String,
+        state: Option<DeviceState>,
+    },
+    /// The subscriber fell behind and missed some events, so the
+    /// state of any device may have changed without it being told.
+    /// This is never emitted; it is produced by `recv_event`.
+    Resync { missed: u64 },
+}
+
+pub struct EventBus {
+    tx: broadcast::Sender<DeviceEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (tx, _rx) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { tx }
+    }
+}
+
+impl EventBus {
+    /// Deliver an event to all current subscribers.
+    /// It is not an error for there to be no subscribers.
+    pub fn emit(&self, event: DeviceEvent) {
+        log::trace!("emit {event:?}");
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.tx.subscribe()
+    }
+}
+
+/// Receive the next event. If the subscriber fell behind, a `Resync`
+/// event is returned in place of those that were missed.
+/// Returns None once the bus is closed.
+pub async fn recv_event(rx: &mut broadcast::Receiver<DeviceEvent>) -> Option<DeviceEvent> {
+    match rx.recv().await {
+        Ok(event) => Some(event),
+        Err(broadcast::error::RecvError::Lagged(missed)) => {
+            log::warn!("event subscriber lagged and missed {missed} events");
+            Some(DeviceEvent::Resync { missed })
+        }
+        Err(broadcast::error::RecvError::Closed) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn lagging_subscriber() {
+        let bus = EventBus::default();
+        let mut rx = bus.subscribe();
+        for n in 0..EVENT_BUS_CAPACITY + 2 {
+            bus.emit(DeviceEvent::StateChanged {
+                id: format!("{n}"),
+                sku: "H6000".to_string(),
+                state: None,
+            });
+        }
+        // The oldest two were displaced, which we should be told
+        // about before resuming at the oldest retained event
+        assert!(matches!(
+            recv_event(&mut rx).await,
+            Some(DeviceEvent::Resync { missed: 2 })
+        ));
+        assert!(matches!(
+            recv_event(&mut rx).await,
+            Some(DeviceEvent::StateChanged { id, .. }) if id == "2"
+        ));
+    }
+}
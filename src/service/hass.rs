@@ -1,22 +1,54 @@
+use crate::api_log::mask_secret;
 use crate::hass_mqtt::climate::mqtt_set_temperature;
+use crate::hass_mqtt::device_settings::{mqtt_set_alert, mqtt_set_schedule, mqtt_set_setting};
 use crate::hass_mqtt::enumerator::{enumerate_all_entites, enumerate_entities_for_device};
+use crate::hass_mqtt::fan::{mqtt_fan_set_level, mqtt_fan_set_speed};
 use crate::hass_mqtt::humidifier::{mqtt_device_set_work_mode, mqtt_humidifier_set_target};
-use crate::hass_mqtt::instance::EntityList;
-use crate::hass_mqtt::number::mqtt_number_command;
+use crate::hass_mqtt::instance::{config_topic, DiscoveryPacing, EntityInstance, EntityList};
+use crate::hass_mqtt::manifest::{publish_device_manifests, ManifestEntry};
+use crate::hass_mqtt::music::{mqtt_set_music_mode, mqtt_set_music_sensitivity};
+use crate::hass_mqtt::number::{mqtt_countdown_command, mqtt_number_command, mqtt_range_command};
+use crate::hass_mqtt::room::{
+    mqtt_room_brightness_command, mqtt_room_power_command, notify_room_of_state_change,
+};
 use crate::hass_mqtt::select::mqtt_set_mode_scene;
-use crate::lan_api::DeviceColor;
+use crate::hass_mqtt::text::mqtt_set_scene_name;
+use crate::hass_mqtt::virtual_device::{mqtt_virtual_light_command, VirtualLight};
+use crate::hass_mqtt::water_heater::mqtt_water_heater_set_mode;
+use crate::lan_api::{truthy, DeviceColor};
 use crate::opt_env_var;
 use crate::platform_api::{from_json, DeviceType};
+use crate::service::cluster::{
+    leader_topic, mqtt_leader_lease, unshared_topic, Cluster, ClusterRole,
+};
+use crate::service::command_result::track_command;
+use crate::service::composition::virtual_devices;
+use crate::service::config::RoomControls;
 use crate::service::device::Device as ServiceDevice;
+use crate::service::events::{recv_event, DeviceEvent};
+use crate::service::identity::{bridge_id, mqtt_client_id_prefix};
+use crate::service::mirror::MirrorBroker;
+use crate::service::publisher::{PublishStats, Publisher, DEFAULT_PUBLISH_QUEUE_CAPACITY};
+use crate::service::quiet_hours::is_quiet_now;
+use crate::service::scale::CommandScale;
 use crate::service::state::StateHandle;
+use crate::service::state_grace::is_state_held_back;
+use crate::service::transition::{self, LightTarget, Ticket};
 use crate::temperature::TemperatureScale;
 use anyhow::Context;
 use async_channel::Receiver;
+use chrono::Utc;
 use mosquitto_rs::router::{MqttRouter, Params, Payload, State};
 use mosquitto_rs::{Client, Event, QoS};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::broadcast::Receiver as BroadcastReceiver;
+use tokio::sync::oneshot;
 
 const HASS_REGISTER_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(15);
 
@@ -49,6 +81,59 @@ pub struct HassArguments {
     #[arg(long, global = true, default_value = "homeassistant")]
     hass_discovery_prefix: String,
 
+    /// The maximum number of entity configs to publish to home assistant
+    /// per second when registering entities. Lower this if your broker
+    /// or home assistant struggles with the burst of configs at startup.
+    /// You may also set this via the GOVEE_HASS_DISCOVERY_RATE environment
+    /// variable.
+    /// If unspecified, uses 10.
+    #[arg(long, global = true)]
+    hass_discovery_rate: Option<f64>,
+
+    /// The number of entity configs to publish at a time when registering
+    /// entities. Each batch must have been sent to the broker before the
+    /// next one is started.
+    /// You may also set this via the GOVEE_HASS_DISCOVERY_BATCH_SIZE
+    /// environment variable.
+    /// If unspecified, uses 10.
+    #[arg(long, global = true)]
+    hass_discovery_batch_size: Option<usize>,
+
+    /// When running multiple replicas of govee2mqtt against the same
+    /// broker, set this to the same group name on each of them.
+    /// Commands will be received via `$share/<group>/` subscriptions
+    /// so that only one replica handles each command, and a single
+    /// elected leader will publish entity configs and state.
+    /// You may also set this via the GOVEE_MQTT_SHARED_GROUP
+    /// environment variable.
+    #[arg(long, global = true)]
+    mqtt_shared_group: Option<String>,
+
+    /// Run as a cold standby for another govee2mqtt instance connected
+    /// to the same broker. A standby instance publishes nothing and
+    /// ignores commands until the primary instance stops publishing its
+    /// heartbeat, at which point it takes over.
+    /// You may also set GOVEE_MQTT_STANDBY=true via the environment.
+    #[arg(long, global = true)]
+    standby: bool,
+
+    /// Publish a heartbeat on the leader topic, so that an instance
+    /// running with --standby can take over if this one goes away.
+    /// You may also set GOVEE_MQTT_HEARTBEAT=true via the environment.
+    #[arg(long, global = true)]
+    heartbeat: bool,
+
+    /// A name that distinguishes this instance from other govee2mqtt
+    /// instances connected to the same broker and Home Assistant,
+    /// such as one per VLAN. It is folded into the MQTT client id,
+    /// the bridge-wide topics, the bridge device name and the unique
+    /// ids of the bridge's own entities. It may contain ASCII letters,
+    /// digits, '-' and '_'.
+    /// You may also set this via the GOVEE_INSTANCE_NAME environment
+    /// variable.
+    #[arg(long, global = true)]
+    instance_name: Option<String>,
+
     /// The temperature scale to use when showing temperature values as
     /// entities in home assistant. Can be either "C" or "F" for Celsius
     /// or Farenheit respectively.
@@ -56,6 +141,14 @@ pub struct HassArguments {
     /// variable.
     #[arg(long, global = true)]
     temperature_scale: Option<String>,
+
+    /// Exchange color temperatures with home assistant in mireds
+    /// rather than in Kelvin, for versions of home assistant prior
+    /// to 2024.3.
+    /// You may also set GOVEE_HASS_COLOR_TEMP_MIREDS=true via the
+    /// environment.
+    #[arg(long, global = true)]
+    hass_color_temp_mireds: bool,
 }
 
 impl HassArguments {
@@ -96,6 +189,110 @@ impl HassArguments {
         }
     }
 
+    pub fn mqtt_bind_address(&self) -> Option<&str> {
+        self.mqtt_bind_address.as_deref()
+    }
+
+    pub fn mqtt_shared_group(&self) -> anyhow::Result<Option<String>> {
+        match self.mqtt_shared_group.clone() {
+            Some(g) => Ok(Some(g)),
+            None => opt_env_var("GOVEE_MQTT_SHARED_GROUP"),
+        }
+    }
+
+    pub fn standby(&self) -> anyhow::Result<bool> {
+        if let Some(v) = opt_env_var::<String>("GOVEE_MQTT_STANDBY")? {
+            return truthy(&v);
+        }
+        Ok(self.standby)
+    }
+
+    pub fn heartbeat(&self) -> anyhow::Result<bool> {
+        if let Some(v) = opt_env_var::<String>("GOVEE_MQTT_HEARTBEAT")? {
+            return truthy(&v);
+        }
+        Ok(self.heartbeat)
+    }
+
+    pub fn cluster_role(&self) -> anyhow::Result<ClusterRole> {
+        match (
+            self.mqtt_shared_group()?,
+            self.standby()?,
+            self.heartbeat()?,
+        ) {
+            (Some(_), true, _) | (Some(_), _, true) | (None, true, true) => anyhow::bail!(
+                "--standby, --heartbeat and --mqtt-shared-group are mutually \
+                 exclusive; please use only one of them"
+            ),
+            (Some(group), false, false) => Ok(ClusterRole::Shared { group }),
+            (None, true, false) => Ok(ClusterRole::Standby),
+            (None, false, true) => Ok(ClusterRole::Heartbeat),
+            (None, false, false) => Ok(ClusterRole::Primary),
+        }
+    }
+
+    pub fn instance_name(&self) -> anyhow::Result<Option<String>> {
+        match self.instance_name.clone() {
+            Some(n) => Ok(Some(n)),
+            None => opt_env_var("GOVEE_INSTANCE_NAME"),
+        }
+    }
+
+    /// The settings that are in effect, with secrets masked
+    pub fn effective_settings(&self) -> anyhow::Result<JsonValue> {
+        let pacing = self.discovery_pacing()?;
+        Ok(json!({
+            "mqtt_host": self.opt_mqtt_host()?,
+            "mqtt_port": self.mqtt_port()?,
+            "mqtt_username": self.mqtt_username()?,
+            "mqtt_password": mask_secret(self.mqtt_password()?),
+            "mqtt_bind_address": self.mqtt_bind_address(),
+            "mqtt_shared_group": self.mqtt_shared_group()?,
+            "standby": self.standby()?,
+            "heartbeat": self.heartbeat()?,
+            "instance_name": self.instance_name()?,
+            "hass_discovery_prefix": self.hass_discovery_prefix,
+            "hass_discovery_rate": pacing.rate,
+            "hass_discovery_batch_size": pacing.batch_size,
+            "color_temp_units": format!("{:?}", self.color_temp_units()?),
+            "temperature_scale": self.temperature_scale()?.to_string(),
+        }))
+    }
+
+    pub fn discovery_pacing(&self) -> anyhow::Result<DiscoveryPacing> {
+        let rate = match self.hass_discovery_rate {
+            Some(r) => r,
+            None => opt_env_var("GOVEE_HASS_DISCOVERY_RATE")?.unwrap_or(10.0),
+        };
+        let batch_size = match self.hass_discovery_batch_size {
+            Some(b) => b,
+            None => opt_env_var("GOVEE_HASS_DISCOVERY_BATCH_SIZE")?.unwrap_or(10),
+        };
+        anyhow::ensure!(
+            rate.is_finite() && rate > 0.0,
+            "the hass discovery rate must be greater than zero"
+        );
+        anyhow::ensure!(
+            batch_size > 0,
+            "the hass discovery batch size must be greater than zero"
+        );
+        Ok(DiscoveryPacing { rate, batch_size })
+    }
+
+    pub fn color_temp_units(&self) -> anyhow::Result<ColorTempUnits> {
+        // The flag takes precedence over the environment
+        let mireds = self.hass_color_temp_mireds
+            || opt_env_var::<String>("GOVEE_HASS_COLOR_TEMP_MIREDS")?
+                .map(|v| truthy(&v))
+                .transpose()?
+                .unwrap_or(false);
+        Ok(if mireds {
+            ColorTempUnits::Mired
+        } else {
+            ColorTempUnits::Kelvin
+        })
+    }
+
     pub fn temperature_scale(&self) -> anyhow::Result<TemperatureScale> {
         match &self.temperature_scale {
             Some(s) => Ok(s.parse()?),
@@ -108,14 +305,63 @@ impl HassArguments {
 
 #[derive(Clone)]
 pub struct HassClient {
-    client: Client,
+    cluster: Arc<Cluster>,
+    publisher: Arc<Publisher>,
+    discovery_pacing: DiscoveryPacing,
+    /// Confirmations for configs that have been queued but that
+    /// haven't yet been waited on via `wait_for_configs`
+    pending_configs: Arc<Mutex<Vec<oneshot::Receiver<()>>>>,
+    /// The config topics that we have published since we
+    /// became the leader
+    known_configs: Arc<Mutex<HashSet<String>>>,
+    /// When set, configs for topics that are in `known_configs` are
+    /// not republished, and the count is incremented for each
+    /// config that is published
+    new_configs: Option<Arc<AtomicUsize>>,
+    /// When set, entity configs are recorded here rather than
+    /// being published
+    manifest: Option<Arc<Mutex<Vec<ManifestEntry>>>>,
 }
 
 impl HassClient {
-    async fn register_with_hass(&self, state: &StateHandle) -> anyhow::Result<()> {
+    pub fn cluster(&self) -> &Cluster {
+        &self.cluster
+    }
+
+    pub fn discovery_pacing(&self) -> DiscoveryPacing {
+        self.discovery_pacing
+    }
+
+    /// Returns a client that records the entities whose configs it is
+    /// asked to publish, rather than publishing them
+    pub fn manifest_recording(&self) -> (Self, Arc<Mutex<Vec<ManifestEntry>>>) {
+        let manifest = Arc::new(Mutex::new(vec![]));
+        let client = Self {
+            manifest: Some(manifest.clone()),
+            ..self.clone()
+        };
+        (client, manifest)
+    }
+
+    pub fn manifest_recorder(&self) -> Option<&Mutex<Vec<ManifestEntry>>> {
+        self.manifest.as_deref()
+    }
+
+    pub fn publish_stats(&self) -> PublishStats {
+        self.publisher.stats()
+    }
+
+    pub(crate) async fn register_with_hass(&self, state: &StateHandle) -> anyhow::Result<()> {
+        if !self.cluster.is_leader() {
+            log::debug!("register_with_hass: not the leader, skipping");
+            return Ok(());
+        }
+
         let entities = enumerate_all_entites(state).await?;
 
-        // Register the configs
+        // Register the configs. This returns once they have all been
+        // sent to the broker, so that we never report state for an
+        // entity that hass doesn't know about yet
         log::trace!("register_with_hass: register entities");
         entities.publish_config(state, self).await?;
 
@@ -133,25 +379,213 @@ impl HassClient {
         self.publish(availability_topic(), "online")
             .await
             .context("online -> availability_topic")?;
+        self.advise_hass_of_availability(state)
+            .await
+            .context("advise_hass_of_availability")?;
 
-        // report initial state
         log::trace!("register_with_hass: reporting state");
-        entities.notify_state(self).await.context("notify_state")?;
+        self.notify_reportable_state(state, &entities)
+            .await
+            .context("notify_state")?;
+
+        self.remove_stale_configs(state, &entities)
+            .await
+            .context("remove_stale_configs")?;
+        self.remove_disabled_configs(state, &entities)
+            .await
+            .context("remove_disabled_configs")?;
+
+        log::trace!("register_with_hass: publishing manifests");
+        publish_device_manifests(state, self)
+            .await
+            .context("publish_device_manifests")?;
 
         log::trace!("register_with_hass: done");
 
         Ok(())
     }
 
+    /// Report the state of `entities`, other than that which is being
+    /// held back until the device reports
+    async fn notify_reportable_state(
+        &self,
+        state: &StateHandle,
+        entities: &EntityList,
+    ) -> anyhow::Result<()> {
+        let held_back: HashSet<String> = state
+            .devices()
+            .await
+            .into_iter()
+            .filter(is_state_held_back)
+            .map(|d| d.id)
+            .collect();
+        let mut reportable = entities.clone();
+        reportable.retain_devices(|id| !held_back.contains(id));
+        reportable.notify_state(self).await
+    }
+
+    /// Publish configs and state for any entities that have appeared
+    /// since we registered with hass, such as those added by a change
+    /// to the configuration file, without republishing the others.
+    pub async fn register_new_entities(&self, state: &StateHandle) -> anyhow::Result<()> {
+        if !self.cluster.is_leader() {
+            log::debug!("register_new_entities: not the leader, skipping");
+            return Ok(());
+        }
+
+        let entities = enumerate_all_entites(state).await?;
+        let count = Arc::new(AtomicUsize::new(0));
+        let client = Self {
+            new_configs: Some(count.clone()),
+            ..self.clone()
+        };
+
+        let mut added = EntityList::new();
+        for entity in entities.iter() {
+            let before = count.load(Ordering::Relaxed);
+            entity
+                .publish_config(state, &client)
+                .await
+                .context("register_new_entities")?;
+            if count.load(Ordering::Relaxed) != before {
+                added.push(entity.clone());
+            }
+        }
+        let removed = self
+            .remove_stale_configs(state, &entities)
+            .await
+            .context("remove_stale_configs")?;
+        if added.is_empty() && removed == 0 {
+            return Ok(());
+        }
+
+        if !added.is_empty() {
+            log::info!("Registering {} new entities with hass", added.len());
+            client.wait_for_configs().await;
+            self.advise_hass_of_availability(state)
+                .await
+                .context("advise_hass_of_availability")?;
+            added.notify_state(self).await.context("notify_state")?;
+        }
+        publish_device_manifests(state, self)
+            .await
+            .context("publish_device_manifests")
+    }
+
+    /// Republish the configs and state of the entities of a device,
+    /// such as after its scene list has changed
+    pub async fn republish_device_entities(
+        &self,
+        state: &StateHandle,
+        device: &ServiceDevice,
+    ) -> anyhow::Result<()> {
+        if !self.cluster.is_leader() {
+            return Ok(());
+        }
+
+        let mut entities = EntityList::new();
+        enumerate_entities_for_device(device, state, &mut entities).await?;
+        entities.publish_config(state, self).await?;
+        if is_state_held_back(device) {
+            return Ok(());
+        }
+        entities.notify_state(self).await
+    }
+
+    /// Remove the configs that we published earlier but which no longer
+    /// correspond to any of `entities`, such as those for capabilities
+    /// that a device stopped reporting after a firmware update.
+    /// Returns the number of configs that were removed.
+    async fn remove_stale_configs(
+        &self,
+        state: &StateHandle,
+        entities: &EntityList,
+    ) -> anyhow::Result<usize> {
+        if !entities.is_complete() {
+            // Entities that are missing because of a transient
+            // failure would otherwise be deleted from hass, taking
+            // the user's customizations of them along with them
+            log::warn!("Not removing stale entities, as some could not be enumerated");
+            return Ok(0);
+        }
+
+        let (recorder, recorded) = self.manifest_recording();
+        for entity in entities.iter() {
+            entity.publish_config(state, &recorder).await?;
+        }
+        let disco = state.get_hass_disco_prefix().await;
+        let current: HashSet<String> = recorded
+            .lock()
+            .iter()
+            .map(|entry| config_topic(&disco, &entry.platform, &entry.unique_id))
+            .collect();
+
+        let stale: Vec<String> = {
+            let mut known = self.known_configs.lock();
+            let stale: Vec<String> = known
+                .iter()
+                .filter(|topic| !current.contains(*topic))
+                .cloned()
+                .collect();
+            for topic in &stale {
+                known.remove(topic);
+            }
+            stale
+        };
+
+        for topic in &stale {
+            log::info!("Removing entity that no longer applies: {topic}");
+            // An empty config causes hass to delete the entity
+            self.publish(topic, "").await?;
+        }
+        Ok(stale.len())
+    }
+
+    /// Remove the configs of the entities that are disabled by the
+    /// `disabled_entities` configuration. Configs that were published
+    /// by an earlier run are unknown to us, so they are all removed,
+    /// and the removal is retained so that any retained config held
+    /// by the broker is cleared too.
+    async fn remove_disabled_configs(
+        &self,
+        state: &StateHandle,
+        entities: &EntityList,
+    ) -> anyhow::Result<()> {
+        let (recorder, recorded) = self.manifest_recording();
+        for entity in entities.disabled() {
+            entity.publish_config(state, &recorder).await?;
+        }
+        let disco = state.get_hass_disco_prefix().await;
+        let topics: Vec<String> = recorded
+            .lock()
+            .iter()
+            .map(|entry| config_topic(&disco, &entry.platform, &entry.unique_id))
+            .collect();
+
+        for topic in topics {
+            log::info!("Removing disabled entity: {topic}");
+            self.known_configs.lock().remove(&topic);
+            if !self.cluster.is_leader() {
+                continue;
+            }
+            // An empty config causes hass to delete the entity
+            self.publisher.enqueue_retained(topic, vec![]);
+        }
+        Ok(())
+    }
+
     pub async fn publish<T: AsRef<str> + std::fmt::Display, P: AsRef<[u8]> + std::fmt::Display>(
         &self,
         topic: T,
         payload: P,
     ) -> anyhow::Result<()> {
+        if !self.cluster.is_leader() {
+            log::trace!("{topic} -> {payload} (suppressed, not the leader)");
+            return Ok(());
+        }
         log::trace!("{topic} -> {payload}");
-        self.client
-            .publish(topic, payload, QoS::AtMostOnce, false)
-            .await?;
+        self.publisher
+            .enqueue(topic.to_string(), payload.as_ref().to_vec());
         Ok(())
     }
 
@@ -161,26 +595,172 @@ impl HassClient {
         payload: P,
     ) -> anyhow::Result<()> {
         let payload = serde_json::to_string(&payload)?;
+        if !self.cluster.is_leader() {
+            log::trace!("{topic} -> {payload} (suppressed, not the leader)");
+            return Ok(());
+        }
         log::trace!("{topic} -> {payload}");
-        self.client
-            .publish(topic, payload, QoS::AtMostOnce, false)
-            .await?;
+        self.publisher
+            .enqueue(topic.to_string(), payload.into_bytes());
         Ok(())
     }
 
+    /// Like `publish_obj`, but each payload is delivered rather than
+    /// only the latest one for the topic. It is published whether
+    /// or not this instance is the leader, as the event may have
+    /// happened on any instance, such as a command that it handled.
+    pub async fn publish_event_obj<T: AsRef<str> + std::fmt::Display, P: Serialize>(
+        &self,
+        topic: T,
+        payload: P,
+    ) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(&payload)?;
+        log::trace!("{topic} -> {payload}");
+        self.publisher
+            .enqueue_event(topic.to_string(), payload.into_bytes());
+        Ok(())
+    }
+
+    /// Like `publish_obj`, but the broker retains the payload
+    /// for clients that subscribe later
+    pub async fn publish_retained_obj<T: AsRef<str> + std::fmt::Display, P: Serialize>(
+        &self,
+        topic: T,
+        payload: P,
+    ) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(&payload)?;
+        if !self.cluster.is_leader() {
+            log::trace!("{topic} -> {payload} (suppressed, not the leader)");
+            return Ok(());
+        }
+        log::trace!("{topic} -> {payload} (retained)");
+        self.publisher
+            .enqueue_retained(topic.to_string(), payload.into_bytes());
+        Ok(())
+    }
+
+    /// Queue an entity config for publishing. Use `wait_for_configs`
+    /// to wait until it has been sent to the broker.
+    pub async fn publish_config_obj<T: AsRef<str> + std::fmt::Display, P: Serialize>(
+        &self,
+        topic: T,
+        payload: P,
+    ) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(&payload)?;
+        if !self.cluster.is_leader() {
+            log::trace!("{topic} -> {payload} (suppressed, not the leader)");
+            return Ok(());
+        }
+        log::trace!("{topic} -> {payload}");
+        let is_new = self.known_configs.lock().insert(topic.to_string());
+        if let Some(count) = &self.new_configs {
+            if !is_new {
+                return Ok(());
+            }
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        let confirmation = self
+            .publisher
+            .enqueue_confirmed(topic.to_string(), payload.into_bytes());
+        self.pending_configs.lock().push(confirmation);
+        Ok(())
+    }
+
+    /// Wait until the configs queued by `publish_config_obj` have
+    /// been sent to the broker
+    pub async fn wait_for_configs(&self) {
+        let pending = std::mem::take(&mut *self.pending_configs.lock());
+        let total = pending.len();
+        let mut failed = 0;
+        for confirmation in pending {
+            if confirmation.await.is_err() {
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            log::warn!("{failed} of {total} entity configs could not be sent to the broker");
+        }
+    }
+
     pub async fn advise_hass_of_light_state(
         &self,
         device: &ServiceDevice,
         state: &StateHandle,
     ) -> anyhow::Result<()> {
+        self.advise_hass_of_device_availability(device).await?;
+        if is_state_held_back(device) {
+            return Ok(());
+        }
+
         let mut entities = EntityList::new();
         enumerate_entities_for_device(device, state, &mut entities).await?;
+        if is_quiet_now() {
+            entities.retain_non_diagnostic();
+        }
         entities.notify_state(self).await?;
 
         Ok(())
     }
 }
 
+impl HassClient {
+    pub async fn advise_hass_of_device_availability(
+        &self,
+        device: &ServiceDevice,
+    ) -> anyhow::Result<()> {
+        let availability = if device.is_reachable(Utc::now()) {
+            "online"
+        } else {
+            "offline"
+        };
+        self.publish(device_availability_topic(device), availability)
+            .await
+    }
+
+    /// Report the availability of each of the devices; this is
+    /// done periodically, as a device that drops off the network
+    /// doesn't otherwise cause any events
+    pub async fn advise_hass_of_availability(&self, state: &StateHandle) -> anyhow::Result<()> {
+        for device in state.devices().await {
+            self.advise_hass_of_device_availability(&device).await?;
+        }
+        Ok(())
+    }
+}
+
+impl HassClient {
+    /// A client that queues publishes without ever sending them,
+    /// for examining the entities that we would register without
+    /// being connected to a broker
+    pub(crate) fn detached() -> Self {
+        Self {
+            cluster: Arc::new(Cluster::new("detached".to_string(), ClusterRole::Primary)),
+            publisher: Arc::new(Publisher::new(DEFAULT_PUBLISH_QUEUE_CAPACITY)),
+            discovery_pacing: DiscoveryPacing {
+                rate: 10.0,
+                batch_size: 10,
+            },
+            pending_configs: Default::default(),
+            known_configs: Default::default(),
+            new_configs: None,
+            manifest: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl HassClient {
+    /// A client that queues publishes without ever sending them,
+    /// so that tests can examine what would have been sent
+    pub(crate) fn for_test() -> Self {
+        Self::detached()
+    }
+
+    pub(crate) fn take_published(&self) -> Vec<(String, Vec<u8>)> {
+        self.publisher.drain()
+    }
+}
+
 pub fn topic_safe_string(s: &str) -> String {
     let mut result = String::new();
     for c in s.chars() {
@@ -194,7 +774,11 @@ pub fn topic_safe_string(s: &str) -> String {
 }
 
 pub fn topic_safe_id(device: &ServiceDevice) -> String {
-    let mut id = device.id.to_string();
+    topic_safe_id_str(&device.id)
+}
+
+pub fn topic_safe_id_str(id: &str) -> String {
+    let mut id = id.to_string();
     id.retain(|c| c != ':');
     id.retain(|c| c != ' ');
     id
@@ -221,15 +805,20 @@ pub fn light_segment_state_topic(device: &ServiceDevice, segment: u32) -> String
 /// All entities use the same topic so that we can mark unavailable
 /// via last-will
 pub fn availability_topic() -> String {
-    "gv2mqtt/availability".to_string()
+    format!("{}/availability", bridge_id())
+}
+
+/// Where we report whether an individual device is reachable
+pub fn device_availability_topic(device: &ServiceDevice) -> String {
+    format!("gv2mqtt/{id}/availability", id = topic_safe_id(device))
 }
 
 pub fn oneclick_topic() -> String {
-    "gv2mqtt/oneclick".to_string()
+    format!("{}/oneclick", bridge_id())
 }
 
 pub fn purge_cache_topic() -> String {
-    "gv2mqtt/purge-caches".to_string()
+    format!("{}/purge-caches", bridge_id())
 }
 
 #[derive(Deserialize)]
@@ -250,13 +839,28 @@ async fn mqtt_request_platform_data(
     Ok(())
 }
 
+/// Someone clicked the "Poll Now" button
+async fn mqtt_poll_now(
+    Params(IdParameter { id }): Params<IdParameter>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    let device = state.resolve_device_read_only(&id).await?;
+    log::info!("Poll Now for {device}");
+    if !state.poll_device_now(&device).await? {
+        log::warn!("Unable to poll {device}");
+    }
+    Ok(())
+}
+
 #[derive(Deserialize, Debug, Clone)]
-struct HassLightCommand {
-    state: String,
-    color_temp: Option<u32>,
-    color: Option<DeviceColor>,
-    effect: Option<String>,
-    brightness: Option<u8>,
+pub struct HassLightCommand {
+    pub state: String,
+    pub color_temp: Option<u32>,
+    pub color: Option<DeviceColor>,
+    pub effect: Option<String>,
+    pub brightness: Option<u8>,
+    /// How long to take over the change, in seconds
+    pub transition: Option<f32>,
 }
 
 /// HASS is sending a command to a light
@@ -270,17 +874,34 @@ async fn mqtt_light_command(
     let command: HassLightCommand = serde_json::from_str(&payload)?;
     log::info!("Command for {device}: {payload}");
 
+    apply_light_command(&state, &device, &command).await
+}
+
+/// Apply a light command from hass to the specified device
+pub async fn apply_light_command(
+    state: &StateHandle,
+    device: &ServiceDevice,
+    command: &HassLightCommand,
+) -> anyhow::Result<()> {
     let is_light = device.device_type() == DeviceType::Light;
+    let ticket = Ticket::begin(&device.id);
+
+    if let Some(duration) = command.transition.filter(|t| *t > 0.0) {
+        if !transition_light(state, device, command, &ticket, duration).await? {
+            log::info!("{device}: transition superseded by a later command");
+            return Ok(());
+        }
+    }
 
     if command.state == "OFF" {
         if is_light {
             state
-                .device_light_power_on(&device, false)
+                .device_light_power_on(device, false)
                 .await
                 .context("mqtt_light_command: state.device_power_on")?;
         } else {
             state
-                .device_set_brightness(&device, 0)
+                .device_set_brightness(device, 0)
                 .await
                 .context("mqtt_light_command: state.device_set_brightness")?;
         }
@@ -289,7 +910,7 @@ async fn mqtt_light_command(
 
         if let Some(brightness) = command.brightness {
             state
-                .device_set_brightness(&device, brightness)
+                .device_set_brightness(device, brightness)
                 .await
                 .context("mqtt_light_command: state.device_set_brightness")?;
             power_on = false;
@@ -297,7 +918,7 @@ async fn mqtt_light_command(
 
         if let Some(effect) = &command.effect {
             state
-                .device_set_scene(&device, effect)
+                .device_set_scene(device, effect)
                 .await
                 .context("mqtt_light_command: state.device_set_scene")?;
             // It doesn't make sense to vary color properties
@@ -309,14 +930,17 @@ async fn mqtt_light_command(
 
         if let Some(color) = &command.color {
             state
-                .device_set_color_rgb(&device, color.r, color.g, color.b)
+                .device_set_color_rgb(device, color.r, color.g, color.b)
                 .await
                 .context("mqtt_light_command: state.device_set_color_rgb")?;
             power_on = false;
         }
         if let Some(color_temp) = command.color_temp {
             state
-                .device_set_color_temperature(&device, mired_to_kelvin(color_temp))
+                .device_set_color_temperature(
+                    device,
+                    state.get_color_temp_units().await.to_kelvin(color_temp),
+                )
                 .await
                 .context("mqtt_light_command: state.device_set_color_temperature")?;
             power_on = false;
@@ -325,7 +949,7 @@ async fn mqtt_light_command(
         if power_on {
             if is_light {
                 state
-                    .device_light_power_on(&device, true)
+                    .device_light_power_on(device, true)
                     .await
                     .context("mqtt_light_command: state.device_power_on")?;
             } else if command.brightness.is_none() {
@@ -334,7 +958,7 @@ async fn mqtt_light_command(
                 // brightness to something, and we know we didn't set
                 // the brightness just now, so let's turn it on 100%
                 state
-                    .device_set_brightness(&device, 100)
+                    .device_set_brightness(device, 100)
                     .await
                     .context("mqtt_light_command: state.device_set_brightness")?;
             }
@@ -344,6 +968,72 @@ async fn mqtt_light_command(
     Ok(())
 }
 
+/// Fade the light towards the values in `command` over `duration`
+/// seconds, leaving the final values to be applied as usual.
+/// Returns false if the transition was superseded by a later command.
+async fn transition_light(
+    state: &StateHandle,
+    device: &ServiceDevice,
+    command: &HassLightCommand,
+    ticket: &Ticket,
+    duration: f32,
+) -> anyhow::Result<bool> {
+    if command.state == "OFF" || command.effect.is_some() {
+        log::info!("{device}: transitions only apply when changing brightness or color");
+        return Ok(true);
+    }
+    let (Some(lan_dev), Some(from)) = (&device.lan_device, device.device_state()) else {
+        log::info!("{device}: transitions require the LAN API; changing immediately");
+        return Ok(true);
+    };
+
+    let target = LightTarget {
+        brightness: command.brightness,
+        color: command.color,
+        kelvin: match command.color_temp {
+            Some(value) => Some(state.get_color_temp_units().await.to_kelvin(value)),
+            None => None,
+        },
+    };
+    let steps = transition::plan(&from, target, Duration::from_secs_f32(duration));
+    transition::run(lan_dev, ticket, steps)
+        .await
+        .context("transition_light")
+}
+
+#[derive(Deserialize)]
+pub struct IdAndScale {
+    pub id: String,
+    /// Taken from the config file when the topic doesn't specify it
+    pub scale: Option<CommandScale>,
+}
+
+impl IdAndScale {
+    pub async fn resolve_scale(&self, state: &StateHandle) -> CommandScale {
+        match self.scale {
+            Some(scale) => scale,
+            None => state.get_config().await.command_scale,
+        }
+    }
+}
+
+/// Something other than hass is setting the brightness of a device
+async fn mqtt_set_brightness(
+    Payload(value): Payload<f64>,
+    Params(params): Params<IdAndScale>,
+    State(state): State<StateHandle>,
+) -> anyhow::Result<()> {
+    let device = state.resolve_device_for_control(&params.id).await?;
+    let scale = params.resolve_scale(&state).await;
+    let percent = scale.to_native(value, &(0..101));
+    log::info!("Set brightness of {device} to {percent}% ({value} as {scale:?})");
+
+    state
+        .device_set_brightness(&device, percent as u8)
+        .await
+        .context("mqtt_set_brightness: state.device_set_brightness")
+}
+
 #[derive(Deserialize)]
 struct IdAndSeg {
     id: String,
@@ -361,7 +1051,7 @@ async fn mqtt_light_segment_command(
     let command: HassLightCommand = from_json(&payload)?;
     log::info!("Command for {device} segment {segment}: {payload}");
 
-    if let Some(client) = state.get_platform_client().await {
+    if let Some(client) = state.get_platform_client_for_control().await {
         let info = device
             .http_device_info
             .as_ref()
@@ -453,7 +1143,7 @@ async fn mqtt_switch_command(
 
     if instance == "powerSwitch" {
         state.device_power_on(&device, on).await?;
-    } else if let Some(client) = state.get_platform_client().await {
+    } else if let Some(client) = state.get_platform_client_for_control().await {
         if let Some(http_dev) = &device.http_device_info {
             client.set_toggle_state(http_dev, &instance, on).await?;
         } else {
@@ -482,6 +1172,33 @@ pub fn kelvin_to_mired(kelvin: u32) -> u32 {
     }
 }
 
+/// The units in which color temperatures are exchanged with hass.
+/// Kelvin is native to the devices and avoids the rounding loss of
+/// converting to and from mireds, but needs Home Assistant 2024.3
+/// or later.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorTempUnits {
+    #[default]
+    Kelvin,
+    Mired,
+}
+
+impl ColorTempUnits {
+    pub fn kelvin_in_units(self, kelvin: u32) -> u32 {
+        match self {
+            Self::Kelvin => kelvin,
+            Self::Mired => kelvin_to_mired(kelvin),
+        }
+    }
+
+    pub fn to_kelvin(self, value: u32) -> u32 {
+        match self {
+            Self::Kelvin => value,
+            Self::Mired => mired_to_kelvin(value),
+        }
+    }
+}
+
 /// HASS is advising us that its status has changed
 async fn mqtt_homeassitant_status(
     Payload(status): Payload<String>,
@@ -514,67 +1231,195 @@ async fn run_mqtt_loop(
         state: &StateHandle,
     ) -> anyhow::Result<Arc<MqttRouter<StateHandle>>> {
         let disco_prefix = state.get_hass_disco_prefix().await;
+        let hass = state.get_hass_client().await.expect("have hass client");
+        let cluster = hass.cluster();
         let mut router: MqttRouter<StateHandle> = MqttRouter::new(client.clone());
 
         router
             .route(format!("{disco_prefix}/status"), mqtt_homeassitant_status)
             .await?;
+        if cluster.elects_leader() {
+            router.route(leader_topic(), mqtt_leader_lease).await?;
+        }
 
         router
-            .route("gv2mqtt/light/:id/command", mqtt_light_command)
+            .route(
+                cluster.command_route("gv2mqtt/light/:id/command"),
+                mqtt_light_command,
+            )
             .await?;
         router
             .route(
-                "gv2mqtt/light/:id/command/:segment",
+                cluster.command_route("gv2mqtt/light/:id/command/:segment"),
                 mqtt_light_segment_command,
             )
             .await?;
         router
-            .route("gv2mqtt/switch/:id/command/:instance", mqtt_switch_command)
+            .route(
+                cluster.command_route("gv2mqtt/virtual/:id/command"),
+                mqtt_virtual_light_command,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/room/:id/power/command"),
+                mqtt_room_power_command,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/room/:id/brightness/command"),
+                mqtt_room_brightness_command,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/switch/:id/command/:instance"),
+                mqtt_switch_command,
+            )
             .await?;
 
-        router.route(oneclick_topic(), mqtt_oneclick).await?;
-        router.route(purge_cache_topic(), mqtt_purge_caches).await?;
+        router
+            .route(cluster.command_route(&oneclick_topic()), mqtt_oneclick)
+            .await?;
         router
             .route(
-                "gv2mqtt/:id/request-platform-data",
+                cluster.command_route(&purge_cache_topic()),
+                mqtt_purge_caches,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/request-platform-data"),
                 mqtt_request_platform_data,
             )
             .await?;
+        router
+            .route(cluster.command_route("gv2mqtt/:id/poll-now"), mqtt_poll_now)
+            .await?;
         router
             .route(
-                "gv2mqtt/number/:id/command/:mode_name/:work_mode",
+                cluster.command_route("gv2mqtt/number/:id/command/:mode_name/:work_mode"),
                 mqtt_number_command,
             )
             .await?;
         router
-            .route("gv2mqtt/humidifier/:id/set-mode", mqtt_device_set_work_mode)
+            .route(
+                cluster.command_route("gv2mqtt/number/:id/range/:instance"),
+                mqtt_range_command,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/number/:id/countdown/:instance"),
+                mqtt_countdown_command,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/set-music-mode"),
+                mqtt_set_music_mode,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/number/:id/music-sensitivity"),
+                mqtt_set_music_sensitivity,
+            )
             .await?;
         router
-            .route("gv2mqtt/:id/set-work-mode", mqtt_device_set_work_mode)
+            .route(
+                cluster.command_route("gv2mqtt/humidifier/:id/set-mode"),
+                mqtt_device_set_work_mode,
+            )
             .await?;
         router
             .route(
-                "gv2mqtt/humidifier/:id/set-target",
+                cluster.command_route("gv2mqtt/:id/set-work-mode"),
+                mqtt_device_set_work_mode,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/humidifier/:id/set-target"),
                 mqtt_humidifier_set_target,
             )
             .await?;
         router
             .route(
-                "gv2mqtt/:id/set-temperature/:instance/:units",
+                cluster.command_route("gv2mqtt/fan/:id/set-speed"),
+                mqtt_fan_set_speed,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/fan-speed"),
+                mqtt_fan_set_level,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/fan-speed/:scale"),
+                mqtt_fan_set_level,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/brightness"),
+                mqtt_set_brightness,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/brightness/:scale"),
+                mqtt_set_brightness,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/set-temperature/:instance/:units"),
                 mqtt_set_temperature,
             )
             .await?;
         router
-            .route("gv2mqtt/:id/set-mode-scene", mqtt_set_mode_scene)
+            .route(
+                cluster.command_route("gv2mqtt/water_heater/:id/set-mode"),
+                mqtt_water_heater_set_mode,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/set-mode-scene"),
+                mqtt_set_mode_scene,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/set-scene-name"),
+                mqtt_set_scene_name,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/set-alert/:alert"),
+                mqtt_set_alert,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/set-setting/:setting"),
+                mqtt_set_setting,
+            )
+            .await?;
+        router
+            .route(
+                cluster.command_route("gv2mqtt/:id/set-schedule/:schedule"),
+                mqtt_set_schedule,
+            )
             .await?;
 
         tokio::time::sleep(HASS_REGISTER_DELAY).await;
-        state
-            .get_hass_client()
-            .await
-            .expect("have hass client")
-            .register_with_hass(&state)
+        hass.register_with_hass(state)
             .await
             .context("register_with_hass")?;
 
@@ -586,13 +1431,43 @@ async fn run_mqtt_loop(
 
     while let Ok(event) = subscriber.recv().await {
         match event {
-            Event::Message(msg) => {
+            Event::Message(mut msg) => {
                 let router = router.clone();
                 let state = state.clone();
+                if let Some(hass) = state.get_hass_client().await {
+                    let cluster = hass.cluster();
+                    msg.topic = cluster.rewrite_incoming_topic(&msg.topic);
+                    if !cluster.should_handle_commands()
+                        && msg.topic.starts_with("gv2mqtt")
+                        && msg.topic != leader_topic()
+                    {
+                        log::trace!("Standby: ignoring {msg:?}");
+                        continue;
+                    }
+                    if cluster.is_shared()
+                        && msg.topic.starts_with("$share/")
+                        && cluster.is_duplicate_command(&msg.topic, msg.qos, msg.mid)
+                    {
+                        log::debug!("Ignoring duplicate command {msg:?}");
+                        continue;
+                    }
+                }
                 tokio::spawn(async move {
-                    if let Err(err) = router.dispatch(msg.clone(), state.clone()).await {
-                        log::error!("While dispatching {msg:?}: {err:#}");
+                    let topic = unshared_topic(&msg.topic).to_string();
+                    if !topic.starts_with("gv2mqtt") || topic == leader_topic() {
+                        if let Err(err) = router.dispatch(msg.clone(), state.clone()).await {
+                            log::error!("While dispatching {msg:?}: {err:#}");
+                        }
+                        return;
                     }
+
+                    run_command(&state, &topic, &msg.payload, async {
+                        router
+                            .dispatch(msg.clone(), state.clone())
+                            .await
+                            .map_err(anyhow::Error::from)
+                    })
+                    .await;
                 });
             }
             Event::Disconnected(reason) => {
@@ -613,23 +1488,91 @@ async fn run_mqtt_loop(
     Ok(())
 }
 
+/// Run a command received on `topic`, then publish its result
+async fn run_command<F>(state: &StateHandle, topic: &str, payload: &[u8], dispatch: F)
+where
+    F: std::future::Future<Output = anyhow::Result<()>>,
+{
+    let (result, tracked) = track_command(topic, payload, dispatch).await;
+    if let Err(err) = result {
+        log::error!("While dispatching {topic}: {err:#}");
+    }
+    if let Some(hass) = state.get_hass_client().await {
+        if let Err(err) = tracked.publish(&hass).await {
+            log::error!("While publishing result of {topic}: {err:#}");
+        }
+    }
+}
+
+/// Relay device state changes to hass
+async fn publish_device_events(state: StateHandle, mut events: BroadcastReceiver<DeviceEvent>) {
+    while let Some(event) = recv_event(&mut events).await {
+        match event {
+            DeviceEvent::StateChanged { id, .. } => {
+                let Some(hass) = state.get_hass_client().await else {
+                    continue;
+                };
+                let Some(device) = state.device_by_id(&id).await else {
+                    continue;
+                };
+                if let Err(err) = hass.advise_hass_of_light_state(&device, &state).await {
+                    log::error!("While advising hass of state of {device}: {err:#}");
+                }
+                for v in virtual_devices(&state).await {
+                    if v.has_member(&state, &id).await {
+                        let light = VirtualLight::new(v, &state).await;
+                        if let Err(err) = light.notify_state(&hass).await {
+                            log::error!("While advising hass of virtual device state: {err:#}");
+                        }
+                    }
+                }
+                if state.get_config().await.room_controls == RoomControls::Lights {
+                    if let Err(err) = notify_room_of_state_change(&state, &hass, &device).await {
+                        log::error!("While advising hass of room state: {err:#}");
+                    }
+                }
+            }
+            DeviceEvent::Resync { missed } => {
+                let Some(hass) = state.get_hass_client().await else {
+                    continue;
+                };
+                // We can't tell which devices changed, so report them all
+                log::warn!("Missed {missed} device events; reporting the state of all devices");
+                let result = async {
+                    let entities = enumerate_all_entites(&state).await?;
+                    hass.notify_reportable_state(&state, &entities).await
+                }
+                .await;
+                if let Err(err) = result {
+                    log::error!("While advising hass of the state of all devices: {err:#}");
+                }
+            }
+        }
+    }
+}
+
 pub async fn spawn_hass_integration(
     state: StateHandle,
     args: &HassArguments,
 ) -> anyhow::Result<()> {
-    let client = Client::with_id(
-        &format!("govee2mqtt/{}", uuid::Uuid::new_v4().simple()),
-        true,
-    )?;
+    let instance_id = uuid::Uuid::new_v4().simple().to_string();
+    let client = Client::with_id(&format!("{}/{instance_id}", mqtt_client_id_prefix()), true)?;
+    let cluster = Arc::new(Cluster::new(instance_id, args.cluster_role()?));
 
     state.set_temperature_scale(args.temperature_scale()?).await;
+    state.set_color_temp_units(args.color_temp_units()?).await;
 
     let mqtt_host = args.mqtt_host()?;
     let mqtt_username = args.mqtt_username()?;
     let mqtt_password = args.mqtt_password()?;
     let mqtt_port = args.mqtt_port()?;
 
-    client.set_last_will(availability_topic(), "offline", QoS::AtMostOnce, false)?;
+    if !cluster.elects_leader() {
+        // When clustered, another replica may still be serving, so we
+        // must not have the broker mark everything as unavailable
+        // just because this one went away.
+        client.set_last_will(availability_topic(), "offline", QoS::AtMostOnce, false)?;
+    }
 
     if mqtt_username.is_some() != mqtt_password.is_some() {
         log::error!(
@@ -642,21 +1585,54 @@ pub async fn spawn_hass_integration(
             &mqtt_host,
             mqtt_port.into(),
             Duration::from_secs(120),
-            args.mqtt_bind_address.as_deref(),
+            args.mqtt_bind_address(),
         )
         .await
         .with_context(|| format!("connecting to mqtt broker {mqtt_host}:{mqtt_port}"))?;
     let subscriber = client.subscriber().expect("to own the subscriber");
 
+    let publisher = Arc::new(Publisher::new(DEFAULT_PUBLISH_QUEUE_CAPACITY));
+    {
+        let publisher = publisher.clone();
+        let client = client.clone();
+        tokio::spawn(async move { publisher.run(client).await });
+    }
+    for config in &state.get_config().await.mirror_brokers {
+        publisher.add_mirror(MirrorBroker::spawn(config.clone()));
+    }
+
     state
         .set_hass_client(HassClient {
-            client: client.clone(),
+            cluster: cluster.clone(),
+            publisher,
+            discovery_pacing: args.discovery_pacing()?,
+            pending_configs: Default::default(),
+            known_configs: Default::default(),
+            new_configs: None,
+            manifest: None,
         })
         .await;
 
+    if cluster.elects_leader() {
+        log::info!(
+            "Running as instance {} with role {:?}",
+            cluster.instance_id(),
+            args.cluster_role()?
+        );
+    }
+    if cluster.publishes_lease() {
+        let client = client.clone();
+        tokio::spawn(async move { cluster.run_election(client).await });
+    }
+
     let disco_prefix = args.hass_discovery_prefix.clone();
     state.set_hass_disco_prefix(disco_prefix).await;
 
+    // Subscribe before we return, so that we don't miss any events
+    // that are emitted while the mqtt loop is starting up
+    let events = state.events().subscribe();
+    tokio::spawn(publish_device_events(state.clone(), events));
+
     tokio::spawn(async move {
         let res = run_mqtt_loop(state, subscriber, client).await;
         if let Err(err) = res {
@@ -694,3 +1670,206 @@ fn test_camel_case_to_space_separated() {
         "Oscillation Toggle"
     );
 }
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_remove_stale_configs() {
+    use crate::hass_mqtt::button::ButtonConfig;
+
+    let state = Arc::new(crate::service::state::State::new());
+    state
+        .set_hass_disco_prefix("homeassistant".to_string())
+        .await;
+    let client = HassClient::for_test();
+
+    let mut entities = EntityList::new();
+    entities.add(ButtonConfig::new("Purge Caches", purge_cache_topic()));
+    {
+        let mut known = client.known_configs.lock();
+        known.insert(format!(
+            "homeassistant/button/{}/config",
+            crate::service::identity::global_unique_id("purge_caches")
+        ));
+        known.insert("homeassistant/sensor/sensor-gone/config".to_string());
+    }
+
+    assert_eq!(
+        client
+            .remove_stale_configs(&state, &entities)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        client.take_published(),
+        vec![(
+            "homeassistant/sensor/sensor-gone/config".to_string(),
+            vec![]
+        )]
+    );
+    // It is forgotten, so isn't removed a second time
+    assert_eq!(
+        client
+            .remove_stale_configs(&state, &entities)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stale_configs_survive_failed_enumeration() {
+    use crate::platform_api::HttpDeviceInfo;
+
+    let state = Arc::new(crate::service::state::State::new());
+    state
+        .set_hass_disco_prefix("homeassistant".to_string())
+        .await;
+    for json in [
+        r#"{"sku": "H5112", "device": "AA:BB:CC:DD:EE:FF:42:2C", "deviceName": "Thermometer",
+            "type": "devices.types.thermometer", "capabilities": []}"#,
+        // The workMode capability lacks its fields, so this device
+        // can't be enumerated
+        r#"{"sku": "H7126", "device": "AA:BB:CC:DD:EE:FF:42:2D", "deviceName": "Purifier",
+            "type": "devices.types.air_purifier", "capabilities": [
+                {"type": "devices.capabilities.work_mode", "instance": "workMode",
+                 "parameters": {"dataType": "STRUCT", "fields": []}}]}"#,
+    ] {
+        let info: HttpDeviceInfo = from_json(json).unwrap();
+        state
+            .device_mut(&info.sku, &info.device)
+            .await
+            .set_http_device_info(info);
+    }
+
+    let entities = enumerate_all_entites(&state).await.unwrap();
+    assert!(!entities.is_complete());
+
+    let client = HassClient::for_test();
+    let purifier_config = "homeassistant/select/gv2mqtt-AABBCCDDEEFF422D-workMode/config";
+    client
+        .known_configs
+        .lock()
+        .insert(purifier_config.to_string());
+    assert_eq!(
+        client
+            .remove_stale_configs(&state, &entities)
+            .await
+            .unwrap(),
+        0
+    );
+    assert!(client.take_published().is_empty());
+    assert!(client.known_configs.lock().contains(purifier_config));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stale_configs_survive_failed_scene_fetch() {
+    use crate::cache::{cache_get, CacheGetOptions};
+    use crate::undoc_api::LightEffectCategory;
+    use std::time::Duration;
+
+    // The scene catalog is fetched through the cache, so recording a
+    // failure there makes the fetch fail without reaching out to Govee
+    std::env::set_var("GOVEE_CACHE_DIR", std::env::temp_dir());
+    let ttl = Duration::from_secs(600);
+    let _ = cache_get::<Vec<LightEffectCategory>, _>(
+        CacheGetOptions {
+            topic: "undoc-api",
+            key: "scenes-H0000",
+            soft_ttl: ttl,
+            hard_ttl: ttl,
+            negative_ttl: ttl,
+            allow_stale: false,
+        },
+        async { anyhow::bail!("offline") },
+    )
+    .await;
+
+    let state = Arc::new(crate::service::state::State::new());
+    state
+        .set_hass_disco_prefix("homeassistant".to_string())
+        .await;
+    state
+        .device_mut("H0000", "AA:BB:CC:DD:EE:FF:42:2E")
+        .await
+        .set_lan_device(
+            serde_json::from_value(serde_json::json!({
+                "ip": "10.0.0.2",
+                "device": "AA:BB:CC:DD:EE:FF:42:2E",
+                "sku": "H0000",
+                "bleVersionHard": "",
+                "bleVersionSoft": "",
+                "wifiVersionHard": "",
+                "wifiVersionSoft": "",
+            }))
+            .unwrap(),
+        );
+
+    let entities = enumerate_all_entites(&state).await.unwrap();
+    assert!(!entities.is_complete());
+
+    let client = HassClient::for_test();
+    let scene_config = "homeassistant/select/gv2mqtt-AABBCCDDEEFF422E-mode-scene/config";
+    client.known_configs.lock().insert(scene_config.to_string());
+    assert_eq!(
+        client
+            .remove_stale_configs(&state, &entities)
+            .await
+            .unwrap(),
+        0
+    );
+    assert!(client.take_published().is_empty());
+    assert!(client.known_configs.lock().contains(scene_config));
+}
+
+#[cfg(test)]
+#[test]
+fn test_topic_safe_id_is_plain() {
+    use crate::service::config::DeviceIdFormat;
+
+    // Topics use the plain form, whatever the device_id_format
+    let device = ServiceDevice::new("H6199", "aa:bb:cc:dd:ee:ff:42:2a");
+    assert_eq!(topic_safe_id(&device), "AABBCCDDEEFF422A");
+    assert_eq!(
+        topic_safe_id(&device),
+        DeviceIdFormat::Plain.format(&device.id)
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_command_results_are_each_published() {
+    let state = Arc::new(crate::service::state::State::new());
+    // A replica that isn't the leader still reports the
+    // results of the commands that it handled
+    let client = HassClient {
+        cluster: Arc::new(Cluster::new(
+            "b".to_string(),
+            ClusterRole::Shared {
+                group: "govee".to_string(),
+            },
+        )),
+        ..HassClient::for_test()
+    };
+    assert!(!client.cluster().is_leader());
+    state.set_hass_client(client).await;
+
+    let topic = "gv2mqtt/purge-caches";
+    run_command(&state, topic, b"", async { Ok(()) }).await;
+    run_command(&state, topic, b"", async { anyhow::bail!("failed") }).await;
+
+    let published = state.get_hass_client().await.unwrap().take_published();
+    let results: Vec<JsonValue> = published
+        .iter()
+        .map(|(topic, payload)| {
+            assert_eq!(topic, "gv2mqtt/command_result");
+            serde_json::from_slice(payload).unwrap()
+        })
+        .collect();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["applied"], true);
+    assert_eq!(results[1]["applied"], false);
+    assert_eq!(results[1]["error"], "failed");
+}
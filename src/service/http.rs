@@ -1,11 +1,17 @@
+use crate::hass_mqtt::manifest::device_manifest;
 use crate::service::coordinator::Coordinator;
 use crate::service::device::{Device, DeviceState};
+use crate::service::events::recv_event;
+use crate::service::identity::{bridge_id, bridge_name, instance_name};
 use crate::service::state::StateHandle;
+use crate::service::support_matrix::support_matrix;
+use crate::version_info::govee_version;
 use anyhow::Context;
 use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
-use axum::routing::get;
+use axum::routing::{delete, get};
 use axum::{Json, Router};
 use serde::Serialize;
 use std::net::IpAddr;
@@ -55,6 +61,7 @@ async fn resolve_device_read_only(state: &StateHandle, id: &str) -> Result<Devic
 async fn list_devices(State(state): State<StateHandle>) -> Result<Response, Response> {
     let mut devices = state.devices().await;
     devices.sort_by_key(|d| (d.room_name().map(|name| name.to_string()), d.name()));
+    let id_format = state.get_config().await.device_id_format;
 
     #[derive(Serialize)]
     struct DeviceItem {
@@ -64,6 +71,7 @@ async fn list_devices(State(state): State<StateHandle>) -> Result<Response, Resp
         pub room: Option<String>,
         pub ip: Option<IpAddr>,
         pub state: Option<DeviceState>,
+        pub removed: bool,
     }
 
     let devices: Vec<_> = devices
@@ -73,14 +81,52 @@ async fn list_devices(State(state): State<StateHandle>) -> Result<Response, Resp
             room: d.room_name().map(|r| r.to_string()),
             ip: d.ip_addr(),
             state: d.device_state(),
+            removed: d.removed,
+            id: id_format.format(&d.id),
             sku: d.sku,
-            id: d.id,
         })
         .collect();
 
     Ok(Json(devices).into_response())
 }
 
+/// Removes a device: its entities are removed from hass and it is
+/// no longer polled, until it is added back via `add_device`
+async fn remove_device(
+    State(state): State<StateHandle>,
+    Path(id): Path<String>,
+) -> Result<Response, Response> {
+    let device = resolve_device_read_only(&state, &id).await?;
+
+    if !state
+        .set_device_removed(&device, true)
+        .await
+        .map_err(generic)?
+    {
+        return Err(bad_request(format!("{device} has already been removed")));
+    }
+
+    Ok(response_with_code(StatusCode::OK, "ok"))
+}
+
+/// Adds back a device that was removed via `remove_device`
+async fn add_device(
+    State(state): State<StateHandle>,
+    Path(id): Path<String>,
+) -> Result<Response, Response> {
+    let device = resolve_device_read_only(&state, &id).await?;
+
+    if !state
+        .set_device_removed(&device, false)
+        .await
+        .map_err(generic)?
+    {
+        return Err(bad_request(format!("{device} has not been removed")));
+    }
+
+    Ok(response_with_code(StatusCode::OK, "ok"))
+}
+
 /// Turns on a given device
 async fn device_power_on(
     State(state): State<StateHandle>,
@@ -187,6 +233,25 @@ async fn device_list_scenes(
     Ok(Json(scenes).into_response())
 }
 
+/// Returns the entities that are registered with hass for a given device
+async fn device_list_entities(
+    State(state): State<StateHandle>,
+    Path(id): Path<String>,
+) -> Result<Response, Response> {
+    let device = resolve_device_read_only(&state, &id).await?;
+    let hass = state
+        .get_hass_client()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("MQTT is not configured"))
+        .map_err(not_found)?;
+
+    let manifest = device_manifest(&device, &state, &hass)
+        .await
+        .map_err(generic)?;
+
+    Ok(Json(manifest).into_response())
+}
+
 async fn list_one_clicks(State(state): State<StateHandle>) -> Result<Response, Response> {
     let undoc = state
         .get_undoc_client()
@@ -225,13 +290,90 @@ async fn activate_one_click(
     Ok(response_with_code(StatusCode::OK, "ok"))
 }
 
-async fn redirect_to_index() -> Response {
-    axum::response::Redirect::to("/assets/index.html").into_response()
+/// Returns a stream of server-sent events describing changes
+/// to device state
+async fn event_stream(State(state): State<StateHandle>) -> Response {
+    let events = state.events().subscribe();
+    let stream = futures_util::stream::unfold(events, |mut events| async move {
+        let event = recv_event(&mut events).await?;
+        let sse = SseEvent::default().json_data(&event);
+        Some((sse, events))
+    });
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Returns statistics about the outbound mqtt publish queue
+async fn publish_stats(State(state): State<StateHandle>) -> Result<Response, Response> {
+    let hass = state
+        .get_hass_client()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("MQTT is not configured"))
+        .map_err(not_found)?;
+    Ok(Json(hass.publish_stats()).into_response())
+}
+
+/// Identifies this instance, for telling apart several
+/// instances that share a broker
+async fn service_status() -> Response {
+    Json(serde_json::json!({
+        "version": govee_version(),
+        "instance_name": instance_name(),
+        "bridge_id": bridge_id(),
+        "bridge_name": bridge_name(),
+    }))
+    .into_response()
+}
+
+/// Returns whether we are running in safe mode, and why
+async fn startup_status(State(state): State<StateHandle>) -> Result<Response, Response> {
+    let startup = state
+        .get_startup_tracker()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Startup is not being tracked"))
+        .map_err(not_found)?;
+    Ok(Json(startup.status()).into_response())
+}
+
+/// When the UI is reached via Home Assistant ingress, the Supervisor
+/// removes its path prefix from the request and passes it to us in
+/// this header, so that we can send the browser back under it.
+/// The UI itself uses relative URLs so that it works either way.
+const INGRESS_PATH_HEADER: &str = "X-Ingress-Path";
+const INGRESS_PATH_PREFIX: &str = "/api/hassio_ingress/";
+
+/// The effective configuration: the settings from the command line
+/// and environment, and the configuration file with its defaults
+async fn effective_config(State(state): State<StateHandle>) -> Response {
+    let config = state.get_config().await;
+    Json(serde_json::json!({
+        "settings": state.get_effective_settings().await,
+        "config": *config,
+    }))
+    .into_response()
+}
+
+async fn get_support_matrix() -> Result<Response, Response> {
+    let matrix = support_matrix().await.map_err(generic)?;
+    Ok(Json(matrix).into_response())
+}
+
+async fn redirect_to_index(headers: HeaderMap) -> Response {
+    let prefix = headers
+        .get(INGRESS_PATH_HEADER)
+        .and_then(|value| value.to_str().ok())
+        // Don't allow the header to redirect anywhere else
+        .filter(|path| path.starts_with(INGRESS_PATH_PREFIX))
+        .unwrap_or("")
+        .trim_end_matches('/');
+    axum::response::Redirect::to(&format!("{prefix}/assets/index.html")).into_response()
 }
 
 pub async fn run_http_server(state: StateHandle, port: u16) -> anyhow::Result<()> {
     let app = Router::new()
         .route("/api/devices", get(list_devices))
+        .route("/api/devices/:id", delete(remove_device).put(add_device))
         .route("/api/device/:id/power/on", get(device_power_on))
         .route("/api/device/:id/power/off", get(device_power_off))
         .route(
@@ -245,6 +387,13 @@ pub async fn run_http_server(state: StateHandle, port: u16) -> anyhow::Result<()
         .route("/api/device/:id/color/:color", get(device_set_color))
         .route("/api/device/:id/scene/:scene", get(device_set_scene))
         .route("/api/device/:id/scenes", get(device_list_scenes))
+        .route("/api/device/:id/entities", get(device_list_entities))
+        .route("/api/events", get(event_stream))
+        .route("/api/mqtt/publish-stats", get(publish_stats))
+        .route("/api/startup", get(startup_status))
+        .route("/api/status", get(service_status))
+        .route("/api/config", get(effective_config))
+        .route("/api/support-matrix", get(get_support_matrix))
         .route("/api/oneclicks", get(list_one_clicks))
         .route("/api/oneclick/activate/:scene", get(activate_one_click))
         .route("/", get(redirect_to_index))
@@ -262,3 +411,120 @@ pub async fn run_http_server(state: StateHandle, port: u16) -> anyhow::Result<()
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform_api::{from_json, HttpDeviceInfo};
+    use crate::service::hass::HassClient;
+    use crate::service::removed::RemovedDevices;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn remove_and_add_device() {
+        let state = Arc::new(crate::service::state::State::new());
+        let path = std::env::temp_dir().join(format!(
+            "govee2mqtt-removed-{}.json",
+            uuid::Uuid::new_v4().simple()
+        ));
+        state
+            .set_removed_devices(Arc::new(RemovedDevices::load(path.clone())))
+            .await;
+        state
+            .set_hass_disco_prefix("homeassistant".to_string())
+            .await;
+        state.set_hass_client(HassClient::for_test()).await;
+        let info: HttpDeviceInfo = from_json(
+            r#"{"sku": "H5112", "device": "AA:BB:CC:DD:EE:FF:42:2C",
+                "deviceName": "Thermometer", "type": "devices.types.thermometer",
+                "capabilities": []}"#,
+        )
+        .unwrap();
+        state
+            .device_mut(&info.sku, &info.device)
+            .await
+            .set_http_device_info(info);
+
+        // Registration waits for the configs to be sent, so
+        // collect what is published as it would be sent
+        let hass = state.get_hass_client().await.unwrap();
+        let sent = Arc::new(parking_lot::Mutex::new(vec![]));
+        {
+            let hass = hass.clone();
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                loop {
+                    sent.lock().extend(hass.take_published());
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                }
+            });
+        }
+        let take_sent = || {
+            sent.lock().extend(hass.take_published());
+            std::mem::take(&mut *sent.lock())
+        };
+
+        hass.register_new_entities(&state).await.unwrap();
+        let configs: Vec<String> = take_sent()
+            .into_iter()
+            .filter(|(topic, _)| topic.contains("AABBCCDDEEFF422C") && topic.ends_with("/config"))
+            .map(|(topic, _)| topic)
+            .collect();
+        assert!(!configs.is_empty());
+
+        let id = "aabbccddeeff422c".to_string();
+        let status = |result: Result<Response, Response>| match result {
+            Ok(response) | Err(response) => response.status(),
+        };
+
+        assert_eq!(
+            status(remove_device(State(state.clone()), Path(id.clone())).await),
+            StatusCode::OK
+        );
+        let device = state.device_by_id("AA:BB:CC:DD:EE:FF:42:2C").await.unwrap();
+        assert!(device.removed);
+        // Each of its configs is cleared
+        let cleared: Vec<String> = take_sent()
+            .into_iter()
+            .filter(|(_, payload)| payload.is_empty())
+            .map(|(topic, _)| topic)
+            .collect();
+        for topic in &configs {
+            assert!(cleared.contains(topic), "{topic} was not cleared");
+        }
+        assert_eq!(
+            status(remove_device(State(state.clone()), Path(id.clone())).await),
+            StatusCode::BAD_REQUEST
+        );
+
+        assert_eq!(
+            status(add_device(State(state.clone()), Path(id.clone())).await),
+            StatusCode::OK
+        );
+        assert!(
+            !state
+                .device_by_id("AA:BB:CC:DD:EE:FF:42:2C")
+                .await
+                .unwrap()
+                .removed
+        );
+        let published = take_sent();
+        for topic in &configs {
+            assert!(
+                published.iter().any(|(t, p)| t == topic && !p.is_empty()),
+                "{topic} was not registered again"
+            );
+        }
+        assert_eq!(
+            status(add_device(State(state.clone()), Path(id.clone())).await),
+            StatusCode::BAD_REQUEST
+        );
+
+        assert_eq!(
+            status(remove_device(State(state.clone()), Path("nope".to_string())).await),
+            StatusCode::NOT_FOUND
+        );
+
+        std::fs::remove_file(&path).ok();
+    }
+}
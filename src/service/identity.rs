@@ -0,0 +1,115 @@
+//! The identity of this bridge, as seen by the MQTT broker and by
+//! Home Assistant.
+//!
+//! Several bridges can share a broker and a Home Assistant instance,
+//! for example one per VLAN, provided that each is given a distinct
+//! instance name. The name is folded into the identifiers that would
+//! otherwise collide: the MQTT client id, the bridge-wide topics such
+//! as availability and the leader heartbeat, the bridge device in
+//! Home Assistant and the unique ids of its global entities.
+//! Devices are identified by their own ids and are unaffected.
+//!
+//! Without an instance name, everything is identified exactly as it
+//! was before instance names existed, so that existing installations
+//! keep their entities.
+use once_cell::sync::OnceCell;
+
+static INSTANCE_NAME: OnceCell<String> = OnceCell::new();
+
+pub fn validate_instance_name(name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(!name.is_empty(), "the instance name must not be empty");
+    anyhow::ensure!(
+        name.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "the instance name '{name}' may only contain ASCII letters, \
+         digits, '-' and '_', as it is used in MQTT topics"
+    );
+    Ok(())
+}
+
+/// Set the instance name for the remainder of the process.
+/// This must happen before any MQTT or Home Assistant identifiers
+/// are computed.
+pub fn set_instance_name(name: String) -> anyhow::Result<()> {
+    validate_instance_name(&name)?;
+    INSTANCE_NAME
+        .set(name)
+        .map_err(|_| anyhow::anyhow!("the instance name has already been set"))
+}
+
+pub fn instance_name() -> Option<&'static str> {
+    INSTANCE_NAME.get().map(|s| s.as_str())
+}
+
+fn bridge_id_for(name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("gv2mqtt-{name}"),
+        None => "gv2mqtt".to_string(),
+    }
+}
+
+fn bridge_name_for(name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("Govee to MQTT ({name})"),
+        None => "Govee to MQTT".to_string(),
+    }
+}
+
+fn global_unique_id_for(name: Option<&str>, slug: &str) -> String {
+    match name {
+        Some(name) => format!("global-{name}-{slug}"),
+        None => format!("global-{slug}"),
+    }
+}
+
+/// The identifier of this bridge. This is the prefix of the
+/// bridge-wide topics and the identifier of the bridge device
+/// in Home Assistant.
+pub fn bridge_id() -> String {
+    bridge_id_for(instance_name())
+}
+
+/// The name of the bridge device in Home Assistant
+pub fn bridge_name() -> String {
+    bridge_name_for(instance_name())
+}
+
+/// The unique id of an entity that belongs to the bridge itself
+/// rather than to any particular device
+pub fn global_unique_id(slug: &str) -> String {
+    global_unique_id_for(instance_name(), slug)
+}
+
+/// The prefix of the MQTT client id; a per-process suffix is
+/// added to it so that restarts don't collide with a lingering
+/// session on the broker
+pub fn mqtt_client_id_prefix() -> String {
+    match instance_name() {
+        Some(name) => format!("govee2mqtt-{name}"),
+        None => "govee2mqtt".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(bridge_id_for(None), "gv2mqtt");
+        assert_eq!(bridge_name_for(None), "Govee to MQTT");
+        assert_eq!(global_unique_id_for(None, "version"), "global-version");
+
+        assert_eq!(bridge_id_for(Some("vlan2")), "gv2mqtt-vlan2");
+        assert_eq!(bridge_name_for(Some("vlan2")), "Govee to MQTT (vlan2)");
+        assert_eq!(
+            global_unique_id_for(Some("vlan2"), "version"),
+            "global-vlan2-version"
+        );
+
+        assert!(validate_instance_name("upstairs_2").is_ok());
+        assert!(validate_instance_name("").is_err());
+        assert!(validate_instance_name("a/b").is_err());
+        assert!(validate_instance_name("#").is_err());
+    }
+}
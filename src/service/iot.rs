@@ -1,13 +1,23 @@
-use crate::ble::{Base64HexBytes, GoveeBlePacket, HumidifierAutoMode, NotifyHumidifierMode};
+use crate::ble::{
+    Base64HexBytes, GoveeBlePacket, HumidifierAutoMode, NotifyHumidifierMode, NotifySceneCode,
+    SetSceneCode,
+};
 use crate::lan_api::{DeviceColor, DeviceStatus};
-use crate::platform_api::from_json;
+use crate::platform_api::{from_json, DeviceType};
+use crate::service::alarm::{alarm_change, alarm_topic};
+use crate::service::button_press::{button_event_topic, parse_button_press};
+use crate::service::command_result::{note_transport, Transport};
+use crate::service::device::{normalize_device_id, Freshness};
 use crate::service::state::StateHandle;
-use crate::undoc_api::{ms_timestamp, DeviceEntry, LoginAccountResponse, ParsedOneClick};
+use crate::undoc_api::{
+    ms_timestamp, DeviceEntry, GoveeUndocumentedApi, LoginAccountResponse, ParsedOneClick,
+};
 use crate::Args;
 use anyhow::Context;
 use async_channel::Receiver;
 use mosquitto_rs::{Event, QoS};
 use serde::Deserialize;
+use serde_json::Value as JsonValue;
 use std::time::Duration;
 use tokio::time::timeout;
 
@@ -45,6 +55,7 @@ impl IotClient {
 
     pub async fn set_power_state(&self, device: &DeviceEntry, on: bool) -> anyhow::Result<()> {
         log::trace!("set_power_state for {} to {on}", device.device);
+        note_transport(Transport::Iot);
         let device_topic = device.device_topic()?;
 
         fn pwr(is_on: bool, on: u8, off: u8) -> u8 {
@@ -84,6 +95,7 @@ impl IotClient {
 
     pub async fn set_brightness(&self, device: &DeviceEntry, percent: u8) -> anyhow::Result<()> {
         log::trace!("set_brightness for {} to {percent}", device.device);
+        note_transport(Transport::Iot);
         let device_topic = device.device_topic()?;
         self.client
             .publish(
@@ -113,6 +125,7 @@ impl IotClient {
         kelvin: u32,
     ) -> anyhow::Result<()> {
         log::trace!("set_color_temperature for {} to {kelvin}", device.device);
+        note_transport(Transport::Iot);
         let device_topic = device.device_topic()?;
 
         self.client
@@ -150,6 +163,7 @@ impl IotClient {
         b: u8,
     ) -> anyhow::Result<()> {
         log::trace!("set_color_rgb for {} to {r},{g},{b}", device.device);
+        note_transport(Transport::Iot);
         let device_topic = device.device_topic()?;
 
         self.client
@@ -185,6 +199,7 @@ impl IotClient {
         commands: Vec<String>,
     ) -> anyhow::Result<()> {
         log::trace!("send_real for {} to {commands:?}", device.device);
+        note_transport(Transport::Iot);
         let device_topic = device.device_topic()?;
 
         self.client
@@ -210,6 +225,7 @@ impl IotClient {
     }
 
     pub async fn activate_one_click(&self, item: &ParsedOneClick) -> anyhow::Result<()> {
+        note_transport(Transport::Iot);
         for entry in &item.entries {
             for command in &entry.msgs {
                 self.client
@@ -227,17 +243,13 @@ impl IotClient {
     }
 }
 
-pub async fn start_iot_client(
+/// Connect to the Govee IoT service on behalf of the account,
+/// returning the client and the stream of its events
+async fn connect_iot_client(
     args: &Args,
-    state: StateHandle,
-    acct: Option<LoginAccountResponse>,
-) -> anyhow::Result<()> {
+    acct: &LoginAccountResponse,
+) -> anyhow::Result<(mosquitto_rs::Client, Receiver<Event>)> {
     let client = args.undoc_args.api_client()?;
-    let acct = match acct {
-        Some(a) => a,
-        None => client.login_account_cached().await?,
-    };
-    log::trace!("{acct:#?}");
     let res = client.get_iot_key(&acct.token).await?;
     log::trace!("{res:#?}");
 
@@ -287,6 +299,20 @@ pub async fn start_iot_client(
     log::info!("Connected to IoT: {status}");
 
     let subscriptions = client.subscriber().expect("first and only");
+    Ok((client, subscriptions))
+}
+
+pub async fn start_iot_client(
+    args: &Args,
+    state: StateHandle,
+    acct: Option<LoginAccountResponse>,
+) -> anyhow::Result<()> {
+    let acct = match acct {
+        Some(a) => a,
+        None => args.undoc_args.api_client()?.login_account_cached().await?,
+    };
+    log::trace!("{acct:#?}");
+    let (client, subscriptions) = connect_iot_client(args, &acct).await?;
 
     state
         .set_iot_client(IotClient {
@@ -328,6 +354,13 @@ struct StateUpdate {
     pub color_temperature_kelvin: Option<u32>,
     pub sku: Option<String>,
     pub device: Option<String>,
+    /// Thermo-hygrometers report hundredths of a degree Celsius
+    pub tem: Option<i64>,
+    /// and hundredths of a percent of relative humidity
+    pub hum: Option<i64>,
+    /// Presence sensors report 1 while someone is detected
+    #[serde(alias = "bodyAppeared")]
+    pub presence: Option<u8>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -370,6 +403,62 @@ impl Packet {
     }
 }
 
+/// Collect the messages that the IoT service pushes about the device
+/// over the next `duration`, as received, so that they can be saved
+/// as fixtures. Sensors only push when something changes, so the
+/// device needs to be triggered while this is running.
+pub async fn capture_device_messages(
+    args: &Args,
+    device_id: &str,
+    duration: Duration,
+) -> anyhow::Result<Vec<JsonValue>> {
+    let acct = args.undoc_args.api_client()?.login_account_cached().await?;
+    let (client, subscriptions) = connect_iot_client(args, &acct).await?;
+    client
+        .subscribe(&acct.topic, QoS::AtMostOnce)
+        .await
+        .context("subscribe to account topic")?;
+
+    let wanted = normalize_device_id(device_id);
+    let deadline = tokio::time::Instant::now() + duration;
+    let mut messages = vec![];
+    while let Ok(Ok(event)) = tokio::time::timeout_at(deadline, subscriptions.recv()).await {
+        let Event::Message(msg) = event else {
+            continue;
+        };
+        let Ok(packet) = from_json::<Packet, _>(&msg.payload) else {
+            continue;
+        };
+        if packet.device().map(normalize_device_id).as_deref() == Some(wanted.as_str()) {
+            messages.push(serde_json::from_slice(&msg.payload)?);
+        }
+    }
+    Ok(messages)
+}
+
+/// Record the name of the scene that a light reports being in.
+/// Codes that aren't in the scene catalog for the SKU are ignored.
+async fn resolve_scene_code(state: StateHandle, sku: String, device_id: String, code: u16) {
+    match GoveeUndocumentedApi::get_scene_name_by_code(&sku, code).await {
+        Ok(Some(scene)) => {
+            log::info!("{device_id} reports that it is in scene {scene}");
+            state
+                .device_mut(&sku, &device_id)
+                .await
+                .set_active_scene(Some(&scene));
+            if let Err(err) = state.notify_of_state_change(&device_id).await {
+                log::warn!("{device_id}: {err:#}");
+            }
+        }
+        Ok(None) => {
+            log::debug!("{device_id} reports unknown scene code {code}");
+        }
+        Err(err) => {
+            log::warn!("Unable to resolve scene code {code} for {sku}: {err:#}");
+        }
+    }
+}
+
 async fn run_iot_subscriber(
     subscriptions: Receiver<Event>,
     state: StateHandle,
@@ -386,6 +475,9 @@ async fn run_iot_subscriber(
                     Ok(packet) => {
                         log::debug!("{packet:?}");
                         if let Some((sku, device_id)) = packet.sku_and_device() {
+                            let mut alarm = None;
+                            let mut button_press = None;
+                            let mut scene_code = None;
                             {
                                 let mut device = state.device_mut(sku, device_id).await;
                                 let mut state = match device.iot_device_status.clone() {
@@ -416,7 +508,12 @@ async fn run_iot_subscriber(
 
                                 if let Some(op) = &packet.op {
                                     for cmd in &op.command {
-                                        let decoded = cmd.decode_for_sku(sku);
+                                        let mut decoded = cmd.decode_for_sku(sku);
+                                        if matches!(decoded, GoveeBlePacket::Generic(_))
+                                            && device.device_type() == DeviceType::Light
+                                        {
+                                            decoded = cmd.decode_for_sku("Generic:Light");
+                                        }
                                         log::debug!("Decoded: {decoded:?} for {sku}");
                                         match decoded {
                                             GoveeBlePacket::NotifyHumidifierNightlight(nl) => {
@@ -442,6 +539,16 @@ async fn run_iot_subscriber(
                                                     mode, param,
                                                 );
                                             }
+                                            GoveeBlePacket::NotifySceneCode(NotifySceneCode {
+                                                code,
+                                            })
+                                            | GoveeBlePacket::SetSceneCode(SetSceneCode { code }) =>
+                                            {
+                                                // The light was put into a scene, perhaps
+                                                // from the Govee app; we resolve its name
+                                                // after we have released the device
+                                                scene_code.replace(code);
+                                            }
                                             GoveeBlePacket::Generic(_) => {
                                                 // Ignore packets that we can't decode
                                             }
@@ -466,9 +573,71 @@ async fn run_iot_subscriber(
                                 if let Some(on_off) = packet.state.on_off {
                                     state.on = on_off != 0;
                                 }
-                                device.set_iot_device_status(state);
+                                device.set_iot_device_status(state, Freshness::now());
+
+                                if let Some(presence) = packet.state.presence {
+                                    device.iot_presence.replace(presence == 1);
+                                }
+
+                                if let (Some(kind), Ok(raw)) = (
+                                    device.alarm_kind(),
+                                    serde_json::from_slice::<JsonValue>(&msg.payload),
+                                ) {
+                                    if let Some(active) = alarm_change(kind, device.iot_alarm, &raw)
+                                    {
+                                        device.iot_alarm.replace(active);
+                                        alarm.replace((alarm_topic(&device), raw));
+                                    }
+                                }
+
+                                if device.has_button_events() {
+                                    match serde_json::from_slice::<JsonValue>(&msg.payload)
+                                        .ok()
+                                        .and_then(|raw| {
+                                            raw.get("state").and_then(parse_button_press)
+                                        }) {
+                                        Some(press) => {
+                                            log::info!("{device} reports {press:?}");
+                                            button_press
+                                                .replace((button_event_topic(&device), press));
+                                        }
+                                        None => {
+                                            log::info!(
+                                                "{device} sent a message that isn't a \
+                                                 recognized button press: {payload}"
+                                            );
+                                        }
+                                    }
+                                }
+
+                                if packet.state.tem.is_some() || packet.state.hum.is_some() {
+                                    device.set_iot_thermometer_state(
+                                        packet.state.tem,
+                                        packet.state.hum,
+                                    );
+                                }
+                            }
+                            if let Some(code) = scene_code {
+                                // Resolving the name may need to fetch the scene
+                                // catalog, which mustn't hold up the other messages
+                                tokio::spawn(resolve_scene_code(
+                                    state.clone(),
+                                    sku.to_string(),
+                                    device_id.to_string(),
+                                    code,
+                                ));
                             }
                             state.notify_of_state_change(device_id).await?;
+                            if let Some((topic, raw)) = alarm {
+                                if let Some(hass) = state.get_hass_client().await {
+                                    hass.publish_obj(topic, raw).await?;
+                                }
+                            }
+                            if let Some((topic, press)) = button_press {
+                                if let Some(hass) = state.get_hass_client().await {
+                                    hass.publish_obj(topic, press).await?;
+                                }
+                            }
                         }
                     }
                     Err(err) => {
@@ -509,3 +678,31 @@ async fn run_iot_subscriber(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn presence_packet() {
+        let packet: Packet = from_json(
+            r#"{"sku":"H5127","device":"12:34:56:78:9A:BC:DE:F0","cmd":"status","state":{"presence":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            packet.sku_and_device(),
+            Some(("H5127", "12:34:56:78:9A:BC:DE:F0"))
+        );
+        assert_eq!(packet.state.presence, Some(1));
+
+        let packet: Packet = from_json(
+            r#"{"cmd":"status","state":{"sku":"H5127","device":"12:34:56:78:9A:BC:DE:F0","bodyAppeared":0}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            packet.sku_and_device(),
+            Some(("H5127", "12:34:56:78:9A:BC:DE:F0"))
+        );
+        assert_eq!(packet.state.presence, Some(0));
+    }
+}
@@ -0,0 +1,186 @@
+//! Copies of what we publish, sent to additional brokers.
+//!
+//! A mirror broker might be a cloud-hosted broker that feeds a remote
+//! dashboard, and that only permits a handful of topics. Each mirror
+//! has its own queue and connection, so a mirror that is slow or
+//! unreachable doesn't hold up the primary broker. Topics are renamed
+//! according to the mirror's mapping, and those that it doesn't map
+//! are not sent to it. Nothing is subscribed to on a mirror; commands
+//! are only accepted via the primary broker.
+use crate::service::config::MirrorBrokerConfig;
+use crate::service::identity::mqtt_client_id_prefix;
+use crate::service::publisher::{Publisher, DEFAULT_PUBLISH_QUEUE_CAPACITY};
+use anyhow::Context;
+use async_channel::Receiver;
+use mosquitto_rs::{Client, Event};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait before the first attempt to reconnect, both
+/// while establishing the connection and after it has dropped
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// The delay between attempts doubles up to this limit
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+
+pub struct MirrorBroker {
+    config: MirrorBrokerConfig,
+    publisher: Publisher,
+}
+
+impl MirrorBroker {
+    fn new(config: MirrorBrokerConfig) -> Self {
+        Self {
+            config,
+            publisher: Publisher::new(DEFAULT_PUBLISH_QUEUE_CAPACITY),
+        }
+    }
+
+    /// Create the mirror and maintain its connection in the background
+    pub fn spawn(config: MirrorBrokerConfig) -> Arc<Self> {
+        let mirror = Arc::new(Self::new(config));
+        tokio::spawn(mirror.clone().run());
+        mirror
+    }
+
+    /// Queue a copy of a message for this broker, if its topic is mapped
+    pub fn forward(&self, topic: &str, payload: &[u8], retain: bool) {
+        let Some(topic) = self.config.map_topic(topic) else {
+            return;
+        };
+        if retain {
+            self.publisher.enqueue_retained(topic, payload.to_vec());
+        } else {
+            self.publisher.enqueue(topic, payload.to_vec());
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("{}:{}", self.config.host, self.config.port)
+    }
+
+    async fn connect(&self) -> anyhow::Result<(Client, Receiver<Event>)> {
+        let instance_id = uuid::Uuid::new_v4().simple().to_string();
+        let client = Client::with_id(
+            &format!("{}/mirror/{instance_id}", mqtt_client_id_prefix()),
+            true,
+        )?;
+        client.set_username_and_password(
+            self.config.username.as_deref(),
+            self.config.password.as_deref(),
+        )?;
+        // Once connected, mosquitto reconnects by itself, and the
+        // client can't be told to stop doing so in favor of a new
+        // one, so have it back off in the same way that `run` does
+        client.set_reconnect_delay(MIN_RECONNECT_DELAY, MAX_RECONNECT_DELAY, true)?;
+        client
+            .connect(
+                &self.config.host,
+                self.config.port.into(),
+                Duration::from_secs(120),
+                None,
+            )
+            .await
+            .with_context(|| format!("connecting to mirror broker {}", self.name()))?;
+        let subscriber = client.subscriber().expect("to own the subscriber");
+        Ok((client, subscriber))
+    }
+
+    /// Send the queue to the broker while it is connected. Returns
+    /// once the connection has ended for good; mosquitto reconnects
+    /// by itself after an unexpected disconnect, but not otherwise.
+    async fn serve(self: &Arc<Self>, client: Client, subscriber: Receiver<Event>) {
+        let spawn_sender = || {
+            let mirror = self.clone();
+            let client = client.clone();
+            tokio::spawn(async move { mirror.publisher.run(client).await })
+        };
+
+        // Messages queued while disconnected wait here rather than
+        // failing to publish, and are conflated in the meantime
+        let mut sender = Some(spawn_sender());
+        while let Ok(event) = subscriber.recv().await {
+            match event {
+                Event::Disconnected(reason) => {
+                    log::warn!(
+                        "Mirror broker {} disconnected with reason={reason}",
+                        self.name()
+                    );
+                    if let Some(sender) = sender.take() {
+                        sender.abort();
+                    }
+                }
+                Event::Connected(status) => {
+                    log::info!(
+                        "Mirror broker {} connected with status={status}",
+                        self.name()
+                    );
+                    if sender.is_none() {
+                        sender.replace(spawn_sender());
+                    }
+                }
+                Event::Message(_) => {}
+            }
+        }
+        if let Some(sender) = sender {
+            sender.abort();
+        }
+    }
+
+    /// Connect to the broker, retrying with an increasing delay until
+    /// it can be reached. Reconnecting after the connection drops is
+    /// left to mosquitto, which backs off in the same way.
+    async fn run(self: Arc<Self>) {
+        let mut delay = MIN_RECONNECT_DELAY;
+        loop {
+            match self.connect().await {
+                Ok((client, subscriber)) => {
+                    log::info!("Connected to mirror broker {}", self.name());
+                    delay = MIN_RECONNECT_DELAY;
+                    self.serve(client, subscriber).await;
+                    log::warn!("Connection to mirror broker {} was closed", self.name());
+                }
+                Err(err) => {
+                    log::warn!("{err:#}; will retry in {delay:?}");
+                }
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::service::config::TopicMappingConfig;
+
+    #[test]
+    fn fan_out() {
+        let mirror = Arc::new(MirrorBroker::new(MirrorBrokerConfig {
+            host: "remote".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            topics: vec![TopicMappingConfig {
+                from: "gv2mqtt/sensor/".to_string(),
+                to: "remote/".to_string(),
+            }],
+        }));
+        let primary = Publisher::new(DEFAULT_PUBLISH_QUEUE_CAPACITY);
+        primary.add_mirror(mirror.clone());
+
+        primary.enqueue("gv2mqtt/sensor/a/state".to_string(), b"1".to_vec());
+        primary.enqueue("gv2mqtt/light/b/state".to_string(), b"2".to_vec());
+        let _confirmation =
+            primary.enqueue_confirmed("gv2mqtt/sensor/c/state".to_string(), b"3".to_vec());
+
+        assert_eq!(primary.drain().len(), 3);
+        assert_eq!(
+            mirror.publisher.drain(),
+            vec![
+                ("remote/a/state".to_string(), b"1".to_vec()),
+                ("remote/c/state".to_string(), b"3".to_vec()),
+            ]
+        );
+    }
+}
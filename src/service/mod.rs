@@ -1,7 +1,26 @@
+pub mod alarm;
+pub mod button_press;
+pub mod cluster;
+pub mod command_result;
+pub mod composition;
+pub mod config;
 pub mod coordinator;
 pub mod device;
+pub mod events;
 pub mod hass;
 pub mod http;
+pub mod identity;
 pub mod iot;
+pub mod mirror;
+pub mod polling;
+pub mod publisher;
+pub mod quiet_hours;
 pub mod quirks;
+pub mod removed;
+pub mod scale;
+pub mod schedule;
+pub mod startup;
 pub mod state;
+pub mod state_grace;
+pub mod support_matrix;
+pub mod transition;
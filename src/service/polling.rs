@@ -0,0 +1,149 @@
+//! The cadences at which we refresh the different kinds of
+//! information that we hold about the devices.
+//!
+//! Device state changes often and is worth checking every few
+//! minutes, whereas the scene catalogs and the account metadata
+//! (device names, rooms and capabilities) rarely change, so they
+//! are refreshed much less frequently in order to conserve the
+//! platform API quota.
+//!
+//! Each wait is jittered so that several instances, or several
+//! loops within one instance, don't settle into hitting the APIs
+//! at the same moment.
+use once_cell::sync::OnceCell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+pub const DEFAULT_STATE_POLL_INTERVAL: Duration = Duration::from_secs(900);
+pub const DEFAULT_SCENE_REFRESH_INTERVAL: Duration = Duration::from_secs(86400);
+pub const DEFAULT_METADATA_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How far either side of the nominal interval a wait may fall
+const JITTER_FRACTION: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PollIntervals {
+    /// How stale the state of a device may become before we poll it
+    pub state: Duration,
+    /// How often to refresh the scene catalogs
+    pub scenes: Duration,
+    /// How often to refresh the device list and room assignments
+    pub metadata: Duration,
+}
+
+impl Default for PollIntervals {
+    fn default() -> Self {
+        Self {
+            state: DEFAULT_STATE_POLL_INTERVAL,
+            scenes: DEFAULT_SCENE_REFRESH_INTERVAL,
+            metadata: DEFAULT_METADATA_REFRESH_INTERVAL,
+        }
+    }
+}
+
+static POLL_INTERVALS: OnceCell<PollIntervals> = OnceCell::new();
+
+/// Set the intervals for the remainder of the process
+pub fn set_poll_intervals(intervals: PollIntervals) -> anyhow::Result<()> {
+    for (label, interval) in [
+        ("state poll", intervals.state),
+        ("scene refresh", intervals.scenes),
+        ("metadata refresh", intervals.metadata),
+    ] {
+        anyhow::ensure!(
+            !interval.is_zero(),
+            "the {label} interval must be greater than zero"
+        );
+    }
+    POLL_INTERVALS
+        .set(intervals)
+        .map_err(|_| anyhow::anyhow!("the poll intervals have already been set"))
+}
+
+pub fn poll_intervals() -> PollIntervals {
+    POLL_INTERVALS.get().copied().unwrap_or_default()
+}
+
+/// How stale the state of a device may become before we poll it
+pub fn state_poll_interval() -> chrono::Duration {
+    chrono::Duration::from_std(poll_intervals().state).expect("interval to be in range")
+}
+
+/// Scale `interval` by `1 + JITTER_FRACTION * position`, where
+/// `position` lies between -1.0 and 1.0
+fn jitter_at(interval: Duration, position: f64) -> Duration {
+    interval.mul_f64(1.0 + JITTER_FRACTION * position.clamp(-1.0, 1.0))
+}
+
+/// Returns `interval` adjusted by a random amount of up to 10%
+/// either way
+pub fn jittered(interval: Duration) -> Duration {
+    let (random, _) = uuid::Uuid::new_v4().as_u64_pair();
+    let position = (random as f64 / u64::MAX as f64) * 2.0 - 1.0;
+    jitter_at(interval, position)
+}
+
+/// A position between -1.0 and 1.0 that is stable for a given device
+fn device_position(device_id: &str) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    device_id.hash(&mut hasher);
+    (hasher.finish() as f64 / u64::MAX as f64) * 2.0 - 1.0
+}
+
+/// Returns `interval` adjusted by up to 10% either way, by an
+/// amount that is stable for the device. Polling each device at a
+/// slightly different interval prevents them from all falling due
+/// at the same time.
+pub fn device_poll_interval(device_id: &str, interval: chrono::Duration) -> chrono::Duration {
+    match interval.to_std() {
+        Ok(std) => chrono::Duration::from_std(jitter_at(std, device_position(device_id)))
+            .unwrap_or(interval),
+        Err(_) => interval,
+    }
+}
+
+/// The delay between successive items so that `count` of them
+/// are spread evenly across `window`
+pub fn spread_evenly(window: Duration, count: usize) -> Duration {
+    window / u32::try_from(count.max(1)).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn jitter() {
+        let hour = Duration::from_secs(3600);
+        assert_eq!(jitter_at(hour, -1.0), Duration::from_secs(3240));
+        assert_eq!(jitter_at(hour, 0.0), hour);
+        assert_eq!(jitter_at(hour, 1.0), Duration::from_secs(3960));
+        assert_eq!(jitter_at(hour, 5.0), Duration::from_secs(3960));
+
+        for _ in 0..100 {
+            let j = jittered(hour);
+            assert!(j >= Duration::from_secs(3240) && j <= Duration::from_secs(3960));
+        }
+    }
+
+    #[test]
+    fn per_device_interval() {
+        let interval = chrono::Duration::seconds(900);
+        let a = device_poll_interval("AA:BB:CC:DD:EE:FF:00:01", interval);
+        let b = device_poll_interval("AA:BB:CC:DD:EE:FF:00:02", interval);
+        assert_eq!(a, device_poll_interval("AA:BB:CC:DD:EE:FF:00:01", interval));
+        assert_ne!(a, b);
+        for d in [a, b] {
+            assert!(d >= chrono::Duration::seconds(810) && d <= chrono::Duration::seconds(990));
+        }
+    }
+
+    #[test]
+    fn spreading() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(spread_evenly(minute, 0), minute);
+        assert_eq!(spread_evenly(minute, 1), minute);
+        assert_eq!(spread_evenly(minute, 4), Duration::from_secs(15));
+    }
+}
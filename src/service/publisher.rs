@@ -0,0 +1,374 @@
+//! A bounded outbound queue for MQTT publishes.
+//!
+//! Bursts of state updates are conflated per topic: if a topic already
+//! has a message waiting to be sent, the new payload replaces it in place,
+//! so only the latest state is ever delivered. The queue holds at most
+//! `capacity` distinct topics; anything beyond that is dropped and counted,
+//! which keeps memory bounded when the broker can't keep up.
+//!
+//! Callers that need to know when a message has actually been handed
+//! to the broker, such as discovery configs that must be in place before
+//! the corresponding state is published, can use `enqueue_confirmed`.
+//!
+//! Messages that describe events rather than state, such as the results
+//! of commands, must each be delivered, so `enqueue_event` queues them
+//! in order without conflating them. They are sent ahead of state.
+//!
+//! Everything that is queued is also handed to any mirror brokers,
+//! each of which has its own queue and connection.
+use crate::service::mirror::MirrorBroker;
+use mosquitto_rs::{Client, QoS};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Notify};
+
+/// Default maximum number of distinct topics that may be pending
+pub const DEFAULT_PUBLISH_QUEUE_CAPACITY: usize = 4096;
+
+/// Don't log about dropped messages more often than this
+const DROP_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct Queue {
+    order: VecDeque<String>,
+    pending: HashMap<String, Vec<u8>>,
+    /// Notified once the pending payload for the topic has been published
+    waiters: HashMap<String, Vec<oneshot::Sender<()>>>,
+    /// Topics whose pending payload should be retained by the broker
+    retained: HashSet<String>,
+    /// Messages that are sent in order, without being conflated
+    events: VecDeque<(String, Vec<u8>)>,
+}
+
+/// A message that has been taken from the queue to be sent
+struct Outgoing {
+    topic: String,
+    payload: Vec<u8>,
+    retain: bool,
+    /// Notified once the message has been published
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    published: AtomicU64,
+    conflated: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublishStats {
+    /// Messages handed to the broker
+    pub published: u64,
+    /// Messages that were superseded by a newer payload for
+    /// the same topic before they could be sent
+    pub conflated: u64,
+    /// Messages discarded because the queue was full
+    pub dropped: u64,
+    /// Messages that the mqtt client failed to publish
+    pub failed: u64,
+    /// Number of topics currently waiting to be sent
+    pub queued: usize,
+}
+
+pub struct Publisher {
+    capacity: usize,
+    queue: Mutex<Queue>,
+    notify: Notify,
+    counters: Counters,
+    last_drop_log: Mutex<Option<Instant>>,
+    mirrors: Mutex<Vec<Arc<MirrorBroker>>>,
+}
+
+impl Publisher {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            queue: Mutex::new(Queue::default()),
+            notify: Notify::new(),
+            counters: Counters::default(),
+            last_drop_log: Mutex::new(None),
+            mirrors: Mutex::new(vec![]),
+        }
+    }
+
+    /// Copy everything that is subsequently queued to `mirror`
+    pub fn add_mirror(&self, mirror: Arc<MirrorBroker>) {
+        self.mirrors.lock().push(mirror);
+    }
+
+    fn fan_out(&self, topic: &str, payload: &[u8], retain: bool) {
+        for mirror in self.mirrors.lock().iter() {
+            mirror.forward(topic, payload, retain);
+        }
+    }
+
+    pub fn enqueue(&self, topic: String, payload: Vec<u8>) {
+        self.fan_out(&topic, &payload, false);
+        self.enqueue_local(topic, payload, false, None);
+    }
+
+    /// Queue the payload for the local broker. The `retain` flag follows
+    /// the latest payload for the topic. If `waiter` is given, it is
+    /// resolved once the payload has been published; if the payload is
+    /// dropped, the waiter is dropped along with it.
+    fn enqueue_local(
+        &self,
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+        waiter: Option<oneshot::Sender<()>>,
+    ) {
+        {
+            let mut queue = self.queue.lock();
+            if let Some(existing) = queue.pending.get_mut(&topic) {
+                *existing = payload;
+                self.counters.conflated.fetch_add(1, Ordering::Relaxed);
+            } else if queue.order.len() >= self.capacity {
+                drop(queue);
+                self.record_drop(&topic);
+                return;
+            } else {
+                queue.order.push_back(topic.clone());
+                queue.pending.insert(topic.clone(), payload);
+            }
+            if retain {
+                queue.retained.insert(topic.clone());
+            } else {
+                queue.retained.remove(&topic);
+            }
+            if let Some(waiter) = waiter {
+                queue.waiters.entry(topic).or_default().push(waiter);
+            }
+        }
+        self.notify.notify_one();
+    }
+
+    /// Like `enqueue`, but returns a receiver that resolves once the
+    /// payload has been published. If the message is dropped, or the
+    /// publish fails, the receiver resolves to an error instead.
+    pub fn enqueue_confirmed(&self, topic: String, payload: Vec<u8>) -> oneshot::Receiver<()> {
+        self.fan_out(&topic, &payload, false);
+        let (tx, rx) = oneshot::channel();
+        self.enqueue_local(topic, payload, false, Some(tx));
+        rx
+    }
+
+    /// Like `enqueue`, but the payload is not conflated with any other
+    /// payload for the same topic, so that every message is delivered
+    pub fn enqueue_event(&self, topic: String, payload: Vec<u8>) {
+        self.fan_out(&topic, &payload, false);
+        {
+            let mut queue = self.queue.lock();
+            if queue.order.len() + queue.events.len() >= self.capacity {
+                drop(queue);
+                self.record_drop(&topic);
+                return;
+            }
+            queue.events.push_back((topic, payload));
+        }
+        self.notify.notify_one();
+    }
+
+    /// Like `enqueue`, but the broker will retain the payload
+    pub fn enqueue_retained(&self, topic: String, payload: Vec<u8>) {
+        self.fan_out(&topic, &payload, true);
+        self.enqueue_local(topic, payload, true, None);
+    }
+
+    fn record_drop(&self, topic: &str) {
+        let dropped = self.counters.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut last = self.last_drop_log.lock();
+        if last
+            .map(|t| t.elapsed() >= DROP_LOG_INTERVAL)
+            .unwrap_or(true)
+        {
+            log::warn!(
+                "MQTT publish queue is full ({} topics); dropped {topic}. \
+                 {dropped} messages dropped in total",
+                self.capacity
+            );
+            last.replace(Instant::now());
+        }
+    }
+
+    fn pop(&self) -> Option<Outgoing> {
+        let mut queue = self.queue.lock();
+        if let Some((topic, payload)) = queue.events.pop_front() {
+            return Some(Outgoing {
+                topic,
+                payload,
+                retain: false,
+                waiters: vec![],
+            });
+        }
+        let topic = queue.order.pop_front()?;
+        let payload = queue
+            .pending
+            .remove(&topic)
+            .expect("pending and order to be consistent");
+        let waiters = queue.waiters.remove(&topic).unwrap_or_default();
+        let retain = queue.retained.remove(&topic);
+        Some(Outgoing {
+            topic,
+            payload,
+            retain,
+            waiters,
+        })
+    }
+
+    /// Remove and return everything that is waiting to be sent
+    #[cfg(test)]
+    pub fn drain(&self) -> Vec<(String, Vec<u8>)> {
+        std::iter::from_fn(|| self.pop().map(|msg| (msg.topic, msg.payload))).collect()
+    }
+
+    pub fn stats(&self) -> PublishStats {
+        PublishStats {
+            published: self.counters.published.load(Ordering::Relaxed),
+            conflated: self.counters.conflated.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            queued: {
+                let queue = self.queue.lock();
+                queue.order.len() + queue.events.len()
+            },
+        }
+    }
+
+    /// Drain the queue into the broker, forever
+    pub async fn run(&self, client: Client) {
+        loop {
+            while let Some(Outgoing {
+                topic,
+                payload,
+                retain,
+                waiters,
+            }) = self.pop()
+            {
+                match client
+                    .publish(&topic, &payload, QoS::AtMostOnce, retain)
+                    .await
+                {
+                    Ok(_) => {
+                        self.counters.published.fetch_add(1, Ordering::Relaxed);
+                        for waiter in waiters {
+                            let _ = waiter.send(());
+                        }
+                    }
+                    Err(err) => {
+                        self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                        log::error!("Failed to publish to {topic}: {err:#}");
+                    }
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conflation_and_capacity() {
+        let publisher = Publisher::new(2);
+        publisher.enqueue("a".to_string(), b"1".to_vec());
+        publisher.enqueue("b".to_string(), b"1".to_vec());
+        publisher.enqueue("a".to_string(), b"2".to_vec());
+        publisher.enqueue("c".to_string(), b"1".to_vec());
+
+        let stats = publisher.stats();
+        assert_eq!(stats.conflated, 1);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.queued, 2);
+
+        let pop = || publisher.pop().map(|msg| (msg.topic, msg.payload));
+
+        // "a" keeps its original position, but with the latest payload
+        assert_eq!(pop(), Some(("a".to_string(), b"2".to_vec())));
+        assert_eq!(pop(), Some(("b".to_string(), b"1".to_vec())));
+        assert_eq!(pop(), None);
+
+        publisher.enqueue("c".to_string(), b"1".to_vec());
+        assert_eq!(publisher.stats().queued, 1);
+    }
+
+    #[test]
+    fn confirmation() {
+        let publisher = Publisher::new(1);
+        let mut first = publisher.enqueue_confirmed("a".to_string(), b"1".to_vec());
+        let mut dropped = publisher.enqueue_confirmed("b".to_string(), b"1".to_vec());
+        // The queue was full, so this will never be sent
+        assert!(dropped.try_recv().is_err());
+
+        let waiters = publisher.pop().unwrap().waiters;
+        assert_eq!(waiters.len(), 1);
+        assert!(first.try_recv().is_err());
+        for waiter in waiters {
+            waiter.send(()).unwrap();
+        }
+        assert!(first.try_recv().is_ok());
+    }
+
+    #[test]
+    fn events() {
+        let publisher = Publisher::new(3);
+        publisher.enqueue("a".to_string(), b"1".to_vec());
+        publisher.enqueue_event("r".to_string(), b"1".to_vec());
+        publisher.enqueue_event("r".to_string(), b"2".to_vec());
+        publisher.enqueue_event("r".to_string(), b"3".to_vec());
+        assert_eq!(publisher.stats().dropped, 1);
+
+        assert_eq!(
+            publisher.drain(),
+            vec![
+                ("r".to_string(), b"1".to_vec()),
+                ("r".to_string(), b"2".to_vec()),
+                ("a".to_string(), b"1".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn retained() {
+        let publisher = Publisher::new(2);
+        publisher.enqueue_retained("a".to_string(), b"1".to_vec());
+        publisher.enqueue("b".to_string(), b"1".to_vec());
+
+        let msg = publisher.pop().unwrap();
+        assert_eq!(msg.topic, "a");
+        assert!(msg.retain);
+        let msg = publisher.pop().unwrap();
+        assert_eq!(msg.topic, "b");
+        assert!(!msg.retain);
+    }
+
+    #[test]
+    fn retained_then_plain() {
+        let publisher = Publisher::new(2);
+        publisher.enqueue_retained("a".to_string(), b"1".to_vec());
+        publisher.enqueue("a".to_string(), b"2".to_vec());
+
+        // The plain payload replaced the retained one, so it isn't retained
+        let msg = publisher.pop().unwrap();
+        assert_eq!(msg.payload, b"2");
+        assert!(!msg.retain);
+
+        // and nothing carries over to the next payload for the topic
+        publisher.enqueue("a".to_string(), b"3".to_vec());
+        assert!(!publisher.pop().unwrap().retain);
+
+        publisher.enqueue("a".to_string(), b"4".to_vec());
+        publisher.enqueue_retained("a".to_string(), b"5".to_vec());
+        let msg = publisher.pop().unwrap();
+        assert_eq!(msg.payload, b"5");
+        assert!(msg.retain);
+    }
+}
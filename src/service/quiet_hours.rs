@@ -0,0 +1,107 @@
+//! An optional daily window, in local time, during which we hold back
+//! the state of diagnostic entities such as the Wi-Fi signal strength
+//! and device status, to reduce broker and network traffic overnight.
+//!
+//! Entities that control or report on the device itself continue to
+//! be updated as usual; the diagnostics, including the firmware update
+//! checks, catch up on the first state poll after the window ends.
+use chrono::NaiveTime;
+use once_cell::sync::OnceCell;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Parse a window of the form `HH:MM-HH:MM`.
+    /// A window whose end is before its start spans midnight.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("quiet hours {s:?} must be of the form HH:MM-HH:MM"))?;
+        let parse_time = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|err| anyhow::anyhow!("invalid time {t:?} in quiet hours {s:?}: {err}"))
+        };
+        let hours = Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        };
+        anyhow::ensure!(
+            hours.start != hours.end,
+            "quiet hours {s:?} must not start and end at the same time"
+        );
+        Ok(hours)
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+static QUIET_HOURS: OnceCell<QuietHours> = OnceCell::new();
+
+/// Set the quiet hours for the remainder of the process
+pub fn set_quiet_hours(hours: QuietHours) -> anyhow::Result<()> {
+    QUIET_HOURS
+        .set(hours)
+        .map_err(|_| anyhow::anyhow!("quiet hours have already been set"))?;
+    log::info!(
+        "Diagnostic entities will not be updated between {} and {}",
+        hours.start.format("%H:%M"),
+        hours.end.format("%H:%M")
+    );
+    Ok(())
+}
+
+/// Returns true if diagnostic publishes should be held back right now
+pub fn is_quiet_now() -> bool {
+    QUIET_HOURS
+        .get()
+        .map(|hours| hours.contains(chrono::Local::now().time()))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn t(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            QuietHours::parse("23:00-06:30").unwrap(),
+            QuietHours {
+                start: t(23, 0),
+                end: t(6, 30)
+            }
+        );
+        assert!(QuietHours::parse("23:00").is_err());
+        assert!(QuietHours::parse("25:00-06:00").is_err());
+        assert!(QuietHours::parse("06:00-06:00").is_err());
+    }
+
+    #[test]
+    fn containment() {
+        let overnight = QuietHours::parse("23:00-06:30").unwrap();
+        assert!(overnight.contains(t(23, 0)));
+        assert!(overnight.contains(t(2, 0)));
+        assert!(overnight.contains(NaiveTime::from_hms_opt(6, 29, 59).unwrap()));
+        assert!(!overnight.contains(t(6, 30)));
+        assert!(!overnight.contains(t(12, 0)));
+
+        let afternoon = QuietHours::parse("13:00-15:00").unwrap();
+        assert!(afternoon.contains(t(14, 0)));
+        assert!(!afternoon.contains(t(15, 0)));
+        assert!(!afternoon.contains(t(2, 0)));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::platform_api::DeviceType;
+use crate::service::alarm::AlarmKind;
 use crate::temperature::TemperatureUnits;
 use once_cell::sync::Lazy;
 use std::borrow::Cow;
@@ -20,6 +21,58 @@ impl HumidityUnits {
     }
 }
 
+/// A friendlier name, and an icon, for a toggle capability
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ToggleLabel {
+    pub instance: &'static str,
+    pub name: &'static str,
+    pub icon: Option<&'static str>,
+}
+
+impl ToggleLabel {
+    pub const fn new(instance: &'static str, name: &'static str, icon: &'static str) -> Self {
+        Self {
+            instance,
+            name,
+            icon: Some(icon),
+        }
+    }
+}
+
+/// The instances by which most devices advertise a child lock,
+/// which disables the buttons on the device itself
+const CHILD_LOCK_INSTANCES: &[&str] = &["controlLock", "childLock"];
+const CHILD_LOCK_NAME: &str = "Child Lock";
+const CHILD_LOCK_ICON: &str = "mdi:lock";
+
+/// The instances by which heaters, purifiers and thermometers
+/// advertise turning their on-device display on and off, and
+/// adjusting its brightness
+const DISPLAY_INSTANCES: &[&str] = &["displayToggle", "screenToggle", "screenSwitch"];
+const DISPLAY_BRIGHTNESS_INSTANCES: &[&str] = &["displayBrightness", "screenBrightness"];
+
+/// The power switch of an ice maker starts and stops making ice
+const ICE_MAKER_TOGGLE_LABELS: &[ToggleLabel] = &[ToggleLabel::new(
+    "powerSwitch",
+    "Ice Making",
+    "mdi:snowflake",
+)];
+
+/// Labels for toggle instances that appear across a range of
+/// devices. Quirks can override these for specific SKUs.
+const TOGGLE_LABELS: &[ToggleLabel] = &[
+    ToggleLabel::new("ecoMode", "Eco Mode", "mdi:leaf"),
+    ToggleLabel::new("ecoModeToggle", "Eco Mode", "mdi:leaf"),
+    ToggleLabel::new("energySavingToggle", "Eco Mode", "mdi:leaf"),
+    ToggleLabel::new("awayMode", "Away Mode", "mdi:home-export-outline"),
+    ToggleLabel::new("awayModeToggle", "Away Mode", "mdi:home-export-outline"),
+    ToggleLabel::new("vacationModeToggle", "Away Mode", "mdi:home-export-outline"),
+    ToggleLabel::new("oscillationToggle", "Oscillation", "mdi:arrow-oscillating"),
+    ToggleLabel::new("displayToggle", "Display", "mdi:monitor"),
+    ToggleLabel::new("screenToggle", "Display", "mdi:monitor"),
+    ToggleLabel::new("screenSwitch", "Display", "mdi:monitor"),
+];
+
 #[derive(Clone, Debug)]
 pub struct Quirk {
     pub sku: Cow<'static, str>,
@@ -38,6 +91,26 @@ pub struct Quirk {
     /// their state.
     pub iot_api_supported: bool,
     pub show_as_preset_buttons: Option<&'static [&'static str]>,
+    /// If true, this is a bluetooth gateway/hub that relays
+    /// data for other devices, rather than being useful in
+    /// and of itself
+    pub gateway: bool,
+    /// If true, this is an mmWave sensor that detects whether
+    /// someone is present
+    pub presence_sensor: bool,
+    /// For sensors that push an alarm via IoT, the kind of alarm
+    pub alarm: Option<AlarmKind>,
+    pub toggle_labels: Option<&'static [ToggleLabel]>,
+    /// For devices that don't use one of the usual instance
+    /// names for their child lock capability
+    pub child_lock_instance: Option<&'static str>,
+    /// If true, the firmware accepts scene codes via the LAN API,
+    /// so that scenes can be activated without the cloud
+    pub lan_scenes: bool,
+    /// If true, this is a scene switch or remote that pushes
+    /// its button presses via IoT. The layout of those messages
+    /// hasn't yet been confirmed against a real device.
+    pub button_events: bool,
 }
 
 impl Quirk {
@@ -60,6 +133,13 @@ impl Quirk {
             platform_humidity_sensor_units: None,
             iot_api_supported: false,
             show_as_preset_buttons: None,
+            gateway: false,
+            presence_sensor: false,
+            alarm: None,
+            toggle_labels: None,
+            child_lock_instance: None,
+            lan_scenes: false,
+            button_events: false,
         }
     }
 
@@ -83,6 +163,37 @@ impl Quirk {
         Self::device(sku, DeviceType::Thermometer, "mdi:thermometer")
     }
 
+    pub fn gateway<SKU: Into<Cow<'static, str>>>(sku: SKU) -> Self {
+        let mut quirk = Self::device(sku, DeviceType::Sensor, "mdi:router-wireless");
+        quirk.gateway = true;
+        quirk
+    }
+
+    pub fn presence_sensor<SKU: Into<Cow<'static, str>>>(sku: SKU) -> Self {
+        let mut quirk = Self::device(sku, DeviceType::Sensor, "mdi:motion-sensor");
+        quirk.presence_sensor = true;
+        quirk
+    }
+
+    pub fn leak_sensor<SKU: Into<Cow<'static, str>>>(sku: SKU) -> Self {
+        let mut quirk = Self::device(sku, DeviceType::Sensor, "mdi:water-alert");
+        quirk.alarm = Some(AlarmKind::Leak);
+        quirk
+    }
+
+    pub fn contact_sensor<SKU: Into<Cow<'static, str>>>(sku: SKU) -> Self {
+        let mut quirk = Self::device(sku, DeviceType::Sensor, "mdi:door");
+        quirk.alarm = Some(AlarmKind::Contact);
+        quirk
+    }
+
+    pub fn scene_switch<SKU: Into<Cow<'static, str>>>(sku: SKU) -> Self {
+        let mut quirk =
+            Self::device(sku, DeviceType::Sensor, "mdi:gesture-tap-button").with_ble_only(true);
+        quirk.button_events = true;
+        quirk
+    }
+
     pub fn with_rgb(mut self) -> Self {
         self.supports_rgb = true;
         self
@@ -123,6 +234,21 @@ impl Quirk {
         self
     }
 
+    pub fn with_toggle_labels(mut self, labels: &'static [ToggleLabel]) -> Self {
+        self.toggle_labels.replace(labels);
+        self
+    }
+
+    pub fn with_child_lock_instance(mut self, instance: &'static str) -> Self {
+        self.child_lock_instance.replace(instance);
+        self
+    }
+
+    pub fn with_lan_scenes(mut self) -> Self {
+        self.lan_scenes = true;
+        self
+    }
+
     pub fn with_broken_platform(mut self) -> Self {
         self.avoid_platform_api = true;
         self
@@ -157,7 +283,7 @@ const TV_BACK: &str = "mdi:television-ambient-light";
 const DESK: &str = "mdi:desk-lamp";
 const HEX: &str = "mdi:hexagon-multiple";
 const TRIANGLE: &str = "mdi:triangle";
-const NIGHTLIGHT: &str = "mdi:lightbulb-night";
+pub const NIGHTLIGHT: &str = "mdi:lightbulb-night";
 const WALL_SCONCE: &str = "mdi:wall-sconce";
 const OUTDOOR_LAMP: &str = "mdi:outdoor-lamp";
 const SPOTLIGHT: &str = "mdi:lightbulb-spot";
@@ -230,6 +356,7 @@ fn load_quirks() -> HashMap<String, Quirk> {
         Quirk::thermometer("H5051")
             .with_platform_temperature_sensor_units(TemperatureUnits::Farenheit)
             .with_platform_humidity_sensor_units(HumidityUnits::RelativePercent),
+        Quirk::thermometer("H5075").with_ble_only(true),
         Quirk::thermometer("H5100")
             .with_platform_temperature_sensor_units(TemperatureUnits::Farenheit)
             .with_platform_humidity_sensor_units(HumidityUnits::RelativePercent),
@@ -239,6 +366,17 @@ fn load_quirks() -> HashMap<String, Quirk> {
         Quirk::thermometer("H5179")
             .with_platform_temperature_sensor_units(TemperatureUnits::Farenheit)
             .with_platform_humidity_sensor_units(HumidityUnits::RelativePercent),
+        // Bluetooth gateways; these relay data for the sensors
+        // that are paired with them
+        Quirk::gateway("H5040"),
+        Quirk::gateway("H5042"),
+        Quirk::gateway("H5043"),
+        Quirk::gateway("H5044"),
+        Quirk::presence_sensor("H5127"),
+        // These push their alarms via IoT through their gateway
+        Quirk::leak_sensor("H5054"),
+        Quirk::contact_sensor("H5123"),
+        Quirk::scene_switch("H5126"),
         Quirk::device("H7170", DeviceType::Kettle, "mdi:kettle")
             .with_platform_temperature_sensor_units(TemperatureUnits::Farenheit),
         Quirk::device("H7171", DeviceType::Kettle, "mdi:kettle")
@@ -247,9 +385,11 @@ fn load_quirks() -> HashMap<String, Quirk> {
         Quirk::device("H7173", DeviceType::Kettle, "mdi:kettle")
             .with_platform_temperature_sensor_units(TemperatureUnits::Farenheit)
             .with_show_as_preset_modes(&["Tea", "Coffee", "DIY"]),
+        Quirk::device("H7172", DeviceType::IceMaker, "mdi:cube-outline")
+            .with_toggle_labels(ICE_MAKER_TOGGLE_LABELS),
         // Lights from the list of LAN API enabled devices
         // at <https://app-h5.govee.com/user-manual/wlan-guide>
-        Quirk::lan_api_capable_light("H6072", FLOOR_LAMP),
+        Quirk::lan_api_capable_light("H6072", FLOOR_LAMP).with_lan_scenes(),
         Quirk::lan_api_capable_light("H619B", STRIP),
         Quirk::lan_api_capable_light("H619C", STRIP),
         Quirk::lan_api_capable_light("H619Z", STRIP),
@@ -265,7 +405,7 @@ fn load_quirks() -> HashMap<String, Quirk> {
         Quirk::lan_api_capable_light("H6066", HEX),
         Quirk::lan_api_capable_light("H6067", TRIANGLE),
         Quirk::lan_api_capable_light("H6073", FLOOR_LAMP),
-        Quirk::lan_api_capable_light("H6076", FLOOR_LAMP),
+        Quirk::lan_api_capable_light("H6076", FLOOR_LAMP).with_lan_scenes(),
         Quirk::lan_api_capable_light("H6078", FLOOR_LAMP),
         Quirk::lan_api_capable_light("H6087", WALL_SCONCE),
         Quirk::lan_api_capable_light("H610A", STRIP),
@@ -316,3 +456,81 @@ fn load_quirks() -> HashMap<String, Quirk> {
 pub fn resolve_quirk(sku: &str) -> Option<&'static Quirk> {
     QUIRKS.get(sku)
 }
+
+/// Returns every known quirk, ordered by SKU
+pub fn all_quirks() -> Vec<&'static Quirk> {
+    let mut quirks: Vec<_> = QUIRKS.values().collect();
+    quirks.sort_by(|a, b| a.sku.cmp(&b.sku));
+    quirks
+}
+
+/// If the capability instance is the child lock for the specified
+/// SKU, returns the instance name
+fn child_lock_instance(sku: &str, instance: &str) -> Option<&'static str> {
+    match resolve_quirk(sku).and_then(|q| q.child_lock_instance) {
+        Some(child_lock) => Some(child_lock).filter(|&c| c == instance),
+        None => CHILD_LOCK_INSTANCES
+            .iter()
+            .find(|&&c| c == instance)
+            .copied(),
+    }
+}
+
+pub fn is_child_lock_instance(sku: &str, instance: &str) -> bool {
+    child_lock_instance(sku, instance).is_some()
+}
+
+pub fn is_display_instance(instance: &str) -> bool {
+    DISPLAY_INSTANCES.contains(&instance)
+}
+
+pub fn is_display_brightness_instance(instance: &str) -> bool {
+    DISPLAY_BRIGHTNESS_INSTANCES.contains(&instance)
+}
+
+/// Returns the label for a toggle capability instance,
+/// preferring any that is specific to the SKU
+pub fn resolve_toggle_label(sku: &str, instance: &str) -> Option<ToggleLabel> {
+    if let Some(child_lock) = child_lock_instance(sku, instance) {
+        return Some(ToggleLabel::new(
+            child_lock,
+            CHILD_LOCK_NAME,
+            CHILD_LOCK_ICON,
+        ));
+    }
+
+    resolve_quirk(sku)
+        .and_then(|q| q.toggle_labels)
+        .unwrap_or(&[])
+        .iter()
+        .chain(TOGGLE_LABELS.iter())
+        .find(|label| label.instance == instance)
+        .copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn toggle_labels() {
+        assert_eq!(
+            resolve_toggle_label("H7131", "ecoModeToggle").map(|l| l.name),
+            Some("Eco Mode")
+        );
+        assert_eq!(
+            resolve_toggle_label("H7131", "oscillationToggle").map(|l| l.name),
+            Some("Oscillation")
+        );
+        assert_eq!(resolve_toggle_label("H7131", "warmMistToggle"), None);
+        assert_eq!(
+            resolve_toggle_label("H7131", "controlLock").map(|l| l.name),
+            Some("Child Lock")
+        );
+        assert_eq!(
+            resolve_toggle_label("H7172", "powerSwitch").map(|l| l.name),
+            Some("Ice Making")
+        );
+        assert_eq!(resolve_toggle_label("H7131", "powerSwitch"), None);
+    }
+}
@@ -0,0 +1,99 @@
+//! Devices that have been removed at runtime via the HTTP API.
+//!
+//! A device that misbehaves can be removed without editing the
+//! configuration file or restarting: its entities are removed from
+//! hass and it is no longer polled. The ids of removed devices are
+//! kept in a small state file so that they stay removed across
+//! restarts, until they are added back again.
+use parking_lot::Mutex;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+pub fn removed_devices_file_name() -> PathBuf {
+    crate::cache::cache_dir().join("govee2mqtt-removed-devices.json")
+}
+
+pub struct RemovedDevices {
+    path: PathBuf,
+    ids: Mutex<BTreeSet<String>>,
+}
+
+impl RemovedDevices {
+    pub fn load(path: PathBuf) -> Self {
+        let ids = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|err| {
+                log::warn!("Ignoring unreadable {}: {err:#}", path.display());
+                BTreeSet::new()
+            }),
+            Err(_) => BTreeSet::new(),
+        };
+        Self {
+            path,
+            ids: Mutex::new(ids),
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.lock().contains(id)
+    }
+
+    /// Record that the device has been removed.
+    /// Returns false if it had already been removed.
+    pub fn remove(&self, id: &str) -> bool {
+        let mut ids = self.ids.lock();
+        if !ids.insert(id.to_string()) {
+            return false;
+        }
+        self.save(&ids);
+        true
+    }
+
+    /// Record that the device has been added back.
+    /// Returns false if it hadn't been removed.
+    pub fn restore(&self, id: &str) -> bool {
+        let mut ids = self.ids.lock();
+        if !ids.remove(id) {
+            return false;
+        }
+        self.save(&ids);
+        true
+    }
+
+    fn save(&self, ids: &BTreeSet<String>) {
+        let result = serde_json::to_string(ids)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Ok(std::fs::write(&self.path, text)?));
+        if let Err(err) = result {
+            log::warn!("Unable to write {}: {err:#}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn persisted() {
+        let path = std::env::temp_dir().join(format!(
+            "govee2mqtt-removed-{}.json",
+            uuid::Uuid::new_v4().simple()
+        ));
+
+        let removed = RemovedDevices::load(path.clone());
+        assert!(removed.remove("AA:BB:CC:DD:EE:FF:42:2A"));
+        assert!(!removed.remove("AA:BB:CC:DD:EE:FF:42:2A"));
+        assert!(removed.remove("11:22:33:44:55:66:77:88"));
+
+        let removed = RemovedDevices::load(path.clone());
+        assert!(removed.contains("AA:BB:CC:DD:EE:FF:42:2A"));
+        assert!(removed.restore("AA:BB:CC:DD:EE:FF:42:2A"));
+        assert!(!removed.restore("AA:BB:CC:DD:EE:FF:42:2A"));
+
+        let removed = RemovedDevices::load(path.clone());
+        assert!(!removed.contains("AA:BB:CC:DD:EE:FF:42:2A"));
+        assert!(removed.contains("11:22:33:44:55:66:77:88"));
+
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,79 @@
+//! Consumers of the command topics don't agree on how to express a
+//! level such as brightness or fan speed: some send a percentage, some
+//! send 0-255 in the style of DMX and Alexa, and others send the value
+//! that the device itself uses. Rather than guessing, the level command
+//! topics accept the scale as a suffix, such as
+//! `gv2mqtt/<id>/brightness/byte`, falling back to the `command_scale`
+//! from the configuration file when there is no suffix.
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandScale {
+    /// 0-100
+    #[default]
+    Percent,
+    /// 0-255
+    Byte,
+    /// The range that the device itself uses
+    Native,
+}
+
+impl CommandScale {
+    /// The value that represents the top of the range,
+    /// or None for the native scale
+    fn max(&self) -> Option<f64> {
+        match self {
+            Self::Percent => Some(100.),
+            Self::Byte => Some(255.),
+            Self::Native => None,
+        }
+    }
+
+    /// Returns true if `value` represents zero, or off, rather than
+    /// a position within the device's range
+    pub fn is_zero(&self, value: f64, native: &Range<i64>) -> bool {
+        match self.max() {
+            Some(_) => value <= 0.,
+            None => value < native.start as f64,
+        }
+    }
+
+    /// Map `value`, expressed in this scale, onto the `native` range,
+    /// clamping it to fit
+    pub fn to_native(self, value: f64, native: &Range<i64>) -> i64 {
+        let lo = native.start as f64;
+        let hi = (native.end - 1) as f64;
+        let scaled = match self.max() {
+            Some(max) => lo + (hi - lo) * value / max,
+            None => value,
+        };
+        (scaled.round() as i64).clamp(native.start, native.end - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scaling() {
+        let brightness = 0..101;
+        assert_eq!(CommandScale::Percent.to_native(42., &brightness), 42);
+        assert_eq!(CommandScale::Byte.to_native(255., &brightness), 100);
+        assert_eq!(CommandScale::Byte.to_native(128., &brightness), 50);
+        assert_eq!(CommandScale::Native.to_native(150., &brightness), 100);
+
+        let speed = 1..9;
+        assert_eq!(CommandScale::Percent.to_native(100., &speed), 8);
+        assert_eq!(CommandScale::Percent.to_native(50., &speed), 5);
+        assert_eq!(CommandScale::Byte.to_native(1., &speed), 1);
+        assert_eq!(CommandScale::Native.to_native(3., &speed), 3);
+
+        assert!(CommandScale::Percent.is_zero(0., &speed));
+        assert!(!CommandScale::Percent.is_zero(1., &speed));
+        assert!(CommandScale::Native.is_zero(0., &speed));
+        assert!(!CommandScale::Native.is_zero(1., &speed));
+    }
+}
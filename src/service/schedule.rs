@@ -0,0 +1,322 @@
+//! Schedules (timers and alarms) that are stored on the device
+//! itself, and which the Govee app reports via the device list.
+//!
+//! The layout of this data isn't documented and differs between
+//! models, so parsing is deliberately lenient: we look for a list of
+//! entries that each have some notion of an hour, minute and whether
+//! they are enabled, accepting the various spellings of those fields.
+use crate::undoc_api::DeviceSettings;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleKind {
+    Timer,
+    WakeUp,
+    Sleep,
+}
+
+impl ScheduleKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Timer => "Timer",
+            Self::WakeUp => "Wake-up Alarm",
+            Self::Sleep => "Sleep Timer",
+        }
+    }
+
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Self::Timer => "timer",
+            Self::WakeUp => "wake-up",
+            Self::Sleep => "sleep",
+        }
+    }
+
+    /// The name of the setting in the undocumented API
+    pub fn key(&self) -> &'static str {
+        match self {
+            Self::Timer => "timer",
+            Self::WakeUp => "wakeUpSetting",
+            Self::Sleep => "sleepSetting",
+        }
+    }
+
+    pub fn setting_mut<'a>(&self, settings: &'a mut DeviceSettings) -> &'a mut Option<JsonValue> {
+        match self {
+            Self::Timer => &mut settings.timer,
+            Self::WakeUp => &mut settings.wake_up_setting,
+            Self::Sleep => &mut settings.sleep_setting,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeviceSchedule {
+    pub kind: ScheduleKind,
+    /// Position of this entry within the device's list
+    pub index: usize,
+    pub enabled: bool,
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    /// The app's representation of the days on which the
+    /// schedule repeats
+    pub repeat: Option<JsonValue>,
+    /// How long the schedule runs for, in minutes, such as the time
+    /// taken by a sleep timer to fade out
+    pub duration: Option<u32>,
+    /// The entry as reported by the app, for the benefit of
+    /// anyone wanting to decode more of it
+    pub raw: JsonValue,
+}
+
+impl DeviceSchedule {
+    pub fn name(&self) -> String {
+        format!("{} {}", self.kind.label(), self.index + 1)
+    }
+
+    pub fn slug(&self) -> String {
+        format!("{}-{}", self.kind.slug(), self.index)
+    }
+
+    /// Returns the time of day formatted as `HH:MM`
+    pub fn time(&self) -> Option<String> {
+        Some(format!("{:02}:{:02}", self.hour?, self.minute.unwrap_or(0)))
+    }
+
+    /// A summary of the schedule, for listing alongside others
+    pub fn summary(&self) -> JsonValue {
+        json!({
+            "name": self.name(),
+            "enabled": self.enabled,
+            "time": self.time(),
+            "duration": self.duration,
+            "repeat": self.repeat,
+        })
+    }
+}
+
+const ENABLE_FIELDS: &[&str] = &["enable", "on", "onOff", "open"];
+const OVERALL_ENABLE_FIELDS: &[&str] = &["enable", "on", "onOff"];
+const HOUR_FIELDS: &[&str] = &["hour", "openHour", "startHour"];
+const DURATION_FIELDS: &[&str] = &["duration", "closeTime", "sleepTime"];
+
+pub(crate) fn first_field<'a>(entry: &'a JsonValue, names: &[&str]) -> Option<&'a JsonValue> {
+    names.iter().find_map(|name| entry.get(*name))
+}
+
+pub(crate) fn as_bool(value: &JsonValue) -> Option<bool> {
+    match value {
+        JsonValue::Bool(b) => Some(*b),
+        JsonValue::Number(n) => n.as_i64().map(|n| n != 0),
+        _ => None,
+    }
+}
+
+fn as_u8(value: &JsonValue) -> Option<u8> {
+    value.as_u64().and_then(|n| u8::try_from(n).ok())
+}
+
+/// Locate the list of entries: either the value is itself a list,
+/// or it is an object that holds the list alongside an overall
+/// enable flag
+fn entries(value: &JsonValue) -> (Option<bool>, Vec<&JsonValue>) {
+    match value {
+        JsonValue::Array(list) => (None, list.iter().collect()),
+        JsonValue::Object(map) => {
+            let overall = first_field(value, OVERALL_ENABLE_FIELDS).and_then(as_bool);
+            for list in map.values() {
+                if let JsonValue::Array(list) = list {
+                    return (overall, list.iter().collect());
+                }
+            }
+            // A single schedule rather than a list of them
+            if first_field(value, HOUR_FIELDS).is_some()
+                || first_field(value, DURATION_FIELDS).is_some()
+            {
+                return (None, vec![value]);
+            }
+            (overall, vec![])
+        }
+        _ => (None, vec![]),
+    }
+}
+
+pub fn parse_schedules(kind: ScheduleKind, value: &JsonValue) -> Vec<DeviceSchedule> {
+    let (overall, list) = entries(value);
+    list.into_iter()
+        .filter(|entry| entry.is_object())
+        .enumerate()
+        .map(|(index, entry)| {
+            let enabled = first_field(entry, ENABLE_FIELDS)
+                .and_then(as_bool)
+                .unwrap_or(true);
+            DeviceSchedule {
+                kind,
+                index,
+                enabled: enabled && overall.unwrap_or(true),
+                hour: first_field(entry, HOUR_FIELDS).and_then(as_u8),
+                minute: first_field(entry, &["minute", "min", "openMin", "startMin"])
+                    .and_then(as_u8),
+                repeat: first_field(entry, &["repeat", "weekDays", "week"]).cloned(),
+                duration: first_field(entry, DURATION_FIELDS)
+                    .and_then(|v| v.as_u64())
+                    .and_then(|n| u32::try_from(n).ok()),
+                raw: entry.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Set the first of `names` that is present in `entry` to `enabled`,
+/// in the same representation as it had before, or add an `enable`
+/// field if there is none
+fn set_enable_field(entry: &mut JsonValue, names: &[&str], enabled: bool) {
+    let Some(map) = entry.as_object_mut() else {
+        return;
+    };
+    let name = names
+        .iter()
+        .find(|name| map.contains_key(**name))
+        .copied()
+        .unwrap_or("enable");
+    let value = match map.get(name) {
+        Some(JsonValue::Number(_)) => json!(enabled as u8),
+        _ => json!(enabled),
+    };
+    map.insert(name.to_string(), value);
+}
+
+/// Returns a copy of the setting `value` in which the schedule at
+/// `index`, as numbered by `parse_schedules`, is enabled or disabled.
+/// Enabling an entry of a list that is disabled overall enables the
+/// list too. Returns None if there is no such schedule.
+pub fn set_schedule_enabled(value: &JsonValue, index: usize, enabled: bool) -> Option<JsonValue> {
+    let mut value = value.clone();
+    let overall = match &value {
+        JsonValue::Object(_) => first_field(&value, OVERALL_ENABLE_FIELDS).and_then(as_bool),
+        _ => None,
+    };
+    let is_single = first_field(&value, HOUR_FIELDS).is_some()
+        || first_field(&value, DURATION_FIELDS).is_some();
+
+    let list = match &mut value {
+        JsonValue::Array(list) => Some(list),
+        JsonValue::Object(map) => map.values_mut().find_map(|v| v.as_array_mut()),
+        _ => return None,
+    };
+    match list {
+        Some(list) => {
+            let entry = list
+                .iter_mut()
+                .filter(|entry| entry.is_object())
+                .nth(index)?;
+            set_enable_field(entry, ENABLE_FIELDS, enabled);
+            if enabled && overall == Some(false) {
+                set_enable_field(&mut value, OVERALL_ENABLE_FIELDS, true);
+            }
+        }
+        // A single schedule rather than a list of them
+        None if is_single && index == 0 => set_enable_field(&mut value, ENABLE_FIELDS, enabled),
+        None => return None,
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lenient_parsing() {
+        let timers = parse_schedules(
+            ScheduleKind::Timer,
+            &json!({
+                "enable": 1,
+                "timers": [
+                    {"openHour": 7, "openMin": 30, "open": 1, "repeat": 127},
+                    {"hour": 22, "minute": 5, "enable": false},
+                ]
+            }),
+        );
+        assert_eq!(timers.len(), 2);
+        assert_eq!(timers[0].time().as_deref(), Some("07:30"));
+        assert!(timers[0].enabled);
+        assert_eq!(timers[0].repeat, Some(json!(127)));
+        assert_eq!(timers[1].time().as_deref(), Some("22:05"));
+        assert!(!timers[1].enabled);
+        assert_eq!(timers[1].name(), "Timer 2");
+
+        // An overall disable applies to all of the entries
+        let timers = parse_schedules(
+            ScheduleKind::Timer,
+            &json!({"enable": 0, "list": [{"hour": 6}]}),
+        );
+        assert!(!timers[0].enabled);
+
+        let wake = parse_schedules(
+            ScheduleKind::WakeUp,
+            &json!({"hour": 6, "minute": 45, "onOff": 1}),
+        );
+        assert_eq!(wake.len(), 1);
+        assert_eq!(wake[0].slug(), "wake-up-0");
+        assert!(wake[0].enabled);
+
+        let sleep = parse_schedules(
+            ScheduleKind::Sleep,
+            &json!({"enable": 1, "duration": 30, "startBri": 80}),
+        );
+        assert_eq!(sleep.len(), 1);
+        assert_eq!(sleep[0].duration, Some(30));
+        assert_eq!(sleep[0].time(), None);
+        assert_eq!(sleep[0].summary()["name"], json!("Sleep Timer 1"));
+
+        assert!(parse_schedules(ScheduleKind::Timer, &json!("bogus")).is_empty());
+        assert!(parse_schedules(ScheduleKind::Sleep, &json!({"enable": 0})).is_empty());
+    }
+
+    #[test]
+    fn enabling() {
+        let timers = json!({
+            "enable": 0,
+            "timers": [
+                {"openHour": 7, "openMin": 30, "open": 1},
+                "bogus",
+                {"hour": 22, "minute": 5, "enable": false},
+            ]
+        });
+        let updated = set_schedule_enabled(&timers, 1, true).unwrap();
+        assert_eq!(
+            updated,
+            json!({
+                "enable": 1,
+                "timers": [
+                    {"openHour": 7, "openMin": 30, "open": 1},
+                    "bogus",
+                    {"hour": 22, "minute": 5, "enable": true},
+                ]
+            })
+        );
+        let parsed = parse_schedules(ScheduleKind::Timer, &updated);
+        assert!(parsed[0].enabled && parsed[1].enabled);
+
+        let updated = set_schedule_enabled(&updated, 0, false).unwrap();
+        assert_eq!(updated["timers"][0]["open"], json!(0));
+        assert_eq!(updated["enable"], json!(1));
+        assert_eq!(set_schedule_enabled(&timers, 2, true), None);
+
+        let wake = json!({"hour": 6, "minute": 45, "onOff": 1});
+        assert_eq!(
+            set_schedule_enabled(&wake, 0, false),
+            Some(json!({"hour": 6, "minute": 45, "onOff": 0}))
+        );
+        assert_eq!(
+            set_schedule_enabled(&json!([{"hour": 6}]), 0, false),
+            Some(json!([{"hour": 6, "enable": false}]))
+        );
+        assert_eq!(set_schedule_enabled(&json!({"enable": 0}), 0, true), None);
+        assert_eq!(set_schedule_enabled(&json!("bogus"), 0, true), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Detects when the service is crash-looping during startup.
+//!
+//! Before starting each subsystem we record its name in a small state
+//! file, and clear it once startup has completed. Finding a name in the
+//! file when we start means that the previous run died while starting
+//! that subsystem. After enough consecutive failures we start in safe
+//! mode: the device subsystems are skipped, leaving just the MQTT status
+//! reporting and the HTTP diagnostics endpoints, so that users can still
+//! find out what is going wrong. If the MQTT integration is the subsystem
+//! that keeps failing then it is skipped too.
+//!
+//! Completing startup, including in safe mode, resets the count, so the
+//! next restart tries everything again.
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The number of consecutive failed starts that triggers safe mode
+const SAFE_MODE_THRESHOLD: u32 = 3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    PlatformApi,
+    UndocApi,
+    LanDiscovery,
+    Hass,
+}
+
+impl Subsystem {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::PlatformApi => "Platform API",
+            Self::UndocApi => "Undocumented API",
+            Self::LanDiscovery => "LAN discovery",
+            Self::Hass => "MQTT integration",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct StartupRecord {
+    /// The subsystem that is currently being started
+    pub in_progress: Option<Subsystem>,
+    /// The number of consecutive runs that died during startup
+    pub consecutive_failures: u32,
+    /// The subsystem that was being started when the most
+    /// recent of those runs died
+    pub last_failed: Option<Subsystem>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct StartupStatus {
+    pub safe_mode: bool,
+    #[serde(flatten)]
+    pub record: StartupRecord,
+}
+
+impl StartupStatus {
+    /// Explains why we are in safe mode
+    pub fn reason(&self) -> String {
+        format!(
+            "{} consecutive failed starts, the most recent while starting {}",
+            self.record.consecutive_failures,
+            self.record
+                .last_failed
+                .map(|s| s.label())
+                .unwrap_or("an unknown subsystem")
+        )
+    }
+}
+
+pub struct StartupTracker {
+    path: PathBuf,
+    record: Mutex<StartupRecord>,
+    safe_mode: bool,
+}
+
+pub fn startup_file_name() -> PathBuf {
+    crate::cache::cache_dir().join("govee2mqtt-startup.json")
+}
+
+impl StartupTracker {
+    /// Load the record left by the previous run, and decide
+    /// whether we need to start in safe mode
+    pub fn load(path: PathBuf) -> Self {
+        let mut record: StartupRecord = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|err| {
+                log::warn!("Ignoring unreadable {}: {err:#}", path.display());
+                StartupRecord::default()
+            }),
+            Err(_) => StartupRecord::default(),
+        };
+
+        if let Some(subsystem) = record.in_progress.take() {
+            record.consecutive_failures += 1;
+            record.last_failed.replace(subsystem);
+        }
+        let safe_mode = record.consecutive_failures >= SAFE_MODE_THRESHOLD;
+
+        Self {
+            path,
+            record: Mutex::new(record),
+            safe_mode,
+        }
+    }
+
+    pub fn is_safe_mode(&self) -> bool {
+        self.safe_mode
+    }
+
+    pub fn status(&self) -> StartupStatus {
+        StartupStatus {
+            safe_mode: self.safe_mode,
+            record: self.record.lock().clone(),
+        }
+    }
+
+    /// Returns true if `subsystem` should be started
+    pub fn should_start(&self, subsystem: Subsystem) -> bool {
+        if !self.safe_mode {
+            return true;
+        }
+        subsystem == Subsystem::Hass && self.record.lock().last_failed != Some(subsystem)
+    }
+
+    /// Record that we are about to start `subsystem`
+    pub fn begin(&self, subsystem: Subsystem) {
+        let mut record = self.record.lock();
+        record.in_progress.replace(subsystem);
+        self.save(&record);
+    }
+
+    /// Record that startup completed successfully
+    pub fn finished(&self) {
+        let mut record = self.record.lock();
+        record.in_progress.take();
+        record.consecutive_failures = 0;
+        self.save(&record);
+    }
+
+    fn save(&self, record: &StartupRecord) {
+        let result = serde_json::to_string(record)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Ok(std::fs::write(&self.path, text)?));
+        if let Err(err) = result {
+            log::warn!("Unable to write {}: {err:#}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crash_loop() {
+        let path = std::env::temp_dir().join(format!(
+            "govee2mqtt-startup-{}.json",
+            uuid::Uuid::new_v4().simple()
+        ));
+
+        // Die while starting LAN discovery, several times over
+        for _ in 0..SAFE_MODE_THRESHOLD {
+            let tracker = StartupTracker::load(path.clone());
+            assert!(!tracker.is_safe_mode());
+            tracker.begin(Subsystem::PlatformApi);
+            tracker.begin(Subsystem::LanDiscovery);
+        }
+
+        let tracker = StartupTracker::load(path.clone());
+        assert!(tracker.is_safe_mode());
+        assert_eq!(
+            tracker.status().record.last_failed,
+            Some(Subsystem::LanDiscovery)
+        );
+        assert!(!tracker.should_start(Subsystem::LanDiscovery));
+        assert!(!tracker.should_start(Subsystem::PlatformApi));
+        assert!(tracker.should_start(Subsystem::Hass));
+        tracker.begin(Subsystem::Hass);
+        tracker.finished();
+
+        // Having started up in safe mode, try everything again
+        let tracker = StartupTracker::load(path.clone());
+        assert!(!tracker.is_safe_mode());
+        assert_eq!(tracker.status().record.consecutive_failures, 0);
+
+        std::fs::remove_file(&path).ok();
+    }
+}
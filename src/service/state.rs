@@ -1,31 +1,77 @@
 use crate::ble::{Base64HexBytes, SetHumidifierMode, SetHumidifierNightlightParams};
+use crate::hass_mqtt::climate::{parse_temperature_constraints, TemperatureConstraints};
 use crate::lan_api::{Client as LanClient, DeviceStatus as LanDeviceStatus, LanDevice};
-use crate::platform_api::{DeviceCapability, GoveeApiClient};
+use crate::platform_api::{DeviceCapability, GoveeApiClient, PlatformApiUsage, RequestTally};
+use crate::service::command_result::{note_device, note_transport, Transport};
+use crate::service::config::{
+    AppSettings, DisabledEntityConfig, FavoriteScenesConfig, ServiceConfig,
+};
 use crate::service::coordinator::Coordinator;
-use crate::service::device::Device;
-use crate::service::hass::{topic_safe_id, HassClient};
+use crate::service::device::{normalize_device_id, Device, Freshness, MusicSetting};
+use crate::service::events::{DeviceEvent, EventBus};
+use crate::service::hass::{ColorTempUnits, HassClient};
 use crate::service::iot::IotClient;
+use crate::service::removed::RemovedDevices;
+use crate::service::startup::StartupTracker;
 use crate::temperature::{TemperatureScale, TemperatureValue};
-use crate::undoc_api::GoveeUndocumentedApi;
+use crate::undoc_api::{DeviceSettings, GoveeUndocumentedApi};
 use anyhow::Context;
 use serde_json::Value as JsonValue;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard, Semaphore};
 use tokio::time::{sleep, Duration};
 
+/// The number of independently locked partitions of the device map.
+/// LAN discovery, the poller and MQTT handlers all update devices
+/// concurrently; spreading the devices across shards means that they
+/// rarely contend for the same lock.
+const NUM_DEVICE_SHARDS: usize = 16;
+
+struct DeviceShards {
+    shards: Vec<Mutex<HashMap<String, Device>>>,
+}
+
+impl Default for DeviceShards {
+    fn default() -> Self {
+        Self {
+            shards: (0..NUM_DEVICE_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        }
+    }
+}
+
+impl DeviceShards {
+    fn shard_for(&self, id: &str) -> &Mutex<HashMap<String, Device>> {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
 #[derive(Default)]
 pub struct State {
-    devices_by_id: Mutex<HashMap<String, Device>>,
+    devices_by_id: DeviceShards,
     semaphore_by_id: Mutex<HashMap<String, Arc<Semaphore>>>,
     lan_client: Mutex<Option<LanClient>>,
     platform_client: Mutex<Option<GoveeApiClient>>,
+    platform_api_usage: Mutex<PlatformApiUsage>,
+    platform_api_requests: Arc<RequestTally>,
     undoc_client: Mutex<Option<GoveeUndocumentedApi>>,
     iot_client: Mutex<Option<IotClient>>,
     hass_client: Mutex<Option<HassClient>>,
     hass_discovery_prefix: Mutex<String>,
     temperature_scale: Mutex<TemperatureScale>,
+    color_temp_units: Mutex<ColorTempUnits>,
+    events: EventBus,
+    config: Mutex<Arc<ServiceConfig>>,
+    startup: Mutex<Option<Arc<StartupTracker>>>,
+    removed_devices: Mutex<Option<Arc<RemovedDevices>>>,
+    effective_settings: Mutex<JsonValue>,
 }
 
 pub type StateHandle = Arc<State>;
@@ -43,6 +89,104 @@ impl State {
         *self.temperature_scale.lock().await
     }
 
+    pub async fn set_color_temp_units(&self, units: ColorTempUnits) {
+        *self.color_temp_units.lock().await = units;
+    }
+
+    pub async fn get_color_temp_units(&self) -> ColorTempUnits {
+        *self.color_temp_units.lock().await
+    }
+
+    pub async fn set_config(&self, config: ServiceConfig) {
+        *self.config.lock().await = Arc::new(config);
+        self.apply_device_config().await;
+    }
+
+    /// Apply the per-device options from the configuration file to
+    /// the devices that we know about. This needs to be repeated as
+    /// devices are discovered, since they may be configured by name.
+    pub async fn apply_device_config(&self) {
+        let config = self.get_config().await;
+        let removed = self.get_removed_devices().await;
+        for shard in &self.devices_by_id.shards {
+            for d in shard.lock().await.values_mut() {
+                d.removed = removed.as_ref().is_some_and(|r| r.contains(&d.id));
+                d.ignore_cloud_state = config
+                    .ignore_cloud_state
+                    .iter()
+                    .any(|label| d.matches_label(label));
+                d.lan_scenes = config.lan_scenes.iter().any(|label| d.matches_label(label));
+            }
+        }
+    }
+
+    pub async fn get_config(&self) -> Arc<ServiceConfig> {
+        self.config.lock().await.clone()
+    }
+
+    pub async fn set_startup_tracker(&self, tracker: Arc<StartupTracker>) {
+        self.startup.lock().await.replace(tracker);
+    }
+
+    pub async fn get_startup_tracker(&self) -> Option<Arc<StartupTracker>> {
+        self.startup.lock().await.clone()
+    }
+
+    pub async fn set_removed_devices(&self, removed: Arc<RemovedDevices>) {
+        self.removed_devices.lock().await.replace(removed);
+        self.apply_device_config().await;
+    }
+
+    pub async fn get_removed_devices(&self) -> Option<Arc<RemovedDevices>> {
+        self.removed_devices.lock().await.clone()
+    }
+
+    /// Remove the device, or add it back again, persistently.
+    /// Its entities are removed from, or added back to, hass.
+    /// Returns false if the device was already in that state.
+    pub async fn set_device_removed(
+        self: &Arc<Self>,
+        device: &Device,
+        removed: bool,
+    ) -> anyhow::Result<bool> {
+        let store = self
+            .get_removed_devices()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("device removal is not available"))?;
+        let changed = if removed {
+            store.remove(&device.id)
+        } else {
+            store.restore(&device.id)
+        };
+        if !changed {
+            return Ok(false);
+        }
+        log::info!(
+            "{} {device}",
+            if removed { "Removed" } else { "Added back" }
+        );
+        self.apply_device_config().await;
+
+        if let Some(hass) = self.get_hass_client().await {
+            hass.register_new_entities(self).await?;
+        }
+        Ok(true)
+    }
+
+    /// Record the settings that were resolved from the command line
+    /// and environment at startup, with secrets masked
+    pub async fn set_effective_settings(&self, settings: JsonValue) {
+        *self.effective_settings.lock().await = settings;
+    }
+
+    pub async fn get_effective_settings(&self) -> JsonValue {
+        self.effective_settings.lock().await.clone()
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
     pub async fn set_hass_disco_prefix(&self, prefix: String) {
         *self.hass_discovery_prefix.lock().await = prefix;
     }
@@ -53,23 +197,52 @@ impl State {
 
     /// Returns a mutable version of the specified device, creating
     /// an entry for it if necessary.
+    /// Devices are keyed by their normalized id, so that the same
+    /// physical device reported by different transports maps to a
+    /// single entry.
     pub async fn device_mut(&self, sku: &str, id: &str) -> MappedMutexGuard<Device> {
-        let devices = self.devices_by_id.lock().await;
+        let key = normalize_device_id(id);
+        let devices = self.devices_by_id.shard_for(&key).lock().await;
         MutexGuard::map(devices, |devices| {
-            devices
-                .entry(id.to_string())
-                .or_insert_with(|| Device::new(sku, id))
+            let device = devices.entry(key).or_insert_with(|| Device::new(sku, id));
+            device.note_sku(sku);
+            device
         })
     }
 
     pub async fn devices(&self) -> Vec<Device> {
-        self.devices_by_id.lock().await.values().cloned().collect()
+        let mut result = vec![];
+        for shard in &self.devices_by_id.shards {
+            result.extend(shard.lock().await.values().cloned());
+        }
+        result
+    }
+
+    /// Associate devices with the gateway that relays their data,
+    /// based on the sub-device list that the Govee app reports for
+    /// each gateway.
+    pub async fn link_gateway_devices(&self) {
+        let devices = self.devices().await;
+        for gateway in devices.iter().filter(|d| d.is_gateway()) {
+            for sub in gateway.gateway_sub_devices() {
+                let Some(child) = devices.iter().find(|d| !d.is_gateway() && sub.matches(d)) else {
+                    log::trace!("{gateway}: sub device {sub:?} not found");
+                    continue;
+                };
+                log::debug!("{child} is attached to gateway {gateway}");
+                self.device_mut(&child.sku, &child.id)
+                    .await
+                    .gateway_id
+                    .replace(gateway.id.to_string());
+            }
+        }
     }
 
     /// Returns an immutable copy of the specified Device
     pub async fn device_by_id(&self, id: &str) -> Option<Device> {
-        let devices = self.devices_by_id.lock().await;
-        devices.get(id).cloned()
+        let key = normalize_device_id(id);
+        let devices = self.devices_by_id.shard_for(&key).lock().await;
+        devices.get(&key).cloned()
     }
 
     async fn semaphore_for_device(&self, device: &Device) -> Arc<Semaphore> {
@@ -101,17 +274,28 @@ impl State {
             .resolve_device(label)
             .await
             .ok_or_else(|| anyhow::anyhow!("device '{label}' not found"))?;
+        note_device(&device.id);
         let semaphore = self.semaphore_for_device(&device).await;
         let permit = semaphore.acquire_owned().await?;
         let (tx, rx) = tokio::sync::oneshot::channel();
 
+        // Any poll that is already in flight may return the state
+        // from before this command, so have its result discarded
+        self.device_mut(&device.sku, &device.id)
+            .await
+            .note_command();
+
         // Schedule a task that will poll the device a short
         // time after the Coordinator is dropped, to reconcile
         // any changed state
         let state = self.clone();
+        let sku = device.sku.to_string();
         let device_id = device.id.to_string();
         tokio::spawn(async move {
             let _ = rx.await;
+            // Likewise for polls that began while the command
+            // was being sent, and start the settle time afresh
+            state.device_mut(&sku, &device_id).await.note_command();
             state.poll_after_control(device_id).await
         });
 
@@ -121,22 +305,14 @@ impl State {
     /// Resolve a device using its name, computed name, id or label,
     /// ignoring case.
     pub async fn resolve_device(&self, label: &str) -> Option<Device> {
-        let devices = self.devices_by_id.lock().await;
-
         // Try by id first
-        if let Some(device) = devices.get(label) {
-            return Some(device.clone());
-        }
-
-        for d in devices.values() {
-            if d.name().eq_ignore_ascii_case(label)
-                || d.id.eq_ignore_ascii_case(label)
-                || topic_safe_id(d).eq_ignore_ascii_case(label)
-                || d.ip_addr()
-                    .map(|ip| ip.to_string().eq_ignore_ascii_case(label))
-                    .unwrap_or(false)
-                || d.computed_name().eq_ignore_ascii_case(label)
-            {
+        if let Some(device) = self.device_by_id(label).await {
+            return Some(device);
+        }
+
+        for shard in &self.devices_by_id.shards {
+            let devices = shard.lock().await;
+            if let Some(d) = devices.values().find(|d| d.matches_label(label)) {
                 return Some(d.clone());
             }
         }
@@ -169,13 +345,51 @@ impl State {
     }
 
     pub async fn set_platform_client(&self, client: GoveeApiClient) {
-        self.platform_client.lock().await.replace(client);
+        self.platform_client
+            .lock()
+            .await
+            .replace(client.with_request_tally(self.platform_api_requests.clone()));
+    }
+
+    /// The number of platform API requests made in the last 24 hours
+    pub fn platform_api_requests_last_day(&self) -> usize {
+        self.platform_api_requests.last_day()
+    }
+
+    /// The number of platform API requests made in the last hour
+    pub fn platform_api_requests_last_hour(&self) -> usize {
+        self.platform_api_requests.last_hour()
+    }
+
+    /// The number of platform API requests that have failed since startup
+    pub fn platform_api_errors(&self) -> u64 {
+        self.platform_api_requests.errors()
     }
 
     pub async fn get_platform_client(&self) -> Option<GoveeApiClient> {
         self.platform_client.lock().await.clone()
     }
 
+    pub async fn set_platform_api_usage(&self, usage: PlatformApiUsage) {
+        *self.platform_api_usage.lock().await = usage;
+    }
+
+    /// Returns the platform client if it may be used to control devices
+    pub async fn get_platform_client_for_control(&self) -> Option<GoveeApiClient> {
+        if !self.platform_api_usage.lock().await.allows_control() {
+            return None;
+        }
+        self.get_platform_client().await
+    }
+
+    /// Returns the platform client if it may be used to poll device state
+    pub async fn get_platform_client_for_state(&self) -> Option<GoveeApiClient> {
+        if !self.platform_api_usage.lock().await.allows_state() {
+            return None;
+        }
+        self.get_platform_client().await
+    }
+
     pub async fn set_undoc_client(&self, client: GoveeUndocumentedApi) {
         self.undoc_client.lock().await.replace(client);
     }
@@ -218,24 +432,28 @@ impl State {
     }
 
     pub async fn poll_platform_api(self: &Arc<Self>, device: &Device) -> anyhow::Result<bool> {
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_state().await {
             let device_state = device.device_state();
             log::info!("requesting update via Platform API {device} {device_state:?}");
             if let Some(info) = &device.http_device_info {
+                let generation = device.command_generation();
+                let freshness = Freshness::now();
                 let http_state = client
                     .get_device_state(info)
                     .await
                     .context("get_device_state")?;
                 log::trace!("updated state for {device}");
 
-                {
+                let applied = {
                     let mut device = self.device_mut(&device.sku, &device.id).await;
-                    device.set_http_device_state(http_state);
                     device.set_last_polled();
+                    device.apply_polled_http_device_state(http_state, generation, freshness)
+                };
+                if applied {
+                    self.notify_of_state_change(&device.id)
+                        .await
+                        .context("state.notify_of_state_change")?;
                 }
-                self.notify_of_state_change(&device.id)
-                    .await
-                    .context("state.notify_of_state_change")?;
                 return Ok(true);
             }
         } else {
@@ -246,6 +464,26 @@ impl State {
         Ok(false)
     }
 
+    /// Refresh the state of the device right away, however recently it
+    /// was last polled. The LAN API is queried if the device is on the
+    /// LAN, along with either IoT or the Platform API in the same way
+    /// as the periodic poll. Returns false if none of them could be used.
+    pub async fn poll_device_now(self: &Arc<Self>, device: &Device) -> anyhow::Result<bool> {
+        let mut polled = false;
+        if let Some(lan_dev) = &device.lan_device {
+            if self.get_lan_client().await.is_some() {
+                self.poll_lan_api(lan_dev, |_| true).await?;
+                polled = true;
+            }
+        }
+
+        if !device.needs_platform_poll() && self.poll_iot_api(device).await? {
+            return Ok(true);
+        }
+
+        Ok(self.poll_platform_api(device).await? || polled)
+    }
+
     async fn poll_lan_api<F: Fn(&LanDeviceStatus) -> bool>(
         self: &Arc<Self>,
         device: &LanDevice,
@@ -255,11 +493,12 @@ impl State {
             Some(client) => {
                 let deadline = Instant::now() + Duration::from_secs(5);
                 while Instant::now() <= deadline {
+                    let freshness = Freshness::now();
                     let status = client.query_status(device).await?;
                     let accepted = (acceptor)(&status);
                     self.device_mut(&device.sku, &device.device)
                         .await
-                        .set_lan_device_status(status);
+                        .set_lan_device_status(status, freshness);
                     if accepted {
                         break;
                     }
@@ -279,7 +518,7 @@ impl State {
         value: V,
     ) -> anyhow::Result<()> {
         let value: JsonValue = value.into();
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_control().await {
             if let Some(info) = &device.http_device_info {
                 log::info!("Using Platform API to send {value:?} control to {device}");
                 client.control_device(info, capability, value).await?;
@@ -328,7 +567,7 @@ impl State {
             }
         }
 
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_control().await {
             if let Some(info) = &device.http_device_info {
                 log::info!("Using Platform API to set {device} light {instance_name} state");
                 client.set_toggle_state(info, instance_name, on).await?;
@@ -361,7 +600,7 @@ impl State {
             }
         }
 
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_control().await {
             if let Some(info) = &device.http_device_info {
                 log::info!("Using Platform API to set {device} power state");
                 client.set_power_state(info, on).await?;
@@ -405,7 +644,7 @@ impl State {
             }
         }
 
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_control().await {
             if let Some(info) = &device.http_device_info {
                 log::info!("Using Platform API to set {device} brightness");
                 client.set_brightness(info, percent).await?;
@@ -441,7 +680,7 @@ impl State {
             }
         }
 
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_control().await {
             if let Some(info) = &device.http_device_info {
                 log::info!("Using Platform API to set {device} color temperature");
                 client.set_color_temperature(info, kelvin).await?;
@@ -498,15 +737,41 @@ impl State {
             }
         }
 
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_control().await {
             if let Some(info) = &device.http_device_info {
-                client.set_work_mode(info, work_mode, value).await?;
+                client
+                    .set_work_mode(info, work_mode.into(), value.into())
+                    .await?;
                 return Ok(());
             }
         }
         anyhow::bail!("Unable to control humidifier parameter work_mode={work_mode} for {device}");
     }
 
+    /// Like humidifier_set_parameter, but for work modes whose
+    /// values may be strings. Those can only be set via the
+    /// platform API.
+    pub async fn device_set_work_mode(
+        self: &Arc<Self>,
+        device: &Device,
+        work_mode: JsonValue,
+        value: JsonValue,
+    ) -> anyhow::Result<()> {
+        if let (Some(work_mode), Some(value)) = (work_mode.as_i64(), value.as_i64()) {
+            return self
+                .humidifier_set_parameter(device, work_mode, value)
+                .await;
+        }
+
+        if let Some(client) = self.get_platform_client_for_control().await {
+            if let Some(info) = &device.http_device_info {
+                client.set_work_mode(info, work_mode, value).await?;
+                return Ok(());
+            }
+        }
+        anyhow::bail!("Unable to set work_mode={work_mode} for {device} without the platform API");
+    }
+
     pub async fn device_set_color_rgb(
         self: &Arc<Self>,
         device: &Device,
@@ -548,7 +813,7 @@ impl State {
             }
         }
 
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_control().await {
             if let Some(info) = &device.http_device_info {
                 log::info!("Using Platform API to set {device} color");
                 client.set_color_rgb(info, r, g, b).await?;
@@ -588,26 +853,236 @@ impl State {
     }
 
     pub async fn device_list_scenes(&self, device: &Device) -> anyhow::Result<Vec<String>> {
-        // TODO: some plumbing to maintain offline scene controls for preferred-LAN control
         if let Some(client) = self.get_platform_client().await {
             if let Some(info) = &device.http_device_info {
                 return Ok(sort_and_dedup_scenes(client.list_scene_names(info).await?));
             }
         }
 
+        if device.lan_device.is_some() {
+            // Without the platform API, we can still offer the scenes
+            // that the LAN API is able to activate. A failure to fetch
+            // the catalog is reported rather than treated as having
+            // no scenes, so that the scene entities aren't removed.
+            let names = GoveeUndocumentedApi::get_lan_scene_names(&device.sku)
+                .await
+                .with_context(|| format!("fetching the scene catalog for {device}"))?;
+            return Ok(sort_and_dedup_scenes(names));
+        }
+
         log::trace!("Platform API unavailable: Don't know how to list scenes for {device}");
 
         Ok(vec![])
     }
 
+    pub async fn device_list_diy_scenes(&self, device: &Device) -> anyhow::Result<Vec<String>> {
+        if let Some(client) = self.get_platform_client().await {
+            if let Some(info) = &device.http_device_info {
+                return client.list_diy_scene_names(info).await;
+            }
+        }
+        Ok(vec![])
+    }
+
+    /// Returns the range of color temperatures that a light can
+    /// reproduce, taking into account any correction made in the
+    /// configuration file for the device or for its SKU
+    pub async fn device_color_temperature_range(&self, device: &Device) -> Option<(u32, u32)> {
+        let config = self.get_config().await;
+        for range in &config.color_temp_ranges {
+            if let Some(label) = &range.device {
+                if let Some(d) = self.resolve_device(label).await {
+                    if d.id == device.id {
+                        return Some(range.range());
+                    }
+                }
+            }
+        }
+        if let Some(range) = config.color_temp_ranges.iter().find(|r| {
+            r.sku
+                .as_deref()
+                .map(|sku| sku.eq_ignore_ascii_case(&device.sku))
+                .unwrap_or(false)
+        }) {
+            return Some(range.range());
+        }
+        device.get_color_temperature_range()
+    }
+
+    /// Returns the range of target temperatures for the `instance`
+    /// temperature setting of a device, narrowed to any limits
+    /// configured for it
+    pub async fn device_temperature_constraints(
+        &self,
+        device: &Device,
+        instance: &DeviceCapability,
+    ) -> anyhow::Result<TemperatureConstraints> {
+        let constraints = parse_temperature_constraints(instance)?;
+        let config = self.get_config().await;
+        for limits in &config.setpoint_limits {
+            if let Some(d) = self.resolve_device(&limits.device).await {
+                if d.id == device.id {
+                    return Ok(constraints.restrict(limits));
+                }
+            }
+        }
+        Ok(constraints)
+    }
+
+    /// Returns the entries of `disabled_entities` that apply to a device
+    pub async fn device_disabled_entities(&self, device: &Device) -> Vec<DisabledEntityConfig> {
+        let config = self.get_config().await;
+        let mut disabled = vec![];
+        for entry in &config.disabled_entities {
+            if let Some(d) = self.resolve_device(&entry.device).await {
+                if d.id == device.id {
+                    disabled.push(entry.clone());
+                }
+            }
+        }
+        disabled
+    }
+
+    /// Returns the favorite scenes configured for a device, if any
+    pub async fn device_favorite_scenes(&self, device: &Device) -> Option<FavoriteScenesConfig> {
+        let config = self.get_config().await;
+        for favorites in &config.favorite_scenes {
+            if let Some(d) = self.resolve_device(&favorites.device).await {
+                if d.id == device.id {
+                    return Some(favorites.clone());
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the scenes that the user favorited for a device in
+    /// the Govee app, if any
+    async fn device_app_favorite_scenes(&self, device: &Device) -> Option<FavoriteScenesConfig> {
+        let client = self.get_undoc_client().await?;
+        match client
+            .get_favorite_scene_names(&device.sku, &device.id)
+            .await
+        {
+            Ok(scenes) if !scenes.is_empty() => Some(FavoriteScenesConfig {
+                device: device.id.to_string(),
+                scenes,
+                show_all: false,
+            }),
+            Ok(_) => None,
+            Err(err) => {
+                log::debug!("Unable to fetch the app favorite scenes for {device}: {err:#}");
+                None
+            }
+        }
+    }
+
+    /// Returns the scenes to offer in hass for a device. These are
+    /// the configured favorites, or failing that the favorites from
+    /// the Govee app, or failing that the whole catalog.
+    pub async fn device_offered_scenes(&self, device: &Device) -> anyhow::Result<Vec<String>> {
+        let scenes = self.device_list_scenes(device).await?;
+        if let Some(favorites) = self.device_favorite_scenes(device).await {
+            return Ok(favorites.apply(scenes));
+        }
+        if scenes.is_empty() {
+            return Ok(scenes);
+        }
+        if let Some(favorites) = self.device_app_favorite_scenes(device).await {
+            // The app may know of scenes that the platform API doesn't
+            // offer, so only filter when some of them are present
+            let offered = favorites.apply(scenes.clone());
+            if !offered.is_empty() {
+                return Ok(offered);
+            }
+        }
+        Ok(scenes)
+    }
+
+    pub async fn device_set_music_mode(
+        self: &Arc<Self>,
+        device: &Device,
+        setting: MusicSetting,
+    ) -> anyhow::Result<()> {
+        if let Some(client) = self.get_platform_client_for_control().await {
+            if let Some(info) = &device.http_device_info {
+                log::info!("Using Platform API to set {device} music mode to {setting:?}");
+                client
+                    .set_music_mode(info, setting.mode, setting.sensitivity)
+                    .await?;
+                self.device_mut(&device.sku, &device.id)
+                    .await
+                    .set_music_setting(setting);
+                return self.notify_of_state_change(&device.id).await;
+            }
+        }
+
+        anyhow::bail!("Unable to set music mode for {device}");
+    }
+
+    /// Change settings of a device that are made in the Govee app.
+    /// `settings` is sent to the undocumented API, and `apply` makes
+    /// the same change to our copy so that it is reflected at once,
+    /// rather than when the device list is next refreshed.
+    pub async fn device_set_app_settings<F: FnOnce(&mut DeviceSettings)>(
+        self: &Arc<Self>,
+        device: &Device,
+        settings: JsonValue,
+        apply: F,
+    ) -> anyhow::Result<()> {
+        if self.get_config().await.app_settings != AppSettings::Writable {
+            anyhow::bail!(
+                "changing the settings of {device} requires \"app_settings\" to be \"writable\""
+            );
+        }
+        let client = self.get_undoc_client().await.ok_or_else(|| {
+            anyhow::anyhow!(
+                "changing the settings of {device} requires the Govee account to be configured"
+            )
+        })?;
+        log::info!("Using undocumented API to change {device} settings: {settings}");
+        note_transport(Transport::UndocApi);
+        let acct = client.login_account_cached().await?;
+        client
+            .set_device_settings(&acct.token, &device.sku, &device.id, settings)
+            .await?;
+
+        if let Some(info) = self
+            .device_mut(&device.sku, &device.id)
+            .await
+            .undoc_device_info
+            .as_mut()
+        {
+            apply(&mut info.entry.device_ext.device_settings);
+        }
+        self.notify_of_state_change(&device.id).await
+    }
+
     pub async fn device_set_target_temperature(
         self: &Arc<Self>,
         device: &Device,
         instance_name: &str,
         target: TemperatureValue,
     ) -> anyhow::Result<()> {
-        if let Some(client) = self.get_platform_client().await {
+        if let Some(client) = self.get_platform_client_for_control().await {
             if let Some(info) = &device.http_device_info {
+                let target = match info.capability_by_instance(instance_name) {
+                    Some(cap) => {
+                        let clamped = self
+                            .device_temperature_constraints(device, cap)
+                            .await?
+                            .clamp(target);
+                        // Allow for the rounding in converting between scales
+                        if (clamped.as_celsius() - target.as_celsius()).abs() > 0.01 {
+                            log::warn!(
+                                "Target temperature {target} for {device} is outside \
+                                 of its allowed range; using {clamped} instead"
+                            );
+                        }
+                        clamped
+                    }
+                    None => target,
+                };
                 log::info!("Using Platform API to set {device} target temperature to {target}");
                 client
                     .set_target_temperature(info, instance_name, target)
@@ -627,8 +1102,27 @@ impl State {
         // TODO: some plumbing to maintain offline scene controls for preferred-LAN control
         let avoid_platform_api = device.avoid_platform_api();
 
+        if let Some(lan_dev) = device
+            .lan_device
+            .as_ref()
+            .filter(|_| device.has_lan_scenes())
+        {
+            log::info!("Using LAN API to set {device} to scene {scene}");
+            match lan_dev.set_scene_by_name(scene).await {
+                Ok(()) => {
+                    self.device_mut(&device.sku, &device.id)
+                        .await
+                        .set_active_scene(Some(scene));
+                    return Ok(());
+                }
+                Err(err) => {
+                    log::warn!("Failed to set {device} to scene {scene} via the LAN API: {err:#}");
+                }
+            }
+        }
+
         if !avoid_platform_api {
-            if let Some(client) = self.get_platform_client().await {
+            if let Some(client) = self.get_platform_client_for_control().await {
                 if let Some(info) = &device.http_device_info {
                     log::info!("Using Platform API to set {device} to scene {scene}");
                     client.set_scene_by_name(info, scene).await?;
@@ -660,10 +1154,11 @@ impl State {
             anyhow::bail!("cannot find device {device_id}!?");
         };
 
-        if let Some(hass) = self.get_hass_client().await {
-            hass.advise_hass_of_light_state(&canonical_device, self)
-                .await?;
-        }
+        self.events.emit(DeviceEvent::StateChanged {
+            id: canonical_device.id.to_string(),
+            sku: canonical_device.sku.to_string(),
+            state: canonical_device.device_state(),
+        });
 
         Ok(())
     }
@@ -0,0 +1,56 @@
+//! An optional grace period after startup during which we hold back
+//! the state of each device until we have a fresh reading for it.
+//!
+//! Some of what we know about a device at startup comes from cached
+//! responses, such as the thermometer readings in the device list
+//! from the undocumented API, which may be hours old. Publishing those
+//! would have hass briefly show them as current. Discovery configs are
+//! published as usual; the state of a device is published once it has
+//! been received via the LAN, IoT or Platform APIs, or at the end of
+//! the grace period for those devices that haven't reported by then.
+use crate::service::device::Device;
+use crate::service::state::StateHandle;
+use once_cell::sync::OnceCell;
+use tokio::time::{Duration, Instant};
+
+static GRACE_PERIOD_END: OnceCell<Instant> = OnceCell::new();
+
+/// Start the grace period, for the remainder of the process
+pub fn set_state_grace_period(period: Duration) -> anyhow::Result<()> {
+    GRACE_PERIOD_END
+        .set(Instant::now() + period)
+        .map_err(|_| anyhow::anyhow!("state grace period has already been set"))?;
+    log::info!("The state of each device will be held back for up to {period:?} until it reports");
+    Ok(())
+}
+
+fn grace_period_end() -> Option<Instant> {
+    GRACE_PERIOD_END
+        .get()
+        .copied()
+        .filter(|end| Instant::now() < *end)
+}
+
+/// Returns true if the state of the device should not be published yet
+pub fn is_state_held_back(device: &Device) -> bool {
+    grace_period_end().is_some() && !device.has_fresh_reading()
+}
+
+/// Wait for the grace period to end, then publish the state of
+/// the devices that haven't reported during it
+pub async fn release_held_state(state: StateHandle) {
+    let Some(end) = grace_period_end() else {
+        return;
+    };
+    tokio::time::sleep_until(end).await;
+
+    for device in state.devices().await {
+        if device.has_fresh_reading() {
+            continue;
+        }
+        log::info!("{device} didn't report during the state grace period");
+        if let Err(err) = state.notify_of_state_change(&device.id).await {
+            log::error!("While publishing held state of {device}: {err:#}");
+        }
+    }
+}
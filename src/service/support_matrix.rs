@@ -0,0 +1,124 @@
+//! Computes which transports and entities we provide for each of the
+//! SKUs in the quirks database, by running the entity enumeration for
+//! a stand-in device of each SKU. This keeps the support matrix in
+//! step with the code rather than relying on hand-maintained docs.
+use crate::hass_mqtt::enumerator::enumerate_entities_for_device;
+use crate::hass_mqtt::instance::EntityList;
+use crate::service::device::Device;
+use crate::service::hass::HassClient;
+use crate::service::quirks::{all_quirks, Quirk};
+use crate::service::state::State;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// The id given to the stand-in devices
+const PLACEHOLDER_ID: &str = "00:00:00:00:00:00:00:00";
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SupportMatrixEntry {
+    pub sku: String,
+    /// The kind of device, such as `light` or `humidifier`
+    pub device_type: String,
+    /// The means by which we can talk to the device
+    pub transports: Vec<&'static str>,
+    /// The hass integrations of the entities that we register
+    /// based on what is known about the SKU alone
+    pub entities: Vec<String>,
+}
+
+fn transports(quirk: &Quirk) -> Vec<&'static str> {
+    if quirk.ble_only {
+        return vec![];
+    }
+    let mut result = vec![];
+    if quirk.lan_api_capable {
+        result.push("LAN");
+    }
+    if !quirk.avoid_platform_api {
+        result.push("Platform API");
+    }
+    if quirk.iot_api_supported {
+        result.push("IoT");
+    }
+    result
+}
+
+pub async fn support_matrix() -> anyhow::Result<Vec<SupportMatrixEntry>> {
+    // A blank state has no API clients, so the enumeration
+    // can't reach out to the network
+    let state = Arc::new(State::new());
+    let (client, recorded) = HassClient::detached().manifest_recording();
+
+    let mut matrix = vec![];
+    for quirk in all_quirks() {
+        let device = Device::new(quirk.sku.as_ref(), PLACEHOLDER_ID);
+        let mut entities = EntityList::new();
+        enumerate_entities_for_device(&device, &state, &mut entities).await?;
+        for entity in entities.iter() {
+            entity.publish_config(&state, &client).await?;
+        }
+        let platforms: BTreeSet<String> = std::mem::take(&mut *recorded.lock())
+            .into_iter()
+            .map(|entry| entry.platform)
+            .collect();
+
+        let device_type = device.device_type().to_string();
+        matrix.push(SupportMatrixEntry {
+            sku: quirk.sku.to_string(),
+            device_type: device_type
+                .strip_prefix("devices.types.")
+                .unwrap_or(&device_type)
+                .to_string(),
+            transports: transports(quirk),
+            entities: platforms.into_iter().collect(),
+        });
+    }
+    Ok(matrix)
+}
+
+/// Render the matrix as a markdown table
+pub fn format_support_matrix(matrix: &[SupportMatrixEntry]) -> String {
+    let mut table = "|SKU|Type|Transports|Entities|\n|---|----|----------|--------|\n".to_string();
+    for entry in matrix {
+        let transports = if entry.transports.is_empty() {
+            "None (BLE only)".to_string()
+        } else {
+            entry.transports.join(", ")
+        };
+        table.push_str(&format!(
+            "|{}|{}|{}|{}|\n",
+            entry.sku,
+            entry.device_type,
+            transports,
+            entry.entities.join(", ")
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn matrix() {
+        let matrix = support_matrix().await.unwrap();
+        let find = |sku: &str| matrix.iter().find(|e| e.sku == sku).unwrap();
+
+        let lamp = find("H6072");
+        assert_eq!(lamp.device_type, "light");
+        assert_eq!(lamp.transports, vec!["LAN", "Platform API", "IoT"]);
+        assert!(lamp.entities.contains(&"light".to_string()));
+
+        let presence = find("H5127");
+        assert_eq!(presence.device_type, "sensor");
+        assert!(presence.entities.contains(&"binary_sensor".to_string()));
+        assert!(find("H5054")
+            .entities
+            .contains(&"binary_sensor".to_string()));
+
+        assert!(find("H5075").transports.is_empty());
+        assert!(format_support_matrix(&matrix).contains("|H5075|thermometer|None (BLE only)|"));
+    }
+}
@@ -0,0 +1,216 @@
+//! Home Assistant can ask for a light to change gradually, by
+//! including a transition time in seconds with its command.
+//! The devices themselves change immediately, so a transition is
+//! made by sending a series of intermediate values over the LAN API,
+//! which is quick and has no quota. Transitions aren't attempted via
+//! the cloud APIs, where each step would count against the quota.
+//!
+//! A command that arrives while a transition is underway stops that
+//! transition, so that the most recent command always wins.
+use crate::lan_api::{DeviceColor, LanDevice};
+use crate::service::device::DeviceState;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The time between successive intermediate values
+const STEP_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Longer transitions are shortened to this
+const MAX_TRANSITION: Duration = Duration::from_secs(60);
+
+/// The generation of the most recent command for each device
+static GENERATIONS: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(Default::default);
+
+/// Identifies a command for a device, so that a transition can
+/// tell whether it has been superseded by a later command
+pub struct Ticket {
+    device_id: String,
+    generation: u64,
+}
+
+impl Ticket {
+    /// Begin a command for a device, superseding any transition
+    /// that is underway for it
+    pub fn begin(device_id: &str) -> Self {
+        let mut generations = GENERATIONS.lock();
+        let generation = generations.entry(device_id.to_string()).or_default();
+        *generation += 1;
+        Self {
+            device_id: device_id.to_string(),
+            generation: *generation,
+        }
+    }
+
+    fn is_current(&self) -> bool {
+        GENERATIONS.lock().get(&self.device_id) == Some(&self.generation)
+    }
+}
+
+/// The values that a light should have at the end of a transition.
+/// Anything that is `None` is left as it is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LightTarget {
+    pub brightness: Option<u8>,
+    pub color: Option<DeviceColor>,
+    pub kelvin: Option<u32>,
+}
+
+fn step_count(duration: Duration) -> u32 {
+    let steps = duration.min(MAX_TRANSITION).as_millis() / STEP_INTERVAL.as_millis();
+    (steps as u32).max(1)
+}
+
+fn lerp(from: f64, to: f64, fraction: f64) -> f64 {
+    from + (to - from) * fraction
+}
+
+fn lerp_u8(from: u8, to: u8, fraction: f64) -> u8 {
+    lerp(from as f64, to as f64, fraction).round() as u8
+}
+
+/// Returns the values to send, ending with `target`, to move a
+/// light from its current state to `target` over `duration`.
+///
+/// A light that is off fades in from its lowest brightness, up to
+/// the requested brightness or, failing that, the brightness that
+/// it had before it was turned off. Color and color temperature can
+/// only be faded from a known value, and otherwise change at once.
+pub fn plan(from: &DeviceState, target: LightTarget, duration: Duration) -> Vec<LightTarget> {
+    let target = LightTarget {
+        brightness: target.brightness.or((!from.on).then_some(from.brightness)),
+        ..target
+    };
+    let start_brightness = if from.on { from.brightness } else { 0 };
+    let color_known = from.on && from.kelvin == 0;
+    let kelvin_known = from.on && from.kelvin != 0;
+
+    let count = step_count(duration);
+    (1..=count)
+        .map(|step| {
+            let fraction = step as f64 / count as f64;
+            LightTarget {
+                brightness: target
+                    .brightness
+                    .map(|b| lerp_u8(start_brightness, b, fraction).max(1)),
+                color: target.color.map(|c| {
+                    if color_known {
+                        DeviceColor {
+                            r: lerp_u8(from.color.r, c.r, fraction),
+                            g: lerp_u8(from.color.g, c.g, fraction),
+                            b: lerp_u8(from.color.b, c.b, fraction),
+                        }
+                    } else {
+                        c
+                    }
+                }),
+                kelvin: target.kelvin.map(|k| {
+                    if kelvin_known {
+                        lerp(from.kelvin as f64, k as f64, fraction).round() as u32
+                    } else {
+                        k
+                    }
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Send each of `steps` to the device in turn.
+/// Returns false if the transition was superseded by a later command
+/// before it completed.
+pub async fn run(
+    lan_dev: &LanDevice,
+    ticket: &Ticket,
+    steps: Vec<LightTarget>,
+) -> anyhow::Result<bool> {
+    let mut last = LightTarget::default();
+    for (i, step) in steps.into_iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(STEP_INTERVAL).await;
+        }
+        if !ticket.is_current() {
+            return Ok(false);
+        }
+        if let Some(brightness) = step
+            .brightness
+            .filter(|_| step.brightness != last.brightness)
+        {
+            lan_dev.send_brightness(brightness).await?;
+        }
+        if let Some(color) = step.color.filter(|_| step.color != last.color) {
+            lan_dev.send_color_rgb(color).await?;
+        }
+        if let Some(kelvin) = step.kelvin.filter(|_| step.kelvin != last.kelvin) {
+            lan_dev.send_color_temperature_kelvin(kelvin).await?;
+        }
+        last = step;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        assert_eq!(step_count(Duration::ZERO), 1);
+        assert_eq!(step_count(Duration::from_secs(2)), 8);
+        assert_eq!(step_count(Duration::from_secs(3600)), 240);
+    }
+
+    #[test]
+    fn planning() {
+        let on = DeviceState {
+            on: true,
+            light_on: Some(true),
+            online: Some(true),
+            kelvin: 2700,
+            color: DeviceColor { r: 0, g: 0, b: 0 },
+            brightness: 20,
+            scene: None,
+            source: "test",
+            updated: chrono::Utc::now(),
+        };
+        let target = LightTarget {
+            brightness: Some(60),
+            kelvin: Some(6500),
+            color: None,
+        };
+        let steps = plan(&on, target, Duration::from_secs(1));
+        assert_eq!(
+            steps.iter().map(|s| s.brightness).collect::<Vec<_>>(),
+            vec![Some(30), Some(40), Some(50), Some(60)]
+        );
+        assert_eq!(
+            steps.iter().map(|s| s.kelvin).collect::<Vec<_>>(),
+            vec![Some(3650), Some(4600), Some(5550), Some(6500)]
+        );
+
+        // From off, fade in up to the previous brightness, and
+        // change to a color at once since it isn't known
+        let off = DeviceState { on: false, ..on };
+        let red = DeviceColor { r: 255, g: 0, b: 0 };
+        let steps = plan(
+            &off,
+            LightTarget {
+                color: Some(red),
+                ..Default::default()
+            },
+            Duration::from_secs(1),
+        );
+        assert_eq!(
+            steps,
+            [5, 10, 15, 20]
+                .into_iter()
+                .map(|b| LightTarget {
+                    brightness: Some(b),
+                    color: Some(red),
+                    kelvin: None,
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+}
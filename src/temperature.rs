@@ -141,6 +141,10 @@ impl TemperatureValue {
         self.value
     }
 
+    pub fn unit(&self) -> TemperatureUnits {
+        self.unit
+    }
+
     /// Normalize away scaled temperature units
     pub fn normalize(&self) -> Self {
         let normalized = self.value / self.unit.factor();
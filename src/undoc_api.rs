@@ -1,11 +1,13 @@
 #![allow(unused)]
-use crate::cache::{cache_get, CacheComputeResult, CacheGetOptions};
+use crate::api_log::LoggedJson;
+use crate::cache::{cache_get, invalidate_key, CacheComputeResult, CacheGetOptions};
 use crate::lan_api::{boolean_int, truthy};
 use crate::opt_env_var;
 use crate::platform_api::{
     from_json, http_response_body, DeviceCapability, DeviceCapabilityKind, DeviceParameters,
     EnumOption,
 };
+use anyhow::Context;
 use reqwest::Method;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -207,7 +209,7 @@ impl GoveeUndocumentedApi {
                 Method::POST,
                 "https://app2.govee.com/account/rest/account/v1/login",
             )
-            .json(&serde_json::json!({
+            .logged_json(&serde_json::json!({
                 "email": self.email,
                 "password": self.password,
                 "client": &self.client_id,
@@ -277,6 +279,105 @@ impl GoveeUndocumentedApi {
         Ok(resp)
     }
 
+    /// Change settings of a device that are made in the Govee app,
+    /// such as whether it sends alerts to the phone.
+    /// `settings` holds only the settings that are to be changed,
+    /// named as they are in DeviceSettings.
+    pub async fn set_device_settings(
+        &self,
+        token: &str,
+        sku: &str,
+        device: &str,
+        settings: JsonValue,
+    ) -> anyhow::Result<()> {
+        let response = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?
+            .request(
+                Method::POST,
+                "https://app2.govee.com/device/rest/devices/v1/settings",
+            )
+            .header("Authorization", format!("Bearer {token}"))
+            .header("appVersion", APP_VERSION)
+            .header("clientId", &self.client_id)
+            .header("clientType", "1")
+            .header("iotVersion", "0")
+            .header("timestamp", ms_timestamp())
+            .header("User-Agent", user_agent())
+            .logged_json(&json!({
+                "sku": sku,
+                "device": device,
+                "deviceSettings": settings,
+            }))
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            self.invalidate_account_login();
+        }
+
+        let _: JsonValue = http_response_body(response).await?;
+        Ok(())
+    }
+
+    /// Fetch a page of the temperature/humidity history that the Govee
+    /// cloud retains for thermometers and hygrometers.
+    /// Pass `index: 0` to fetch the most recent readings, and the
+    /// `index` from the prior response to continue further back in time.
+    pub async fn get_thermometer_history(
+        &self,
+        token: &str,
+        sku: &str,
+        device: &str,
+        index: u64,
+        limit: u64,
+    ) -> anyhow::Result<ThermometerHistory> {
+        let resp = self
+            .get_thermometer_history_json(token, sku, device, index, limit)
+            .await?;
+        ThermometerHistory::from_response(resp)
+    }
+
+    /// Like `get_thermometer_history`, but returns the response as-is,
+    /// for capturing it as a fixture
+    pub async fn get_thermometer_history_json(
+        &self,
+        token: &str,
+        sku: &str,
+        device: &str,
+        index: u64,
+        limit: u64,
+    ) -> anyhow::Result<JsonValue> {
+        let response = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?
+            .request(
+                Method::POST,
+                "https://app2.govee.com/th/rest/devices/v1/data/load",
+            )
+            .header("Authorization", format!("Bearer {token}"))
+            .header("appVersion", APP_VERSION)
+            .header("clientId", &self.client_id)
+            .header("clientType", "1")
+            .header("iotVersion", "0")
+            .header("timestamp", ms_timestamp())
+            .header("User-Agent", user_agent())
+            .logged_json(&serde_json::json!({
+                "sku": sku,
+                "device": device,
+                "index": index,
+                "limit": limit,
+            }))
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            self.invalidate_account_login();
+        }
+
+        http_response_body(response).await
+    }
+
     pub fn invalidate_community_login(&self) {
         crate::cache::invalidate_key("undoc-api", "community-login").ok();
     }
@@ -297,7 +398,7 @@ impl GoveeUndocumentedApi {
                     .timeout(Duration::from_secs(60))
                     .build()?
                     .request(Method::POST, "https://community-api.govee.com/os/v1/login")
-                    .json(&serde_json::json!({
+                    .logged_json(&serde_json::json!({
                         "email": self.email,
                         "password": self.password,
                     }))
@@ -339,6 +440,12 @@ impl GoveeUndocumentedApi {
         .await
     }
 
+    /// Discard the cached scene catalog for a SKU, so that
+    /// it is fetched afresh when next needed
+    pub fn invalidate_scenes_for_device(sku: &str) -> anyhow::Result<()> {
+        invalidate_key("undoc-api", &format!("scenes-{sku}"))
+    }
+
     pub async fn get_scenes_for_device(sku: &str) -> anyhow::Result<Vec<LightEffectCategory>> {
         let key = format!("scenes-{sku}");
 
@@ -348,7 +455,7 @@ impl GoveeUndocumentedApi {
                 key: &key,
                 soft_ttl: ONE_DAY,
                 hard_ttl: ONE_WEEK,
-                negative_ttl: Duration::from_secs(1),
+                negative_ttl: FIFTEEN_MINS,
                 allow_stale: true,
             },
             async {
@@ -374,6 +481,99 @@ impl GoveeUndocumentedApi {
         .await
     }
 
+    /// Returns the ids of the scenes that the user has marked as
+    /// favorites for a device in the Govee app, most recent first
+    pub async fn get_favorite_scene_ids(
+        &self,
+        token: &str,
+        sku: &str,
+        device: &str,
+    ) -> anyhow::Result<Vec<u32>> {
+        cache_get(
+            CacheGetOptions {
+                topic: "undoc-api",
+                key: &format!("favorite-scenes-{device}"),
+                soft_ttl: HALF_DAY,
+                hard_ttl: ONE_WEEK,
+                negative_ttl: FIFTEEN_MINS,
+                allow_stale: true,
+            },
+            async {
+                let resp = self.get_favorite_scenes_json(token, sku, device).await?;
+                Ok(CacheComputeResult::Value(
+                    FavoriteScenesResponse::scene_ids(resp)?,
+                ))
+            },
+        )
+        .await
+    }
+
+    /// Like `get_favorite_scene_ids`, but returns the response as-is
+    /// and without caching it, for capturing it as a fixture
+    pub async fn get_favorite_scenes_json(
+        &self,
+        token: &str,
+        sku: &str,
+        device: &str,
+    ) -> anyhow::Result<JsonValue> {
+        let response = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?
+            .request(
+                Method::GET,
+                format!(
+                    "https://app2.govee.com/appsku/v1/light-effect-libraries/favorites?sku={sku}&device={device}"
+                ),
+            )
+            .header("Authorization", format!("Bearer {token}"))
+            .header("appVersion", APP_VERSION)
+            .header("clientId", &self.client_id)
+            .header("clientType", "1")
+            .header("iotVersion", "0")
+            .header("timestamp", ms_timestamp())
+            .header("User-Agent", user_agent())
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            self.invalidate_account_login();
+        }
+
+        http_response_body(response).await
+    }
+
+    /// Returns the names of the scenes that the user has marked as
+    /// favorites for a device in the Govee app
+    pub async fn get_favorite_scene_names(
+        &self,
+        sku: &str,
+        device: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let acct = self.login_account_cached().await?;
+        let ids = self
+            .get_favorite_scene_ids(&acct.token, sku, device)
+            .await?;
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let catalog = Self::get_scenes_for_device(sku).await?;
+        Ok(favorite_scene_names(&catalog, &ids))
+    }
+
+    /// Returns the names of the scenes for a SKU that can be
+    /// activated via the LAN API
+    pub async fn get_lan_scene_names(sku: &str) -> anyhow::Result<Vec<String>> {
+        let catalog = Self::get_scenes_for_device(sku).await?;
+        Ok(lan_scene_names(&catalog))
+    }
+
+    /// Returns the name of the scene that a light reports by its
+    /// code when it is put into that scene
+    pub async fn get_scene_name_by_code(sku: &str, code: u16) -> anyhow::Result<Option<String>> {
+        let catalog = Self::get_scenes_for_device(sku).await?;
+        Ok(scene_name_for_code(&catalog, code))
+    }
+
     /// This is present primarily to workaround a bug where Govee aren't returning
     /// the full list of scenes via their supported platform API
     pub async fn synthesize_platform_api_scene_list(
@@ -479,6 +679,46 @@ impl GoveeUndocumentedApi {
     }
 }
 
+/// The LAN API activates a scene by its code, so only those
+/// scenes that have a code can be used with it
+fn lan_scene_names(catalog: &[LightEffectCategory]) -> Vec<String> {
+    catalog
+        .iter()
+        .flat_map(|c| &c.scenes)
+        .filter(|s| s.light_effects.iter().any(|e| e.scene_code != 0))
+        .map(|s| s.scene_name.clone())
+        .collect()
+}
+
+fn scene_name_for_code(catalog: &[LightEffectCategory], code: u16) -> Option<String> {
+    if code == 0 {
+        return None;
+    }
+    catalog
+        .iter()
+        .flat_map(|c| &c.scenes)
+        .find(|s| s.light_effects.iter().any(|e| e.scene_code == code))
+        .map(|s| s.scene_name.clone())
+}
+
+/// Resolves favorite scene ids to their names in the catalog,
+/// skipping any that the catalog doesn't know
+fn favorite_scene_names(catalog: &[LightEffectCategory], ids: &[u32]) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for id in ids {
+        let scene = catalog
+            .iter()
+            .flat_map(|c| &c.scenes)
+            .find(|s| s.scene_id == *id);
+        if let Some(scene) = scene {
+            if !names.contains(&scene.scene_name) {
+                names.push(scene.scene_name.clone());
+            }
+        }
+    }
+    names
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedOneClick {
     pub name: String,
@@ -566,6 +806,36 @@ pub struct LightEffectEntry {
     pub speed_info: JsonValue,
 }
 
+/// Unknown fields are tolerated, and a response without any
+/// recognizable scenes is treated as there being no favorites.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FavoriteScenesResponse {
+    #[serde(default)]
+    pub data: FavoriteSceneList,
+}
+
+impl FavoriteScenesResponse {
+    pub fn scene_ids(resp: JsonValue) -> anyhow::Result<Vec<u32>> {
+        let parsed: Self = serde_json::from_value(resp.clone())
+            .with_context(|| format!("favorite scenes are not in the expected format: {resp}"))?;
+        Ok(parsed.data.scenes.into_iter().map(|s| s.scene_id).collect())
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FavoriteSceneList {
+    #[serde(default)]
+    pub scenes: Vec<FavoriteScene>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FavoriteScene {
+    pub scene_id: u32,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
@@ -835,6 +1105,11 @@ pub struct DeviceSettings {
     pub device_name: Option<String>,
     pub version_hard: Option<String>,
     pub version_soft: Option<String>,
+    /// The newest firmware available for the device. This hasn't been
+    /// seen in any of the captured device lists, so when and how it is
+    /// reported is unconfirmed; we take it to be the newest version of
+    /// the same firmware as `version_soft`.
+    pub latest_version_soft: Option<String>,
     pub play_state: Option<bool>,
     pub tem_min: Option<i64>,
     pub tem_max: Option<i64>,
@@ -865,6 +1140,13 @@ pub struct DeviceSettings {
     /// eg: Glide Hexa. Value is base64 encoded data
     pub shapes: Option<String>,
     pub support_ble_broad_v3: Option<bool>,
+
+    /// On-device timers; see crate::service::schedule
+    pub timer: Option<JsonValue>,
+    /// Alarm clock/sunrise settings; see crate::service::schedule
+    pub wake_up_setting: Option<JsonValue>,
+    /// Fall-asleep (sunset) settings; see crate::service::schedule
+    pub sleep_setting: Option<JsonValue>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -880,6 +1162,73 @@ pub struct ExtResources {
     pub ic: Option<u32>,
 }
 
+/// Unknown fields are tolerated here, and `from_response` checks
+/// that the readings are plausible before they are used.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ThermometerHistory {
+    #[serde(default)]
+    pub datas: Vec<ThermometerReading>,
+    /// Cursor to pass to the next request in order to fetch
+    /// older readings
+    pub index: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ThermometerReading {
+    /// Hundredths of a degree Celsius
+    pub tem: i64,
+    /// Hundredths of a percent relative humidity
+    pub hum: i64,
+    /// timestamp in milliseconds
+    pub time: i64,
+}
+
+impl ThermometerHistory {
+    pub fn from_response(resp: JsonValue) -> anyhow::Result<Self> {
+        let history: Self = serde_json::from_value(resp.clone()).with_context(|| {
+            format!("thermometer history is not in the expected format: {resp}")
+        })?;
+        history.check_plausible()?;
+        Ok(history)
+    }
+
+    /// Reject readings that are out of the range of a thermometer, or
+    /// from outside of the lifetime of the Govee cloud, which is what
+    /// we'd see if the fields were not in the units that we expect
+    fn check_plausible(&self) -> anyhow::Result<()> {
+        // 2015-01-01
+        const EARLIEST_MS: i64 = 1_420_070_400_000;
+        let latest_ms = chrono::Utc::now().timestamp_millis() + 86_400_000;
+        for reading in &self.datas {
+            anyhow::ensure!(
+                (-5000..=10000).contains(&reading.tem)
+                    && (0..=10000).contains(&reading.hum)
+                    && (EARLIEST_MS..=latest_ms).contains(&reading.time),
+                "thermometer history is not in the expected format: {reading:?}. \
+                 Please report this, along with the output of capture-fixture \
+                 for the device"
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ThermometerReading {
+    pub fn celsius(&self) -> f64 {
+        self.tem as f64 / 100.0
+    }
+
+    pub fn relative_humidity(&self) -> f64 {
+        self.hum as f64 / 100.0
+    }
+
+    pub fn timestamp(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        chrono::DateTime::from_timestamp_millis(self.time)
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
@@ -953,6 +1302,84 @@ mod test {
         k9::assert_matches_snapshot!(format!("{resp:#?}"));
     }
 
+    #[test]
+    fn lan_scenes() {
+        let resp: LightEffectLibraryResponse =
+            from_json(include_str!("../test-data/light-effect-library-h6072.json")).unwrap();
+        let mut categories = resp.data.categories;
+        let names = lan_scene_names(&categories);
+        assert_eq!(names.len(), 65);
+        assert_eq!(names[..4], ["Sunrise", "Sunset", "Ocean", "Forest"]);
+
+        // A scene without a code can't be activated via the LAN API
+        for effect in &mut categories[0].scenes[1].light_effects {
+            effect.scene_code = 0;
+        }
+        let names = lan_scene_names(&categories);
+        assert_eq!(names.len(), 64);
+        assert_eq!(names[..3], ["Sunrise", "Ocean", "Forest"]);
+    }
+
+    #[test]
+    fn thermometer_history_plausibility() {
+        let history = ThermometerHistory::from_response(json!({
+            "datas": [{"tem": 2150, "hum": 4525, "time": 1_700_000_000_000i64}],
+            "index": 1
+        }))
+        .unwrap();
+        assert_eq!(history.datas[0].celsius(), 21.5);
+        assert_eq!(history.index, Some(1));
+
+        // Seconds rather than milliseconds
+        assert!(ThermometerHistory::from_response(json!({
+            "datas": [{"tem": 2150, "hum": 4525, "time": 1_700_000_000}]
+        }))
+        .is_err());
+        // Tenths rather than hundredths
+        assert!(ThermometerHistory::from_response(json!({
+            "datas": [{"tem": 215, "hum": 45250, "time": 1_700_000_000_000i64}]
+        }))
+        .is_err());
+        assert!(ThermometerHistory::from_response(json!({"data": {"list": []}})).is_ok());
+        assert!(ThermometerHistory::from_response(json!({"datas": "nope"})).is_err());
+    }
+
+    #[test]
+    fn scene_by_code() {
+        let resp: LightEffectLibraryResponse =
+            from_json(include_str!("../test-data/light-effect-library-h6072.json")).unwrap();
+        assert_eq!(
+            scene_name_for_code(&resp.data.categories, 2100).as_deref(),
+            Some("Sunset")
+        );
+        assert_eq!(scene_name_for_code(&resp.data.categories, 0), None);
+        assert_eq!(scene_name_for_code(&resp.data.categories, 65000), None);
+    }
+
+    #[test]
+    fn favorite_scenes() {
+        let resp: LightEffectLibraryResponse =
+            from_json(include_str!("../test-data/light-effect-library-h6072.json")).unwrap();
+        assert_eq!(
+            favorite_scene_names(&resp.data.categories, &[1608, 99999, 1606, 1608]),
+            vec!["Ocean".to_string(), "Sunrise".to_string()]
+        );
+
+        assert_eq!(
+            FavoriteScenesResponse::scene_ids(json!({
+                "data": {"scenes": [{"sceneId": 1608, "sceneName": "Ocean"}]},
+                "message": "",
+                "status": 200
+            }))
+            .unwrap(),
+            vec![1608]
+        );
+        assert!(FavoriteScenesResponse::scene_ids(json!({"status": 200}))
+            .unwrap()
+            .is_empty());
+        assert!(FavoriteScenesResponse::scene_ids(json!({"data": {"scenes": [{}]}})).is_err());
+    }
+
     #[test]
     fn issue_14() {
         let resp: DevicesResponse = from_json(include_str!("../test-data/issue14.json")).unwrap();